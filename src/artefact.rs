@@ -0,0 +1,324 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::Read,
+    path::{absolute, Path, PathBuf},
+    time::Instant,
+};
+
+use anyhow::{anyhow, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use indicatif::{HumanBytes, HumanDuration};
+use tar::{Archive, Builder};
+use tracing::{info, instrument};
+
+use crate::presets::Preset;
+use crate::progress;
+
+// artefact: hf://org/model points at a Hugging Face Hub model instead of a local file
+pub fn hf_model_id(artefact: &str) -> Option<&str> {
+    artefact.strip_prefix("hf://")
+}
+
+// artefact: s3://bucket/key is already in S3 (e.g. from a training job), so it's used as is.
+// Returns the bucket and key
+pub fn s3_location(artefact: &str) -> Option<(&str, &str)> {
+    artefact.strip_prefix("s3://")?.split_once('/')
+}
+
+// Placeholders allowed in artefact_key
+pub const KEY_PLACEHOLDERS: &[&str] = &["name", "version", "timestamp", "file", "git_sha"];
+pub const DEFAULT_KEY_TEMPLATE: &str = "{name}/{timestamp}/{file}";
+pub const DEFAULT_VERSIONED_KEY_TEMPLATE: &str = "{name}/{version}/{file}";
+
+// Fill in an artefact_key template like models/{name}/{git_sha}/{file}
+pub fn render_key(template: &str, values: &HashMap<&str, String>) -> Result<String> {
+    let mut key = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Unclosed {{ in artefact_key: {}", template))?;
+        let placeholder = &rest[start + 1..start + end];
+        let value = values.get(placeholder).ok_or_else(|| {
+            anyhow!(
+                "Unknown placeholder {{{}}} in artefact_key, use {}",
+                placeholder,
+                KEY_PLACEHOLDERS.iter().map(|p| format!("{{{}}}", p)).collect::<Vec<_>>().join(", ")
+            )
+        })?;
+        key.push_str(&rest[..start]);
+        key.push_str(value);
+        rest = &rest[start + end + 1..];
+    }
+    key.push_str(rest);
+    Ok(key.trim_start_matches('/').to_string())
+}
+
+// Env vars that tell the container which Hub model to download. Our generated containers, vLLM
+// and TGI all read HF_MODEL_ID and HF_TOKEN. The token is picked up from the environment sageturner runs in
+// (needed for gated and private models) and ends up in the SageMaker model's environment
+pub fn hf_environment(model_id: &str) -> HashMap<String, String> {
+    let mut env = HashMap::new();
+    env.insert("HF_MODEL_ID".to_string(), model_id.to_string());
+    let token = std::env::var("HF_TOKEN").or_else(|_| std::env::var("HUGGING_FACE_HUB_TOKEN"));
+    match token {
+        Ok(t) if !t.is_empty() => {
+            info!("Passing HF_TOKEN from your environment to the endpoint, so it can download {}", model_id);
+            env.insert("HF_TOKEN".to_string(), t);
+        }
+        _ => info!("No HF_TOKEN in your environment, {} will be downloaded anonymously", model_id),
+    }
+    env
+}
+
+// Never worth shipping in a model artefact or image
+const DEFAULT_EXCLUDES: &[&str] = &[".git", "__pycache__", ".ipynb_checkpoints", ".DS_Store"];
+
+// The files to leave out when packaging a directory: the defaults plus the config's exclude globs.
+// Like .gitignore, a pattern without a / (*.ipynb, checkpoints) matches at any depth,
+// one with a / (data/raw/*) matches from the top of the directory
+pub fn excludes(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    let defaults = DEFAULT_EXCLUDES.iter().map(|e| e.to_string());
+    for pattern in defaults.chain(patterns.iter().cloned()) {
+        let pattern = pattern.trim_end_matches('/');
+        let pattern = match pattern.strip_prefix('/') {
+            Some(anchored) => anchored.to_string(),
+            None if pattern.contains('/') => pattern.to_string(),
+            None => format!("**/{}", pattern),
+        };
+        let glob = GlobBuilder::new(&pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| anyhow!("Invalid exclude pattern {}: {}", pattern, e))?;
+        builder.add(glob);
+    }
+    Ok(builder.build()?)
+}
+
+// Tar + gzip the contents of a directory into dest_dir/model.tar.gz, with the directory's
+// contents at the root of the archive (SageMaker extracts it to /opt/ml/model)
+#[instrument(name = "package", skip_all)]
+pub fn package_dir(dir: &Path, dest_dir: &Path, excludes: &GlobSet) -> Result<PathBuf> {
+    let dir = absolute(dir)?;
+    if !dir.is_dir() {
+        return Err(anyhow!("Can't package {}: not a directory", dir.display()));
+    }
+    info!("Packaging {} into model.tar.gz", dir.display());
+    let started = Instant::now();
+
+    let tar_gz_path = dest_dir.join("model.tar.gz");
+    let progress = progress::written(format!("Packaging {}", dir.display()));
+    let tar_gz = progress.wrap_write(File::create(&tar_gz_path)?);
+    let mut builder = Builder::new(GzEncoder::new(tar_gz, Compression::default()));
+    append_dir_filtered(&mut builder, &dir, &dir, excludes)?;
+    builder.into_inner()?.finish()?;
+    progress.finish_and_clear();
+    info!("Packaged {} in {}", HumanBytes(progress.position()), HumanDuration(started.elapsed()));
+
+    Ok(tar_gz_path)
+}
+
+// package_dir on a blocking thread, so packaging a big artefact doesn't hold up the image building and pushing
+// alongside it
+pub async fn package_dir_blocking(dir: PathBuf, dest_dir: PathBuf, excludes: GlobSet) -> Result<PathBuf> {
+    let span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || span.in_scope(|| package_dir(&dir, &dest_dir, &excludes))).await?
+}
+
+// Every file under dir, relative to it, leaving out the same things package_dir does
+pub fn list_files(dir: &Path, excludes: &GlobSet) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_files(dir, dir, excludes, &mut files)?;
+    Ok(files)
+}
+
+// How much would be uploaded from dir: the size of every file list_files returns
+pub fn dir_size(dir: &Path, excludes: &GlobSet) -> Result<u64> {
+    list_files(dir, excludes)?
+        .iter()
+        .map(|file| Ok(std::fs::metadata(dir.join(file))?.len()))
+        .sum()
+}
+
+fn collect_files(root: &Path, dir: &Path, excludes: &GlobSet, files: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = path.strip_prefix(root)?;
+        if excludes.is_match(name) {
+            continue;
+        }
+        if path.is_dir() {
+            collect_files(root, &path, excludes, files)?;
+        } else {
+            files.push(name.to_path_buf());
+        }
+    }
+    Ok(())
+}
+
+// Add the contents of dir to the archive, relative to root, skipping anything excluded
+pub fn append_dir_filtered<W: std::io::Write>(
+    builder: &mut Builder<W>,
+    root: &Path,
+    dir: &Path,
+    excludes: &GlobSet,
+) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+    // Stable archive order, so the same directory always packages to the same tarball
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let name = path.strip_prefix(root)?;
+        if excludes.is_match(name) {
+            continue;
+        }
+        if path.is_dir() {
+            builder.append_dir(name, &path)?;
+            append_dir_filtered(builder, root, &path, excludes)?;
+        } else {
+            builder.append_path_with_name(&path, name)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn extract_tar_gz(tar_gz: &Path, dest_dir: &Path) -> Result<()> {
+    let mut archive = Archive::new(GzDecoder::new(File::open(tar_gz)?));
+    archive.unpack(dest_dir)?;
+    Ok(())
+}
+
+// Triton's model repository has one directory per model, each with a config.pbtxt.
+// Returns the model to load in single model mode: model_name if given, otherwise the only model in the repository
+pub fn triton_model_name(repository: &Path, model_name: Option<&str>) -> Result<String> {
+    let models: Vec<String> = std::fs::read_dir(repository)?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().join("config.pbtxt").is_file())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect();
+
+    match model_name {
+        Some(m) if models.iter().any(|n| n == m) => Ok(m.to_string()),
+        Some(m) => Err(anyhow!(
+            "Triton model {} not found in {}: expected a {}/config.pbtxt",
+            m,
+            repository.display(),
+            m
+        )),
+        None => match models.as_slice() {
+            [only] => Ok(only.clone()),
+            [] => Err(anyhow!(
+                "No models found in Triton model repository {}: expected <model name>/config.pbtxt",
+                repository.display()
+            )),
+            _ => Err(anyhow!(
+                "Triton model repository {} has several models ({}), set container.triton.model_name to pick one",
+                repository.display(),
+                models.join(", ")
+            )),
+        },
+    }
+}
+
+// What inspecting an artefact told us about the model inside it
+#[derive(Debug)]
+pub struct Detected {
+    pub preset: Preset,
+    // The pipeline task, for Hugging Face models whose config.json names the architecture
+    pub task: Option<String>,
+}
+
+// Look at the file names inside a .tar.gz or directory artefact (and a Hugging Face config.json,
+// if there is one) to work out which preset can serve it
+pub fn detect_preset(artefact: &Path) -> Result<Option<Detected>> {
+    info!("Inspecting artefact {} to detect the framework", artefact.display());
+    let mut names = Vec::new();
+    let mut hf_config = None;
+
+    if artefact.is_dir() {
+        list_dir(artefact, artefact, &mut names)?;
+        hf_config = std::fs::read_to_string(artefact.join("config.json")).ok();
+    } else {
+        let mut archive = Archive::new(GzDecoder::new(File::open(artefact)?));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = entry.path()?.to_string_lossy().trim_start_matches("./").to_string();
+            if name == "config.json" {
+                let mut contents = String::new();
+                entry.read_to_string(&mut contents)?;
+                hf_config = Some(contents);
+            }
+            names.push(name);
+        }
+    }
+
+    Ok(detect_from_names(&names, hf_config.as_deref()))
+}
+
+fn list_dir(root: &Path, dir: &Path, names: &mut Vec<String>) -> Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            list_dir(root, &path, names)?;
+        } else if let Ok(relative) = path.strip_prefix(root) {
+            names.push(relative.to_string_lossy().to_string());
+        }
+    }
+    Ok(())
+}
+
+fn detect_from_names(names: &[String], hf_config: Option<&str>) -> Option<Detected> {
+    let file_name = |n: &String| n.rsplit('/').next().unwrap_or(n).to_string();
+    let has_file = |f: &str| names.iter().any(|n| file_name(n) == f);
+    let has_ext = |exts: &[&str]| names.iter().any(|n| exts.iter().any(|e| n.ends_with(e)));
+    let preset = |preset| Some(Detected { preset, task: None });
+
+    // Most specific first: Hugging Face layouts also contain .bin files
+    if has_file("modules.json") || has_file("sentence_bert_config.json") {
+        return preset(Preset::Embeddings);
+    }
+    if hf_config.is_some() && (has_ext(&[".safetensors"]) || has_file("pytorch_model.bin")) {
+        let task = hf_config.and_then(task_from_hf_config);
+        return Some(Detected { preset: Preset::Transformers, task });
+    }
+    if has_file("saved_model.pb") || has_ext(&[".keras", ".h5"]) {
+        return preset(Preset::Tensorflow);
+    }
+    if has_file("xgboost-model") || has_ext(&[".ubj", ".bst"]) {
+        return preset(Preset::Xgboost);
+    }
+    if has_ext(&[".onnx"]) {
+        return preset(Preset::Onnx);
+    }
+    if has_ext(&[".joblib", ".pkl", ".pickle"]) {
+        return preset(Preset::Sklearn);
+    }
+    if has_ext(&[".pt", ".pth", ".bin"]) {
+        return preset(Preset::Pytorch);
+    }
+    None
+}
+
+// Hugging Face config.json names the model class, e.g. "architectures": ["BertForSequenceClassification"],
+// whose suffix tells us the pipeline task
+fn task_from_hf_config(config: &str) -> Option<String> {
+    let tasks = [
+        ("ForSequenceClassification", "text-classification"),
+        ("ForTokenClassification", "token-classification"),
+        ("ForQuestionAnswering", "question-answering"),
+        ("ForImageClassification", "image-classification"),
+        ("ForCausalLM", "text-generation"),
+        ("ForConditionalGeneration", "text2text-generation"),
+        ("ForMaskedLM", "fill-mask"),
+    ];
+    let architectures = config.split("\"architectures\"").nth(1)?.split(']').next()?;
+    tasks
+        .iter()
+        .find(|(suffix, _)| architectures.contains(suffix))
+        .map(|(_, task)| task.to_string())
+}
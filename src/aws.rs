@@ -1,16 +1,360 @@
+use std::collections::{HashMap, HashSet};
 use std::path::absolute;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
 use anyhow::{anyhow, Result};
-use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_sagemaker::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use aws_config::{sts::AssumeRoleProvider, timeout::TimeoutConfig, Region};
+use aws_credential_types::provider::{error::CredentialsError, ProvideCredentials, SharedCredentialsProvider};
+use aws_sdk_s3::primitives::{ByteStream, Length};
+use aws_sdk_s3::types::{
+    AbortIncompleteMultipartUpload, BucketAccelerateStatus, BucketLifecycleConfiguration,
+    BucketVersioningStatus, CompletedMultipartUpload, CompletedPart, ExpirationStatus,
+    LifecycleExpiration, LifecycleRule, LifecycleRuleFilter, NoncurrentVersionExpiration,
+    PublicAccessBlockConfiguration, ServerSideEncryption, ServerSideEncryptionByDefault,
+    ServerSideEncryptionConfiguration, ServerSideEncryptionRule, VersioningConfiguration,
+};
 use aws_sdk_s3::client::Waiters;
 use aws_sdk_sagemaker::types::{
-    ContainerDefinition, ProductionVariant, ProductionVariantServerlessConfig
+    ContainerDefinition, DataCaptureConfig, EndpointStatus, ModelCompressionType, ModelDataSource, ProductionVariant,
+    ProductionVariantServerlessConfig, S3ModelDataSource, S3ModelDataType, Tag
 };
 use aws_sdk_iam::client::Waiters as iam_waiters;
+use aws_sdk_sagemaker::config::ConfigBag;
+use aws_sdk_sagemaker::operation::create_endpoint_config::builders::CreateEndpointConfigFluentBuilder;
+use aws_smithy_runtime_api::{
+    box_error::BoxError,
+    client::{
+        interceptors::{context::FinalizerInterceptorContextRef, Intercept},
+        orchestrator::Metadata,
+        runtime_components::RuntimeComponents,
+    },
+};
 use base64::prelude::*;
 use bollard::auth::DockerCredentials;
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use globset::GlobSet;
+use indicatif::{HumanBytes, HumanDuration};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, instrument, warn};
+
+use crate::artefact;
+use crate::errors;
+use crate::monitor;
+use crate::progress;
+use crate::retry;
+use crate::provenance::{self, Provenance};
+use crate::model_config::{EndpointUrl, UploadConfig};
+
+// Every AWS client sageturner uses, built from one shared config so they all talk to the same region
+#[derive(Clone)]
+pub struct AwsClients {
+    pub region: String,
+    pub sage: aws_sdk_sagemaker::Client,
+    // For invoking endpoints
+    pub runtime: aws_sdk_sagemakerruntime::Client,
+    pub ecr: aws_sdk_ecr::Client,
+    pub iam: aws_sdk_iam::Client,
+    pub s3: aws_sdk_s3::Client,
+    pub ssm: aws_sdk_ssm::Client,
+    pub secrets: aws_sdk_secretsmanager::Client,
+    pub sts: aws_sdk_sts::Client,
+    pub logs: aws_sdk_cloudwatchlogs::Client,
+    pub cloudwatch: aws_sdk_cloudwatch::Client,
+    pub quotas: aws_sdk_servicequotas::Client,
+    pub sns: aws_sdk_sns::Client,
+    pub events: aws_sdk_eventbridge::Client,
+    pub dynamodb: aws_sdk_dynamodb::Client,
+}
+
+// Where the AWS clients' settings come from: flags, then configs. Anything unset comes from your AWS config and environment
+#[derive(Debug, Clone)]
+pub struct AwsSettings {
+    pub region: Option<String>,
+    pub profile: Option<String>,
+    // A role to assume, whose credentials every client uses instead
+    pub assume_role_arn: Option<String>,
+    pub external_id: Option<String>,
+    // Endpoints to use instead of AWS's, e.g. LocalStack's
+    pub endpoint_url: Option<EndpointUrl>,
+}
+
+impl AwsClients {
+    pub async fn load(settings: AwsSettings) -> Result<AwsClients> {
+        let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest())
+            .timeout_config(TimeoutConfig::builder().connect_timeout(Duration::from_secs(8)).build());
+        if let Some(p) = &settings.profile {
+            check_profile_exists(p)?;
+            loader = loader.profile_name(p);
+        }
+        if let Some(r) = settings.region {
+            loader = loader.region(Region::new(r));
+        }
+        // One URL goes in the shared config, so assuming a role uses it too
+        if let Some(EndpointUrl::All(url)) = &settings.endpoint_url {
+            info!("Using AWS endpoint {}", url);
+            loader = loader.endpoint_url(url);
+        }
+        let mut config = loader.load().await;
+
+        // The profile's (or environment's) credentials are only used to assume the role, e.g. a CI account's
+        // credentials assuming a deploy role in a workload account
+        if let Some(role_arn) = settings.assume_role_arn {
+            check_role_arn(&role_arn)?;
+            info!("Assuming role {}", role_arn);
+            let mut provider = AssumeRoleProvider::builder(role_arn).session_name("sageturner").configure(&config);
+            if let Some(id) = settings.external_id {
+                provider = provider.external_id(id);
+            }
+            let provider = provider.build().await;
+            config = config.into_builder().credentials_provider(SharedCredentialsProvider::new(provider)).build();
+        }
+        check_credentials(&config, settings.profile.as_deref()).await?;
+        let region = config
+            .region()
+            .ok_or_else(|| {
+                anyhow!("No AWS region configured. Pass --region, set region in your config, or set one with aws configure or AWS_REGION")
+            })?
+            .to_string();
+
+        let service_url = |service: &str| match &settings.endpoint_url {
+            Some(EndpointUrl::PerService(urls)) => urls.get(service).inspect(|url| {
+                info!("Using {} endpoint {}", service, url);
+            }),
+            _ => None,
+        };
+        // Each client's config, with the service's own endpoint if it has one
+        macro_rules! client {
+            ($sdk:ident, $service:literal) => {{
+                let mut builder = $sdk::config::Builder::from(&config).interceptor(LogRequestIds);
+                if let Some(url) = service_url($service) {
+                    builder = builder.endpoint_url(url);
+                }
+                $sdk::Client::from_conf(builder.build())
+            }};
+        }
+        // Buckets on custom endpoints (LocalStack, MinIO) usually aren't reachable as <bucket>.<host>
+        let mut s3_builder = aws_sdk_s3::config::Builder::from(&config).interceptor(LogRequestIds);
+        if let Some(url) = service_url("s3") {
+            s3_builder = s3_builder.endpoint_url(url);
+        }
+        if config.endpoint_url().is_some() || service_url("s3").is_some() {
+            s3_builder = s3_builder.force_path_style(true);
+        }
+
+        let clients = AwsClients {
+            sage: client!(aws_sdk_sagemaker, "sagemaker"),
+            runtime: client!(aws_sdk_sagemakerruntime, "sagemaker-runtime"),
+            ecr: client!(aws_sdk_ecr, "ecr"),
+            iam: client!(aws_sdk_iam, "iam"),
+            s3: aws_sdk_s3::Client::from_conf(s3_builder.build()),
+            ssm: client!(aws_sdk_ssm, "ssm"),
+            secrets: client!(aws_sdk_secretsmanager, "secretsmanager"),
+            sts: client!(aws_sdk_sts, "sts"),
+            logs: client!(aws_sdk_cloudwatchlogs, "logs"),
+            cloudwatch: client!(aws_sdk_cloudwatch, "monitoring"),
+            quotas: client!(aws_sdk_servicequotas, "servicequotas"),
+            sns: client!(aws_sdk_sns, "sns"),
+            events: client!(aws_sdk_eventbridge, "events"),
+            dynamodb: client!(aws_sdk_dynamodb, "dynamodb"),
+            region,
+        };
+        clients.check_regions()?;
+        Ok(clients)
+    }
+
+    // SageMaker can only pull images from ECR and model data from S3 in its own region, so a client
+    // configured for somewhere else would fail halfway through a deploy
+    fn check_regions(&self) -> Result<()> {
+        let regions = [
+            ("SageMaker", self.sage.config().region()),
+            ("ECR", self.ecr.config().region()),
+            ("S3", self.s3.config().region()),
+        ];
+        for (service, region) in regions {
+            let region = region.map(|r| r.as_ref()).unwrap_or("no region");
+            if region != self.region {
+                return Err(anyhow!("The {} client is configured for {}, not {}", service, region, self.region));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Logs the request ID of every AWS call, which -v shows, for when AWS support asks for them
+#[derive(Debug)]
+struct LogRequestIds;
+
+impl Intercept for LogRequestIds {
+    fn name(&self) -> &'static str {
+        "LogRequestIds"
+    }
+
+    fn read_after_attempt(
+        &self,
+        context: &FinalizerInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> std::result::Result<(), BoxError> {
+        let Some(response) = context.response() else {
+            return Ok(());
+        };
+        // S3 has its own header
+        let headers = response.headers();
+        if let Some(request_id) = headers.get("x-amzn-requestid").or(headers.get("x-amz-request-id")) {
+            match cfg.load::<Metadata>() {
+                Some(operation) => debug!("{} {} request ID {}", operation.service(), operation.name(), request_id),
+                None => debug!("Request ID {}", request_id),
+            }
+        }
+        Ok(())
+    }
+}
+
+// The SDK quietly falls back to no credentials for a profile that doesn't exist, which fails much later
+// with a confusing error, so check the shared config and credentials files have it
+// The AWS config file, then the credentials file
+fn profile_files() -> [String; 2] {
+    let home = std::env::var("HOME").unwrap_or_default();
+    [
+        std::env::var("AWS_CONFIG_FILE").unwrap_or(format!("{}/.aws/config", home)),
+        std::env::var("AWS_SHARED_CREDENTIALS_FILE").unwrap_or(format!("{}/.aws/credentials", home)),
+    ]
+}
+
+fn check_profile_exists(profile: &str) -> Result<()> {
+    let files = profile_files();
+    let mut profiles = Vec::new();
+    for file in &files {
+        let Ok(contents) = std::fs::read_to_string(file) else { continue };
+        for line in contents.lines() {
+            // [profile name] in the config file, [name] in the credentials file ([default] in either)
+            if let Some(section) = line.trim().strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = section.trim().strip_prefix("profile ").unwrap_or(section).trim();
+                profiles.push(name.to_string());
+            }
+        }
+    }
+    if profiles.iter().any(|p| p == profile) {
+        return Ok(());
+    }
+    profiles.sort();
+    profiles.dedup();
+    Err(anyhow!(
+        "No AWS profile called {} in {}. Your profiles: {}",
+        profile,
+        files.join(" or "),
+        if profiles.is_empty() { "none".to_string() } else { profiles.join(", ") }
+    ))
+}
+
+// Whether a profile signs in with IAM Identity Center (SSO), from an sso_session or sso_start_url in its section of
+// the config file
+fn is_sso_profile(profile: &str) -> bool {
+    let [config_file, _] = profile_files();
+    let Ok(contents) = std::fs::read_to_string(config_file) else { return false };
+    let mut in_profile = false;
+    for line in contents.lines().map(str::trim) {
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_profile = section.trim().strip_prefix("profile ").unwrap_or(section).trim() == profile;
+        } else if in_profile && (line.starts_with("sso_session") || line.starts_with("sso_start_url")) {
+            return true;
+        }
+    }
+    false
+}
+
+// Credentials are loaded on the first request, and when they can't be, that fails as a dispatch failure with why
+// buried in it. Loading them up front means saying what's actually wrong: an SSO session that's expired, or no
+// credentials at all
+async fn check_credentials(config: &aws_config::SdkConfig, profile: Option<&str>) -> Result<()> {
+    let Some(provider) = config.credentials_provider() else {
+        return Ok(());
+    };
+    let Err(err) = provider.provide_credentials().await else {
+        return Ok(());
+    };
+    debug!("Couldn't load AWS credentials: {}", DisplayErrorContext(&err));
+    let named = profile.map(|p| p.to_string()).or(std::env::var("AWS_PROFILE").ok());
+    let profile = named.as_deref().unwrap_or("default");
+    // What to pass aws configure and aws sso login
+    let login = match &named {
+        Some(profile) => format!(" --profile {}", profile),
+        None => String::new(),
+    };
+    if is_sso_profile(profile) {
+        return Err(errors::credentials(
+            format!("Your AWS SSO session for profile {} has expired, or you haven't logged in yet", profile),
+            format!("Run aws sso login{}, then try again", login),
+        ));
+    }
+    match err {
+        CredentialsError::CredentialsNotLoaded(_) => Err(errors::credentials(
+            match &named {
+                Some(profile) => format!("Profile {} doesn't have any AWS credentials", profile),
+                None => "No AWS credentials found, in the environment, your AWS config or from an instance role".to_string(),
+            },
+            format!(
+                "Run aws configure{}, or aws sso login{} if you use IAM Identity Center. Or pick another profile with --profile or \
+                 AWS_PROFILE",
+                login, login
+            ),
+        )),
+        err => Err(errors::credentials(
+            format!("Couldn't load AWS credentials for profile {}: {}", profile, DisplayErrorContext(&err)),
+            "Check the profile in your AWS config, and that whatever it gets credentials from is working".to_string(),
+        )),
+    }
+}
+
+// Images in ECR have the registry's region in their URI: <account>.dkr.ecr.<region>.amazonaws.com/<repo>
+pub fn check_image_region(image_uri: &str, region: &str) -> Result<()> {
+    let image_region = image_uri
+        .split('/')
+        .next()
+        .and_then(|registry| registry.split_once(".dkr.ecr."))
+        .and_then(|(_, rest)| rest.split('.').next());
+    match image_region {
+        Some(r) if r != region => Err(anyhow!(
+            "Image {} is in ECR in {}, but you're deploying to {}. SageMaker can only pull images from its own region",
+            image_uri, r, region
+        )),
+        _ => Ok(()),
+    }
+}
+
+// overrides.role_arn can be a role name in this account, or the full ARN of a role. ARNs are used as they are,
+// without an IAM lookup, so roles in other accounts (or that the deploying identity can't read) work
+pub async fn resolve_role_arn(role: &str, client: &aws_sdk_iam::Client) -> Result<String> {
+    if role.starts_with("arn:") {
+        check_role_arn(role)?;
+        return Ok(role.to_string());
+    }
+    get_role_arn(role, client).await
+}
+
+// arn:<partition>:iam::<12 digit account>:role/<optional path/>name
+pub fn check_role_arn(arn: &str) -> Result<()> {
+    let parts: Vec<&str> = arn.splitn(6, ':').collect();
+    let valid = match parts.as_slice() {
+        ["arn", partition, "iam", "", account, resource] => {
+            !partition.is_empty()
+                && account.len() == 12
+                && account.chars().all(|c| c.is_ascii_digit())
+                && resource.strip_prefix("role/").is_some_and(|name| !name.is_empty() && !name.ends_with('/'))
+        }
+        _ => false,
+    };
+    if !valid {
+        return Err(anyhow!(
+            "{} isn't an IAM role ARN, they look like arn:aws:iam::123456789012:role/my-sagemaker-role",
+            arn
+        ));
+    }
+    Ok(())
+}
 
 pub async fn get_role_arn(role_name: &str, client: &aws_sdk_iam::Client) -> Result<String> {
     match client.get_role().role_name(role_name).send().await {
@@ -18,258 +362,2022 @@ pub async fn get_role_arn(role_name: &str, client: &aws_sdk_iam::Client) -> Resu
             match r.role() {
                 Some(r) => Ok(r.arn.clone()),
                 None => {
-                    Err(anyhow!("Error getting role ARN"))
+                    Err(anyhow!("IAM didn't return role {}", role_name))
                 },
             }
         },
-        Err(e) => Err(anyhow!("Error getting role ARN: {}", e)),
+        Err(e) => Err(errors::aws(e, format!("Couldn't get role {}", role_name))),
     }
 
 }
 
+// What the role SageMaker runs models as is allowed to do
+pub struct RoleOptions {
+    // The buckets artefacts are read from: a name, or a name ending in * for a bucket per region
+    pub bucket: String,
+    // Other buckets with artefacts in, for configs whose artefact is an s3:// URI outside the bucket
+    pub artefact_buckets: Vec<String>,
+    // The ECR repositories (named after the models) images are pulled from. Empty for every one in the account
+    pub repositories: Vec<String>,
+    // Attach AmazonSageMakerFullAccess instead of a policy scoped to the above
+    pub full_access: bool,
+    // The KMS key artefacts are encrypted with, which the role has to be able to decrypt with
+    pub kms_key_arn: Option<String>,
+    // The IAM path the role goes under, / if unset
+    pub path: Option<String>,
+    pub permissions_boundary: Option<String>,
+    // Trusted to assume the role, besides sagemaker.amazonaws.com: ARNs, or services (anything ending .amazonaws.com)
+    pub trusted_principals: Vec<String>,
+    // How long to wait for a new role to show up. IAM is eventually consistent, and can take minutes on a busy day
+    pub wait: Duration,
+}
+
+pub const ROLE_POLICY_NAME: &str = "sageturner-execution";
+
+// Create the role SageMaker runs models as, if it doesn't exist, and return its ARN
 pub async fn create_sagemaker_role(
     role_name: &str,
+    region: &str,
+    options: &RoleOptions,
     client: &aws_sdk_iam::Client,
-) -> Result<()> {
-    let trust_policy = r#"{
+) -> Result<String> {
+    if let Some(path) = &options.path {
+        if !path.starts_with('/') || !path.ends_with('/') {
+            return Err(anyhow!("Role path {} has to start and end with /, like /sageturner/", path));
+        }
+    }
+    if let Some(boundary) = &options.permissions_boundary {
+        if !boundary.starts_with("arn:") || !boundary.contains(":policy/") {
+            return Err(anyhow!("Permissions boundary {} isn't a policy ARN, like arn:aws:iam::123456789012:policy/boundary", boundary));
+        }
+    }
+    let trust_policy = trust_policy(&options.trusted_principals).to_string();
+
+    info!("role: {}", role_name);
+    // Setup can be run again (and once per region, for multi-region deploys), but IAM roles are global
+    match client.get_role().role_name(role_name).send().await {
+        Ok(existing) => {
+            info!("Role already exists");
+            // Bring it up to date with the trust policy and boundary asked for. The path can't be changed
+            let existing_path = existing.role().map(|r| r.path()).unwrap_or("/");
+            if options.path.as_deref().is_some_and(|p| p != existing_path) {
+                info!("The role's path is {}, and IAM can't move a role to another path", existing_path);
+            }
+            client
+                .update_assume_role_policy()
+                .role_name(role_name)
+                .policy_document(&trust_policy)
+                .send()
+                .await
+                .map_err(|e| errors::aws(e, format!("Couldn't update the trust policy of role {}", role_name)))?;
+            if let Some(boundary) = &options.permissions_boundary {
+                info!("Setting permissions boundary {}", boundary);
+                client
+                    .put_role_permissions_boundary()
+                    .role_name(role_name)
+                    .permissions_boundary(boundary)
+                    .send()
+                    .await
+                    .map_err(|e| errors::aws(e, format!("Couldn't set the permissions boundary of role {}", role_name)))?;
+            }
+        }
+        Err(_) => {
+            client
+                .create_role()
+                .role_name(role_name)
+                .assume_role_policy_document(&trust_policy)
+                .set_path(options.path.clone())
+                .set_permissions_boundary(options.permissions_boundary.clone())
+                .send()
+                .await
+                .map_err(|e| errors::aws(e, format!("Couldn't create role {}", role_name)))?;
+
+            info!("Waiting for role to be created...");
+            client.wait_until_role_exists().role_name(role_name).wait(options.wait).await.map_err(|e| {
+                errors::timeout(format!(
+                    "Role {} didn't show up within {}, pass --role-wait to give it longer: {}",
+                    role_name,
+                    HumanDuration(options.wait),
+                    e
+                ))
+            })?;
+            info!("Role created");
+        }
+    }
+
+    let role_arn = get_role_arn(role_name, client).await?;
+    if options.full_access {
+        // Attaching a policy that's already attached does nothing
+        info!("Attaching AmazonSageMakerFullAccess");
+        client
+            .attach_role_policy()
+            .role_name(role_name)
+            .policy_arn(format!("arn:{}:iam::aws:policy/AmazonSageMakerFullAccess", partition(region)))
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't attach AmazonSageMakerFullAccess to role {}", role_name)))?;
+    } else {
+        // A role from before setup scoped its policy, or set up with --full-access, would otherwise keep full access
+        let full_access = format!("arn:{}:iam::aws:policy/AmazonSageMakerFullAccess", partition(region));
+        match client.detach_role_policy().role_name(role_name).policy_arn(&full_access).send().await {
+            Ok(_) => info!("Detached AmazonSageMakerFullAccess, pass --full-access to keep it"),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_entity_exception()) => {}
+            Err(e) => return Err(errors::aws(e, format!("Couldn't detach AmazonSageMakerFullAccess from role {}", role_name))),
+        }
+        // The account is in the role's ARN: arn:<partition>:iam::<account>:role/<name>
+        let account = role_arn.split(':').nth(4).unwrap_or_default();
+        let policy = execution_policy(partition(region), account, options);
+        // put_role_policy replaces the policy, so running setup again updates it
+        info!("Putting policy {}", ROLE_POLICY_NAME);
+        client
+            .put_role_policy()
+            .role_name(role_name)
+            .policy_name(ROLE_POLICY_NAME)
+            .policy_document(policy.to_string())
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't put policy {} on role {}", ROLE_POLICY_NAME, role_name)))?;
+    }
+    Ok(role_arn)
+}
+
+// SageMaker, and any extra principals, can assume the role
+fn trust_policy(trusted_principals: &[String]) -> serde_json::Value {
+    let (services, arns): (Vec<&String>, Vec<&String>) =
+        trusted_principals.iter().partition(|p| p.ends_with(".amazonaws.com"));
+    let mut principal = serde_json::json!({
+        "Service": std::iter::once("sagemaker.amazonaws.com").chain(services.iter().map(|s| s.as_str())).collect::<Vec<_>>()
+    });
+    if !arns.is_empty() {
+        principal["AWS"] = serde_json::json!(arns);
+    }
+    serde_json::json!({
         "Version": "2012-10-17",
         "Statement": [
             {
                 "Effect": "Allow",
-                "Principal": {
-                    "Service": "sagemaker.amazonaws.com"
-                },
+                "Principal": principal,
                 "Action": "sts:AssumeRole"
             }
         ]
-    }"#;
+    })
+}
+
+// Just what SageMaker needs to run a sageturner model: read the artefact, pull the image (from the account's
+// repositories, or the AWS images TGI, Triton and Model Monitor use), write logs and metrics, and write what
+// Model Monitor captures
+fn execution_policy(partition: &str, account: &str, options: &RoleOptions) -> serde_json::Value {
+    let repositories: Vec<String> = match options.repositories.is_empty() {
+        true => vec![format!("arn:{}:ecr:*:{}:repository/*", partition, account)],
+        false => options
+            .repositories
+            .iter()
+            .map(|r| format!("arn:{}:ecr:*:{}:repository/{}", partition, account, r))
+            .collect(),
+    };
+    let mut statements = Vec::new();
+    if let Some(key) = &options.kms_key_arn {
+        statements.push(serde_json::json!({
+            "Sid": "DecryptArtefacts",
+            "Effect": "Allow",
+            "Action": "kms:Decrypt",
+            "Resource": key
+        }));
+    }
+    let aws_images = [
+        format!("arn:{}:ecr:*:*:repository/huggingface-pytorch-tgi-inference", partition),
+        format!("arn:{}:ecr:*:*:repository/sagemaker-tritonserver", partition),
+        format!("arn:{}:ecr:*:*:repository/{}", partition, monitor::ANALYZER_REPOSITORY),
+    ];
+    statements.extend([
+        serde_json::json!({
+            "Sid": "ReadArtefacts",
+            "Effect": "Allow",
+            "Action": ["s3:GetObject", "s3:ListBucket"],
+            "Resource": std::iter::once(&options.bucket)
+                .chain(&options.artefact_buckets)
+                .flat_map(|b| [format!("arn:{}:s3:::{}", partition, b), format!("arn:{}:s3:::{}/*", partition, b)])
+                .collect::<Vec<_>>()
+        }),
+        // Model Monitor's captured data, baselines and reports
+        serde_json::json!({
+            "Sid": "WriteMonitoring",
+            "Effect": "Allow",
+            "Action": "s3:PutObject",
+            "Resource": format!("arn:{}:s3:::{}/{}/*", partition, options.bucket, monitor::MONITORING_PREFIX)
+        }),
+        serde_json::json!({
+            "Sid": "PullImages",
+            "Effect": "Allow",
+            "Action": ["ecr:BatchGetImage", "ecr:GetDownloadUrlForLayer", "ecr:BatchCheckLayerAvailability"],
+            "Resource": repositories.into_iter().chain(aws_images).collect::<Vec<_>>()
+        }),
+        // ECR login tokens aren't for a repository, so can't be scoped to one
+        serde_json::json!({
+            "Sid": "LogInToEcr",
+            "Effect": "Allow",
+            "Action": "ecr:GetAuthorizationToken",
+            "Resource": "*"
+        }),
+        serde_json::json!({
+            "Sid": "WriteLogs",
+            "Effect": "Allow",
+            "Action": ["logs:CreateLogGroup", "logs:CreateLogStream", "logs:PutLogEvents", "logs:DescribeLogStreams"],
+            "Resource": format!("arn:{}:logs:*:{}:log-group:/aws/sagemaker/*", partition, account)
+        }),
+        serde_json::json!({
+            "Sid": "WriteMetrics",
+            "Effect": "Allow",
+            "Action": "cloudwatch:PutMetricData",
+            "Resource": "*",
+            "Condition": { "StringLike": { "cloudwatch:namespace": ["/aws/sagemaker/*", "aws/sagemaker/*", "AWS/SageMaker"] } }
+        }),
+    ]);
+    serde_json::json!({ "Version": "2012-10-17", "Statement": statements })
+}
+
+// Create the artefact bucket, if it doesn't exist, and return its ARN
+pub async fn create_sagemaker_bucket(bucket_name: &str, region: &str, client: &aws_sdk_s3::Client) -> Result<String> {
+    info!("bucket: {}", bucket_name);
+    info!("Checking if bucket already exists");
+    let already_exists = client.head_bucket().bucket(bucket_name).send().await.is_ok();
+
+    if !already_exists {
+        info!("Creating bucket in {}", region);
+        // us-east-1 is S3's default location, and CreateBucket rejects it as an explicit constraint
+        let cfg = (region != "us-east-1").then(|| {
+            aws_sdk_s3::types::CreateBucketConfiguration::builder()
+                .location_constraint(aws_sdk_s3::types::BucketLocationConstraint::from(region))
+                .build()
+        });
+        match client
+            .create_bucket()
+            .bucket(bucket_name)
+            .set_create_bucket_configuration(cfg)
+            .send()
+            .await
+            .map_err(|e| e.into_service_error())
+        {
+            Ok(_) => info!("Bucket created"),
+            // head_bucket can fail for a bucket we own, e.g. without s3:ListBucket
+            Err(e) if e.is_bucket_already_owned_by_you() => info!("Bucket already exists"),
+            Err(e) if e.is_bucket_already_exists() => {
+                return Err(anyhow!(
+                    "Bucket {} belongs to another AWS account. Bucket names are global, pick another with --bucket-name",
+                    bucket_name
+                ))
+            }
+            Err(e) => return Err(errors::aws(e, format!("Couldn't create bucket {}", bucket_name))),
+        }
+    } else {
+        info!("Bucket already exists");
+    }
+    Ok(format!("arn:{}:s3:::{}", partition(region), bucket_name))
+}
+
+// S3 reports buckets in us-east-1 with no location constraint, and some old eu-west-1 buckets as "EU"
+pub async fn check_bucket_region(bucket_name: &str, region: &str, client: &aws_sdk_s3::Client) -> Result<()> {
+    let location = client
+        .get_bucket_location()
+        .bucket(bucket_name)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't get the region of bucket {}", bucket_name)))?;
+    let bucket_region = match location.location_constraint().map(|c| c.as_str()) {
+        None | Some("") => "us-east-1",
+        Some("EU") => "eu-west-1",
+        Some(r) => r,
+    };
+    if bucket_region != region {
+        return Err(anyhow!(
+            "Bucket {} is in {}, but you're deploying to {}. SageMaker needs the artefact in the same region as the endpoint: override bucket_name with a bucket in {}",
+            bucket_name, bucket_region, region, region
+        ));
+    }
+    Ok(())
+}
+
+// A client that uploads through the bucket's Transfer Acceleration endpoint
+pub async fn accelerated_s3_client(bucket_name: &str, client: &aws_sdk_s3::Client) -> Result<aws_sdk_s3::Client> {
+    let accelerate = client
+        .get_bucket_accelerate_configuration()
+        .bucket(bucket_name)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't check transfer acceleration on bucket {}", bucket_name)))?;
+    if accelerate.status() != Some(&BucketAccelerateStatus::Enabled) {
+        return Err(anyhow!(
+            "Transfer acceleration isn't enabled on bucket {}. Enable it with: aws s3api put-bucket-accelerate-configuration --bucket {} --accelerate-configuration Status=Enabled",
+            bucket_name, bucket_name
+        ));
+    }
+    info!("Uploading with S3 Transfer Acceleration");
+    let config = client.config().to_builder().accelerate(true).build();
+    Ok(aws_sdk_s3::Client::from_conf(config))
+}
+
+pub struct BucketOptions {
+    pub versioning: bool,
+    pub expire_after_days: Option<i32>,
+    pub abort_multipart_after_days: i32,
+    // Encrypt artefacts with this KMS key by default, instead of S3's own keys
+    pub kms_key_arn: Option<String>,
+}
+
+// Keeps storage costs down and the bucket locked down. Applied to existing buckets too, so re-running setup
+// updates the rules
+pub async fn configure_sagemaker_bucket(
+    bucket_name: &str,
+    region: &str,
+    options: &BucketOptions,
+    client: &aws_sdk_s3::Client,
+) -> Result<()> {
+    if options.abort_multipart_after_days < 1 || options.expire_after_days.is_some_and(|d| d < 1) {
+        return Err(anyhow!("Lifecycle rules need to be at least 1 day"));
+    }
+    harden_bucket(bucket_name, region, options.kms_key_arn.as_deref(), client).await?;
+    if options.versioning {
+        info!("Enabling versioning");
+        client
+            .put_bucket_versioning()
+            .bucket(bucket_name)
+            .versioning_configuration(
+                VersioningConfiguration::builder()
+                    .status(BucketVersioningStatus::Enabled)
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't enable versioning on bucket {}", bucket_name)))?;
+    }
+
+    info!(
+        "Applying lifecycle rules: abort incomplete multipart uploads after {} days{}",
+        options.abort_multipart_after_days,
+        options
+            .expire_after_days
+            .map(|d| format!(", expire artefacts after {} days", d))
+            .unwrap_or_default()
+    );
+    let mut rules = vec![LifecycleRule::builder()
+        .id("sageturner-abort-incomplete-multipart")
+        .status(ExpirationStatus::Enabled)
+        .filter(LifecycleRuleFilter::builder().prefix("").build())
+        .abort_incomplete_multipart_upload(
+            AbortIncompleteMultipartUpload::builder()
+                .days_after_initiation(options.abort_multipart_after_days)
+                .build(),
+        )
+        .build()?];
+    if let Some(days) = options.expire_after_days {
+        rules.push(
+            LifecycleRule::builder()
+                .id("sageturner-expire-artefacts")
+                .status(ExpirationStatus::Enabled)
+                .filter(LifecycleRuleFilter::builder().prefix("").build())
+                .expiration(LifecycleExpiration::builder().days(days).build())
+                .noncurrent_version_expiration(
+                    NoncurrentVersionExpiration::builder()
+                        .noncurrent_days(days)
+                        .build(),
+                )
+                .build()?,
+        );
+    }
+    client
+        .put_bucket_lifecycle_configuration()
+        .bucket(bucket_name)
+        .lifecycle_configuration(
+            BucketLifecycleConfiguration::builder()
+                .set_rules(Some(rules))
+                .build()?,
+        )
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't put lifecycle rules on bucket {}", bucket_name)))?;
+    Ok(())
+}
+
+// What security scanners look for on a bucket: no public access, encryption at rest and TLS only
+async fn harden_bucket(bucket_name: &str, region: &str, kms_key_arn: Option<&str>, client: &aws_sdk_s3::Client) -> Result<()> {
+    info!("Blocking public access");
+    client
+        .put_public_access_block()
+        .bucket(bucket_name)
+        .public_access_block_configuration(
+            PublicAccessBlockConfiguration::builder()
+                .block_public_acls(true)
+                .ignore_public_acls(true)
+                .block_public_policy(true)
+                .restrict_public_buckets(true)
+                .build(),
+        )
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't block public access to bucket {}", bucket_name)))?;
+
+    let encryption = match kms_key_arn {
+        Some(key) => {
+            info!("Encrypting with KMS key {}", key);
+            // Bucket keys cut the KMS requests (and their cost) for every object read and written
+            ServerSideEncryptionRule::builder()
+                .apply_server_side_encryption_by_default(
+                    ServerSideEncryptionByDefault::builder()
+                        .sse_algorithm(ServerSideEncryption::AwsKms)
+                        .kms_master_key_id(key)
+                        .build()?,
+                )
+                .bucket_key_enabled(true)
+                .build()
+        }
+        None => {
+            info!("Encrypting with S3 managed keys");
+            ServerSideEncryptionRule::builder()
+                .apply_server_side_encryption_by_default(
+                    ServerSideEncryptionByDefault::builder().sse_algorithm(ServerSideEncryption::Aes256).build()?,
+                )
+                .build()
+        }
+    };
+    client
+        .put_bucket_encryption()
+        .bucket(bucket_name)
+        .server_side_encryption_configuration(ServerSideEncryptionConfiguration::builder().rules(encryption).build()?)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't turn on encryption for bucket {}", bucket_name)))?;
+
+    info!("Denying requests without TLS");
+    // The statement is added to the bucket's policy (or replaces an earlier one from setup), keeping whatever else
+    // it grants
+    let mut policy = match client.get_bucket_policy().bucket(bucket_name).send().await {
+        Ok(output) => serde_json::from_str(output.policy().unwrap_or("{}"))
+            .map_err(|e| anyhow!("Couldn't read the policy on bucket {}: {}", bucket_name, e))?,
+        Err(e) if e.as_service_error().and_then(|e| e.code()) == Some("NoSuchBucketPolicy") => serde_json::json!({}),
+        Err(e) => return Err(errors::aws(e, format!("Couldn't get the policy on bucket {}", bucket_name))),
+    };
+    let bucket_arn = format!("arn:{}:s3:::{}", partition(region), bucket_name);
+    let deny_insecure = serde_json::json!({
+        "Sid": "DenyInsecureTransport",
+        "Effect": "Deny",
+        "Principal": "*",
+        "Action": "s3:*",
+        "Resource": [bucket_arn.clone(), format!("{}/*", bucket_arn)],
+        "Condition": { "Bool": { "aws:SecureTransport": "false" } }
+    });
+    let mut statements: Vec<serde_json::Value> = match policy.get("Statement") {
+        Some(serde_json::Value::Array(statements)) => statements.clone(),
+        Some(statement) => vec![statement.clone()],
+        None => Vec::new(),
+    };
+    statements.retain(|s| s.get("Sid").and_then(|sid| sid.as_str()) != Some("DenyInsecureTransport"));
+    statements.push(deny_insecure);
+    policy["Version"] = serde_json::json!("2012-10-17");
+    policy["Statement"] = serde_json::json!(statements);
+    client
+        .put_bucket_policy()
+        .bucket(bucket_name)
+        .policy(policy.to_string())
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't put the policy on bucket {}", bucket_name)))?;
+    Ok(())
+}
+
+// ECR's Docker logins last 12 hours, so one is kept in your cache dir for each registry (which is per account and
+// region) and reused while it has at least this long left, rather than every push asking for another
+const ECR_LOGIN_MIN_LIFE: chrono::TimeDelta = chrono::TimeDelta::hours(1);
+
+#[derive(Serialize, Deserialize)]
+struct EcrLogin {
+    username: String,
+    password: String,
+    endpoint: String,
+    // RFC 3339
+    expires_at: String,
+}
+
+impl EcrLogin {
+    fn credentials(self) -> DockerCredentials {
+        DockerCredentials {
+            username: Some(self.username),
+            password: Some(self.password),
+            serveraddress: Some(self.endpoint),
+            ..Default::default()
+        }
+    }
+}
+
+// A Docker login for the registry, <account>.dkr.ecr.<region>.amazonaws.com, from the cache if there's one that
+// hasn't nearly expired
+pub async fn get_docker_credentials_for_ecr(ecr_client: &aws_sdk_ecr::Client, registry: &str) -> Result<DockerCredentials> {
+    let cache_path = ecr_login_cache_path(registry);
+    if let Some(login) = cache_path.as_deref().and_then(read_ecr_login) {
+        debug!("Using the cached Docker login for {}, which expires at {}", registry, login.expires_at);
+        return Ok(login.credentials());
+    }
+    info!("Getting Docker Credentials");
+    // assume default registry
+    let ecr_auth = ecr_client
+        .get_authorization_token()
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, "Couldn't get a Docker login for ECR"))?;
+    let auth = ecr_auth.authorization_data().first().ok_or_else(|| anyhow!("ECR didn't return a Docker login"))?;
+
+    let token = auth.authorization_token().ok_or_else(|| anyhow!("Couldn't read auth token"))?;
+    let decoded_token = String::from_utf8(BASE64_STANDARD.decode(token)?)?;
+    // user:password
+    let (username, password) = decoded_token.split_once(':').ok_or_else(|| anyhow!("Couldn't read auth token"))?;
+
+    let endpoint = auth.proxy_endpoint().ok_or_else(|| anyhow!("Couldn't read proxy endpoint"))?;
+    let login = EcrLogin {
+        username: username.to_string(),
+        password: password.to_string(),
+        endpoint: endpoint.to_string(),
+        expires_at: auth
+            .expires_at()
+            .and_then(|t| DateTime::from_timestamp(t.secs(), 0))
+            .unwrap_or_else(|| Utc::now() + ECR_LOGIN_MIN_LIFE)
+            .to_rfc3339(),
+    };
+    // Only ever saves a call, so a cache that can't be written isn't worth failing over
+    if let Some(path) = &cache_path {
+        if let Err(e) = write_ecr_login(path, &login) {
+            debug!("Couldn't cache the Docker login for {} at {}: {}", registry, path.display(), e);
+        }
+    }
+    Ok(login.credentials())
+}
+
+// Drop the registry's cached login, after a push that failed, in case it was the login's fault
+pub fn forget_ecr_login(registry: &str) {
+    if let Some(path) = ecr_login_cache_path(registry) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+// ~/.cache/sageturner/ecr/<registry>.json, or under $XDG_CACHE_HOME
+fn ecr_login_cache_path(registry: &str) -> Option<PathBuf> {
+    let cache_home = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+    // Registries behind a custom endpoint_url can have a port
+    Some(cache_home.join("sageturner").join("ecr").join(format!("{}.json", registry.replace([':', '/'], "_"))))
+}
+
+fn read_ecr_login(path: &Path) -> Option<EcrLogin> {
+    let login: EcrLogin = serde_json::from_slice(&std::fs::read(path).ok()?).ok()?;
+    let expires_at = DateTime::parse_from_rfc3339(&login.expires_at).ok()?;
+    (expires_at.with_timezone(&Utc) - Utc::now() > ECR_LOGIN_MIN_LIFE).then_some(login)
+}
+
+// Written to a temp file that only you can read, then moved into place, so deploys running at once never see half
+// of one
+fn write_ecr_login(path: &Path, login: &EcrLogin) -> Result<()> {
+    let dir = path.parent().ok_or_else(|| anyhow!("{} has no parent directory", path.display()))?;
+    std::fs::create_dir_all(dir)?;
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    serde_json::to_writer(&mut file, login)?;
+    file.persist(path)?;
+    Ok(())
+}
+
+// Where SageMaker gets the model from: a .tar.gz it extracts, or an S3 prefix whose files it
+// copies as they are (no packing, and much faster loading for big LLM weights)
+#[derive(Debug)]
+pub enum ModelData {
+    Compressed(String),
+    Uncompressed(String),
+}
+
+impl ModelData {
+    pub fn s3_uri(&self) -> &str {
+        match self {
+            ModelData::Compressed(uri) | ModelData::Uncompressed(uri) => uri,
+        }
+    }
+}
+
+// Everything sageturner creates in SageMaker is tagged with the model's name and version,
+// which is how list finds them again
+pub const MODEL_TAG: &str = "sageturner:model";
+pub const VERSION_TAG: &str = "sageturner:version";
+pub const ENVIRONMENT_TAG: &str = "sageturner:environment";
+
+// Sageturner's own tags plus the tags from the config
+pub fn sageturner_tags(
+    name: &str,
+    version: Option<&str>,
+    environment: Option<&str>,
+    provenance: &Provenance,
+    extra: &HashMap<String, String>,
+) -> Vec<Tag> {
+    let mut tags = vec![Tag::builder().key(MODEL_TAG).value(name).build()];
+    if let Some(v) = version {
+        tags.push(Tag::builder().key(VERSION_TAG).value(v).build());
+    }
+    if let Some(e) = environment {
+        tags.push(Tag::builder().key(ENVIRONMENT_TAG).value(e).build());
+    }
+    tags.extend(provenance.tags().into_iter().map(|(k, v)| Tag::builder().key(k).value(v).build()));
+    tags.extend(extra.iter().map(|(k, v)| Tag::builder().key(k).value(v).build()));
+    tags
+}
+
+// When an endpoint with a ttl should be deleted, as an RFC 3339 UTC time
+pub const EXPIRES_AT_TAG: &str = "sageturner:expires-at";
+
+// Tag the endpoint with when it expires, or untag it if it no longer has a ttl. Every deploy sets it again, so
+// redeploying an endpoint pushes its expiry back
+pub async fn set_endpoint_expiry(
+    endpoint_name: &str,
+    expires_at: Option<DateTime<Utc>>,
+    sage_client: &aws_sdk_sagemaker::Client,
+) -> Result<()> {
+    let endpoint = sage_client.describe_endpoint().endpoint_name(endpoint_name).send().await?;
+    let arn = endpoint.endpoint_arn().unwrap_or_default();
+    match expires_at {
+        Some(at) => {
+            let at = at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+            info!("Endpoint {} expires at {}, sageturner gc --expired deletes it after that", endpoint_name, at);
+            sage_client
+                .add_tags()
+                .resource_arn(arn)
+                .tags(Tag::builder().key(EXPIRES_AT_TAG).value(at).build())
+                .send()
+                .await?;
+        }
+        None => {
+            sage_client.delete_tags().resource_arn(arn).tag_keys(EXPIRES_AT_TAG).send().await?;
+        }
+    }
+    Ok(())
+}
+
+// Endpoints whose ttl has run out, and when they expired
+pub async fn list_expired_endpoints(
+    now: DateTime<Utc>,
+    sage_client: &aws_sdk_sagemaker::Client,
+) -> Result<Vec<(String, DateTime<Utc>)>> {
+    let mut pages = sage_client.list_endpoints().into_paginator().send();
+    let mut expired = Vec::new();
+    while let Some(page) = pages.next().await {
+        for summary in page?.endpoints() {
+            let (Some(name), Some(arn)) = (summary.endpoint_name(), summary.endpoint_arn()) else {
+                continue;
+            };
+            let tags = sage_client.list_tags().resource_arn(arn).send().await?;
+            let expires_at = tags
+                .tags()
+                .iter()
+                .find(|t| t.key() == Some(EXPIRES_AT_TAG))
+                .and_then(|t| t.value())
+                .and_then(|v| DateTime::parse_from_rfc3339(v).ok())
+                .map(|at| at.with_timezone(&Utc));
+            if let Some(at) = expires_at.filter(|at| *at <= now) {
+                expired.push((name.to_string(), at));
+            }
+        }
+    }
+    Ok(expired)
+}
+
+pub async fn delete_endpoint(endpoint_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<()> {
+    monitor::delete_monitoring_schedules(endpoint_name, sage_client)
+        .await
+        .map_err(|e| anyhow!("Couldn't delete endpoint {}'s monitoring schedules: {}", endpoint_name, e))?;
+    sage_client
+        .delete_endpoint()
+        .endpoint_name(endpoint_name)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't delete endpoint {}", endpoint_name)))?;
+    Ok(())
+}
+
+// Who the credentials belong to: the account, and the ARN, e.g. arn:aws:sts::123456789012:assumed-role/ci-deployer/session
+pub struct Identity {
+    pub account: String,
+    pub arn: String,
+}
+
+pub async fn identity(sts_client: &aws_sdk_sts::Client) -> Result<Identity> {
+    let identity = sts_client
+        .get_caller_identity()
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, "Couldn't look up your AWS identity"))?;
+    match (identity.account(), identity.arn()) {
+        (Some(account), Some(arn)) => Ok(Identity { account: account.to_string(), arn: arn.to_string() }),
+        _ => Err(anyhow!("STS didn't return an account and ARN for your AWS identity")),
+    }
+}
+
+// The ARN of whoever the credentials belong to
+pub async fn caller_identity(sts_client: &aws_sdk_sts::Client) -> Result<String> {
+    Ok(identity(sts_client).await?.arn)
+}
+
+// SageMaker names only allow letters, numbers and -, so 1.4.0-rc.1 becomes v1-4-0-rc-1
+pub fn version_slug(version: &str) -> String {
+    let slug: String = version
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    format!("v{}", slug)
+}
+
+// The endpoint a model's deploys to the environment go to
+pub fn endpoint_name(model_name: &str, environment: Option<&str>) -> String {
+    match environment {
+        Some(env) => format!("{}-{}", model_name, env),
+        None => model_name.to_string(),
+    }
+}
+
+// SageMaker reports missing models, endpoint configs and endpoints as a ValidationException saying "Could not find ..."
+fn is_not_found<E: ProvideErrorMetadata>(err: &SdkError<E>) -> bool {
+    err.as_service_error()
+        .and_then(|e| e.message())
+        .is_some_and(|m| m.contains("Could not find"))
+}
+
+// Whether this deploy's SageMaker model already exists. Reusing it is safe (CI re-running a deploy, say) when it was
+// deployed from the same clean commit and config as this deploy. Anything else is a clash: for versioned models,
+// the same version deployed again with changes. A resumed deploy carries on from one that was checked to have the
+// same commit and config, so its model is reused even if there were uncommitted changes
+pub async fn check_existing_model(
+    model_name: &str,
+    version: Option<&str>,
+    provenance: &Provenance,
+    resuming: bool,
+    sage_client: &aws_sdk_sagemaker::Client,
+) -> Result<bool> {
+    let model = match sage_client.describe_model().model_name(model_name).send().await {
+        Ok(m) => m,
+        Err(e) if is_not_found(&e) => return Ok(false),
+        Err(e) => return Err(errors::aws(e, format!("Couldn't check for model {}", model_name))),
+    };
+    let arn = model.model_arn().ok_or_else(|| anyhow!("SageMaker didn't return an ARN for model {}", model_name))?;
+    let tags = sage_client.list_tags().resource_arn(arn).send().await?;
+    let tag = |key: &str| tags.tags().iter().find(|t| t.key() == Some(key)).and_then(|t| t.value());
+
+    let same_config = tag(provenance::GIT_COMMIT_TAG) == provenance.git_commit.as_deref()
+        && tag(provenance::CONFIG_HASH_TAG) == Some(provenance.config_hash.as_str());
+    let same_source = match resuming {
+        true => same_config,
+        false => {
+            same_config
+                && provenance.git_dirty == Some(false)
+                && provenance.git_commit.is_some()
+                && tag(provenance::GIT_DIRTY_TAG) == Some("false")
+        }
+    };
+    if same_source {
+        info!("SageMaker model {} was already deployed from this commit and config, reusing it", model_name);
+        return Ok(true);
+    }
+    match version {
+        Some(v) => Err(anyhow!(
+            "Version {} is already deployed as SageMaker model {}, from a different commit or config. Bump the version in your config",
+            v,
+            model_name
+        )),
+        None => Err(anyhow!(
+            "SageMaker model {} already exists, from a different commit or config. Deploy again in a minute to get a new name, or give your model a version",
+            model_name
+        )),
+    }
+}
+
+pub async fn endpoint_config_exists(endpoint_config_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<bool> {
+    match sage_client.describe_endpoint_config().endpoint_config_name(endpoint_config_name).send().await {
+        Ok(_) => Ok(true),
+        Err(e) if is_not_found(&e) => Ok(false),
+        Err(e) => Err(anyhow!(
+            "Couldn't check for endpoint config {}: {}",
+            endpoint_config_name,
+            aws_sdk_sagemaker::error::DisplayErrorContext(&e)
+        )),
+    }
+}
+
+#[instrument(name = "create_model", skip_all)]
+pub async fn create_sagemaker_model(
+    model_name: &str,
+    execution_role_arn: &str,
+    container_image: &str,
+    sage_client: &aws_sdk_sagemaker::Client,
+    model_data: Option<ModelData>,
+    environment: &HashMap<String, String>,
+    tags: &[Tag],
+) -> Result<()> {
+    let container = ContainerDefinition::builder()
+        .image(container_image)
+        .set_environment((!environment.is_empty()).then(|| environment.clone()));
+    let container = match model_data {
+        Some(ModelData::Compressed(url)) => container.model_data_url(url),
+        Some(ModelData::Uncompressed(prefix)) => {
+            let source = S3ModelDataSource::builder()
+                .s3_uri(prefix)
+                .s3_data_type(S3ModelDataType::S3Prefix)
+                .compression_type(ModelCompressionType::None)
+                .build();
+            container.model_data_source(ModelDataSource::builder().s3_data_source(source).build())
+        }
+        None => container,
+    }
+    .build();
+
+    let request = sage_client
+        .create_model()
+        .set_model_name(Some(model_name.to_string()))
+        .set_execution_role_arn(Some(execution_role_arn.to_string()))
+        .set_primary_container(Some(container))
+        .set_tags(Some(tags.to_vec()));
+    retry::retry(format!("Creating model {}", model_name), retry::ATTEMPTS, || async {
+        request.clone().send().await.map_err(|e| errors::aws(e, format!("Couldn't create model {}", model_name)))
+    })
+    .await?;
+    Ok(())
+}
+
+// Look up a !secret container environment value: an SSM parameter if it starts with / (or is an SSM parameter ARN),
+// otherwise a Secrets Manager secret name or ARN
+pub async fn resolve_secret(
+    name: &str,
+    ssm_client: &aws_sdk_ssm::Client,
+    secrets_client: &aws_sdk_secretsmanager::Client,
+) -> Result<String> {
+    if name.starts_with('/') || (name.starts_with("arn:") && name.contains(":ssm:")) {
+        let parameter = ssm_client
+            .get_parameter()
+            .name(name)
+            .with_decryption(true)
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't read SSM parameter {}", name)))?;
+        parameter
+            .parameter()
+            .and_then(|p| p.value())
+            .map(|v| v.to_string())
+            .ok_or_else(|| anyhow!("SSM parameter {} has no value", name))
+    } else {
+        let secret = secrets_client
+            .get_secret_value()
+            .secret_id(name)
+            .send()
+            .await
+            .map_err(|e| {
+                anyhow!("Couldn't read secret {} from Secrets Manager: {}", name, aws_sdk_secretsmanager::error::DisplayErrorContext(&e))
+            })?;
+        secret
+            .secret_string()
+            .map(|v| v.to_string())
+            .ok_or_else(|| anyhow!("Secret {} has no string value, binary secrets aren't supported", name))
+    }
+}
+
+// A model sageturner deployed, as shown by list
+#[derive(Debug, Serialize)]
+pub struct DeployedModel {
+    pub name: String,
+    pub version: Option<String>,
+    pub sagemaker_model: String,
+    pub created: Option<String>,
+    // The commit it was deployed from, with -dirty if there were uncommitted changes
+    pub commit: Option<String>,
+    pub deployed_by: Option<String>,
+}
+
+// Every model sageturner has deployed (just the versions of one model, if name is given), newest first
+pub async fn list_deployed_models(
+    name: Option<&str>,
+    sage_client: &aws_sdk_sagemaker::Client,
+) -> Result<Vec<DeployedModel>> {
+    let mut pages = sage_client
+        .list_models()
+        .set_name_contains(name.map(|n| n.to_string()))
+        .sort_by(aws_sdk_sagemaker::types::ModelSortKey::CreationTime)
+        .sort_order(aws_sdk_sagemaker::types::OrderKey::Descending)
+        .into_paginator()
+        .send();
+
+    let mut models = Vec::new();
+    while let Some(page) = pages.next().await {
+        for summary in page?.models() {
+            let (Some(sagemaker_model), Some(arn)) = (summary.model_name(), summary.model_arn()) else {
+                continue;
+            };
+            let tags = sage_client.list_tags().resource_arn(arn).send().await?;
+            let tag = |key: &str| {
+                tags.tags()
+                    .iter()
+                    .find(|t| t.key() == Some(key))
+                    .and_then(|t| t.value())
+                    .map(|v| v.to_string())
+            };
+            let Some(model_name) = tag(MODEL_TAG) else { continue };
+            if name.is_some_and(|n| n != model_name) {
+                continue;
+            }
+            models.push(DeployedModel {
+                name: model_name,
+                version: tag(VERSION_TAG),
+                sagemaker_model: sagemaker_model.to_string(),
+                created: summary.creation_time().map(|t| t.to_string()),
+                commit: tag(provenance::GIT_COMMIT_TAG).map(|c| match tag(provenance::GIT_DIRTY_TAG).as_deref() {
+                    Some("true") => format!("{}-dirty", &c[..c.len().min(7)]),
+                    _ => c[..c.len().min(7)].to_string(),
+                }),
+                deployed_by: tag(provenance::DEPLOYED_BY_TAG),
+            });
+        }
+    }
+    Ok(models)
+}
+
+// AWS hosts the Hugging Face Deep Learning Containers in one account for most regions,
+// a handful of opt-in regions have their own
+fn dlc_account(region: &str) -> &'static str {
+    match region {
+        "af-south-1" => "626614931356",
+        "ap-east-1" => "871362719292",
+        "ap-southeast-3" => "907027046896",
+        "eu-south-1" => "692866216735",
+        "me-south-1" => "217643126080",
+        "il-central-1" => "780543022126",
+        "us-gov-east-1" => "151195486783",
+        "us-gov-west-1" => "442386744353",
+        "cn-north-1" | "cn-northwest-1" => "727897471807",
+        _ => "763104351884",
+    }
+}
+
+pub fn tgi_image_uri(region: &str) -> String {
+    format!(
+        "{}.dkr.ecr.{}.{}/huggingface-pytorch-tgi-inference:2.4.0-tgi3.0.1-gpu-py311-cu124-ubuntu22.04",
+        dlc_account(region),
+        region,
+        dns_suffix(region)
+    )
+}
+
+// The SageMaker Triton images live in a different account per region
+pub fn triton_image_uri(region: &str) -> Result<String> {
+    let account = match region {
+        "us-east-1" => "785573368785",
+        "us-east-2" => "007439368137",
+        "us-west-1" => "710691900526",
+        "us-west-2" => "301217895009",
+        "eu-west-1" => "802834080501",
+        "eu-west-2" => "681254210234",
+        "eu-central-1" => "746233611703",
+        "eu-north-1" => "104374241257",
+        "ap-northeast-1" => "500459654110",
+        "ap-northeast-2" => "670800003520",
+        "ap-southeast-1" => "090888218095",
+        "ap-southeast-2" => "666831318237",
+        "ap-south-1" => "453000072557",
+        "ca-central-1" => "464438896020",
+        "sa-east-1" => "756306329178",
+        _ => {
+            return Err(anyhow!(
+                "Sageturner doesn't know the SageMaker Triton image for {}. Set container.triton.image to the image URI for your region",
+                region
+            ))
+        }
+    };
+    Ok(format!(
+        "{}.dkr.ecr.{}.amazonaws.com/sagemaker-tritonserver:24.09-py3",
+        account, region
+    ))
+}
+
+// The partition a region is in, which goes in ARNs: arn:<partition>:...
+pub fn partition(region: &str) -> &'static str {
+    if region.starts_with("cn-") {
+        "aws-cn"
+    } else if region.starts_with("us-gov-") {
+        "aws-us-gov"
+    } else {
+        "aws"
+    }
+}
+
+// The domain AWS's endpoints and ECR registries are under in a region's partition
+pub fn dns_suffix(region: &str) -> &'static str {
+    match partition(region) {
+        "aws-cn" => "amazonaws.com.cn",
+        _ => "amazonaws.com",
+    }
+}
+
+// Each instance type has its own quota of instances across all of an account's endpoints, which is 0 for many GPU types.
+// CreateEndpoint only fails on it once the endpoint is being created, long after the image has been built and pushed,
+// so check it first. Instances being replaced count too: an update brings up the new instances before the old ones go
+pub async fn check_endpoint_quota(
+    instance_type: &str,
+    instance_count: i32,
+    region: &str,
+    sage_client: &aws_sdk_sagemaker::Client,
+    quotas_client: &aws_sdk_servicequotas::Client,
+) -> Result<()> {
+    let quota_name = format!("{} for endpoint usage", instance_type);
+    // Not being able to read quotas (no servicequotas permissions, say) isn't a reason to stop a deploy
+    let quota = match find_sagemaker_quota(&quota_name, quotas_client).await {
+        Ok(Some(q)) => q,
+        Ok(None) => {
+            warn!("Couldn't find the SageMaker quota \"{}\", skipping the quota check", quota_name);
+            return Ok(());
+        }
+        Err(e) => {
+            warn!("Couldn't read your SageMaker quotas, skipping the quota check: {}", e);
+            return Ok(());
+        }
+    };
+    let limit = quota.value().unwrap_or_default() as i32;
+    let in_use = match endpoint_instances_in_use(instance_type, sage_client).await {
+        Ok(n) => n,
+        Err(e) => {
+            warn!("Couldn't count the {} instances your endpoints use, skipping the quota check: {}", instance_type, e);
+            return Ok(());
+        }
+    };
+    info!("Quota check: {} of {} {} instances in use, this deploy needs {}", in_use, limit, instance_type, instance_count);
+    if in_use + instance_count > limit {
+        return Err(anyhow!(
+            "Your account's quota for {} is {} instances, {} are in use by endpoints and this deploy needs {} more. \
+            Request an increase at https://{}.console.aws.amazon.com/servicequotas/home/services/sagemaker/quotas/{}",
+            quota_name,
+            limit,
+            in_use,
+            instance_count,
+            region,
+            quota.quota_code().unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
 
-    println!("role: {}", role_name);
-    client
-        .create_role()
-        .role_name(role_name)
-        .assume_role_policy_document(trust_policy)
-        .send()
-        .await?;
+async fn find_sagemaker_quota(
+    quota_name: &str,
+    quotas_client: &aws_sdk_servicequotas::Client,
+) -> Result<Option<aws_sdk_servicequotas::types::ServiceQuota>> {
+    let mut pages = quotas_client.list_service_quotas().service_code("sagemaker").into_paginator().send();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(|e| errors::aws(e, "Couldn't list SageMaker's quotas"))?;
+        if let Some(quota) = page.quotas().iter().find(|q| q.quota_name() == Some(quota_name)) {
+            return Ok(Some(quota.clone()));
+        }
+    }
+    Ok(None)
+}
 
-    println!("Waiting for role to be created...");
-    client.wait_until_role_exists().role_name(role_name).wait(Duration::from_secs(10)).await?;
+// The instances of instance_type running (or starting) behind the account's endpoints in this region
+async fn endpoint_instances_in_use(instance_type: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<i32> {
+    let mut pages = sage_client.list_endpoints().into_paginator().send();
+    let mut in_use = 0;
+    while let Some(page) = pages.next().await {
+        for summary in page?.endpoints() {
+            let Some(endpoint_name) = summary.endpoint_name() else { continue };
+            let endpoint = sage_client.describe_endpoint().endpoint_name(endpoint_name).send().await?;
+            for variant in endpoint.production_variants() {
+                let count = variant.current_instance_count().or(variant.desired_instance_count()).unwrap_or_default();
+                if count == 0 {
+                    continue;
+                }
+                // The instance type is in the endpoint config, not the endpoint
+                let config = sage_client
+                    .describe_endpoint_config()
+                    .endpoint_config_name(endpoint.endpoint_config_name().unwrap_or_default())
+                    .send()
+                    .await?;
+                let same_type = config
+                    .production_variants()
+                    .iter()
+                    .any(|v| v.variant_name() == variant.variant_name() && v.instance_type().map(|t| t.as_str()) == Some(instance_type));
+                if same_type {
+                    in_use += count;
+                }
+            }
+        }
+    }
+    Ok(in_use)
+}
 
-    println!("Attaching policy");
-    client
-        .attach_role_policy()
-        .role_name(role_name)
-        .policy_arn("arn:aws:iam::aws:policy/AmazonSageMakerFullAccess")
+// The model the endpoint's (first) production variant serves
+pub async fn get_endpoint_model_name(endpoint_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<String> {
+    let endpoint = sage_client
+        .describe_endpoint()
+        .endpoint_name(endpoint_name)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't find endpoint {}", endpoint_name)))?;
+    let config_name = endpoint
+        .endpoint_config_name()
+        .ok_or_else(|| anyhow!("Endpoint {} has no endpoint config", endpoint_name))?;
+    let config = sage_client
+        .describe_endpoint_config()
+        .endpoint_config_name(config_name)
         .send()
         .await?;
-
-    println!("Role created");
-    Ok(())
+    config
+        .production_variants()
+        .first()
+        .and_then(|v| v.model_name())
+        .map(|m| m.to_string())
+        .ok_or_else(|| anyhow!("Endpoint config {} has no model", config_name))
 }
 
-pub async fn create_sagemaker_bucket(bucket_name: &str, client: &aws_sdk_s3::Client) -> Result<()> {
-    println!("bucket: {}", bucket_name);
-    println!("Checking if bucket already exists");
-    let already_exists = client.head_bucket().bucket(bucket_name).send().await.is_ok();
-
-    if !already_exists {
-        println!("Creating bucket");
-        let constraint = aws_sdk_s3::types::BucketLocationConstraint::from("eu-west-2".to_string().as_str());
-        let cfg = aws_sdk_s3::types::CreateBucketConfiguration::builder()
-            .location_constraint(constraint)
-            .build();
-        client
-            .create_bucket()
-            .bucket(bucket_name)
-            .create_bucket_configuration(cfg)
-            .send()
-            .await?;
+pub async fn get_model_data(model_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<ModelData> {
+    let model = sage_client
+        .describe_model()
+        .model_name(model_name)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't find model {}", model_name)))?;
+    let container = model
+        .primary_container()
+        .ok_or_else(|| anyhow!("Model {} has no primary container", model_name))?;
+    if let Some(url) = container.model_data_url() {
+        return Ok(ModelData::Compressed(url.to_string()));
+    }
+    match container.model_data_source().and_then(|s| s.s3_data_source()) {
+        Some(s) => {
+            let uri = s.s3_uri().ok_or_else(|| anyhow!("Model {} has no model data URI", model_name))?;
+            if s.compression_type() == Some(&ModelCompressionType::Gzip) {
+                Ok(ModelData::Compressed(uri.to_string()))
+            } else {
+                Ok(ModelData::Uncompressed(uri.to_string()))
+            }
+        }
+        None => Err(anyhow!("Model {} has no artefact: it was deployed without one, or from the Hugging Face Hub", model_name)),
     }
-    Ok(())
-}
-
-pub async fn get_docker_credentials_for_ecr(
-    ecr_client: &aws_sdk_ecr::Client,
-) -> Result<DockerCredentials> {
-    println!("Getting Docker Credentials");
-    // assume default registry
-    let ecr_auth = ecr_client.get_authorization_token().send().await?;
-
-    let token = ecr_auth.authorization_data()[0]
-        .authorization_token()
-        .ok_or_else(|| anyhow!("Couldn't read auth token"))?;
-    let decoded_token = BASE64_STANDARD.decode(token)?;
-
-    let parts: Vec<_> = decoded_token.split(|c| *c == b':').collect(); // split at : for user:password
-    let username = String::from_utf8(parts[0].to_vec()).unwrap();
-    let password = String::from_utf8(parts[1].to_vec()).unwrap();
-
-    let endpoint = ecr_auth.authorization_data()[0]
-        .proxy_endpoint()
-        .ok_or_else(|| anyhow!("Couldn't read proxy endpoint"))?;
-
-    Ok(DockerCredentials {
-        username: Some(username),
-        password: Some(password),
-        serveraddress: Some(endpoint.to_string()),
-        ..Default::default()
-    })
 }
 
-pub async fn create_sagemaker_model(
-    model_name: &str,
-    execution_role_arn: &str,
-    container_image: &str,
-    sage_client: &aws_sdk_sagemaker::Client,
-    model_data_url: Option<String>,
-    deploy_timestamp: &str
-) -> Result<String> {
-    let container = match model_data_url {
-        Some(u) => {
-            ContainerDefinition::builder()
-                .image(container_image)
-                .model_data_url(u)
-                .build()
+// Download a model's data to output_dir: the tar.gz (extracted if asked), or every file under the prefix
+pub async fn download_model_data(
+    model_data: &ModelData,
+    output_dir: &Path,
+    extract: bool,
+    s3_client: &aws_sdk_s3::Client,
+) -> Result<()> {
+    tokio::fs::create_dir_all(output_dir).await?;
+    match model_data {
+        ModelData::Compressed(uri) => {
+            let (bucket, key) = artefact::s3_location(uri).ok_or_else(|| anyhow!("Invalid S3 URI: {}", uri))?;
+            let file_name = key.rsplit('/').next().unwrap_or("model.tar.gz");
+            let dest = output_dir.join(file_name);
+            info!("Downloading {} to {}", uri, dest.display());
+            download_object(bucket, key, &dest, s3_client).await?;
+            if extract {
+                info!("Extracting {}", dest.display());
+                artefact::extract_tar_gz(&dest, output_dir)?;
+                tokio::fs::remove_file(&dest).await?;
+            }
         }
-        None => {
-            ContainerDefinition::builder()
-                .image(container_image)
-                .build()
+        ModelData::Uncompressed(uri) => {
+            let (bucket, prefix) = artefact::s3_location(uri).ok_or_else(|| anyhow!("Invalid S3 URI: {}", uri))?;
+            info!("Downloading files under {} to {}", uri, output_dir.display());
+            let mut pages = s3_client
+                .list_objects_v2()
+                .bucket(bucket)
+                .prefix(prefix)
+                .into_paginator()
+                .send();
+            while let Some(page) = pages.next().await {
+                for object in page?.contents() {
+                    let Some(key) = object.key() else { continue };
+                    let relative = key.strip_prefix(prefix).unwrap_or(key).trim_start_matches('/');
+                    if relative.is_empty() || relative.ends_with('/') {
+                        continue;
+                    }
+                    let dest = output_dir.join(relative);
+                    if let Some(parent) = dest.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    info!("Downloading {}", relative);
+                    download_object(bucket, key, &dest, s3_client).await?;
+                }
+            }
         }
-    };
+    }
+    info!("Artefact downloaded to {}", output_dir.display());
+    Ok(())
+}
 
-    let model_name_plus_timestamp = model_name.to_string() + deploy_timestamp;
-    sage_client
-        .create_model()
-        .set_model_name(Some(model_name_plus_timestamp.clone()))
-        .set_execution_role_arn(Some(execution_role_arn.to_string()))
-        .set_primary_container(Some(container))
-        .send()
-        .await?;
-    Ok(model_name_plus_timestamp)
+async fn download_object(bucket: &str, key: &str, dest: &Path, s3_client: &aws_sdk_s3::Client) -> Result<()> {
+    let object = s3_client.get_object().bucket(bucket).key(key).send().await?;
+    let mut body = object.body.into_async_read();
+    let mut file = tokio::fs::File::create(dest).await?;
+    tokio::io::copy(&mut body, &mut file).await?;
+    Ok(())
 }
 
-pub async fn create_serverless_endpoint(
+// Model names are already unique per deploy, so the endpoint config usually shares the model's name. A deploy that
+// starts after the model stage makes a new one for the existing model
+pub async fn create_serverless_endpoint_config(
+    endpoint_config_name: &str,
     model_name: &str,
     memory_size: i32,
     max_concurrency: i32,
     provisioned_concurrency: i32,
     sage_client: &aws_sdk_sagemaker::Client,
-    deploy_timestamp: &str
+    tags: &[Tag],
 ) -> Result<()> {
-    let endpoint_name = format!("{}-{}", model_name, deploy_timestamp);
-    let endpoint_config_name = format!("{}-{}", model_name, deploy_timestamp);
-
-    println!(
-        "Creating serverless endpoint {}. Might take a few mins.",
-        endpoint_name
-    );
+    info!("Creating serverless endpoint config {}", endpoint_config_name);
     let serverless_config = ProductionVariantServerlessConfig::builder()
         .max_concurrency(max_concurrency)
         .memory_size_in_mb(memory_size)
-        .provisioned_concurrency(provisioned_concurrency)
+        // SageMaker wants no provisioned concurrency at all rather than 0
+        .set_provisioned_concurrency((provisioned_concurrency > 0).then_some(provisioned_concurrency))
         .build();
 
     let production_variant = ProductionVariant::builder()
-        .variant_name("sageturner-variant-1")
+        .variant_name(VARIANT_NAME)
         .model_name(model_name)
         .serverless_config(serverless_config)
         .build();
 
-    sage_client
+    let request = sage_client
         .create_endpoint_config()
-        .endpoint_config_name(&endpoint_config_name)
+        .endpoint_config_name(endpoint_config_name)
         .production_variants(production_variant)
-        .send()
-        .await?;
+        .set_tags(Some(tags.to_vec()));
+    create_endpoint_config(request, endpoint_config_name).await
+}
 
-    sage_client
-        .create_endpoint()
-        .endpoint_name(endpoint_name)
-        .endpoint_config_name(&endpoint_config_name)
+// The image and model data (S3 URI) a SageMaker model was created with. Models created from a model package
+// have neither, the package does
+pub async fn model_container(model_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<(Option<String>, Option<String>)> {
+    let model = sage_client.describe_model().model_name(model_name).send().await?;
+    let Some(container) = model.primary_container() else {
+        return Ok((None, None));
+    };
+    let artefact = container
+        .model_data_url()
+        .or(container.model_data_source().and_then(|s| s.s3_data_source()).and_then(|s| s.s3_uri()));
+    Ok((container.image().map(|i| i.to_string()), artefact.map(|a| a.to_string())))
+}
+
+// The digest of an ECR image, like sha256:..., if ECR will tell us. Images in other accounts (AWS's own TGI and
+// Triton images) usually can't be described
+pub async fn image_digest(image_uri: &str, ecr_client: &aws_sdk_ecr::Client) -> Option<String> {
+    if let Some((_, digest)) = image_uri.split_once('@') {
+        return Some(digest.to_string());
+    }
+    let (registry, repository) = image_uri.split_once('/')?;
+    let account = registry.split('.').next()?;
+    let (repository, tag) = repository.rsplit_once(':').unwrap_or((repository, "latest"));
+    let images = ecr_client
+        .describe_images()
+        .registry_id(account)
+        .repository_name(repository)
+        .image_ids(aws_sdk_ecr::types::ImageIdentifier::builder().image_tag(tag).build())
         .send()
-        .await?;
+        .await
+        .ok()?;
+    images.image_details().first()?.image_digest().map(|d| d.to_string())
+}
+
+// The repository and tag (or digest) of an image in ECR, e.g. 123456789012.dkr.ecr.eu-west-2.amazonaws.com/resnet-50:1.2.0.
+// None for images elsewhere
+pub fn ecr_image(image_uri: &str) -> Option<(String, String)> {
+    let (registry, image) = image_uri.split_once('/')?;
+    if !registry.contains(".dkr.ecr.") {
+        return None;
+    }
+    let (repository, tag) = image.split_once('@').or_else(|| image.rsplit_once(':'))?;
+    Some((repository.to_string(), tag.to_string()))
+}
+
+// The endpoint config an endpoint is running, or None if there's no such endpoint
+pub async fn current_endpoint_config(endpoint_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<Option<String>> {
+    match sage_client.describe_endpoint().endpoint_name(endpoint_name).send().await {
+        Ok(endpoint) => Ok(endpoint.endpoint_config_name().map(|c| c.to_string())),
+        Err(e) if is_not_found(&e) => Ok(None),
+        Err(e) => Err(errors::aws(e, format!("Couldn't check for endpoint {}", endpoint_name))),
+    }
+}
+
+// The endpoint's ARN, for --output json. It's only reported, so None if it can't be looked up
+pub async fn endpoint_arn(endpoint_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Option<String> {
+    let endpoint = sage_client.describe_endpoint().endpoint_name(endpoint_name).send().await.ok()?;
+    endpoint.endpoint_arn().map(|arn| arn.to_string())
+}
+
+// Delete a deploy's endpoint config and model, either of which may already have gone
+pub async fn delete_model_and_config(name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<()> {
+    match sage_client.delete_endpoint_config().endpoint_config_name(name).send().await {
+        Ok(_) => {}
+        Err(e) if is_not_found(&e) => {}
+        Err(e) => return Err(errors::aws(e, format!("Couldn't delete endpoint config {}", name))),
+    }
+    match sage_client.delete_model().model_name(name).send().await {
+        Ok(_) => Ok(()),
+        Err(e) if is_not_found(&e) => Ok(()),
+        Err(e) => Err(errors::aws(e, format!("Couldn't delete model {}", name))),
+    }
+}
+
+// Every endpoint in the region
+pub async fn endpoint_names(sage_client: &aws_sdk_sagemaker::Client) -> Result<HashSet<String>> {
+    let mut pages = sage_client.list_endpoints().into_paginator().send();
+    let mut names = HashSet::new();
+    while let Some(page) = pages.next().await {
+        names.extend(page?.endpoints().iter().filter_map(|e| e.endpoint_name()).map(|n| n.to_string()));
+    }
+    Ok(names)
+}
+
+// The SageMaker models every endpoint in the region is running, or being updated to, and their endpoint
+// configs, which sageturner names after their model
+pub async fn models_in_use(sage_client: &aws_sdk_sagemaker::Client) -> Result<HashSet<String>> {
+    let mut models = HashSet::new();
+    for name in endpoint_names(sage_client).await? {
+        let Some(config_name) = current_endpoint_config(&name, sage_client).await? else {
+            continue;
+        };
+        // The endpoint may have been deleted since it was listed, so may its config
+        let config = match sage_client.describe_endpoint_config().endpoint_config_name(&config_name).send().await {
+            Ok(config) => config,
+            Err(e) if is_not_found(&e) => continue,
+            Err(e) => return Err(errors::aws(e, format!("Couldn't read endpoint config {}", config_name))),
+        };
+        models.extend(config.production_variants().iter().filter_map(|v| v.model_name()).map(|m| m.to_string()));
+        models.insert(config_name);
+    }
+    Ok(models)
+}
+
+// Delete an object, or everything under a prefix if the URI ends in /
+pub async fn delete_s3_uri(s3_uri: &str, s3_client: &aws_sdk_s3::Client) -> Result<()> {
+    let (bucket, key) = crate::artefact::s3_location(s3_uri).ok_or_else(|| anyhow!("{} isn't an s3:// URI", s3_uri))?;
+    let keys = match key.ends_with('/') {
+        false => vec![key.to_string()],
+        true => {
+            let mut pages = s3_client.list_objects_v2().bucket(bucket).prefix(key).into_paginator().send();
+            let mut keys = Vec::new();
+            while let Some(page) = pages.next().await {
+                let page = page.map_err(|e| errors::aws(e, format!("Couldn't list {}", s3_uri)))?;
+                keys.extend(page.contents().iter().filter_map(|o| o.key()).map(|k| k.to_string()));
+            }
+            keys
+        }
+    };
+    for key in keys {
+        s3_client
+            .delete_object()
+            .bucket(bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't delete s3://{}/{}", bucket, key)))?;
+    }
+    Ok(())
+}
+
+// What a deploy created, rather than found already there, so a failed deploy can clean up after itself
+#[derive(Debug, Default)]
+pub struct Created {
+    pub model: bool,
+    pub endpoint_config: bool,
+    pub endpoint: bool,
+}
 
-    println!(
-        "Serverless endpoint created successfully. It may take a few mins to go live. Check AWS console.");
+impl Created {
+    pub fn any(&self) -> bool {
+        self.model || self.endpoint_config || self.endpoint
+    }
+}
+
+// Delete what a failed deploy created. The endpoint only goes if the deploy created it and it failed, and the model
+// and endpoint config are left alone if the endpoint is running them, e.g. when monitoring couldn't be set up
+pub async fn clean_up_failed_deploy(
+    endpoint_name: &str,
+    model_name: &str,
+    endpoint_config_name: &str,
+    created: &Created,
+    sage_client: &aws_sdk_sagemaker::Client,
+) -> Result<()> {
+    let endpoint = match sage_client.describe_endpoint().endpoint_name(endpoint_name).send().await {
+        Ok(endpoint) => Some(endpoint),
+        Err(e) if is_not_found(&e) => None,
+        Err(e) => return Err(errors::aws(e, format!("Couldn't check for endpoint {}", endpoint_name))),
+    };
+    match endpoint {
+        Some(endpoint) if created.endpoint && endpoint.endpoint_status() == Some(&EndpointStatus::Failed) => {
+            delete_endpoint(endpoint_name, sage_client).await?;
+            info!("Deleted failed endpoint {}", endpoint_name);
+        }
+        Some(endpoint) if endpoint.endpoint_config_name() == Some(endpoint_config_name) => {
+            info!("Endpoint {} is running {}, so leaving it be", endpoint_name, endpoint_config_name);
+            return Ok(());
+        }
+        _ => {}
+    }
+    if created.endpoint_config {
+        match sage_client.delete_endpoint_config().endpoint_config_name(endpoint_config_name).send().await {
+            Ok(_) => info!("Deleted endpoint config {}", endpoint_config_name),
+            Err(e) if is_not_found(&e) => {}
+            Err(e) => return Err(errors::aws(e, format!("Couldn't delete endpoint config {}", endpoint_config_name))),
+        }
+    }
+    if created.model {
+        match sage_client.delete_model().model_name(model_name).send().await {
+            Ok(_) => info!("Deleted model {}", model_name),
+            Err(e) if is_not_found(&e) => {}
+            Err(e) => return Err(errors::aws(e, format!("Couldn't delete model {}", model_name))),
+        }
+    }
     Ok(())
 }
 
-pub async fn create_server_endpoint(
+// Every endpoint config sageturner creates has one production variant, with this name
+pub const VARIANT_NAME: &str = "sageturner-variant-1";
+
+pub async fn create_server_endpoint_config(
+    endpoint_config_name: &str,
     model_name: &str,
     instance_type: &str,
     initial_instance_count: i32,
+    data_capture: Option<DataCaptureConfig>,
     sage_client: &aws_sdk_sagemaker::Client,
-    deploy_timestamp: &str
+    tags: &[Tag],
 ) -> Result<()> {
-    let endpoint_config_name = format!("{}-{}", model_name, deploy_timestamp);
-    let endpoint_name = format!("{}-{}", model_name, deploy_timestamp);
-    println!(
-        "Creating server endpoint {}. Might take a few mins.",
-        endpoint_name
-    );
+    info!("Creating server endpoint config {}", endpoint_config_name);
     let production_variant = ProductionVariant::builder()
-        .variant_name("sageturner-variant-1")
+        .variant_name(VARIANT_NAME)
         .model_name(model_name)
         .instance_type(instance_type.into())
         .initial_instance_count(initial_instance_count)
         .build();
 
-    
-
-    sage_client
+    let request = sage_client
         .create_endpoint_config()
-        .endpoint_config_name(&endpoint_config_name)
+        .endpoint_config_name(endpoint_config_name)
         .production_variants(production_variant)
-        .send()
-        .await?;
+        .set_data_capture_config(data_capture)
+        .set_tags(Some(tags.to_vec()));
+    create_endpoint_config(request, endpoint_config_name).await
+}
 
-    
-    sage_client
-        .create_endpoint()
+async fn create_endpoint_config(request: CreateEndpointConfigFluentBuilder, endpoint_config_name: &str) -> Result<()> {
+    retry::retry(format!("Creating endpoint config {}", endpoint_config_name), retry::ATTEMPTS, || async {
+        request
+            .clone()
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't create endpoint config {}", endpoint_config_name)))
+    })
+    .await?;
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EndpointChange {
+    Created,
+    Updated,
+    // The endpoint was already running this endpoint config
+    Unchanged,
+}
+
+// The endpoint keeps its name across deploys, so whatever calls it doesn't have to change. The first deploy creates it,
+// later ones point it at their new endpoint config, which SageMaker rolls out without downtime
+pub async fn create_or_update_endpoint(
+    endpoint_name: &str,
+    endpoint_config_name: &str,
+    sage_client: &aws_sdk_sagemaker::Client,
+    tags: &[Tag],
+) -> Result<EndpointChange> {
+    let existing = sage_client.describe_endpoint().endpoint_name(endpoint_name).send().await;
+    match existing {
+        Ok(endpoint) if endpoint.endpoint_config_name() == Some(endpoint_config_name) => {
+            info!("Endpoint {} is already running {}, nothing to update", endpoint_name, endpoint_config_name);
+            Ok(EndpointChange::Unchanged)
+        }
+        Ok(_) => {
+            info!("Updating endpoint {} to {}. Might take a few mins.", endpoint_name, endpoint_config_name);
+            let request = sage_client.update_endpoint().endpoint_name(endpoint_name).endpoint_config_name(endpoint_config_name);
+            retry::retry(format!("Updating endpoint {}", endpoint_name), retry::ATTEMPTS, || async {
+                request.clone().send().await.map_err(|e| errors::aws(e, format!("Couldn't update endpoint {}", endpoint_name)))
+            })
+            .await?;
+            Ok(EndpointChange::Updated)
+        }
+        Err(e) if is_not_found(&e) => {
+            info!("Creating endpoint {}. Might take a few mins.", endpoint_name);
+            let request = sage_client
+                .create_endpoint()
+                .endpoint_name(endpoint_name)
+                .endpoint_config_name(endpoint_config_name)
+                .set_tags(Some(tags.to_vec()));
+            retry::retry(format!("Creating endpoint {}", endpoint_name), retry::ATTEMPTS, || async {
+                request.clone().send().await.map_err(|e| errors::aws(e, format!("Couldn't create endpoint {}", endpoint_name)))
+            })
+            .await?;
+            Ok(EndpointChange::Created)
+        }
+        Err(e) => Err(errors::aws(e, format!("Couldn't check for endpoint {}", endpoint_name))),
+    }
+}
+
+// After an update, delete the models and endpoint configs the endpoint used to run, keeping the newest keep
+// of them to roll back to. Only ones sageturner created for the same model and environment are touched
+pub async fn delete_superseded(
+    endpoint_name: &str,
+    current_config: &str,
+    model_name: &str,
+    environment: Option<&str>,
+    keep: usize,
+    sage_client: &aws_sdk_sagemaker::Client,
+) -> Result<()> {
+    let mut pages = sage_client
+        .list_endpoint_configs()
+        .name_contains(endpoint_name)
+        .sort_by(aws_sdk_sagemaker::types::EndpointConfigSortKey::CreationTime)
+        .sort_order(aws_sdk_sagemaker::types::OrderKey::Descending)
+        .into_paginator()
+        .send();
+
+    let mut superseded = Vec::new();
+    while let Some(page) = pages.next().await {
+        for summary in page?.endpoint_configs() {
+            let (Some(config_name), Some(arn)) = (summary.endpoint_config_name(), summary.endpoint_config_arn()) else {
+                continue;
+            };
+            if config_name == current_config {
+                continue;
+            }
+            let tags = sage_client.list_tags().resource_arn(arn).send().await?;
+            let tag = |key: &str| tags.tags().iter().find(|t| t.key() == Some(key)).and_then(|t| t.value());
+            if tag(MODEL_TAG) == Some(model_name) && tag(ENVIRONMENT_TAG) == environment {
+                superseded.push(config_name.to_string());
+            }
+        }
+    }
+
+    let to_delete = superseded.iter().skip(keep).collect::<Vec<_>>();
+    if to_delete.is_empty() {
+        info!("No superseded models to clean up, keeping {} for rollback", superseded.len());
+        return Ok(());
+    }
+    info!("Cleaning up {} superseded models and endpoint configs, keeping {} for rollback", to_delete.len(), keep.min(superseded.len()));
+    // A deploy that started after the model stage has a new config for an older model, which has to stay
+    let current = sage_client.describe_endpoint_config().endpoint_config_name(current_config).send().await?;
+    let in_use: Vec<_> = current.production_variants().iter().filter_map(|v| v.model_name()).collect();
+    for config_name in to_delete {
+        let config = sage_client.describe_endpoint_config().endpoint_config_name(config_name).send().await?;
+        sage_client.delete_endpoint_config().endpoint_config_name(config_name).send().await?;
+        for model in config.production_variants().iter().filter_map(|v| v.model_name()).filter(|m| !in_use.contains(m)) {
+            // The model may already have gone, that's fine
+            if let Err(e) = sage_client.delete_model().model_name(model).send().await {
+                warn!("Couldn't delete model {}: {}", model, aws_sdk_sagemaker::error::DisplayErrorContext(&e));
+            }
+        }
+        info!("  deleted {}", config_name);
+    }
+    Ok(())
+}
+
+// How often to check on an endpoint being created, and how long to give it unless timeouts.endpoint says otherwise.
+// SageMaker gives up on containers that don't pass their health check long before this
+const ENDPOINT_POLL_INTERVAL: Duration = Duration::from_secs(30);
+pub const ENDPOINT_WAIT_LIMIT: Duration = Duration::from_secs(90 * 60);
+// How much of the container's logs to show when an endpoint fails
+const FAILURE_LOG_LINES: usize = 100;
+
+// Wait for the endpoint to go into service. If it fails, show why, with the end of the container's logs,
+// since that's usually where the real error (an import that failed, a crash in load()) is
+pub async fn wait_for_endpoint(
+    endpoint_name: &str,
+    endpoint_config_name: &str,
+    sage_client: &aws_sdk_sagemaker::Client,
+    logs_client: &aws_sdk_cloudwatchlogs::Client,
+    limit: Duration,
+) -> Result<()> {
+    info!("Waiting for endpoint {} to go into service, pass --no-wait to skip this", endpoint_name);
+    let started = std::time::Instant::now();
+    let progress = progress::spinner(format!("Endpoint {}", endpoint_name));
+    let mut last_status = None;
+    loop {
+        let endpoint = sage_client
+            .describe_endpoint()
+            .endpoint_name(endpoint_name)
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't check on endpoint {}", endpoint_name)))?;
+        let status = endpoint.endpoint_status().cloned();
+        if let Some(s) = &status {
+            progress.set_message(format!("Endpoint {} is {}", endpoint_name, s.as_str()));
+        }
+        if matches!(status, Some(EndpointStatus::InService | EndpointStatus::Failed)) {
+            progress.finish_and_clear();
+        }
+        match &status {
+            // A failed update rolls back: the endpoint is in service, but still on its old config
+            Some(EndpointStatus::InService) if endpoint.endpoint_config_name() != Some(endpoint_config_name) => {
+                let reason = endpoint.failure_reason().unwrap_or("SageMaker didn't give a reason");
+                error!("Updating endpoint {} failed, it's still running its previous config: {}", endpoint_name, reason);
+                print_endpoint_logs(endpoint_name, logs_client).await;
+                return Err(errors::endpoint_failed(format!("Endpoint {} failed to update: {}", endpoint_name, reason)));
+            }
+            Some(EndpointStatus::InService) => {
+                info!("Endpoint {} is in service, after {}", endpoint_name, HumanDuration(started.elapsed()));
+                return Ok(());
+            }
+            Some(EndpointStatus::Failed) => {
+                let reason = endpoint.failure_reason().unwrap_or("SageMaker didn't give a reason");
+                error!("Endpoint {} failed: {}", endpoint_name, reason);
+                print_endpoint_logs(endpoint_name, logs_client).await;
+                return Err(errors::endpoint_failed(format!("Endpoint {} failed to deploy: {}", endpoint_name, reason)));
+            }
+            Some(s) if last_status.as_ref() != Some(s) => info!("Endpoint {} is {}", endpoint_name, s.as_str()),
+            _ => {}
+        }
+        last_status = status;
+        if started.elapsed() > limit {
+            progress.abandon();
+            return Err(errors::timeout(format!(
+                "Gave up waiting for endpoint {} after {}. Set timeouts.endpoint to give it longer",
+                endpoint_name,
+                HumanDuration(limit)
+            )));
+        }
+        tokio::time::sleep(ENDPOINT_POLL_INTERVAL).await;
+    }
+}
+
+// Send a request to the endpoint, or one of its variants, and return the status the container answered with and the
+// response body. SageMaker turns answers that aren't 2xx into ModelErrors, which have the container's status and body
+pub async fn invoke_endpoint(
+    endpoint_name: &str,
+    variant: Option<&str>,
+    content_type: &str,
+    body: Vec<u8>,
+    runtime_client: &aws_sdk_sagemakerruntime::Client,
+) -> Result<(i32, Vec<u8>)> {
+    let response = runtime_client
+        .invoke_endpoint()
         .endpoint_name(endpoint_name)
-        .endpoint_config_name(&endpoint_config_name)
+        .set_target_variant(variant.map(|v| v.to_string()))
+        .content_type(content_type)
+        .body(aws_sdk_sagemakerruntime::primitives::Blob::new(body))
         .send()
-        .await?;
+        .await;
+    match response {
+        Ok(response) => Ok((200, response.body().map(|b| b.as_ref().to_vec()).unwrap_or_default())),
+        Err(e) => {
+            if let Some(aws_sdk_sagemakerruntime::operation::invoke_endpoint::InvokeEndpointError::ModelError(error)) = e.as_service_error() {
+                if let Some(status) = error.original_status_code() {
+                    return Ok((status, error.original_message().unwrap_or_default().as_bytes().to_vec()));
+                }
+            }
+            Err(errors::aws(e, format!("Couldn't invoke endpoint {}", endpoint_name)))
+        }
+    }
+}
+
+// Where SageMaker sends the endpoint's container logs
+fn endpoint_log_group(endpoint_name: &str) -> String {
+    format!("/aws/sagemaker/Endpoints/{}", endpoint_name)
+}
+
+// The retention periods CloudWatch Logs accepts, in days
+pub const LOG_RETENTION_DAYS: &[i32] =
+    &[1, 3, 5, 7, 14, 30, 60, 90, 120, 150, 180, 365, 400, 545, 731, 1096, 1827, 2192, 2557, 2922, 3288, 3653];
 
-    println!(
-        "Server endpoint created successfully. It may take a few mins to go live. Check AWS Console.");
+// SageMaker creates the endpoint's log group, which keeps logs forever, when its containers start. Create it first
+// if it isn't there yet (with --no-wait, say), as SageMaker uses an existing group, then set its retention
+pub async fn set_log_retention(endpoint_name: &str, days: i32, logs_client: &aws_sdk_cloudwatchlogs::Client) -> Result<()> {
+    let log_group = endpoint_log_group(endpoint_name);
+    if let Err(e) = logs_client.create_log_group().log_group_name(&log_group).send().await {
+        if !e.as_service_error().is_some_and(|e| e.is_resource_already_exists_exception()) {
+            return Err(anyhow!(
+                "Couldn't create log group {}: {}",
+                log_group,
+                aws_sdk_cloudwatchlogs::error::DisplayErrorContext(&e)
+            ));
+        }
+    }
+    info!("Keeping the endpoint's logs in {} for {} days", log_group, days);
+    logs_client
+        .put_retention_policy()
+        .log_group_name(&log_group)
+        .retention_in_days(days)
+        .send()
+        .await
+        .map_err(|e| {
+            anyhow!(
+                "Couldn't set the retention of log group {}: {}",
+                log_group,
+                aws_sdk_cloudwatchlogs::error::DisplayErrorContext(&e)
+            )
+        })?;
     Ok(())
 }
 
+// Failing to read the logs shouldn't hide the failure we're reporting, so this only prints
+async fn print_endpoint_logs(endpoint_name: &str, logs_client: &aws_sdk_cloudwatchlogs::Client) {
+    let log_group = endpoint_log_group(endpoint_name);
+    match endpoint_logs(&log_group, logs_client).await {
+        Ok(lines) if lines.is_empty() => info!("No logs in {}, the container may not have started", log_group),
+        Ok(lines) => {
+            info!("Last {} lines of logs from {}:", lines.len(), log_group);
+            for line in lines {
+                info!("  {}", line);
+            }
+        }
+        Err(e) => warn!("Couldn't read the endpoint's logs from {}: {}", log_group, e),
+    }
+}
+
+// The last lines the endpoint's containers logged, across its most recently active log streams (one per instance)
+async fn endpoint_logs(log_group: &str, logs_client: &aws_sdk_cloudwatchlogs::Client) -> Result<Vec<String>> {
+    let streams = logs_client
+        .describe_log_streams()
+        .log_group_name(log_group)
+        .order_by(aws_sdk_cloudwatchlogs::types::OrderBy::LastEventTime)
+        .descending(true)
+        .limit(5)
+        .send()
+        .await
+        .map_err(|e| anyhow!("{}", aws_sdk_cloudwatchlogs::error::DisplayErrorContext(&e)))?;
+
+    let mut events = Vec::new();
+    for stream in streams.log_streams() {
+        let Some(stream_name) = stream.log_stream_name() else { continue };
+        let stream_events = logs_client
+            .get_log_events()
+            .log_group_name(log_group)
+            .log_stream_name(stream_name)
+            .start_from_head(false)
+            .limit(FAILURE_LOG_LINES as i32)
+            .send()
+            .await
+            .map_err(|e| anyhow!("{}", aws_sdk_cloudwatchlogs::error::DisplayErrorContext(&e)))?;
+        for event in stream_events.events() {
+            let message = event.message().unwrap_or_default().trim_end();
+            events.push((event.timestamp().unwrap_or_default(), format!("[{}] {}", stream_name, message)));
+        }
+    }
+    events.sort_by_key(|(timestamp, _)| *timestamp);
+    let skip = events.len().saturating_sub(FAILURE_LOG_LINES);
+    Ok(events.into_iter().skip(skip).map(|(_, line)| line).collect())
+}
+
 pub async fn upload_artefact(
     object_path: &str,
     bucket_name: &str,
     s3_key: &str,
     s3_client: &aws_sdk_s3::Client,
-    config_path: &Path
+    config_path: &Path,
+    upload: &UploadConfig,
+    waiter: Duration,
 ) -> Result<String> {
-    println!("Uploading file {} to bucket {} with key {}", object_path, bucket_name, s3_key);
+    info!("Uploading file {} to bucket {} with key {}", object_path, bucket_name, s3_key);
     let artefact_path = Path::new(config_path).join(object_path);
     let arefact_path_abs = absolute(artefact_path)?;
     if !is_tar_gz(&arefact_path_abs) {
-        return Err(anyhow!("Artefact needs to be a .tar.gz file (ask perplexity how to create one, if you're not sure"));
+        return Err(anyhow!("Artefact needs to be a .tar.gz file, or a directory for sageturner to package up: {}", arefact_path_abs.display()));
     }
-    println!("{:?}", &arefact_path_abs);
-    let body = ByteStream::from_path(arefact_path_abs).await?;
-    s3_client
-        .put_object()
-        .bucket(bucket_name)
-        .key(s3_key)
-        .body(body)
-        .send()
-        .await?;
+    debug!("{:?}", &arefact_path_abs);
+    upload_file(&arefact_path_abs, bucket_name, s3_key, s3_client, upload).await?;
 
     s3_client.wait_until_object_exists()
         .bucket(bucket_name)
         .key(s3_key)
-        .wait(Duration::from_secs(8))
-        .await?;
+        .wait(waiter)
+        .await
+        .map_err(|e| {
+            errors::timeout(format!(
+                "s3://{}/{} didn't show up within {}. Set timeouts.waiter to give it longer: {}",
+                bucket_name,
+                s3_key,
+                HumanDuration(waiter),
+                e
+            ))
+        })?;
 
     let s3_path = format!("s3://{}/{}", bucket_name, s3_key);
     Ok(s3_path)
 }
 
+// Upload every file in a directory under an S3 prefix, keeping the directory layout.
+// Returns the s3:// URI of the prefix
+pub async fn upload_dir(
+    dir: &str,
+    bucket_name: &str,
+    s3_prefix: &str,
+    s3_client: &aws_sdk_s3::Client,
+    config_path: &Path,
+    upload: &UploadConfig,
+    excludes: &GlobSet,
+) -> Result<String> {
+    let dir_abs = absolute(config_path.join(dir))?;
+    if !dir_abs.is_dir() {
+        return Err(anyhow!("Uncompressed artefacts need to be a directory: {}", dir_abs.display()));
+    }
+    let files = artefact::list_files(&dir_abs, excludes)?;
+    info!("Uploading {} files from {} to bucket {} under {}", files.len(), dir_abs.display(), bucket_name, s3_prefix);
+    for file in files {
+        let key = format!("{}{}", s3_prefix, file.to_string_lossy().replace('\\', "/"));
+        info!("Uploading {}", file.display());
+        upload_file(&dir_abs.join(&file), bucket_name, &key, s3_client, upload).await?;
+    }
+    Ok(format!("s3://{}/{}", bucket_name, s3_prefix))
+}
+
+#[instrument(name = "upload", skip_all, fields(key = s3_key))]
+async fn upload_file(
+    path: &Path,
+    bucket_name: &str,
+    s3_key: &str,
+    s3_client: &aws_sdk_s3::Client,
+    upload: &UploadConfig
+) -> Result<()> {
+    let size = tokio::fs::metadata(path).await?.len();
+    let part_size = upload.part_size_mb * 1024 * 1024;
+
+    if size <= part_size {
+        retry::retry(format!("Uploading to s3://{}/{}", bucket_name, s3_key), retry::ATTEMPTS, || async {
+            // The body is consumed by each try, so it's read again every time
+            let body = ByteStream::from_path(path).await?;
+            s3_client
+                .put_object()
+                .bucket(bucket_name)
+                .key(s3_key)
+                .body(body)
+                .send()
+                .await
+                .map_err(|e| errors::aws(e, format!("Couldn't upload to s3://{}/{}", bucket_name, s3_key)))
+        })
+        .await?;
+    } else {
+        upload_multipart(path, size, bucket_name, s3_key, s3_client, upload).await?;
+    }
+    Ok(())
+}
+
+// Same as check_s3_artefact, for an uncompressed artefact's S3 prefix
+pub async fn check_s3_prefix(s3_uri: &str, s3_client: &aws_sdk_s3::Client) -> Result<()> {
+    let (bucket, prefix) = artefact::s3_location(s3_uri)
+        .ok_or_else(|| anyhow!("Invalid S3 artefact URI: {}", s3_uri))?;
+    let objects = s3_client
+        .list_objects_v2()
+        .bucket(bucket)
+        .prefix(prefix)
+        .max_keys(1)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't list artefact prefix {}", s3_uri)))?;
+    if objects.contents().is_empty() {
+        return Err(anyhow!("No files found under artefact prefix {}", s3_uri));
+    }
+    Ok(())
+}
+
+// Fail before creating the model if an s3:// artefact isn't there (or we can't read it),
+// rather than when the endpoint fails to start
+pub async fn check_s3_artefact(s3_uri: &str, s3_client: &aws_sdk_s3::Client) -> Result<()> {
+    let (bucket, key) = artefact::s3_location(s3_uri)
+        .ok_or_else(|| anyhow!("Invalid S3 artefact URI: {}", s3_uri))?;
+    s3_client
+        .head_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't find artefact {}", s3_uri)))?;
+    Ok(())
+}
+
+// S3 allows at most 10,000 parts, so very big artefacts get bigger parts than configured
+const MAX_PARTS: u64 = 10_000;
+
+async fn upload_multipart(
+    path: &Path,
+    size: u64,
+    bucket_name: &str,
+    s3_key: &str,
+    s3_client: &aws_sdk_s3::Client,
+    upload: &UploadConfig
+) -> Result<()> {
+    let part_size = (upload.part_size_mb * 1024 * 1024).max(size.div_ceil(MAX_PARTS));
+    let part_count = size.div_ceil(part_size);
+    info!(
+        "Multipart upload: {} parts of {}MB, {} at a time",
+        part_count,
+        part_size / (1024 * 1024),
+        upload.concurrency
+    );
+
+    let request = s3_client.create_multipart_upload().bucket(bucket_name).key(s3_key);
+    let multipart = retry::retry(format!("Starting the upload to s3://{}/{}", bucket_name, s3_key), retry::ATTEMPTS, || async {
+        request
+            .clone()
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't start uploading to s3://{}/{}", bucket_name, s3_key)))
+    })
+    .await?;
+    let upload_id = multipart
+        .upload_id()
+        .ok_or_else(|| anyhow!("S3 didn't return a multipart upload id"))?;
+
+    let started = std::time::Instant::now();
+    let progress = progress::bytes(size, format!("Uploading {}", s3_key));
+
+    let parts = stream::iter(1..=part_count)
+        .map(|part_number| {
+            let offset = (part_number - 1) * part_size;
+            let length = part_size.min(size - offset);
+            let progress = &progress;
+            async move {
+                let part = upload_part(path, offset, length, part_number as i32, bucket_name, s3_key, upload_id, s3_client, upload.part_attempts).await?;
+                progress.inc(length);
+                Ok::<_, anyhow::Error>(part)
+            }
+        })
+        .buffer_unordered(upload.concurrency)
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>();
+
+    let mut parts = match parts {
+        Ok(p) => p,
+        Err(e) => {
+            progress.abandon();
+            // Don't leave the uploaded parts lying around (and billed) in the bucket
+            s3_client
+                .abort_multipart_upload()
+                .bucket(bucket_name)
+                .key(s3_key)
+                .upload_id(upload_id)
+                .send()
+                .await?;
+            return Err(e);
+        }
+    };
+    progress.finish_and_clear();
+    info!("Uploaded {} in {}", HumanBytes(size), HumanDuration(started.elapsed()));
+    parts.sort_by_key(|p| p.part_number());
+
+    let request = s3_client
+        .complete_multipart_upload()
+        .bucket(bucket_name)
+        .key(s3_key)
+        .upload_id(upload_id)
+        .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build());
+    retry::retry(format!("Finishing the upload to s3://{}/{}", bucket_name, s3_key), retry::ATTEMPTS, || async {
+        request
+            .clone()
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't finish uploading to s3://{}/{}", bucket_name, s3_key)))
+    })
+    .await?;
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn upload_part(
+    path: &Path,
+    offset: u64,
+    length: u64,
+    part_number: i32,
+    bucket_name: &str,
+    s3_key: &str,
+    upload_id: &str,
+    s3_client: &aws_sdk_s3::Client,
+    attempts: u32
+) -> Result<CompletedPart> {
+    let uploaded = retry::retry(format!("Uploading part {}", part_number), attempts, || async {
+        // The body is consumed by each try, so re-open the file range every attempt
+        let body = ByteStream::read_from()
+            .path(path)
+            .offset(offset)
+            .length(Length::Exact(length))
+            .build()
+            .await?;
+        s3_client
+            .upload_part()
+            .bucket(bucket_name)
+            .key(s3_key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Part {} failed", part_number)))
+    })
+    .await?;
+    Ok(CompletedPart::builder().part_number(part_number).set_e_tag(uploaded.e_tag).build())
+}
+
 fn is_tar_gz(file_path: &Path) -> bool {
     file_path
         .extension()
@@ -0,0 +1,167 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{anyhow, Result};
+use aws_sdk_sagemaker::types::{ProductionVariant, Tag};
+use tracing::{info, warn};
+
+use crate::{
+    aws::{self, AwsClients},
+    errors,
+    model_config::{CanaryConfig, CanaryPayload},
+};
+
+// While the canary runs, the new model is this variant, next to the endpoint's current one
+const CANARY_VARIANT_NAME: &str = "sageturner-canary";
+
+// The endpoint config the endpoint runs during the canary
+fn canary_config_name(sagemaker_name: &str) -> String {
+    format!("{}-canary", sagemaker_name)
+}
+
+// Check the deploy's model before the endpoint moves onto it. The endpoint is updated to an endpoint config with
+// its current variants, which keep all the traffic, and the new model on one instance with none. Each payload is
+// sent straight to the new model, and if any fails, the endpoint goes back to previous_config
+#[allow(clippy::too_many_arguments)]
+pub async fn run(
+    canary: &CanaryConfig,
+    endpoint_name: &str,
+    sagemaker_name: &str,
+    previous_config: &str,
+    config_dir: &Path,
+    aws: &AwsClients,
+    tags: &[Tag],
+    wait_limit: Duration,
+) -> Result<()> {
+    let sage_client = &aws.sage;
+    let canary_config = canary_config_name(sagemaker_name);
+    if !aws::endpoint_config_exists(&canary_config, sage_client).await? {
+        create_canary_config(&canary_config, sagemaker_name, previous_config, sage_client, tags).await?;
+    }
+    info!("Starting the canary: updating endpoint {} to run {} next to its current model, without traffic", endpoint_name, sagemaker_name);
+    sage_client.update_endpoint().endpoint_name(endpoint_name).endpoint_config_name(&canary_config).send().await?;
+    // If the new model doesn't start, SageMaker puts the endpoint back on previous_config itself
+    if let Err(e) = aws::wait_for_endpoint(endpoint_name, &canary_config, sage_client, &aws.logs, wait_limit).await {
+        delete_canary_config(&canary_config, sage_client).await;
+        return Err(e);
+    }
+
+    let mut failure = None;
+    for payload in &canary.payloads {
+        match send(payload, endpoint_name, config_dir, &aws.runtime).await {
+            Ok(()) => info!("  {} passed", payload.file),
+            Err(e) => {
+                warn!("  {}", e);
+                failure = Some(e);
+                break;
+            }
+        }
+    }
+    let Some(failure) = failure else {
+        info!("Canary passed, moving endpoint {}'s traffic to {}", endpoint_name, sagemaker_name);
+        return Ok(());
+    };
+
+    warn!("Canary failed, putting endpoint {} back on {}", endpoint_name, previous_config);
+    sage_client.update_endpoint().endpoint_name(endpoint_name).endpoint_config_name(previous_config).send().await?;
+    aws::wait_for_endpoint(endpoint_name, previous_config, sage_client, &aws.logs, wait_limit).await?;
+    delete_canary_config(&canary_config, sage_client).await;
+    Err(anyhow!("Canary failed, endpoint {} is still running {}: {}", endpoint_name, previous_config, failure))
+}
+
+// Once the endpoint has been updated to the deploy's endpoint config, delete the canary's. If that update failed,
+// SageMaker left the endpoint on the canary's config, so it goes back to previous_config first
+pub async fn finish(endpoint_name: &str, sagemaker_name: &str, previous_config: &str, aws: &AwsClients, wait_limit: Duration) {
+    let canary_config = canary_config_name(sagemaker_name);
+    match aws::current_endpoint_config(endpoint_name, &aws.sage).await {
+        Ok(Some(current)) if current == canary_config => {
+            info!("Putting endpoint {} back on {}", endpoint_name, previous_config);
+            let restored = match aws.sage.update_endpoint().endpoint_name(endpoint_name).endpoint_config_name(previous_config).send().await {
+                Ok(_) => aws::wait_for_endpoint(endpoint_name, previous_config, &aws.sage, &aws.logs, wait_limit).await,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = restored {
+                warn!("Couldn't put endpoint {} back on {}, it's still running the canary: {}", endpoint_name, previous_config, e);
+                return;
+            }
+        }
+        Ok(_) => {}
+        Err(e) => {
+            warn!("Couldn't check endpoint {} after the canary: {}", endpoint_name, e);
+            return;
+        }
+    }
+    delete_canary_config(&canary_config, &aws.sage).await;
+}
+
+// previous_config's variants, plus the deploy's model with no traffic. Server models get one instance, which is
+// enough to check them
+async fn create_canary_config(
+    canary_config: &str,
+    sagemaker_name: &str,
+    previous_config: &str,
+    sage_client: &aws_sdk_sagemaker::Client,
+    tags: &[Tag],
+) -> Result<()> {
+    let previous = sage_client.describe_endpoint_config().endpoint_config_name(previous_config).send().await?;
+    let deploy = sage_client.describe_endpoint_config().endpoint_config_name(sagemaker_name).send().await?;
+    let variant = deploy
+        .production_variants()
+        .first()
+        .ok_or_else(|| anyhow!("Endpoint config {} has no production variant", sagemaker_name))?;
+    let serverless = variant.serverless_config().is_some();
+    if previous.production_variants().iter().any(|v| v.serverless_config().is_some() != serverless) {
+        return Err(anyhow!(
+            "SageMaker can't run serverless and server models on one endpoint, so the canary can't switch endpoint types. Deploy without canary to switch"
+        ));
+    }
+    let canary_variant = ProductionVariant::builder()
+        .variant_name(CANARY_VARIANT_NAME)
+        .model_name(sagemaker_name)
+        .set_instance_type(variant.instance_type().cloned())
+        .set_initial_instance_count(variant.instance_type().map(|_| 1))
+        .set_serverless_config(variant.serverless_config().cloned())
+        .initial_variant_weight(0.0)
+        .build();
+    let mut variants: Vec<ProductionVariant> =
+        previous.production_variants().iter().filter(|v| v.variant_name() != Some(CANARY_VARIANT_NAME)).cloned().collect();
+    variants.push(canary_variant);
+
+    info!("Creating endpoint config {} for the canary", canary_config);
+    sage_client
+        .create_endpoint_config()
+        .endpoint_config_name(canary_config)
+        .set_production_variants(Some(variants))
+        .set_data_capture_config(previous.data_capture_config().cloned())
+        .set_tags(Some(tags.to_vec()))
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't create endpoint config {}", canary_config)))?;
+    Ok(())
+}
+
+async fn delete_canary_config(canary_config: &str, sage_client: &aws_sdk_sagemaker::Client) {
+    if let Err(e) = sage_client.delete_endpoint_config().endpoint_config_name(canary_config).send().await {
+        warn!("Couldn't delete endpoint config {}: {}", canary_config, aws_sdk_sagemaker::error::DisplayErrorContext(&e));
+    }
+}
+
+// Send the payload to the canary variant, which has to answer with a 2xx
+async fn send(payload: &CanaryPayload, endpoint_name: &str, config_dir: &Path, runtime: &aws_sdk_sagemakerruntime::Client) -> Result<()> {
+    let body = std::fs::read(config_dir.join(&payload.file))?;
+    let (status, body) = aws::invoke_endpoint(endpoint_name, Some(CANARY_VARIANT_NAME), &payload.content_type, body, runtime)
+        .await
+        .map_err(|e| anyhow!("{} failed: {}", payload.file, e))?;
+    if !(200..300).contains(&status) {
+        return Err(anyhow!("{} failed: the model answered {}: {}", payload.file, status, String::from_utf8_lossy(&body)));
+    }
+    if payload.expect_keys.is_empty() {
+        return Ok(());
+    }
+    let json: serde_json::Value =
+        serde_json::from_slice(&body).map_err(|e| anyhow!("{} failed: the response isn't JSON: {}", payload.file, e))?;
+    let missing: Vec<&str> = payload.expect_keys.iter().filter(|k| json.get(k.as_str()).is_none()).map(|k| k.as_str()).collect();
+    if !missing.is_empty() {
+        return Err(anyhow!("{} failed: the response has no {}", payload.file, missing.join(", ")));
+    }
+    Ok(())
+}
@@ -0,0 +1,262 @@
+use std::{fmt::Write, str::FromStr};
+
+use anyhow::anyhow;
+use argh::{CommandInfoWithArgs, FlagInfo, FlagInfoKind, Optionality};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => Err(anyhow!("Invalid shell. bash, zsh or fish only, not: {}", s)),
+        }
+    }
+}
+
+// The values an option can take, for options that take one of a few. Kept in step with their FromStr impls
+fn values(command: &str, flag: &str) -> Option<&'static [&'static str]> {
+    match (command, flag) {
+        (_, "--endpoint-type") => Some(&["serverless", "server"]),
+        (_, "--container-mode") => Some(&["generate", "provide", "vllm", "tgi", "triton"]),
+        ("sageturner deploy", "--from" | "--until") => {
+            Some(&["validate", "build", "push", "upload", "model", "endpoint", "wait", "smoke-test"])
+        }
+        ("sageturner", "--log-format") => Some(&["plain", "full", "json", "github"]),
+        // Subcommands use --output for where to write files
+        ("sageturner", "--output") => Some(&["text", "json"]),
+        ("sageturner completions", "shell") => Some(&["bash", "zsh", "fish"]),
+        _ => None,
+    }
+}
+
+// Options that take a file or directory, which complete file names. Others, like names and regions, complete nothing
+fn takes_path(command: &str, flag: &str) -> bool {
+    match (command, flag) {
+        ("sageturner", _) => false,
+        (_, "--config-path" | "--report" | "--payload" | "--dir" | "--output") => true,
+        _ => false,
+    }
+}
+
+// What an option completes to
+enum Completion {
+    Values(&'static [&'static str]),
+    Files,
+    Nothing,
+}
+
+// A completion script for the shell, for every subcommand and flag in the CLI
+pub fn generate(shell: Shell, cli: &CommandInfoWithArgs) -> String {
+    let commands = walk("sageturner", cli);
+    match shell {
+        Shell::Bash => bash(&commands),
+        Shell::Zsh => zsh(&commands),
+        Shell::Fish => fish(&commands),
+    }
+}
+
+// A command, as the words that run it (like sageturner config migrate), with what it takes
+struct Command<'a> {
+    path: String,
+    info: &'a CommandInfoWithArgs,
+}
+
+impl Command<'_> {
+    fn flags(&self) -> impl Iterator<Item = &FlagInfo<'static>> {
+        self.info.flags.iter().filter(|f| !f.hidden)
+    }
+
+    fn subcommands(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.info.commands.iter().map(|c| (c.name, c.command.description))
+    }
+
+    // The function zsh completes this command with
+    fn function(&self) -> String {
+        format!("_{}", self.path.replace(['-', ' '], "_"))
+    }
+
+    fn completion(&self, flag: &FlagInfo) -> Completion {
+        match values(&self.path, flag.long) {
+            Some(values) => Completion::Values(values),
+            None if takes_path(&self.path, flag.long) => Completion::Files,
+            None => Completion::Nothing,
+        }
+    }
+}
+
+// Every command, parents before their subcommands
+fn walk<'a>(path: &str, info: &'a CommandInfoWithArgs) -> Vec<Command<'a>> {
+    let mut commands = vec![Command { path: path.to_string(), info }];
+    for sub in &info.commands {
+        commands.extend(walk(&format!("{} {}", path, sub.name), &sub.command));
+    }
+    commands
+}
+
+// Bash finds the command being completed from the words before the cursor, then offers its flags and subcommands,
+// or the values of the option just before the cursor
+fn bash(commands: &[Command]) -> String {
+    let mut script = String::from(
+        "_sageturner() {\n    local cur prev path word i\n    cur=\"${COMP_WORDS[COMP_CWORD]}\"\n    \
+         prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n    path=sageturner\n    for ((i = 1; i < COMP_CWORD; i++)); do\n        \
+         word=\"${COMP_WORDS[i]}\"\n        case \"$path $word\" in\n",
+    );
+    for command in &commands[1..] {
+        let _ = writeln!(script, "            \"{}\") path=\"{}\" ;;", command.path, command.path);
+    }
+    script.push_str("        esac\n    done\n\n    case \"$path\" in\n");
+    for command in commands {
+        let _ = writeln!(script, "        \"{}\")", command.path);
+        script.push_str("            case \"$prev\" in\n");
+        for flag in command.flags().filter(|f| matches!(f.kind, FlagInfoKind::Option { .. })) {
+            let names = flag_names(flag).join("|");
+            let reply = match command.completion(flag) {
+                Completion::Values(values) => format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", values.join(" ")),
+                Completion::Files => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+                Completion::Nothing => "COMPREPLY=()".to_string(),
+            };
+            let _ = writeln!(script, "                {}) {}; return ;;", names, reply);
+        }
+        script.push_str("            esac\n");
+        let mut words: Vec<String> = command.flags().flat_map(flag_names).collect();
+        words.extend(command.subcommands().map(|(name, _)| name.to_string()));
+        for positional in command.info.positionals.iter().filter(|p| !p.hidden) {
+            words.extend(values(&command.path, positional.name).unwrap_or_default().iter().map(|v| v.to_string()));
+        }
+        let _ = writeln!(script, "            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", words.join(" "));
+        script.push_str("            ;;\n");
+    }
+    script.push_str("    esac\n}\n\ncomplete -F _sageturner sageturner\n");
+    script
+}
+
+// Zsh gets a function per command, which hands off to its subcommands' once one's been typed
+fn zsh(commands: &[Command]) -> String {
+    let mut script = String::from("#compdef sageturner\n");
+    for command in commands {
+        let _ = write!(script, "\n{}() {{\n    local curcontext=\"$curcontext\" state line\n    _arguments -C", command.function());
+        for flag in command.flags() {
+            let _ = write!(script, " \\\n        {}", zsh_flag(command, flag));
+        }
+        for (i, positional) in command.info.positionals.iter().filter(|p| !p.hidden).enumerate() {
+            let action = match values(&command.path, positional.name) {
+                Some(values) => format!("({})", values.join(" ")),
+                None => " ".to_string(),
+            };
+            let _ = write!(script, " \\\n        '{}:{}:{}'", i + 1, zsh_escape(positional.description), action);
+        }
+        if command.info.commands.is_empty() {
+            script.push('\n');
+        } else {
+            script.push_str(" \\\n        '1: :->command' \\\n        '*:: :->args'\n");
+            script.push_str("    case $state in\n        command)\n            local -a commands\n            commands=(\n");
+            for (name, description) in command.subcommands() {
+                let _ = writeln!(script, "                '{}:{}'", name, zsh_escape(description));
+            }
+            script.push_str("            )\n            _describe command commands\n            ;;\n        args)\n");
+            script.push_str("            case $line[1] in\n");
+            for (name, _) in command.subcommands() {
+                let _ = writeln!(script, "                {}) {}_{} ;;", name, command.function(), name.replace('-', "_"));
+            }
+            script.push_str("            esac\n            ;;\n    esac\n");
+        }
+        script.push_str("}\n");
+    }
+    script.push_str("\n_sageturner \"$@\"\n");
+    script
+}
+
+fn zsh_flag(command: &Command, flag: &FlagInfo) -> String {
+    let description = format!("[{}]", zsh_escape(flag.description));
+    let value = match flag.kind {
+        FlagInfoKind::Switch => String::new(),
+        FlagInfoKind::Option { arg_name } => match command.completion(flag) {
+            Completion::Values(values) => format!(":{}:({})", arg_name, values.join(" ")),
+            Completion::Files => format!(":{}:_files", arg_name),
+            Completion::Nothing => format!(":{}: ", arg_name),
+        },
+    };
+    let repeats = matches!(flag.optionality, Optionality::Repeating);
+    match flag.short {
+        Some(short) => {
+            let exclusive = if repeats { "*".to_string() } else { format!("(-{} {})", short, flag.long) };
+            format!("'{}'{{-{},{}}}'{}{}'", exclusive, short, flag.long, description, value)
+        }
+        None => format!("'{}{}{}{}'", if repeats { "*" } else { "" }, flag.long, description, value),
+    }
+}
+
+// Descriptions go in single quotes, and in _arguments' brackets and colon-separated specs
+fn zsh_escape(s: &str) -> String {
+    s.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:")
+}
+
+// Fish gets a line per flag and subcommand, on the condition that the words typed so far are its command's
+fn fish(commands: &[Command]) -> String {
+    let mut script = String::from("complete -c sageturner -f\n");
+    for command in commands {
+        let words: Vec<&str> = command.path.split(' ').skip(1).collect();
+        let subcommands: Vec<&str> = command.subcommands().map(|(name, _)| name).collect();
+        let mut condition: Vec<String> = words.iter().map(|w| format!("__fish_seen_subcommand_from {}", w)).collect();
+        if words.is_empty() {
+            condition.push("__fish_use_subcommand".to_string());
+        }
+        let condition = condition.join("; and ");
+        script.push('\n');
+        for (name, description) in command.subcommands() {
+            let mut listing = condition.clone();
+            if !words.is_empty() {
+                let _ = write!(listing, "; and not __fish_seen_subcommand_from {}", subcommands.join(" "));
+            }
+            let _ = writeln!(
+                script,
+                "complete -c sageturner -n '{}' -a {} -d '{}'",
+                listing,
+                name,
+                fish_escape(description)
+            );
+        }
+        for flag in command.flags() {
+            let mut line = format!("complete -c sageturner -n '{}'", condition);
+            if let Some(short) = flag.short {
+                let _ = write!(line, " -s {}", short);
+            }
+            let _ = write!(line, " -l {} -d '{}'", flag.long.trim_start_matches("--"), fish_escape(flag.description));
+            if matches!(flag.kind, FlagInfoKind::Option { .. }) {
+                match command.completion(flag) {
+                    Completion::Values(values) => {
+                        let _ = write!(line, " -r -a '{}'", values.join(" "));
+                    }
+                    Completion::Files => line.push_str(" -r -F"),
+                    Completion::Nothing => line.push_str(" -r"),
+                }
+            }
+            script.push_str(&line);
+            script.push('\n');
+        }
+        for positional in command.info.positionals.iter().filter(|p| !p.hidden) {
+            if let Some(values) = values(&command.path, positional.name) {
+                let _ = writeln!(script, "complete -c sageturner -n '{}' -a '{}'", condition, values.join(" "));
+            }
+        }
+    }
+    script
+}
+
+fn fish_escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn flag_names(flag: &FlagInfo) -> Vec<String> {
+    flag.short.map(|short| format!("-{}", short)).into_iter().chain([flag.long.to_string()]).collect()
+}
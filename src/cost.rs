@@ -0,0 +1,158 @@
+use anyhow::{anyhow, Result};
+use tracing::warn;
+
+use crate::{
+    model_config::{ModelConfig, ServerCompute, ServerlessCompute},
+    EndpointType,
+};
+
+// Endpoints bill by the hour, and a month is about 730 hours
+const HOURS_PER_MONTH: f64 = 730.0;
+
+// On-demand SageMaker real-time inference prices in us-east-1, USD per instance hour. Other regions are usually
+// within 10-20% of these, which is close enough to catch a deploy that's ten times the budget
+const HOURLY_PRICES: &[(&str, f64)] = &[
+    ("ml.t2.medium", 0.056),
+    ("ml.g4dn.xlarge", 0.736),
+    ("ml.g4dn.2xlarge", 1.053),
+    ("ml.g4dn.4xlarge", 1.686),
+    ("ml.g4dn.8xlarge", 3.046),
+    ("ml.g4dn.12xlarge", 5.477),
+    ("ml.g4dn.16xlarge", 6.092),
+    ("ml.g5.xlarge", 1.408),
+    ("ml.g5.2xlarge", 1.515),
+    ("ml.g5.4xlarge", 2.03),
+    ("ml.g5.8xlarge", 3.06),
+    ("ml.g5.12xlarge", 7.09),
+    ("ml.g5.16xlarge", 5.12),
+    ("ml.g5.24xlarge", 10.18),
+    ("ml.g5.48xlarge", 20.36),
+    ("ml.g6.xlarge", 1.01),
+    ("ml.g6.2xlarge", 1.22),
+    ("ml.g6.4xlarge", 1.65),
+    ("ml.g6.8xlarge", 2.52),
+    ("ml.g6.12xlarge", 5.81),
+    ("ml.g6.16xlarge", 4.25),
+    ("ml.g6.24xlarge", 8.39),
+    ("ml.g6.48xlarge", 16.78),
+    ("ml.g6e.xlarge", 2.34),
+    ("ml.g6e.2xlarge", 2.82),
+    ("ml.g6e.4xlarge", 3.78),
+    ("ml.g6e.8xlarge", 5.69),
+    ("ml.g6e.12xlarge", 12.27),
+    ("ml.g6e.16xlarge", 9.52),
+    ("ml.g6e.24xlarge", 18.87),
+    ("ml.g6e.48xlarge", 37.74),
+    ("ml.p2.xlarge", 1.125),
+    ("ml.p2.8xlarge", 8.64),
+    ("ml.p2.16xlarge", 17.28),
+    ("ml.p3.2xlarge", 3.825),
+    ("ml.p3.8xlarge", 14.688),
+    ("ml.p3.16xlarge", 28.152),
+    ("ml.p4d.24xlarge", 37.688),
+    ("ml.p5.48xlarge", 113.07),
+    ("ml.inf1.xlarge", 0.297),
+    ("ml.inf1.2xlarge", 0.471),
+    ("ml.inf1.6xlarge", 1.531),
+    ("ml.inf1.24xlarge", 6.126),
+    ("ml.inf2.xlarge", 0.99),
+    ("ml.inf2.8xlarge", 2.36),
+    ("ml.inf2.24xlarge", 7.79),
+    ("ml.inf2.48xlarge", 15.58),
+    ("ml.trn1.2xlarge", 1.56),
+    ("ml.trn1.32xlarge", 26.0),
+    ("ml.trn1n.32xlarge", 29.9),
+];
+
+// CPU instances cost the same per vCPU across a family's sizes, so one price per family covers them: an xlarge's
+const CPU_XLARGE_PRICES: &[(&str, f64)] = &[
+    ("t2", 0.223),
+    ("m4", 0.28),
+    ("m5", 0.23),
+    ("m5d", 0.271),
+    ("m6i", 0.23),
+    ("m7i", 0.242),
+    ("m6g", 0.184),
+    ("c4", 0.279),
+    ("c5", 0.204),
+    ("c5d", 0.23),
+    ("c6i", 0.204),
+    ("c7i", 0.214),
+    ("c6g", 0.163),
+    ("r5", 0.302),
+    ("r5d", 0.348),
+    ("r6i", 0.302),
+    ("r7i", 0.317),
+    ("r6g", 0.242),
+];
+
+// Serverless endpoints bill per GB of memory per second: while handling requests, and all the time for
+// provisioned concurrency
+const SERVERLESS_GB_SECOND: f64 = 0.00002;
+const PROVISIONED_GB_SECOND: f64 = 0.000004646;
+const SECONDS_PER_MONTH: f64 = HOURS_PER_MONTH * 3600.0;
+
+// The hourly price of an instance type, if sageturner knows it
+pub fn hourly_price(instance_type: &str) -> Option<f64> {
+    if let Some((_, price)) = HOURLY_PRICES.iter().find(|(t, _)| *t == instance_type) {
+        return Some(*price);
+    }
+    let (family, size) = instance_type.strip_prefix("ml.")?.split_once('.')?;
+    let (_, xlarge_price) = CPU_XLARGE_PRICES.iter().find(|(f, _)| *f == family)?;
+    let xlarges = match size {
+        "medium" => 0.25,
+        "large" => 0.5,
+        "xlarge" => 1.0,
+        _ => size.strip_suffix("xlarge")?.parse::<f64>().ok()?,
+    };
+    Some(xlarge_price * xlarges)
+}
+
+// What a server endpoint costs a month, running all the time
+pub fn server_monthly_cost(server: &ServerCompute) -> Option<f64> {
+    hourly_price(&server.instance_type).map(|p| p * server.initial_instance_count as f64 * HOURS_PER_MONTH)
+}
+
+// What a serverless endpoint costs a month whatever its traffic: its provisioned concurrency
+pub fn serverless_monthly_cost(serverless: &ServerlessCompute) -> f64 {
+    let gb = serverless.memory as f64 / 1024.0;
+    serverless.provisioned_concurrency as f64 * gb * PROVISIONED_GB_SECOND * SECONDS_PER_MONTH
+}
+
+// The most a serverless endpoint can cost a month: max_concurrency requests at once, all month
+pub fn serverless_max_monthly_cost(serverless: &ServerlessCompute) -> f64 {
+    let gb = serverless.memory as f64 / 1024.0;
+    serverless.max_concurrency as f64 * gb * SERVERLESS_GB_SECOND * SECONDS_PER_MONTH
+}
+
+// The endpoint's estimated monthly cost, if sageturner knows it, and what it's for
+pub fn estimate(model_config: &ModelConfig, endpoint_type: EndpointType) -> (Option<f64>, String) {
+    match (endpoint_type, &model_config.compute.server, &model_config.compute.serverless) {
+        (EndpointType::Server, Some(server), _) => (
+            server_monthly_cost(server),
+            format!("{} x {}", server.initial_instance_count, server.instance_type),
+        ),
+        (EndpointType::Serverless, _, Some(serverless)) => (
+            Some(serverless_monthly_cost(serverless)),
+            format!("{} provisioned concurrency", serverless.provisioned_concurrency),
+        ),
+        _ => (None, format!("this {} endpoint", endpoint_type)),
+    }
+}
+
+// Refuse a deploy whose estimated monthly cost is over max_monthly_cost, unless forced
+pub fn check_budget(estimate: Option<f64>, max_monthly_cost: f64, what: &str, force: bool) -> Result<()> {
+    let over = match estimate {
+        Some(cost) if cost > max_monthly_cost => format!(
+            "{} costs about ${:.0} a month, over your max_monthly_cost of ${:.0}",
+            what, cost, max_monthly_cost
+        ),
+        Some(_) => return Ok(()),
+        None => format!("Sageturner doesn't know what {} costs, so can't check it against max_monthly_cost", what),
+    };
+    if force {
+        warn!("{}. Deploying anyway, --force is set", over);
+        return Ok(());
+    }
+    Err(anyhow!("{}. Pass --force to deploy anyway", over))
+}
@@ -0,0 +1,97 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Value};
+
+use crate::{aws, instances::InstanceKind, EndpointType};
+
+// Dashboards are named after the endpoint, so redeploying updates the same one
+pub fn dashboard_name(endpoint_name: &str) -> String {
+    format!("sageturner-{}", endpoint_name)
+}
+
+// Create (or replace) a CloudWatch dashboard for the endpoint and return its console URL. Invocations, latency and
+// errors come from AWS/SageMaker. Server endpoints add their instances' utilisation, GPUs' too if they have them
+pub async fn put_endpoint_dashboard(
+    endpoint_name: &str,
+    endpoint_type: EndpointType,
+    instance_kind: Option<InstanceKind>,
+    region: &str,
+    cloudwatch_client: &aws_sdk_cloudwatch::Client,
+) -> Result<String> {
+    let name = dashboard_name(endpoint_name);
+    let dims = |namespace: &str, metric: &str| {
+        json!([namespace, metric, "EndpointName", endpoint_name, "VariantName", aws::VARIANT_NAME])
+    };
+    let mut widgets = vec![
+        widget(
+            "Invocations",
+            vec![dims("AWS/SageMaker", "Invocations")],
+            "Sum",
+            region,
+        ),
+        // ModelLatency is in microseconds
+        widget(
+            "Model latency (microseconds)",
+            ["p50", "p90", "p99"]
+                .iter()
+                .map(|stat| json!(["AWS/SageMaker", "ModelLatency", "EndpointName", endpoint_name, "VariantName", aws::VARIANT_NAME, { "stat": stat, "label": stat }]))
+                .collect(),
+            "p50",
+            region,
+        ),
+        widget(
+            "Errors",
+            vec![
+                dims("AWS/SageMaker", "Invocation4XXErrors"),
+                dims("AWS/SageMaker", "Invocation5XXErrors"),
+                dims("AWS/SageMaker", "InvocationModelErrors"),
+            ],
+            "Sum",
+            region,
+        ),
+    ];
+    if endpoint_type == EndpointType::Server {
+        let mut utilisation = vec![
+            dims("/aws/sagemaker/Endpoints", "CPUUtilization"),
+            dims("/aws/sagemaker/Endpoints", "MemoryUtilization"),
+            dims("/aws/sagemaker/Endpoints", "DiskUtilization"),
+        ];
+        if instance_kind == Some(InstanceKind::Gpu) {
+            utilisation.push(dims("/aws/sagemaker/Endpoints", "GPUUtilization"));
+            utilisation.push(dims("/aws/sagemaker/Endpoints", "GPUMemoryUtilization"));
+        }
+        widgets.push(widget("Instance utilisation (%)", utilisation, "Average", region));
+    }
+    // Two widgets to a row
+    for (i, w) in widgets.iter_mut().enumerate() {
+        w["x"] = json!((i % 2) * 12);
+        w["y"] = json!((i / 2) * 6);
+    }
+
+    cloudwatch_client
+        .put_dashboard()
+        .dashboard_name(&name)
+        .dashboard_body(json!({ "widgets": widgets }).to_string())
+        .send()
+        .await
+        .map_err(|e| anyhow!("{}", aws_sdk_cloudwatch::error::DisplayErrorContext(&e)))?;
+    Ok(format!(
+        "https://{}.console.aws.amazon.com/cloudwatch/home?region={}#dashboards:name={}",
+        region, region, name
+    ))
+}
+
+fn widget(title: &str, metrics: Vec<Value>, stat: &str, region: &str) -> Value {
+    json!({
+        "type": "metric",
+        "width": 12,
+        "height": 6,
+        "properties": {
+            "title": title,
+            "metrics": metrics,
+            "stat": stat,
+            "period": 60,
+            "region": region,
+            "view": "timeSeries"
+        }
+    })
+}
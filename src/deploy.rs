@@ -0,0 +1,1559 @@
+use std::{
+    collections::HashMap,
+    io::Write,
+    future::Future,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+use aws_sdk_sagemaker::types::Tag;
+use bollard::Docker;
+use chrono::Utc;
+use indicatif::HumanDuration;
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    artefact, aws, canary, cost, dashboard, diff, docker, errors, git, hooks::{self, Hook}, instances, model_config::{self, Hardware}, monitor, notify, output,
+    promote, prompt, provenance, registry, retry, smoke, state, templates, ContainerMode, EndpointType, Stage, DEFAULT_BUCKET_NAME,
+    DEFAULT_ROLE_NAME,
+};
+
+// SageMaker sends requests to containers on port 8080
+const SERVE_PORT: u16 = 8080;
+
+// One model to deploy: the config passed to deploy, or one of the models in a workspace
+pub struct DeployTarget {
+    pub config_path: String,
+    pub endpoint_type: EndpointType,
+    pub source: ModelSource,
+    pub model_config: model_config::ModelConfig,
+}
+
+impl DeployTarget {
+    pub fn resolved(&self) -> output::ResolvedConfig {
+        let (container_mode, model_package, promoted_from) = match &self.source {
+            ModelSource::Build(mode) => (Some(mode.to_string()), None, None),
+            ModelSource::Package(arn) => (None, Some(arn.clone()), None),
+            ModelSource::Promote { from, .. } => (None, None, Some(from.clone())),
+        };
+        output::ResolvedConfig {
+            model: self.model_config.name.clone(),
+            config_path: self.config_path.clone(),
+            environment: self.model_config.environment.clone(),
+            endpoint_type: self.endpoint_type.to_string(),
+            container_mode,
+            model_package,
+            promoted_from,
+            config_hash: self.model_config.config_hash.clone(),
+            config: self.model_config.resolved.clone(),
+        }
+    }
+}
+
+// Where the deploy's SageMaker model comes from
+pub enum ModelSource {
+    // Built (or pulled) in this container mode, with the config's artefact
+    Build(ContainerMode),
+    // A model package in the Model Registry, by ARN, which has its image and model data already
+    Package(String),
+    // The model another environment's endpoint is running
+    Promote {
+        from: String,
+        endpoint: String,
+        // The container environment variables the environment's config sets, which aren't carried over
+        environment: Vec<String>,
+    },
+}
+
+// How a deploy behaves, from deploy's flags on the CLI. Code deploying without a terminal sets yes, as there's
+// nobody to ask
+pub struct DeployOptions {
+    // Skip the confirmation prompt
+    pub yes: bool,
+    // Wait for the endpoint to go into service
+    pub wait: bool,
+    // Deploy even if it's over budget
+    pub force: bool,
+    // Deploy without an approved model package
+    pub skip_approval: bool,
+    // Delete what a failed deploy created without asking
+    pub clean_on_failure: bool,
+    // Push the image an earlier build left in Docker, without building it again
+    pub skip_build: bool,
+    // Use the image already in ECR with the model's tag, without building or pushing
+    pub skip_push: bool,
+    // Use the artefact the model's last deploy uploaded, from the state
+    pub skip_upload: bool,
+    // Carry on from where the model's last deploy failed, if it did
+    pub resume: bool,
+    // Run only the stages from..=until. Stages before from use what earlier deploys left, those after until don't run
+    pub from: Option<Stage>,
+    pub until: Option<Stage>,
+    // Where the deploy plan and summary go
+    pub output: output::OutputFormat,
+}
+
+impl DeployOptions {
+    pub fn runs(&self, stage: Stage) -> bool {
+        stage.between(self.from, self.until)
+    }
+
+    // Whether the deploy uses an image or artefact from an earlier one, rather than building and uploading its own
+    fn reuses_earlier(&self) -> bool {
+        self.skip_build || self.skip_push || self.skip_upload || self.from.is_some_and(|from| from > Stage::Build)
+    }
+
+    // Whether --until stops the deploy before the stage
+    fn stops_before(&self, stage: Stage) -> bool {
+        self.until.is_some_and(|until| until < stage)
+    }
+}
+
+// A model's image, built by Docker and tagged <name>:latest on this machine
+#[derive(Debug, Clone)]
+pub struct BuiltImage {
+    pub name: String,
+}
+
+// A model's image in ECR, for its SageMaker model to run
+#[derive(Debug, Clone)]
+pub struct PushedImage {
+    pub uri: String,
+}
+
+// Deploys models to the region its AWS clients are for. Docker's only needed to build images, so a deployer for TGI,
+// Triton, model package and promoted deploys can go without it
+pub struct Deployer {
+    aws: aws::AwsClients,
+    docker: Option<Docker>,
+}
+
+// Run one phase of a deploy in its own span, timing it
+macro_rules! phase {
+    ($deploy:expr, $name:literal, $phase:expr) => {
+        $deploy.time($name, $phase.instrument(info_span!($name)))
+    };
+}
+
+impl Deployer {
+    pub fn new(aws: aws::AwsClients, docker: Option<Docker>) -> Deployer {
+        Deployer { aws, docker }
+    }
+
+    // A deployer for the account and region the settings pick, connected to Docker
+    pub async fn connect(settings: aws::AwsSettings) -> Result<Deployer> {
+        let aws = aws::AwsClients::load(settings).await?;
+        let docker = docker::get_client().await?;
+        Ok(Deployer::new(aws, Some(docker)))
+    }
+
+    pub fn aws(&self) -> &aws::AwsClients {
+        &self.aws
+    }
+
+    fn docker(&self) -> Result<&Docker> {
+        self.docker.as_ref().ok_or_else(|| anyhow!("Building an image needs Docker, and this deployer isn't connected to it"))
+    }
+
+    // Build the model's image: the Dockerfile it provides, one generated for it, or vLLM's. TGI and Triton use
+    // AWS's images, so have nothing to build
+    pub async fn build(
+        &self,
+        model_config: &model_config::ModelConfig,
+        container_mode: ContainerMode,
+        config_dir: &Path,
+        labels: &HashMap<String, String>,
+    ) -> Result<BuiltImage> {
+        within("build", model_config.timeouts.build(), self.build_image(model_config, container_mode, config_dir, labels)).await
+    }
+
+    async fn build_image(
+        &self,
+        model_config: &model_config::ModelConfig,
+        container_mode: ContainerMode,
+        config_dir: &Path,
+        labels: &HashMap<String, String>,
+    ) -> Result<BuiltImage> {
+        let docker_client = self.docker()?;
+        match container_mode {
+            ContainerMode::Provide => {
+                let docker_dir = &model_config
+                    .container
+                    .provide_container
+                    .as_ref()
+                    .ok_or_else(|| {
+                        anyhow!("Something went wrong with our validation. Raise an issue.")
+                    })?
+                    .docker_dir;
+                docker::build_image_byo(Path::new(&docker_dir), docker_client, &model_config.name, config_dir, labels).await?;
+            }
+            ContainerMode::Generate => {
+                let generate = model_config
+                    .container
+                    .generate_container
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?;
+                let hub_artefact = model_config.artefact.as_deref().and_then(artefact::hf_model_id).is_some();
+                let model_code = generate
+                    .preset
+                    .map(|p| p.model_code(&generate.preset_options))
+                    .transpose()?;
+
+                // Preset packages go first so the user's python_packages can override a pin
+                let mut python_packages = generate
+                    .preset
+                    .map(|p| p.python_packages(generate.hardware()))
+                    .unwrap_or_default();
+                if hub_artefact {
+                    // serve.py downloads hf:// artefacts with huggingface_hub
+                    python_packages.push("huggingface_hub".to_string());
+                }
+                python_packages.extend(generate.python_packages.clone().unwrap_or_default());
+                let system_packages = generate.system_packages.clone().unwrap_or_default();
+                info!(
+                    "Building dynamically generated image, with \nPython packages: {} \nsystem packages {}\nand your serve code",
+                    python_packages.join(" "),
+                    system_packages.join(" ")
+                );
+                if generate.hardware() == Hardware::Neuron {
+                    info!("Building on the Neuron SDK base image, which comes with its own python version");
+                }
+                let dockerfile = templates::dockerfile(
+                    &templates::DockerfileContext {
+                        hardware: generate.hardware(),
+                        python_version: &generate.python_version,
+                        system_packages: &system_packages,
+                        python_packages: &python_packages,
+                    },
+                    generate.dockerfile_template.as_deref().map(|t| config_dir.join(t)).as_deref(),
+                )?;
+                let serve_code = templates::serve_code(
+                    &templates::ServeContext { port: SERVE_PORT },
+                    generate.serve_template.as_deref().map(|t| config_dir.join(t)).as_deref(),
+                )?;
+                docker::build_image_ez_mode(
+                    &dockerfile,
+                    &serve_code,
+                    &model_config.name,
+                    docker_client,
+                    generate.code_dir.as_deref(),
+                    &generate.exclude,
+                    model_code.as_deref(),
+                    config_dir,
+                    labels,
+                )
+                .await?;
+            }
+            ContainerMode::Vllm => {
+                let vllm = model_config
+                    .container
+                    .vllm
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?;
+                let dockerfile = templates::vllm_dockerfile(
+                    &templates::VllmContext {
+                        model_id: vllm.model_id.as_deref().unwrap_or("/opt/ml/model"),
+                        tensor_parallel_size: vllm.tensor_parallel_size,
+                        max_model_len: vllm.max_model_len,
+                    },
+                    vllm.dockerfile_template.as_deref().map(|t| config_dir.join(t)).as_deref(),
+                )?;
+                docker::build_image_vllm(vllm, &dockerfile, &model_config.name, docker_client, labels).await?;
+            }
+            ContainerMode::Tgi | ContainerMode::Triton => {
+                return Err(anyhow!("{} deploys use AWS's image, so there's nothing to build", container_mode));
+            }
+        }
+
+        Ok(BuiltImage { name: model_config.name.clone() })
+    }
+
+    // The image an earlier build left in Docker, for deploys that only push
+    pub async fn built(&self, name: &str) -> Result<BuiltImage> {
+        self.docker()?
+            .inspect_image(name)
+            .await
+            .map_err(|e| errors::docker(e, format!("There's no image {} from an earlier build to push, drop --skip-build or start --from build", name)))?;
+        Ok(BuiltImage { name: name.to_string() })
+    }
+
+    // Push a built image to its ECR repository, creating the repository if need be, tagged with the model's version
+    pub async fn push(&self, image: &BuiltImage, model_config: &model_config::ModelConfig) -> Result<PushedImage> {
+        let repository = docker::ecr_repository(&self.aws.ecr, &image.name).await?;
+        self.push_to(image, &repository, model_config).await
+    }
+
+    async fn push_to(&self, image: &BuiltImage, repository: &str, model_config: &model_config::ModelConfig) -> Result<PushedImage> {
+        let image_tag = image_tag(model_config);
+        let docker_client = self.docker()?;
+        let doing = format!("Pushing {}:{}", image.name, image_tag);
+        let push = retry::retry(doing, retry::ATTEMPTS, || {
+            docker::push_image(docker_client, &self.aws.ecr, repository, &image.name, &image_tag)
+        });
+        within("push", model_config.timeouts.push(), push).await?;
+        Ok(PushedImage { uri: format!("{repository}:{image_tag}") })
+    }
+
+    // Put the model's artefact in S3 for SageMaker, packaging up directories first, and return where it is.
+    // Artefacts already in S3 are checked rather than uploaded, and Hub models, which the container downloads, and
+    // models without an artefact have no model data
+    pub async fn upload_artefact(
+        &self,
+        model_config: &model_config::ModelConfig,
+        container_mode: ContainerMode,
+        config_dir: &Path,
+        deploy_timestamp: &str,
+    ) -> Result<Option<aws::ModelData>> {
+        let region = self.aws.region.as_str();
+        let s3_client = &self.aws.s3;
+        let (bucket_name, _) = bucket_and_role(model_config, region);
+        // Directory artefacts, and Triton's model repository, are packaged up into a model.tar.gz,
+        // unless they're going up uncompressed. The temp dir has to outlive the upload
+        let package_dir = tempfile::tempdir()?;
+        let uncompressed = model_config.uncompressed_artefact;
+        let excludes = artefact::excludes(&model_config.exclude)?;
+        let package = |dir: PathBuf| async {
+            let packaged = artefact::package_dir_blocking(dir, package_dir.path().to_path_buf(), excludes.clone()).await?;
+            Ok::<_, anyhow::Error>(Some(packaged.to_string_lossy().to_string()))
+        };
+        let artefact = match (&container_mode, &model_config.container.triton) {
+            (ContainerMode::Triton, Some(triton)) if uncompressed => Some(triton.model_repository.clone()),
+            (ContainerMode::Triton, Some(triton)) => package(config_dir.join(&triton.model_repository)).await?,
+            _ => match &model_config.artefact {
+                Some(a) if config_dir.join(a).is_dir() && !uncompressed => package(config_dir.join(a)).await?,
+                a => a.clone(),
+            },
+        };
+
+        // SageMaker can only read model data from a bucket in its own region
+        let uploading = artefact.as_ref().is_some_and(|a| artefact::hf_model_id(a).is_none() && !a.starts_with("s3://"));
+        let accelerated_client;
+        let s3_client = if uploading {
+            aws::check_bucket_region(&bucket_name, region, s3_client).await?;
+            if model_config.upload.transfer_acceleration {
+                accelerated_client = aws::accelerated_s3_client(&bucket_name, s3_client).await?;
+                &accelerated_client
+            } else {
+                s3_client
+            }
+        } else {
+            s3_client
+        };
+
+        // setup's role can only read the bucket it was set up with, and any given with --artefact-bucket
+        if let Some((bucket, _)) = artefact.as_deref().and_then(artefact::s3_location).filter(|(b, _)| *b != bucket_name) {
+            info!(
+                "The artefact is in bucket {}, not {}. If SageMaker can't read it, run sageturner setup with --artefact-bucket {}",
+                bucket, bucket_name, bucket
+            );
+        }
+
+        // Upload a model artefact if we have it. Hub models are downloaded by the container instead
+        let model_data = match artefact {
+            Some(a) if artefact::hf_model_id(&a).is_some() => None,
+            Some(a) if a.starts_with("s3://") && uncompressed => {
+                aws::check_s3_prefix(&a, s3_client).await?;
+                info!("Using uncompressed artefact already in S3: {}", a);
+                Some(aws::ModelData::Uncompressed(a))
+            }
+            Some(a) if a.starts_with("s3://") => {
+                aws::check_s3_artefact(&a, s3_client).await?;
+                info!("Using artefact already in S3: {}", a);
+                Some(aws::ModelData::Compressed(a))
+            }
+            Some(a) if uncompressed => {
+                let s3_prefix = artefact_key(model_config, "model", deploy_timestamp, config_dir)?;
+                let s3_prefix = format!("{}/", s3_prefix.trim_end_matches('/'));
+                let size = artefact::dir_size(&config_dir.join(&a), &excludes)?;
+                let upload = aws::upload_dir(&a, &bucket_name, &s3_prefix, s3_client, config_dir, &model_config.upload, &excludes);
+                let s3_path = within("upload", model_config.timeouts.upload(size), upload).await?;
+                info!("S3 PREFIX PASSED AS uncompressed model data: {}", s3_path);
+                Some(aws::ModelData::Uncompressed(s3_path))
+            }
+            Some(a) => {
+                let path = Path::new(&a);
+                let a_name = path.file_name().ok_or_else(|| anyhow!("Couldn't extract filename from artefact path"))?;
+                let s3_key = artefact_key(model_config, &a_name.to_string_lossy(), deploy_timestamp, config_dir)?;
+                let size = std::fs::metadata(config_dir.join(&a)).map_err(|e| anyhow!("Couldn't read artefact {}: {}", a, e))?.len();
+                let timeouts = &model_config.timeouts;
+                let upload = aws::upload_artefact(&a, &bucket_name, &s3_key, s3_client, config_dir, &model_config.upload, timeouts.waiter());
+                let s3_path = within("upload", timeouts.upload(size), upload).await?;
+                info!("S3 PATH PASSED AS model_data_url: {}", s3_path);
+                Some(aws::ModelData::Compressed(s3_path))
+            }
+            // No artefact to put on S3
+            None => None,
+        };
+        Ok(model_data)
+    }
+
+    // The artefact the model's last deploy in the state used, for deploys that don't upload it again. It's checked
+    // it's still in S3, in case retention or a lifecycle rule has deleted it since
+    pub async fn uploaded_artefact(&self, model_config: &model_config::ModelConfig) -> Result<aws::ModelData> {
+        let state_backend = state::Backend::new(model_config.state_backend.as_ref(), &model_config.config_dir);
+        let state = state_backend.load(&self.aws).await?;
+        let artefact = state
+            .deploys
+            .iter()
+            .rev()
+            .filter(|d| d.model == model_config.name && d.environment == model_config.environment && d.region == self.aws.region)
+            .find_map(|d| d.artefact.clone())
+            .ok_or_else(|| {
+                anyhow!("No earlier deploy of {} in {} has an artefact to reuse, drop --skip-upload or start --from upload", model_config.name, state_backend)
+            })?;
+        info!("Using artefact {} from an earlier deploy, without uploading", artefact);
+        self.still_in_s3(artefact, model_config.uncompressed_artefact).await
+    }
+
+    // An artefact an earlier deploy uploaded, as model data, once it's checked it's still there
+    async fn still_in_s3(&self, artefact: String, uncompressed: bool) -> Result<aws::ModelData> {
+        match uncompressed {
+            true => {
+                aws::check_s3_prefix(&artefact, &self.aws.s3).await?;
+                Ok(aws::ModelData::Uncompressed(artefact))
+            }
+            false => {
+                aws::check_s3_artefact(&artefact, &self.aws.s3).await?;
+                Ok(aws::ModelData::Compressed(artefact))
+            }
+        }
+    }
+
+    // Deploy one model, returning what the deploy did
+    pub async fn deploy(&self, target: DeployTarget, options: &DeployOptions) -> Result<output::DeployResult> {
+        let mut deploy = output::DeployResult::new(&target.model_config.name, &self.aws.region);
+        self.timed_deploy(target, options, &mut deploy).await?;
+        Ok(deploy)
+    }
+
+    // Deploy the model, or every model in a workspace, to the region the clients are for, adding what each deploy did
+    // to deploys
+    pub async fn deploy_all(
+        &self,
+        mut targets: Vec<DeployTarget>,
+        options: &DeployOptions,
+        deploys: &mut Vec<output::DeployResult>,
+    ) -> Result<()> {
+        if targets.len() == 1 {
+            return self.deploy_one(targets.remove(0), options, deploys).await;
+        }
+        // A workspace: deploy every model, even if one fails, then report how they all went
+        let total = targets.len();
+        let mut results = Vec::new();
+        for target in targets {
+            let name = target.model_config.name.clone();
+            let result = self.deploy_one(target, options, deploys).await;
+            if let Err(e) = &result {
+                error!("Deploying {} failed: {}", name, e);
+            }
+            results.push((name, result));
+        }
+        let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+        let mut out = output::human(options.output);
+        writeln!(out, "Deployed {} of {} models:", total - failed, total)?;
+        for (name, result) in &results {
+            match result {
+                Ok(_) => writeln!(out, "  {:<32} deployed", name)?,
+                Err(e) => writeln!(out, "  {:<32} failed: {}", name, e)?,
+            }
+        }
+        if failed > 0 {
+            let message = format!("{} of {} models failed to deploy", failed, total);
+            return Err(errors::summarize(message, results.iter().filter_map(|(_, r)| r.as_ref().err())));
+        }
+        Ok(())
+    }
+
+    // Deploy one model, adding what it did to deploys whether it worked or not
+    async fn deploy_one(&self, target: DeployTarget, options: &DeployOptions, deploys: &mut Vec<output::DeployResult>) -> Result<()> {
+        let mut deploy = output::DeployResult::new(&target.model_config.name, &self.aws.region);
+        let result = self.timed_deploy(target, options, &mut deploy).await;
+        deploys.push(deploy);
+        result
+    }
+
+    // Deploy one model, timing it
+    async fn timed_deploy(&self, target: DeployTarget, options: &DeployOptions, deploy: &mut output::DeployResult) -> Result<()> {
+        let span = info_span!("deploy", model = %target.model_config.name, region = %self.aws.region);
+        let started = std::time::Instant::now();
+        let result = self.process_deploy(target, options, deploy).instrument(span).await;
+        deploy.duration_seconds = started.elapsed().as_secs_f64();
+        deploy.error = result.as_ref().err().map(|e| e.to_string());
+        result
+    }
+
+    async fn process_deploy(&self, target: DeployTarget, options: &DeployOptions, deploy: &mut output::DeployResult) -> Result<()> {
+        let aws = &self.aws;
+        let DeployTarget { config_path, endpoint_type, source, mut model_config } = target;
+        let region = aws.region.as_str();
+        let sage_client = &aws.sage;
+        let container_mode = match &source {
+            ModelSource::Build(container_mode) => {
+                info!(
+                    "Deploying model with config at {} to {} endpoint, {} container mode",
+                    &config_path, &endpoint_type, container_mode
+                );
+                Some(*container_mode)
+            }
+            ModelSource::Package(arn) => {
+                info!("Deploying model package {} with config at {} to {} endpoint", arn, &config_path, &endpoint_type);
+                None
+            }
+            ModelSource::Promote { from, endpoint, .. } => {
+                info!("Promoting the model endpoint {} ({}) is running, with config at {} to {} endpoint", endpoint, from, &config_path, &endpoint_type);
+                None
+            }
+        };
+
+        if let (Some(from), Some(until)) = (options.from, options.until) {
+            if from > until {
+                return Err(anyhow!("--from {} comes after --until {}, so there's nothing to deploy", from, until));
+            }
+        }
+        let config_dir = Path::new(&config_path).parent().expect("Your config path didn't point to a YAML file");
+        let deploy_timestamp = Utc::now().format("%d%m%Y%H%M").to_string();
+
+        if container_mode == Some(ContainerMode::Generate) {
+            model_config::detect_preset(&mut model_config, config_dir)?;
+        }
+        model_config::validate_config(
+            &model_config,
+            &endpoint_type,
+            container_mode.as_ref(),
+            config_dir
+        )?;
+        if model_config.canary.is_some() && !options.wait {
+            return Err(anyhow!("{} has a canary, which needs the deploy to wait for the endpoint, so can't be deployed with --no-wait", model_config.name));
+        }
+        // With require_approval, only approved model packages update the endpoint. A build registers its model for
+        // approval, then stops
+        let gated = model_config.require_approval && matches!(source, ModelSource::Build(_)) && !options.skip_approval;
+        match &source {
+            ModelSource::Package(arn) => registry::check_package(arn, region, options.skip_approval, sage_client).await?,
+            ModelSource::Promote { .. } if model_config.require_approval && !options.skip_approval => {
+                return Err(anyhow!(
+                    "{} has require_approval, so can only be deployed from an approved model package with --model-package, or with --skip-approval",
+                    model_config.name
+                ));
+            }
+            ModelSource::Promote { .. } => {}
+            ModelSource::Build(_) if gated && model_config.registry.is_none() => {
+                return Err(anyhow!(
+                    "{} has require_approval, so can only be deployed from an approved model package with --model-package, or with --skip-approval",
+                    model_config.name
+                ));
+            }
+            ModelSource::Build(_) if model_config.require_approval && options.skip_approval => {
+                warn!("Deploying without an approved model package, --skip-approval is set");
+            }
+            ModelSource::Build(_) => {}
+        }
+
+        // The budget and quota guard what the endpoint stage creates, so they're checked whenever it runs, wherever
+        // the deploy starts from
+        let checks_endpoint = options.runs(Stage::Validate) || options.runs(Stage::Endpoint);
+        let (monthly_cost, cost_of) = cost::estimate(&model_config, endpoint_type);
+        if let Some(budget) = model_config.max_monthly_cost.filter(|_| checks_endpoint) {
+            cost::check_budget(monthly_cost, budget, &cost_of, options.force)?;
+        }
+        // Resources for this deploy are named after the version, or the time if the model isn't versioned. A deploy of
+        // a version that reuses an image or artefact, usually to change other settings, is named after both, so it
+        // doesn't clash with the version's earlier deploy
+        let deploy_id = match &model_config.version {
+            Some(v) if options.reuses_earlier() => format!("{}-{}", aws::version_slug(v), deploy_timestamp),
+            Some(v) => aws::version_slug(v),
+            None => deploy_timestamp.clone(),
+        };
+        // Environments usually share an account, so their resources need different names. The endpoint keeps its
+        // name from deploy to deploy, the model and endpoint config behind it are named for this deploy
+        let endpoint_name = aws::endpoint_name(&model_config.name, model_config.environment.as_deref());
+        let provenance = provenance::Provenance::collect(config_dir, &model_config.config_hash, &aws.sts).await?;
+        let state_backend = state::Backend::new(model_config.state_backend.as_ref(), config_dir);
+        // A resumed deploy keeps the name of the one that failed, so it finds the model and endpoint config that got
+        // as far as being created
+        let resumed = match options.resume && matches!(source, ModelSource::Build(_)) {
+            true => resumable(&model_config, &provenance, &state_backend, aws).await?,
+            false => None,
+        };
+        // Starting after the model stage deploys the model the last deploy created
+        let existing_model = match options.from.is_some_and(|from| from > Stage::Model) {
+            true => Some(last_model(&model_config, &state_backend, aws).await?),
+            false => None,
+        };
+        let sagemaker_name = match (&resumed, existing_model) {
+            (Some(failed), _) => failed.sagemaker_model.clone(),
+            (None, Some(existing)) => existing,
+            (None, None) => format!("{}-{}", endpoint_name, deploy_id),
+        };
+        // The endpoint config shares the model's name, except that starting after the model stage makes a new one
+        // for the existing model, so changed compute settings aren't lost. Starting after the endpoint stage, it's
+        // whichever the endpoint's been updated to
+        let endpoint_config_name = match options.from {
+            Some(from) if from > Stage::Endpoint => aws::current_endpoint_config(&endpoint_name, sage_client)
+                .await?
+                .ok_or_else(|| anyhow!("There's no endpoint {} in {} to start --from {}", endpoint_name, region, from))?,
+            Some(from) if from > Stage::Model => format!("{}-{}", endpoint_name, deploy_timestamp),
+            _ => sagemaker_name.clone(),
+        };
+        deploy.endpoint = Some(endpoint_name.clone());
+        deploy.sagemaker_model = Some(sagemaker_name.clone());
+        let mut tags = aws::sageturner_tags(
+            &model_config.name,
+            model_config.version.as_deref(),
+            model_config.environment.as_deref(),
+            &provenance,
+            &model_config.tags,
+        );
+        if let ModelSource::Package(arn) = &source {
+            tags.push(Tag::builder().key(registry::MODEL_PACKAGE_TAG).value(arn).build());
+        }
+        // The exact model being promoted, found before asking, so the plan shows it
+        let promotion = match &source {
+            ModelSource::Promote { endpoint, .. } => {
+                let promotion = promote::find(endpoint, aws).await?;
+                tags.push(Tag::builder().key(promote::PROMOTED_FROM_TAG).value(&promotion.model).build());
+                Some(promotion)
+            }
+            _ => None,
+        };
+        let labels = provenance.labels();
+        let mut hook_environment = hooks::environment(&model_config, &endpoint_name, &sagemaker_name, region);
+
+        // A model with this name already exists if this exact deploy has been run before, which is fine to carry on from
+        let reuse_model = match options.runs(Stage::Model) {
+            true => aws::check_existing_model(&sagemaker_name, model_config.version.as_deref(), &provenance, resumed.is_some(), sage_client).await?,
+            false => false,
+        };
+        let server = model_config.compute.server.as_ref().filter(|_| checks_endpoint);
+        if let (EndpointType::Server, Some(server)) = (endpoint_type, server) {
+            aws::check_endpoint_quota(
+                &server.instance_type,
+                server.initial_instance_count,
+                region,
+                sage_client,
+                &aws.quotas,
+            )
+            .await?;
+        }
+        if options.until == Some(Stage::Validate) {
+            writeln!(output::human(options.output), "{} is ready to deploy, stopping after the validate stage as --until is set", model_config.name)?;
+            deploy.status = state::DeployStatus::Partial;
+            return Ok(());
+        }
+        if !options.yes {
+            print_deploy_plan(&model_config, &endpoint_name, &sagemaker_name, &endpoint_config_name, endpoint_type, &source, promotion.as_ref(), region, &provenance);
+            // An endpoint that's already running gets what's changing, before against after
+            if let Some(running) = diff::current(&endpoint_name, aws).await? {
+                let image = match (&source, &promotion) {
+                    (ModelSource::Package(arn), _) => diff::PlannedImage::Package(arn.clone()),
+                    (_, Some(promotion)) => diff::PlannedImage::Existing(promotion.image.clone()),
+                    _ => diff::PlannedImage::Built,
+                };
+                let planned = diff::planned(&model_config, endpoint_type, &image);
+                diff::print(&endpoint_name, &running, &planned, &image, &model_config);
+            }
+            if gated {
+                info!("  require_approval is set: the model is registered for approval, and the endpoint isn't changed");
+            }
+            let question = format!("Endpoints cost money while they run. Create these resources in account {}, {}?", provenance.account, region);
+            if !prompt::confirm(&question)? {
+                return Err(anyhow!("Deploy of {} cancelled, nothing was created", model_config.name));
+            }
+        }
+
+        // Nobody else can deploy the endpoint until this deploy's finished
+        let lock = state_backend.lock(&endpoint_name, region, &provenance.deployed_by, &sagemaker_name, aws).await?;
+
+        let started = std::time::Instant::now();
+        let event = |kind, reason| {
+            let mut event = notify::DeployEvent::new(kind, &model_config, &endpoint_name, &sagemaker_name, region, reason);
+            if kind != notify::DeployEventKind::DeployStarted {
+                event.duration_seconds = Some(started.elapsed().as_secs());
+                event.estimated_monthly_cost = monthly_cost.map(|c| c.round());
+            }
+            event
+        };
+        let notifications = model_config.notifications.as_ref();
+        notify::send(notifications, &event(notify::DeployEventKind::DeployStarted, None), aws).await;
+        let mut record = state::DeployRecord::new(&model_config, &endpoint_name, &sagemaker_name, region, &provenance);
+        if let ModelSource::Package(arn) = &source {
+            record.model_package = Some(arn.clone());
+        }
+        record.endpoint_config = Some(endpoint_config_name.clone()).filter(|c| c != &sagemaker_name);
+        // Only the image and artefact carry over. The model and endpoint config are looked for again, as they may
+        // have been cleaned up since
+        if let Some(failed) = &resumed {
+            record.image = failed.image.clone().filter(|_| failed.has_finished(Stage::Push));
+            record.artefact = failed.artefact.clone().filter(|_| failed.has_finished(Stage::Upload));
+            record.stages = failed.stages.iter().copied().filter(|s| matches!(s, Stage::Build | Stage::Push | Stage::Upload)).collect();
+        }
+        if options.runs(Stage::Validate) {
+            record.finished(Stage::Validate);
+        }
+        state::record(&state_backend, &record, aws).await;
+
+        let monitoring_s3_uri = model_config.monitoring.as_ref().map(|m| {
+            let (bucket_name, _) = bucket_and_role(&model_config, region);
+            monitor::monitoring_s3_uri(m, &bucket_name, &endpoint_name)
+        });
+        let mut dashboard_url = None;
+        let mut package_arn = None;
+        let mut created = aws::Created::default();
+        // Everything from here on can fail after the deploy has started, which is sent as an event too
+        let result = async {
+            // Build, push, upload and create the model, or whichever of them are being run
+            if [Stage::Build, Stage::Push, Stage::Upload, Stage::Model].into_iter().any(|stage| options.runs(stage)) {
+                phase!(deploy, "model", async {
+                    match &source {
+                        _ if reuse_model => {}
+                        // Only a build has stages before the model
+                        ModelSource::Package(_) | ModelSource::Promote { .. } if !options.runs(Stage::Model) => {}
+                        ModelSource::Package(arn) => {
+                            let (_, execution_role) = bucket_and_role(&model_config, region);
+                            let role_arn = aws::resolve_role_arn(&execution_role, &aws.iam).await?;
+                            registry::create_model_from_package(arn, &sagemaker_name, &role_arn, sage_client, &tags).await?;
+                            created.model = true;
+                        }
+                        ModelSource::Promote { environment: from_environment, .. } => {
+                            let promotion = promotion.as_ref().expect("found before the deploy started");
+                            let environment = resolve_environment(&model_config, aws).await?;
+                            let (_, execution_role) = bucket_and_role(&model_config, region);
+                            let role_arn = aws::resolve_role_arn(&execution_role, &aws.iam).await?;
+                            promote::create_model(promotion, &sagemaker_name, &role_arn, from_environment, environment, sage_client, &tags)
+                                .await?;
+                            created.model = true;
+                        }
+                        ModelSource::Build(container_mode) => {
+                            self.create_model(
+                                &model_config,
+                                *container_mode,
+                                config_dir,
+                                &sagemaker_name,
+                                &deploy_timestamp,
+                                &tags,
+                                &labels,
+                                &hook_environment,
+                                options,
+                                &mut record,
+                                &state_backend,
+                            )
+                            .await?;
+                            created.model = options.runs(Stage::Model);
+                            // Registered along with the model, so a deploy that's run again doesn't register it twice
+                            if let Some(registry) = model_config.registry.as_ref().filter(|_| options.runs(Stage::Model)) {
+                                let (bucket_name, _) = bucket_and_role(&model_config, region);
+                                package_arn = Some(registry::register_model(
+                                    &model_config,
+                                    registry,
+                                    &sagemaker_name,
+                                    &endpoint_name,
+                                    &bucket_name,
+                                    config_dir,
+                                    aws,
+                                    &tags,
+                                )
+                                .await?);
+                            }
+                        }
+                    }
+                    Ok::<(), anyhow::Error>(())
+                })
+                .await?;
+            }
+            if options.stops_before(Stage::Model) {
+                return Ok(());
+            }
+            // What the model runs, for the project's state
+            let (image, artefact) = aws::model_container(&sagemaker_name, sage_client).await?;
+            record.image_digest = match &image {
+                Some(image) => aws::image_digest(image, &aws.ecr).await,
+                None => None,
+            };
+            record.image = image;
+            record.artefact = artefact;
+            if options.runs(Stage::Model) {
+                record.finished(Stage::Model);
+            }
+            state::record(&state_backend, &record, aws).await;
+            if options.stops_before(Stage::Endpoint) {
+                return Ok(());
+            }
+            hook_environment.extend(record.image.clone().map(|image| ("IMAGE_URI", image)));
+            hook_environment.extend(record.artefact.clone().map(|artefact| ("S3_PATH", artefact)));
+            // What an update replaces, for a canary to run next to and failed smoke tests to go back to
+            let previous_config = aws::current_endpoint_config(&endpoint_name, sage_client).await?.filter(|c| c != &endpoint_config_name);
+            let endpoint_limit = model_config.timeouts.endpoint();
+            let canary_from = previous_config.as_ref().filter(|_| model_config.canary.is_some() && options.runs(Stage::Endpoint));
+            let mut change = None;
+            if options.runs(Stage::Endpoint) {
+                if gated {
+                    return Ok(());
+                }
+                hooks::run(Hook::PreDeploy, &model_config.hooks, &hook_environment, config_dir).await?;
+                phase!(deploy, "endpoint_config", async {
+                    if aws::endpoint_config_exists(&endpoint_config_name, sage_client).await? {
+                        info!("Endpoint config {} already exists, reusing it", endpoint_config_name);
+                    } else {
+                        match endpoint_type {
+                            EndpointType::Serverless => {
+                                let memory = model_config
+                                    .compute
+                                    .serverless
+                                    .as_ref()
+                                    .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
+                                    .memory;
+                                let max_concurrency = model_config
+                                    .compute
+                                    .serverless
+                                    .as_ref()
+                                    .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
+                                    .max_concurrency;
+                                let provisioned_concurrency = model_config
+                                    .compute
+                                    .serverless
+                                    .as_ref()
+                                    .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
+                                    .provisioned_concurrency;
+                                aws::create_serverless_endpoint_config(
+                                    &endpoint_config_name,
+                                    &sagemaker_name,
+                                    memory,
+                                    max_concurrency,
+                                    provisioned_concurrency,
+                                    sage_client,
+                                    &tags
+                                )
+                                .await?;
+                            }
+                            EndpointType::Server => {
+                                let instance_type = model_config
+                                    .compute
+                                    .server
+                                    .as_ref()
+                                    .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
+                                    .instance_type
+                                    .clone();
+                                let initial_instance_count = model_config
+                                    .compute
+                                    .server
+                                    .as_ref()
+                                    .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
+                                    .initial_instance_count;
+                                aws::create_server_endpoint_config(
+                                    &endpoint_config_name,
+                                    &sagemaker_name,
+                                    &instance_type,
+                                    initial_instance_count,
+                                    model_config.monitoring.as_ref().zip(monitoring_s3_uri.as_deref())
+                                        .map(|(m, uri)| monitor::data_capture_config(m, uri)),
+                                    sage_client,
+                                    &tags
+                                )
+                                .await?;
+                            }
+                        }
+                        created.endpoint_config = true;
+                    }
+                    Ok::<(), anyhow::Error>(())
+                })
+                .await?;
+                if let (Some(canary), Some(previous)) = (&model_config.canary, canary_from) {
+                    phase!(deploy, "canary", canary::run(canary, &endpoint_name, &sagemaker_name, previous, config_dir, aws, &tags, endpoint_limit)).await?;
+                }
+                let endpoint = aws::create_or_update_endpoint(&endpoint_name, &endpoint_config_name, sage_client, &tags);
+                change = Some(phase!(deploy, "endpoint", endpoint).await?);
+                created.endpoint = change == Some(aws::EndpointChange::Created);
+                record.finished(Stage::Endpoint);
+                state::record(&state_backend, &record, aws).await;
+                let expires_at = match model_config.ttl.as_deref().map(model_config::parse_ttl).transpose()? {
+                    Some(ttl) => Some(Utc::now().checked_add_signed(ttl).ok_or_else(|| anyhow!("Invalid sageturner config: ttl is too long"))?),
+                    None => None,
+                };
+                aws::set_endpoint_expiry(&endpoint_name, expires_at, sage_client).await?;
+                if let Some(days) = model_config.log_retention_days {
+                    aws::set_log_retention(&endpoint_name, days, &aws.logs).await?;
+                }
+                // A dashboard is nice to have, so one that can't be created doesn't fail the deploy
+                if model_config.dashboard {
+                    let instance_kind = model_config.compute.server.as_ref().and_then(|s| instances::lookup(&s.instance_type).ok());
+                    match dashboard::put_endpoint_dashboard(&endpoint_name, endpoint_type, instance_kind, region, &aws.cloudwatch).await {
+                        Ok(url) => dashboard_url = Some(url),
+                        Err(e) => warn!("Couldn't create the endpoint's dashboard: {}", e),
+                    }
+                }
+            }
+            if options.stops_before(Stage::Wait) {
+                return Ok(());
+            }
+            if options.wait && options.runs(Stage::Wait) {
+                phase!(deploy, "wait", async {
+                    let waited = aws::wait_for_endpoint(&endpoint_name, &endpoint_config_name, sage_client, &aws.logs, endpoint_limit).await;
+                    if let Some(previous) = canary_from {
+                        canary::finish(&endpoint_name, &sagemaker_name, previous, aws, endpoint_limit).await;
+                    }
+                    waited
+                })
+                .await?;
+                record.finished(Stage::Wait);
+            }
+            if options.stops_before(Stage::SmokeTest) {
+                return Ok(());
+            }
+            // The last stage: smoke tests, then monitoring and cleaning up, which need the deploy to have gone through
+            if options.runs(Stage::SmokeTest) {
+                phase!(deploy, "smoke_tests", async {
+                    if !model_config.smoke_tests.is_empty() {
+                        if options.wait {
+                            if let Err(e) = smoke::run(&model_config.smoke_tests, &endpoint_name, config_dir, &aws.runtime).await {
+                                match previous_config.as_ref().filter(|_| model_config.rollback_on_smoke_test_failure) {
+                                    Some(previous) => {
+                                        info!("Rolling endpoint {} back to {}", endpoint_name, previous);
+                                        aws::create_or_update_endpoint(&endpoint_name, previous, sage_client, &[]).await?;
+                                        aws::wait_for_endpoint(&endpoint_name, previous, sage_client, &aws.logs, endpoint_limit).await?;
+                                        return Err(anyhow!("{}, so rolled endpoint {} back to {}", e, endpoint_name, previous));
+                                    }
+                                    None => return Err(e),
+                                }
+                            }
+                        } else {
+                            info!("Not running smoke tests with --no-wait, as the endpoint isn't in service yet");
+                        }
+                    }
+                    Ok::<(), anyhow::Error>(())
+                })
+                .await?;
+                record.finished(Stage::SmokeTest);
+            }
+            // Schedules can only be created for an endpoint that's in service and capturing data
+            let monitoring = model_config.monitoring.as_ref().filter(|_| options.runs(Stage::SmokeTest));
+            if let Some((monitoring, s3_uri)) = monitoring.zip(monitoring_s3_uri.as_deref()) {
+                if options.wait {
+                    let (_, execution_role) = bucket_and_role(&model_config, region);
+                    let role_arn = aws::resolve_role_arn(&execution_role, &aws.iam).await?;
+                    let monitoring =
+                        monitor::set_up_monitoring(monitoring, &endpoint_name, &sagemaker_name, s3_uri, &role_arn, region, sage_client, &tags);
+                    phase!(deploy, "monitoring", monitoring).await?;
+                } else {
+                    info!("Not setting up monitoring with --no-wait, as the endpoint isn't in service yet. Data is still being captured to {}/capture", s3_uri);
+                }
+            }
+            // Only once the update has worked: a failed one rolls back onto the previous model
+            if let Some(keep) = model_config
+                .keep_previous
+                .filter(|_| change == Some(aws::EndpointChange::Updated) && options.runs(Stage::SmokeTest)) {
+                if options.wait {
+                    let cleanup = aws::delete_superseded(
+                        &endpoint_name,
+                        &endpoint_config_name,
+                        &model_config.name,
+                        model_config.environment.as_deref(),
+                        keep as usize,
+                        sage_client,
+                    );
+                    phase!(deploy, "cleanup", cleanup).await?;
+                } else {
+                    info!("Not cleaning up superseded models with --no-wait, as the update hasn't finished yet");
+                }
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+        .await;
+        if result.is_err() && created.any() {
+            record.cleaned_up = clean_up_failed_deploy(&endpoint_name, &sagemaker_name, &endpoint_config_name, &created, options, aws).await;
+        }
+        // A gated deploy stops before the endpoint on purpose, leaving its model to be approved
+        let awaiting_approval = gated && options.runs(Stage::Endpoint);
+        record.status = match &result {
+            Ok(_) if awaiting_approval => state::DeployStatus::AwaitingApproval,
+            Ok(_) if options.stops_before(Stage::SmokeTest) => state::DeployStatus::Partial,
+            Ok(_) if options.wait => state::DeployStatus::InService,
+            Ok(_) => state::DeployStatus::Submitted,
+            Err(_) => state::DeployStatus::Failed,
+        };
+        record.reason = result.as_ref().err().map(|e| e.to_string());
+        state::record(&state_backend, &record, aws).await;
+        if let Some(lock) = lock {
+            lock.release(aws).await;
+        }
+        match &result {
+            // Nothing's in service or submitted yet, so there's no more to say
+            Ok(_) if awaiting_approval || options.stops_before(Stage::SmokeTest) => {}
+            Ok(_) if options.wait => {
+                notify::send(notifications, &event(notify::DeployEventKind::EndpointInService, None), aws).await;
+            }
+            Ok(_) => notify::send(notifications, &event(notify::DeployEventKind::DeploySubmitted, None), aws).await,
+            Err(e) => {
+                let reason = Some(e.to_string());
+                notify::send(notifications, &event(notify::DeployEventKind::DeployFailed, reason), aws).await;
+            }
+        }
+        let deploy_package = match &package_arn {
+            Some(arn) => format!("Once it's approved, deploy it with --model-package {}", arn),
+            None => "Deploy its approved model package with --model-package".to_string(),
+        };
+        deploy.status = record.status;
+        deploy.image = record.image;
+        deploy.image_digest = record.image_digest;
+        deploy.artefact = record.artefact;
+        deploy.model_package = package_arn.or(record.model_package);
+        deploy.dashboard_url = dashboard_url.clone();
+        result?;
+        if awaiting_approval {
+            writeln!(
+                output::human(options.output),
+                "Not updating endpoint {}, require_approval is set. {}, or pass --skip-approval",
+                endpoint_name,
+                deploy_package
+            )?;
+            return Ok(());
+        }
+        if let Some(until) = options.until.filter(|_| options.stops_before(Stage::SmokeTest)) {
+            writeln!(output::human(options.output), "Sageturner stopped after the {} stage of {}, as --until is set", until, sagemaker_name)?;
+            return Ok(());
+        }
+        deploy.endpoint_arn = aws::endpoint_arn(&endpoint_name, sage_client).await;
+        // Too late to stop the deploy, so a failure here only fails the command
+        hooks::run(Hook::PostDeploy, &model_config.hooks, &hook_environment, config_dir)
+            .await
+            .map_err(|e| anyhow!("{}. Endpoint {} is running {} regardless", e, endpoint_name, sagemaker_name))?;
+        let mut out = output::human(options.output);
+        writeln!(out, "Sageturner done! Endpoint {} is running {}", endpoint_name, sagemaker_name)?;
+        if let Some(url) = dashboard_url {
+            writeln!(out, "Dashboard: {}", url)?;
+        }
+        Ok(())
+    }
+
+    // Build and push the image, upload the artefact and create the SageMaker model for this deploy
+    #[allow(clippy::too_many_arguments)]
+    async fn create_model(
+        &self,
+        model_config: &model_config::ModelConfig,
+        container_mode: ContainerMode,
+        config_dir: &Path,
+        sagemaker_name: &str,
+        deploy_timestamp: &str,
+        tags: &[Tag],
+        labels: &HashMap<String, String>,
+        hook_environment: &HashMap<&'static str, String>,
+        options: &DeployOptions,
+        record: &mut state::DeployRecord,
+        state_backend: &state::Backend,
+    ) -> Result<()> {
+        let aws = &self.aws;
+        let region = aws.region.as_str();
+
+        // Secrets in the container environment are looked up now, before anything is built, so they never sit
+        // in the config file or the image
+        let config_environment = resolve_environment(model_config, aws).await?;
+
+        let (bucket_name, execution_role) = bucket_and_role(model_config, region);
+        if let Some(o) = &model_config.overrides {
+            if o.bucket_name.is_some() {
+                info!("Overriding default bucket name with: {}", bucket_name);
+            }
+            if o.role_arn.is_some() {
+                info!("Overriding default role with: {}", execution_role);
+            }
+        }
+
+        let execution_role_arn = aws::resolve_role_arn(&execution_role, &aws.iam).await?;
+
+        // What a resumed deploy already did. Both the build and the upload record what they finish in the state as they
+        // go, so a deploy that fails or is stopped part way can be resumed from there
+        let built = record.has_finished(Stage::Build);
+        let pushed = record.image.clone().filter(|_| record.has_finished(Stage::Push));
+        let uploaded = record.artefact.clone().filter(|_| record.has_finished(Stage::Upload));
+        let progress = Progress { record: tokio::sync::Mutex::new(record), state_backend, aws };
+        // Which of the stages up to the model run. Those before --from use what's already there, as the skip flags do.
+        // --skip-push uses the image already in ECR, so there's nothing to build either
+        let build = options.runs(Stage::Build) && !options.skip_build && !options.skip_push;
+        let push = options.runs(Stage::Push) && !options.skip_push;
+        let upload = options.runs(Stage::Upload) && !options.skip_upload;
+        let makes_model = options.runs(Stage::Model);
+
+        // Before the upload as well as the build, as a pre_build hook might be what puts the artefact in place
+        if container_mode.builds_image() && build && !built && pushed.is_none() {
+            hooks::run(Hook::PreBuild, &model_config.hooks, hook_environment, config_dir).await?;
+        }
+
+        // Container environment variables, set on the SageMaker model
+        let mut environment = HashMap::new();
+        // The image is built and pushed while the artefact is packaged and uploaded, as for a big model each can take
+        // minutes
+        let image = async {
+            let uri = match (container_mode, pushed.as_deref()) {
+                (ContainerMode::Tgi, _) => {
+                    info!("Using the Hugging Face TGI image, nothing to build");
+                    let tgi = model_config
+                        .container
+                        .tgi
+                        .as_ref()
+                        .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?;
+                    environment.extend(tgi.environment());
+                    match &tgi.image {
+                        Some(image) => Some(image.clone()),
+                        None => Some(aws::tgi_image_uri(region)),
+                    }
+                }
+                (ContainerMode::Triton, _) => {
+                    info!("Using the SageMaker Triton image, nothing to build");
+                    let triton = model_config
+                        .container
+                        .triton
+                        .as_ref()
+                        .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?;
+                    let repository = config_dir.join(&triton.model_repository);
+                    let triton_model = artefact::triton_model_name(&repository, triton.model_name.as_deref())?;
+                    environment.insert("SAGEMAKER_TRITON_DEFAULT_MODEL_NAME".to_string(), triton_model);
+                    match &triton.image {
+                        Some(image) => Some(image.clone()),
+                        None => Some(aws::triton_image_uri(region)?),
+                    }
+                }
+                (_, Some(uri)) => {
+                    info!("Using {}, which the deploy being resumed pushed", uri);
+                    Some(uri.to_string())
+                }
+                _ if !push && makes_model => {
+                    let uri = docker::pushed_image(&aws.ecr, &model_config.name, &image_tag(model_config)).await?;
+                    info!("Using {} from ECR, without pushing", uri);
+                    Some(uri)
+                }
+                // Stopping after the build, with --until
+                _ if !push => {
+                    if build && !built {
+                        self.build(model_config, container_mode, config_dir, labels).await?;
+                        progress.finished(Stage::Build, |_| {}).await;
+                    }
+                    None
+                }
+                _ => {
+                    let image = async {
+                        if !build {
+                            info!("Pushing the image from the last build, without building");
+                            return self.built(&model_config.name).await;
+                        }
+                        // The deploy being resumed may have run on another machine, in which case it's built again
+                        if built {
+                            if let Ok(image) = self.built(&model_config.name).await {
+                                info!("Pushing the image the deploy being resumed built");
+                                return Ok(image);
+                            }
+                        }
+                        let image = self.build(model_config, container_mode, config_dir, labels).await?;
+                        progress.finished(Stage::Build, |_| {}).await;
+                        Ok(image)
+                    };
+                    // The ECR repository's looked up, or created, while the image builds
+                    let (image, repository) = tokio::try_join!(image, docker::ecr_repository(&aws.ecr, &model_config.name))?;
+                    let uri = self.push_to(&image, &repository, model_config).await?.uri;
+                    progress.finished(Stage::Push, |record| record.image = Some(uri.clone())).await;
+                    let mut pushed_environment = hook_environment.clone();
+                    pushed_environment.insert("IMAGE_URI", uri.clone());
+                    hooks::run(Hook::PostBuild, &model_config.hooks, &pushed_environment, config_dir).await?;
+                    Some(uri)
+                }
+            };
+            if let Some(uri) = &uri {
+                aws::check_image_region(uri, region)?;
+            }
+            Ok::<_, anyhow::Error>(uri)
+        };
+        let upload = async {
+            let uploads = uploads_artefact(model_config, container_mode);
+            if let Some(artefact) = uploaded.filter(|_| uploads) {
+                info!("Using {}, which the deploy being resumed uploaded", artefact);
+                return self.still_in_s3(artefact, model_config.uncompressed_artefact).await.map(Some);
+            }
+            match (uploads, upload) {
+                (true, false) if makes_model => return self.uploaded_artefact(model_config).await.map(Some),
+                (true, false) => return Ok(None),
+                // Hub and S3 artefacts aren't uploaded, only checked for the model
+                (false, _) if !makes_model => return Ok(None),
+                _ => {}
+            }
+            let model_data = self.upload_artefact(model_config, container_mode, config_dir, deploy_timestamp).await?;
+            if let Some(model_data) = model_data.as_ref().filter(|_| uploads) {
+                progress.finished(Stage::Upload, |record| record.artefact = Some(model_data.s3_uri().to_string())).await;
+            }
+            Ok(model_data)
+        };
+        let (uri, model_data) = tokio::try_join!(image, upload)?;
+        // Stopping before the model, with --until
+        let Some(uri) = uri.filter(|_| makes_model) else {
+            return Ok(());
+        };
+
+        // hf:// artefacts are downloaded from the Hub when the container starts
+        if let Some(model_id) = model_config.artefact.as_deref().and_then(artefact::hf_model_id) {
+            environment.extend(artefact::hf_environment(model_id));
+        }
+
+        // The config's own environment goes last, so it can override ours
+        environment.extend(config_environment);
+
+        aws::create_sagemaker_model(
+            sagemaker_name,
+            &execution_role_arn,
+            &uri,
+            &aws.sage,
+            model_data,
+            &environment,
+            tags
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+// The model's last deploy to this environment and region, if it failed or was stopped, for deploy --resume to carry on
+// with. It has to be from the same config and commit, or what it built and uploaded isn't what this deploy would
+async fn resumable(
+    model_config: &model_config::ModelConfig,
+    provenance: &provenance::Provenance,
+    state_backend: &state::Backend,
+    aws: &aws::AwsClients,
+) -> Result<Option<state::DeployRecord>> {
+    let state = state_backend.load(aws).await?;
+    match state.last_deploy(&model_config.name, model_config.environment.as_deref(), &aws.region) {
+        Some(last) => resume_from(last, provenance).map(|d| d.cloned()),
+        None => {
+            info!("Nothing to resume, {} has no deploys in {}. Deploying from the start", model_config.name, state_backend);
+            Ok(None)
+        }
+    }
+}
+
+// The last deploy, if it's one to resume: it failed, was stopped, or never said how it went
+fn resume_from<'a>(last: &'a state::DeployRecord, provenance: &provenance::Provenance) -> Result<Option<&'a state::DeployRecord>> {
+    if !matches!(last.status, state::DeployStatus::Failed | state::DeployStatus::InProgress | state::DeployStatus::Partial) {
+        info!("Nothing to resume, the last deploy of {} ({}) is {}. Deploying from the start", last.model, last.sagemaker_model, last.status);
+        return Ok(None);
+    }
+    if last.config_hash != provenance.config_hash || last.git_commit != provenance.git_commit {
+        return Err(anyhow!(
+            "Can't resume {}, the config or commit has changed since it failed. Deploy without --resume",
+            last.sagemaker_model
+        ));
+    }
+    let stages: Vec<_> = last.stages.iter().map(|s| s.to_string()).collect();
+    match stages.is_empty() {
+        true => info!("Resuming {}, which failed before finishing anything", last.sagemaker_model),
+        false => info!("Resuming {}, which finished: {}", last.sagemaker_model, stages.join(", ")),
+    }
+    Ok(Some(last))
+}
+
+// The SageMaker model the last deploy of the model to this environment and region created, for a deploy that starts
+// after the model stage
+async fn last_model(model_config: &model_config::ModelConfig, state_backend: &state::Backend, aws: &aws::AwsClients) -> Result<String> {
+    let state = state_backend.load(aws).await?;
+    let model = state
+        .deploys
+        .iter()
+        .rev()
+        .filter(|d| d.model == model_config.name && d.environment == model_config.environment && d.region == aws.region)
+        .filter(|d| !d.rollback && !d.cleaned_up)
+        .find(|d| d.has_finished(Stage::Model) || matches!(d.status, state::DeployStatus::InService | state::DeployStatus::Submitted))
+        .map(|d| d.sagemaker_model.clone())
+        .ok_or_else(|| anyhow!("No earlier deploy of {} in {} created a model to start from, start --from model", model_config.name, state_backend))?;
+    aws::model_container(&model, &aws.sage)
+        .await
+        .map_err(|e| anyhow!("Couldn't find SageMaker model {}, from the last deploy of {}, to start from: {}", model, model_config.name, e))?;
+    info!("Starting from SageMaker model {}, which the last deploy of {} created", model, model_config.name);
+    Ok(model)
+}
+
+// A deploy's record while its build and upload run side by side. Each stage they finish is written to the state
+// straight away, so a deploy that's stopped during the other can still be resumed
+struct Progress<'a> {
+    record: tokio::sync::Mutex<&'a mut state::DeployRecord>,
+    state_backend: &'a state::Backend,
+    aws: &'a aws::AwsClients,
+}
+
+impl Progress<'_> {
+    async fn finished(&self, stage: Stage, update: impl FnOnce(&mut state::DeployRecord)) {
+        // Held while it's written, so an earlier copy of the record can't be written over a later one
+        let mut record = self.record.lock().await;
+        update(&mut record);
+        record.finished(stage);
+        state::record(self.state_backend, &record, self.aws).await;
+    }
+}
+
+// Whether the deploy puts an artefact in S3 itself. Hub and S3 artefacts, and models without one, have nothing to upload
+fn uploads_artefact(model_config: &model_config::ModelConfig, container_mode: ContainerMode) -> bool {
+    let local = model_config.artefact.as_deref().is_some_and(|a| artefact::hf_model_id(a).is_none() && !a.starts_with("s3://"));
+    local || (container_mode == ContainerMode::Triton && model_config.container.triton.is_some())
+}
+
+// The tag an image is pushed with: the model's version, or latest. Docker tags can't have a +, which semver build
+// metadata uses
+fn image_tag(model_config: &model_config::ModelConfig) -> String {
+    model_config.version.as_deref().map(|v| v.replace('+', "-")).unwrap_or("latest".to_string())
+}
+
+// Give a phase until its timeout, then fail saying which setting gives it longer
+async fn within<T>(phase: &str, limit: Duration, work: impl Future<Output = Result<T>>) -> Result<T> {
+    match tokio::time::timeout(limit, work).await {
+        Ok(result) => result,
+        Err(_) => Err(errors::timeout(format!(
+            "Gave up on the {} after {}. Set timeouts.{} or pass --timeout {}=<duration> to give it longer",
+            phase,
+            HumanDuration(limit),
+            phase,
+            phase
+        ))),
+    }
+}
+
+// Offer to delete what a failed deploy created, so running it again starts afresh, or just do it with
+// --clean-on-failure. Returns whether it was cleaned up
+async fn clean_up_failed_deploy(
+    endpoint_name: &str,
+    sagemaker_name: &str,
+    endpoint_config_name: &str,
+    created: &aws::Created,
+    options: &DeployOptions,
+    aws: &aws::AwsClients,
+) -> bool {
+    let clean = match options.clean_on_failure {
+        true => true,
+        // Without a terminal to ask on, leave it be
+        false if !options.yes => prompt::confirm("The deploy failed. Delete the model, endpoint config and endpoint it created?").unwrap_or(false),
+        false => false,
+    };
+    if !clean {
+        info!("Left {} in place, pass --clean-on-failure to have failed deploys delete what they created", sagemaker_name);
+        return false;
+    }
+    match aws::clean_up_failed_deploy(endpoint_name, sagemaker_name, endpoint_config_name, created, &aws.sage).await {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("Couldn't clean up after the failed deploy: {}", e);
+            false
+        }
+    }
+}
+
+// The config's container environment, with secrets looked up
+async fn resolve_environment(model_config: &model_config::ModelConfig, aws: &aws::AwsClients) -> Result<HashMap<String, String>> {
+    let mut environment = HashMap::new();
+    for (key, value) in &model_config.container.environment {
+        let value = match value {
+            model_config::EnvValue::Plain(v) => v.clone(),
+            model_config::EnvValue::Secret(name) => {
+                info!("Reading secret {} for {}", name, key);
+                aws::resolve_secret(name, &aws.ssm, &aws.secrets).await?
+            }
+        };
+        environment.insert(key.clone(), value);
+    }
+    Ok(environment)
+}
+
+
+// What a deploy is about to create, for the confirmation prompt. Like the prompt, it's on stderr, so it doesn't get
+// mixed up with --output json
+#[allow(clippy::too_many_arguments)]
+fn print_deploy_plan(
+    model_config: &model_config::ModelConfig,
+    endpoint_name: &str,
+    sagemaker_name: &str,
+    endpoint_config_name: &str,
+    endpoint_type: EndpointType,
+    source: &ModelSource,
+    promotion: Option<&promote::Promotion>,
+    region: &str,
+    provenance: &provenance::Provenance,
+) {
+    eprintln!("About to deploy {} to account {}, in {}:", model_config.name, provenance.account, region);
+    eprintln!("  As: {}", provenance.deployed_by);
+    eprintln!("  Endpoint: {}, created or updated", endpoint_name);
+    match sagemaker_name == endpoint_config_name {
+        true => eprintln!("  SageMaker model and endpoint config: {}", sagemaker_name),
+        false => eprintln!("  SageMaker model: {}, with endpoint config {}", sagemaker_name, endpoint_config_name),
+    }
+    match (&model_config.compute.server, &model_config.compute.serverless) {
+        (Some(server), _) if endpoint_type == EndpointType::Server => {
+            eprintln!("  Server endpoint: {} x {}", server.initial_instance_count, server.instance_type);
+            if let Some(cost) = cost::server_monthly_cost(server) {
+                eprintln!("  Estimated cost: about ${:.0} a month", cost);
+            }
+        }
+        (_, Some(serverless)) if endpoint_type == EndpointType::Serverless => {
+            eprintln!(
+                "  Serverless endpoint: {} MB memory, max concurrency {}, provisioned concurrency {}",
+                serverless.memory, serverless.max_concurrency, serverless.provisioned_concurrency
+            );
+            eprintln!(
+                "  Estimated cost: about ${:.0} a month plus requests, at most ${:.0} if it's busy all month",
+                cost::serverless_monthly_cost(serverless),
+                cost::serverless_monthly_cost(serverless) + cost::serverless_max_monthly_cost(serverless)
+            );
+        }
+        _ => {}
+    }
+    match source {
+        ModelSource::Build(container_mode) => {
+            eprintln!("  Container: {}", container_mode);
+            if let Some(a) = &model_config.artefact {
+                eprintln!("  Artefact: {}", a);
+            }
+        }
+        ModelSource::Package(arn) => eprintln!("  Model package: {}", arn),
+        ModelSource::Promote { from, endpoint, .. } => {
+            if let Some(promotion) = promotion {
+                eprintln!("  Promoted from: {} ({}, endpoint {})", promotion.model, from, endpoint);
+                eprintln!("  Image: {}", promotion.image);
+            }
+        }
+    }
+    if let (Some(registry), ModelSource::Build(_)) = (&model_config.registry, source) {
+        eprintln!("  Registered in model package group: {}", registry::group_name(model_config, registry));
+    }
+}
+
+// The bucket artefacts go in and the role SageMaker runs the model as: Sageturner's, unless the config overrides them
+pub fn bucket_and_role(model_config: &model_config::ModelConfig, region: &str) -> (String, String) {
+    let overrides = model_config.overrides.as_ref();
+    let mut bucket_name = overrides
+        .and_then(|o| o.bucket_name.clone())
+        .unwrap_or(DEFAULT_BUCKET_NAME.to_string());
+    let execution_role = overrides
+        .and_then(|o| o.role_arn.clone())
+        .unwrap_or(DEFAULT_ROLE_NAME.to_string());
+    // SageMaker needs the artefact in its own region, so a deploy to several regions uses a bucket in each:
+    // bucket_name's {region} is filled in, or the region goes on the end of the name
+    if bucket_name.contains("{region}") {
+        bucket_name = bucket_name.replace("{region}", region);
+    } else if !model_config.regions.is_empty() {
+        bucket_name = format!("{}-{}", bucket_name, region);
+    }
+    (bucket_name, execution_role)
+}
+
+// The S3 key for an artefact, from the artefact_key template
+fn artefact_key(
+    model_config: &model_config::ModelConfig,
+    file: &str,
+    deploy_timestamp: &str,
+    config_dir: &Path,
+) -> Result<String> {
+    let default_template = match model_config.version {
+        Some(_) => artefact::DEFAULT_VERSIONED_KEY_TEMPLATE,
+        None => artefact::DEFAULT_KEY_TEMPLATE,
+    };
+    let template = model_config.artefact_key.as_deref().unwrap_or(default_template);
+    let mut values = HashMap::new();
+    values.insert("name", model_config.name.clone());
+    if let Some(v) = &model_config.version {
+        values.insert("version", v.clone());
+    }
+    values.insert("timestamp", deploy_timestamp.to_string());
+    values.insert("file", file.to_string());
+    if template.contains("{git_sha}") {
+        values.insert("git_sha", git::short_sha(config_dir)?);
+    }
+    artefact::render_key(template, &values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resume_from, DeployOptions};
+    use crate::{output::OutputFormat, provenance::Provenance, state::{DeployRecord, DeployStatus, State}, Stage};
+
+    fn options(from: Option<Stage>, until: Option<Stage>) -> DeployOptions {
+        DeployOptions {
+            yes: true,
+            wait: true,
+            force: false,
+            skip_approval: false,
+            clean_on_failure: false,
+            skip_build: false,
+            skip_push: false,
+            skip_upload: false,
+            resume: false,
+            from,
+            until,
+            output: OutputFormat::Text,
+        }
+    }
+
+    fn provenance() -> Provenance {
+        Provenance {
+            git_commit: Some("4f1c2ab".to_string()),
+            git_branch: Some("main".to_string()),
+            git_dirty: Some(false),
+            config_hash: "c0ffee".to_string(),
+            deployed_by: "arn:aws:iam::123456789012:user/ci".to_string(),
+            account: "123456789012".to_string(),
+        }
+    }
+
+    fn deploy(sagemaker_model: &str, status: DeployStatus) -> DeployRecord {
+        DeployRecord {
+            started: "2026-10-16T12:00:00Z".to_string(),
+            model: "resnet-50".to_string(),
+            version: None,
+            environment: Some("prod".to_string()),
+            endpoint: "resnet-50-prod".to_string(),
+            sagemaker_model: sagemaker_model.to_string(),
+            endpoint_config: None,
+            region: "eu-west-2".to_string(),
+            image: None,
+            image_digest: None,
+            artefact: None,
+            model_package: None,
+            config_hash: "c0ffee".to_string(),
+            git_commit: Some("4f1c2ab".to_string()),
+            deployed_by: "arn:aws:iam::123456789012:user/ci".to_string(),
+            status,
+            reason: None,
+            rollback: false,
+            cleaned_up: false,
+            stages: vec![Stage::Validate, Stage::Build],
+        }
+    }
+
+    #[test]
+    fn until_stops_before_the_stages_after_it() {
+        let wait = options(None, Some(Stage::Wait));
+        assert!(!wait.stops_before(Stage::Wait));
+        assert!(wait.stops_before(Stage::SmokeTest));
+        let from = options(Some(Stage::Endpoint), None);
+        assert!(!from.stops_before(Stage::SmokeTest));
+        assert!(!from.runs(Stage::Model));
+        assert!(from.runs(Stage::SmokeTest));
+    }
+
+    #[test]
+    fn resume_picks_a_deploy_that_didnt_finish() {
+        for status in [DeployStatus::Failed, DeployStatus::InProgress, DeployStatus::Partial] {
+            let last = deploy("resnet-50-prod-161020261200", status);
+            assert_eq!(resume_from(&last, &provenance()).unwrap().map(|d| d.sagemaker_model.as_str()), Some("resnet-50-prod-161020261200"));
+        }
+        for status in [DeployStatus::InService, DeployStatus::Submitted, DeployStatus::AwaitingApproval] {
+            assert!(resume_from(&deploy("resnet-50-prod-161020261200", status), &provenance()).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn resume_only_looks_at_the_last_deploy() {
+        let state = State {
+            deploys: vec![deploy("resnet-50-prod-161020261200", DeployStatus::Failed), deploy("resnet-50-prod-161020261300", DeployStatus::InService)],
+        };
+        let last = state.last_deploy("resnet-50", Some("prod"), "eu-west-2").unwrap();
+        assert!(resume_from(last, &provenance()).unwrap().is_none());
+    }
+
+    #[test]
+    fn resume_needs_the_same_config_and_commit() {
+        let mut last = deploy("resnet-50-prod-161020261200", DeployStatus::Failed);
+        last.git_commit = Some("9d8e7f6".to_string());
+        assert!(resume_from(&last, &provenance()).is_err());
+    }
+}
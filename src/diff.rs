@@ -0,0 +1,171 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use console::Style;
+
+use crate::{aws, errors, model_config::{EnvValue, ModelConfig}, EndpointType};
+
+// What an endpoint runs, as far as the people deploying to it care: its compute, image and environment
+#[derive(Debug, Default)]
+pub struct EndpointSummary {
+    instance_type: Option<String>,
+    instance_count: Option<i32>,
+    memory: Option<i32>,
+    max_concurrency: Option<i32>,
+    provisioned_concurrency: Option<i32>,
+    image: Option<String>,
+    image_digest: Option<String>,
+    environment: BTreeMap<String, String>,
+}
+
+// The image a deploy will run, for the after side of the summary
+pub enum PlannedImage {
+    // Built and pushed by the deploy, so there's no digest yet
+    Built,
+    // Already in ECR, like a promoted model's
+    Existing(String),
+    // Whatever the model package has
+    Package(String),
+}
+
+// What the endpoint runs now, or None if it doesn't exist yet. The image's digest is looked up in ECR when it
+// isn't pinned to one
+pub async fn current(endpoint_name: &str, aws: &aws::AwsClients) -> Result<Option<EndpointSummary>> {
+    let Some(endpoint_config) = aws::current_endpoint_config(endpoint_name, &aws.sage).await? else {
+        return Ok(None);
+    };
+    let config = aws
+        .sage
+        .describe_endpoint_config()
+        .endpoint_config_name(&endpoint_config)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't look up endpoint config {}", endpoint_config)))?;
+    let variants = config.production_variants();
+    let Some(variant) = variants.iter().find(|v| v.variant_name() == Some(aws::VARIANT_NAME)).or(variants.first()) else {
+        return Ok(None);
+    };
+    let serverless = variant.serverless_config();
+    let mut summary = EndpointSummary {
+        instance_type: variant.instance_type().map(|t| t.as_str().to_string()),
+        instance_count: variant.initial_instance_count(),
+        memory: serverless.and_then(|s| s.memory_size_in_mb()),
+        max_concurrency: serverless.and_then(|s| s.max_concurrency()),
+        provisioned_concurrency: serverless.and_then(|s| s.provisioned_concurrency()),
+        ..Default::default()
+    };
+    if let Some(model_name) = variant.model_name() {
+        let model = aws
+            .sage
+            .describe_model()
+            .model_name(model_name)
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't look up model {}", model_name)))?;
+        if let Some(container) = model.primary_container() {
+            summary.image = container.image().map(|i| i.to_string());
+            summary.environment = container.environment().cloned().unwrap_or_default().into_iter().collect();
+        }
+    }
+    if let Some(image) = &summary.image {
+        summary.image_digest = aws::image_digest(image, &aws.ecr).await;
+    }
+    Ok(Some(summary))
+}
+
+// What the deploy will update the endpoint to. Secrets in the environment are shown by name, not looked up
+pub fn planned(model_config: &ModelConfig, endpoint_type: EndpointType, image: &PlannedImage) -> EndpointSummary {
+    let mut summary = EndpointSummary::default();
+    match endpoint_type {
+        EndpointType::Server => {
+            if let Some(server) = &model_config.compute.server {
+                summary.instance_type = Some(server.instance_type.clone());
+                summary.instance_count = Some(server.initial_instance_count);
+            }
+        }
+        EndpointType::Serverless => {
+            if let Some(serverless) = &model_config.compute.serverless {
+                summary.memory = Some(serverless.memory);
+                summary.max_concurrency = Some(serverless.max_concurrency);
+                // SageMaker leaves provisioned concurrency off the endpoint config when it's 0
+                summary.provisioned_concurrency = Some(serverless.provisioned_concurrency).filter(|c| *c > 0);
+            }
+        }
+    }
+    if let PlannedImage::Existing(uri) = image {
+        summary.image = Some(uri.clone());
+        summary.image_digest = uri.split_once('@').map(|(_, digest)| digest.to_string());
+    }
+    summary.environment = model_config
+        .container
+        .environment
+        .iter()
+        .map(|(key, value)| match value {
+            EnvValue::Plain(v) => (key.clone(), v.clone()),
+            EnvValue::Secret(name) => (key.clone(), format!("(secret {})", name)),
+        })
+        .collect();
+    summary
+}
+
+// Print what's changing, before against after, in red and green where stderr takes colour. Like the deploy plan
+// it goes on stderr. Only the environment variables the config sets are compared, as sageturner adds its own
+// (like HF_MODEL_ID) that aren't in the config, and secrets' values are never shown
+pub fn print(endpoint_name: &str, before: &EndpointSummary, after: &EndpointSummary, image: &PlannedImage, model_config: &ModelConfig) {
+    let old = Style::new().red().for_stderr();
+    let new = Style::new().green().for_stderr();
+    let heading = Style::new().bold().for_stderr();
+    let mut lines = Vec::new();
+    let mut change = |name: &str, before: Option<String>, after: Option<String>| {
+        if before != after {
+            let show = |value: Option<String>| value.unwrap_or_else(|| "none".to_string());
+            lines.push(format!("    {}: {} -> {}", name, old.apply_to(show(before)), new.apply_to(show(after))));
+        }
+    };
+    change("Instance type", before.instance_type.clone(), after.instance_type.clone());
+    change("Instance count", before.instance_count.map(|c| c.to_string()), after.instance_count.map(|c| c.to_string()));
+    change("Memory", before.memory.map(|m| format!("{} MB", m)), after.memory.map(|m| format!("{} MB", m)));
+    change("Max concurrency", before.max_concurrency.map(|c| c.to_string()), after.max_concurrency.map(|c| c.to_string()));
+    change(
+        "Provisioned concurrency",
+        before.provisioned_concurrency.map(|c| c.to_string()),
+        after.provisioned_concurrency.map(|c| c.to_string()),
+    );
+    match image {
+        PlannedImage::Built => {
+            let running = match (&before.image, &before.image_digest) {
+                (Some(image), Some(digest)) if !image.contains('@') => Some(format!("{} ({})", image, digest)),
+                (image, _) => image.clone(),
+            };
+            change("Image", running, Some("a new image, built by this deploy".to_string()))
+        }
+        PlannedImage::Existing(_) => {
+            // A promoted image is pinned to its digest, so compare those when both sides have one
+            match (&before.image_digest, &after.image_digest) {
+                (Some(b), Some(a)) => change("Image digest", Some(b.clone()), Some(a.clone())),
+                _ => change("Image", before.image.clone(), after.image.clone()),
+            }
+        }
+        PlannedImage::Package(arn) => change("Image", before.image.clone(), Some(format!("from model package {}", arn))),
+    }
+    for (key, value) in &after.environment {
+        let secret = matches!(model_config.container.environment.get(key), Some(EnvValue::Secret(_)));
+        match before.environment.get(key) {
+            // A secret's value can't be compared without looking it up, so it's only shown if it's new
+            Some(_) if secret => {}
+            Some(previous) if previous == value => {}
+            // The running value isn't shown, as it may be a secret an earlier deploy looked up
+            Some(_) => lines.push(format!("    {}: {}", key, new.apply_to(format!("{} (changed)", value)))),
+            None => lines.push(format!("    {}: {}", key, new.apply_to(format!("{} (new)", value)))),
+        }
+    }
+
+    if lines.is_empty() {
+        eprintln!("  Endpoint {}'s compute, image and environment are unchanged", endpoint_name);
+        return;
+    }
+    eprintln!("  {}", heading.apply_to(format!("Changes to endpoint {}:", endpoint_name)));
+    for line in lines {
+        eprintln!("{}", line);
+    }
+}
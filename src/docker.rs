@@ -2,28 +2,49 @@ use std::{
     collections::HashMap,
     fs::File,
     io::{Read, Write}, path::{Path, absolute},
+    time::Instant,
 };
 
 use anyhow::{anyhow, Result};
 use aws_sdk_ecr::operation::describe_repositories::DescribeRepositoriesError;
 use bollard::{
     image::{BuildImageOptions, PushImageOptions, TagImageOptions},
-    secret::{BuildInfo, ImageId},
     Docker,
 };
+use indicatif::{HumanDuration, ProgressBar};
 use tar::Builder;
 use tempfile::tempdir;
 
 use futures_util::stream::StreamExt;
-
-use crate::aws::get_docker_credentials_for_ecr;
-
-pub async fn get_client() -> Docker {
-    Docker::connect_with_socket_defaults().unwrap()
+use tracing::{debug, info, instrument};
+
+use crate::artefact;
+use crate::errors;
+use crate::aws::{self, forget_ecr_login, get_docker_credentials_for_ecr};
+use crate::model_config::VllmConfig;
+use crate::progress;
+
+// Connect to Docker where DOCKER_HOST says, or its socket if that's not set, and check the daemon answers, so a
+// deploy that can't build fails before it changes anything
+pub async fn get_client() -> Result<Docker> {
+    let host = std::env::var("DOCKER_HOST").unwrap_or_else(|_| "the default Docker socket".to_string());
+    let docker = Docker::connect_with_defaults().map_err(|e| errors::docker(e, format!("Couldn't connect to Docker at {}", host)))?;
+    docker
+        .ping()
+        .await
+        .map_err(|e| errors::docker(e, format!("Docker isn't answering at {}", host)))?;
+    debug!("Connected to Docker at {}", host);
+    Ok(docker)
 }
 
-pub async fn build_image_byo(docker_dir_path: &Path, docker: &Docker, repo_name: &str, config_path: &Path) -> Result<()> {
-    println!("Building your docker image at {}, as {repo_name}:latest", docker_dir_path.display());
+pub async fn build_image_byo(
+    docker_dir_path: &Path,
+    docker: &Docker,
+    repo_name: &str,
+    config_path: &Path,
+    labels: &HashMap<String, String>,
+) -> Result<()> {
+    info!("Building your docker image at {}, as {repo_name}:latest", docker_dir_path.display());
 
     // absolutize path correctly - TODO fix this horrible reassignment
     let docker_dir_path_abs = config_path.join(docker_dir_path);
@@ -46,56 +67,32 @@ pub async fn build_image_byo(docker_dir_path: &Path, docker: &Docker, repo_name:
         dockerfile: "Dockerfile",
         t: repo_name,
         rm: true,
+        labels: label_refs(labels),
         ..Default::default()
     };
-    let mut build = docker.build_image(options, None, Some(contents.into()));
-
-    let mut image_id: String = "".to_string();
-    while let Some(msg) = build.next().await {
-        let build_output = msg?;
-        print!("{}", build_output.stream.unwrap_or_default());
-        if let BuildInfo {
-            aux: Some(ImageId { id: Some(id) }),
-            ..
-        } = build_output
-        {
-            image_id = id;
-        }
-    }
-
-    Ok(())
+    run_build(docker, options, contents).await
 }
 
+// Build a generate_container image from its rendered Dockerfile and serve.py, the user's code_dir and a preset's
+// sageturner.py, if it has one
 #[allow(clippy::too_many_arguments)]
 pub async fn build_image_ez_mode(
-    gpu: bool,
-    extra_python: &str,
-    extra_system: &str,
-    name: &str,
+    dockerfile: &str,
     serve_code: &str,
+    name: &str,
     docker_client: &Docker,
-    python_version: &str,
-    code_location: &str,
-    config_path: &Path
+    code_location: Option<&str>,
+    code_excludes: &[String],
+    model_code: Option<&str>,
+    config_path: &Path,
+    labels: &HashMap<String, String>,
 ) -> Result<()> {
-    println!("Building dynamically generated image, with \nPython packages: {} \nsystem packages {}\nand your serve code", extra_python, extra_system);
-    let dockerfile_contents = if gpu {
-        gpu_dockerfile()
-    } else {
-        cpu_dockerfile()
-    };
-
     let tempdir = tempdir()?;
 
-    // Absolutize code location properly
-    let code_loc_path = config_path.join(code_location);
-    let code_location_abs = absolute(code_loc_path)?;
-    let code_location_abs = code_location_abs.as_path();
-
     // Write dockerfile from contents
     let docker_path = tempdir.path().join("Dockerfile");
     let mut docker_file = File::create(&docker_path)?;
-    docker_file.write_all(dockerfile_contents.as_bytes())?;
+    docker_file.write_all(dockerfile.as_bytes())?;
 
     // Write serve.py - the FastAPI server - from contents
     // expects a file called sageturner.py so it can call 
@@ -107,7 +104,22 @@ pub async fn build_image_ez_mode(
     let tar_path = tempdir.path().join("archive_ez.tar");
     let tar_file = File::create(&tar_path)?;
     let mut builder = Builder::new(tar_file);
-    builder.append_dir_all("", code_location_abs)?; // get everything in the code dir
+    if let Some(code_location) = code_location {
+        // Absolutize code location properly
+        let code_location_abs = absolute(config_path.join(code_location))?;
+        // get everything in the code dir, bar the excludes
+        let excludes = artefact::excludes(code_excludes)?;
+        artefact::append_dir_filtered(&mut builder, &code_location_abs, &code_location_abs, &excludes)?;
+    }
+
+    // Presets generate sageturner.py for the user, validation makes sure the code dir doesn't also have one
+    if let Some(model_code) = model_code {
+        let model_code_path = tempdir.path().join("sageturner.py");
+        let mut model_code_file = File::create(&model_code_path)?;
+        model_code_file.write_all(model_code.as_bytes())?;
+        let mut model_code_file = File::open(&model_code_path)?;
+        builder.append_file("sageturner.py", &mut model_code_file)?;
+    }
 
     // also append the generated serve file and Dockerfile 
     let mut python_file = File::open(&python_path)?;
@@ -121,46 +133,94 @@ pub async fn build_image_ez_mode(
     let mut contents = Vec::new();
     archive.read_to_end(&mut contents)?;
 
-    let mut build_args = HashMap::new();
-    build_args.insert("EXTRA_PYTHON_PACKAGES", extra_python);
-    build_args.insert("EXTRA_SYSTEM_PACKAGES", extra_system);
-    build_args.insert("PYTHON_VERSION", python_version);
-    
+    let options = BuildImageOptions {
+        dockerfile: "Dockerfile",
+        t: name,
+        rm: true,
+        labels: label_refs(labels),
+        ..Default::default()
+    };
+    run_build(docker_client, options, contents).await
+}
+
+pub async fn build_image_vllm(
+    vllm: &VllmConfig,
+    dockerfile: &str,
+    name: &str,
+    docker_client: &Docker,
+    labels: &HashMap<String, String>,
+) -> Result<()> {
+    info!("Building vLLM image for {}", vllm.model_id.as_deref().unwrap_or("the artefact at /opt/ml/model"));
+    let tempdir = tempdir()?;
+
+    let docker_path = tempdir.path().join("Dockerfile");
+    let mut docker_file = File::create(&docker_path)?;
+    docker_file.write_all(dockerfile.as_bytes())?;
+
+    let tar_path = tempdir.path().join("archive_vllm.tar");
+    let tar_file = File::create(&tar_path)?;
+    let mut builder = Builder::new(tar_file);
+    let mut docker_file = File::open(&docker_path)?;
+    builder.append_file("Dockerfile", &mut docker_file)?;
+    builder.finish()?;
+
+    let mut archive = File::open(tar_path)?;
+    let mut contents = Vec::new();
+    archive.read_to_end(&mut contents)?;
 
     let options = BuildImageOptions {
         dockerfile: "Dockerfile",
         t: name,
         rm: true,
-        buildargs: build_args,
+        labels: label_refs(labels),
         ..Default::default()
     };
+    run_build(docker_client, options, contents).await
+}
+
+// Docker streams the build's output in chunks that end in a newline, or are just one. Its steps are enough to follow
+// along, and show on the progress spinner, the rest is for -v
+fn log_build_output(stream: Option<&str>, progress: &ProgressBar) {
+    match stream.map(str::trim_end).filter(|l| !l.is_empty()) {
+        Some(line) if line.starts_with("Step ") => {
+            info!("{}", line);
+            progress.set_message(format!("Building: {}", line));
+        }
+        Some(line) => debug!("{}", line),
+        None => {}
+    }
+}
+
+// BuildImageOptions borrows its strings
+fn label_refs(labels: &HashMap<String, String>) -> HashMap<&str, &str> {
+    labels.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect()
+}
+
+#[instrument(name = "build", skip_all)]
+async fn run_build(docker_client: &Docker, options: BuildImageOptions<&str>, contents: Vec<u8>) -> Result<()> {
+    let started = Instant::now();
+    let progress = progress::spinner("Building");
     let mut build = docker_client.build_image(options, None, Some(contents.into()));
 
     while let Some(msg) = build.next().await {
         match msg {
             Ok(i) => {
-                print!("{}", i.stream.unwrap_or_default())
+                log_build_output(i.stream.as_deref(), &progress)
             },
             Err(e) => {
-                match e {
-                    bollard::errors::Error::DockerStreamError { error } => {
-                        return Err(anyhow!("Docker build error: {}", error))
-                    },
-                    _ => return Err(anyhow!("Other Docker error: {}", e.to_string()))
-                }
+                progress.abandon();
+                return Err(errors::build(e, "Docker build failed"));
             },
         }
     }
+    progress.finish_and_clear();
+    info!("Built the image in {}", HumanDuration(started.elapsed()));
 
     Ok(())
 }
 
-pub async fn push_image(
-    docker: &Docker,
-    ecr_client: &aws_sdk_ecr::Client,
-    image_name: &str,
-) -> Result<String> {
-    println!("Pushing image {} to ECR", image_name);
+// The URI of the image's ECR repository, which is created if it isn't there yet
+pub async fn ecr_repository(ecr_client: &aws_sdk_ecr::Client, image_name: &str) -> Result<String> {
     let repo_check = ecr_client
         .describe_repositories()
         .repository_names(image_name)
@@ -181,7 +241,8 @@ pub async fn push_image(
                         .create_repository()
                         .repository_name(image_name)
                         .send()
-                        .await?;
+                        .await
+                        .map_err(|e| errors::aws(e, format!("Couldn't create ECR repository {}", image_name)))?;
 
                     let new_repo_info = new_repo
                         .repository()
@@ -191,121 +252,81 @@ pub async fn push_image(
                         .clone()
                         .ok_or_else(|| anyhow!("Error reading new repo URI"))?
                 }
-                err => return Err(err.into()),
+                err => return Err(errors::aws(err, format!("Couldn't look up ECR repository {}", image_name))),
             };
         }
     };
+    Ok(uri)
+}
+
+// The URI of an image already pushed to the model's ECR repository with this tag
+pub async fn pushed_image(ecr_client: &aws_sdk_ecr::Client, image_name: &str, tag: &str) -> Result<String> {
+    let missing = || anyhow!("There's no {}:{} in ECR to reuse, drop --skip-push to build and push it", image_name, tag);
+    let repositories = match ecr_client.describe_repositories().repository_names(image_name).send().await {
+        Ok(repositories) => repositories,
+        Err(err) => match err.into_service_error() {
+            DescribeRepositoriesError::RepositoryNotFoundException(_) => return Err(missing()),
+            err => return Err(errors::aws(err, format!("Couldn't look up ECR repository {}", image_name))),
+        },
+    };
+    let repository = repositories
+        .repositories()
+        .first()
+        .and_then(|r| r.repository_uri())
+        .ok_or_else(|| anyhow!("Error reading repo URI"))?;
+    let uri = format!("{}:{}", repository, tag);
+    aws::image_digest(&uri, ecr_client).await.ok_or_else(missing)?;
+    Ok(uri)
+}
 
+// Push the image to its ECR repository, at the URI ecr_repository gave
+#[instrument(name = "push", skip_all, fields(image = image_name, tag))]
+pub async fn push_image(
+    docker: &Docker,
+    ecr_client: &aws_sdk_ecr::Client,
+    uri: &str,
+    image_name: &str,
+    tag: &str,
+) -> Result<()> {
+    info!("Pushing image {}:{} to ECR", image_name, tag);
     docker
         .tag_image(
             image_name,
             Some(TagImageOptions {
-                tag: "latest",
-                repo: &uri,
+                tag,
+                repo: uri,
             }),
         )
-        .await?;
+        .await
+        .map_err(|e| errors::docker(e, format!("Couldn't tag image {}", image_name)))?;
 
     let push_options = Some(PushImageOptions::<String> {
-        tag: "latest".to_string(),
+        tag: tag.to_string(),
     });
-    let credentials = get_docker_credentials_for_ecr(ecr_client).await?;
-    let mut push_stream = docker.push_image(&uri, push_options, Some(credentials));
+    let registry = uri.split('/').next().unwrap_or(uri);
+    let credentials = get_docker_credentials_for_ecr(ecr_client, registry).await?;
+    let started = Instant::now();
+    let progress = progress::spinner(format!("Pushing {}:{}", image_name, tag));
+    let mut push_stream = docker.push_image(uri, push_options, Some(credentials));
 
     while let Some(stream) = push_stream.next().await {
         match stream {
             Ok(p) => {
-                println!("{:?}", p.progress.unwrap_or_default());
+                debug!("{:?}", p.progress.as_deref().unwrap_or_default());
+                // Docker reports each layer in turn, as e.g. Pushing [=====>    ] 12.1MB/48.3MB
+                let layer = [p.status.as_deref(), p.progress.as_deref()].into_iter().flatten().collect::<Vec<_>>().join(" ");
+                if !layer.is_empty() {
+                    progress.set_message(format!("Pushing {}:{}: {}", image_name, tag, layer));
+                }
             },
             Err(e) => {
-                return Err(anyhow!("Docker push error: {}", e.to_string()))
+                progress.abandon();
+                forget_ecr_login(registry);
+                return Err(errors::ecr_push(e, "Docker push failed"));
             },
         }
     }
-    println!("Docker image uploaded successfully");
-    Ok(uri)
-}
-
-fn cpu_dockerfile() -> String {
-    let content = r#"
-    ARG PYTHON_VERSION="3.12"
-    FROM python:${PYTHON_VERSION}
-
-    ARG EXTRA_PYTHON_PACKAGES=""
-    ARG EXTRA_SYSTEM_PACKAGES=""
-
-    RUN apt-get -y update && DEBIAN_FRONTEND=noninteractive apt-get -y install --no-install-recommends ca-certificates && rm -rf /var/lib/apt/lists/*
-
-    RUN python -m ensurepip
-
-    # Install extra system packages
-    RUN if [ "${EXTRA_SYSTEM_PACKAGES}" != "" ]; then apt-get -y install --no-install-recommends ${EXTRA_SYSTEM_PACKAGES}; fi
-
-    # Install FastAPI as standard 
-    RUN pip install fastapi[standard]
-
-    # Install extra python packages 
-    RUN if [ "${EXTRA_PYTHON_PACKAGES}" != "" ]; then pip3 install --no-input ${EXTRA_PYTHON_PACKAGES}; fi
-
-    ENV PYTHONUNBUFFERED=TRUE
-    ENV PYTHONDONTWRITEBYTECODE=TRUE
-    ENV PATH="${PATH}:/opt/program"
-
-    COPY . /opt/program
-    COPY serve.py /opt/program
-    WORKDIR /opt/program
-
-    ENTRYPOINT [ "python", "serve.py" ]
-    "#;
-    content.to_string()
+    progress.finish_and_clear();
+    info!("Docker image uploaded successfully in {}", HumanDuration(started.elapsed()));
+    Ok(())
 }
-
-fn gpu_dockerfile() -> String {
-    let content = r#"
-    ARG PYTHON_VERSION="3.12"
-    FROM ubuntu:${PYTHON_VERSION}
-
-    ARG EXTRA_PYTHON_PACKAGES=""
-    ARG EXTRA_SYSTEM_PACKAGES=""
-
-    RUN apt-get -y update && DEBIAN_FRONTEND=noninteractive apt-get -y install --no-install-recommends \
-        build-essential libssl-dev zlib1g-dev \
-        libbz2-dev libreadline-dev libsqlite3-dev curl git \
-        libncursesw5-dev xz-utils tk-dev libxml2-dev libxmlsec1-dev libffi-dev liblzma-dev wget ca-certificates && rm -rf /var/lib/apt/lists/*
-
-    RUN wget https://developer.download.nvidia.com/compute/cuda/repos/ubuntu2204/x86_64/cuda-ubuntu2204.pin --no-check-certificate && \
-        mv cuda-ubuntu2204.pin /etc/apt/preferences.d/cuda-repository-pin-600 && \
-        wget https://developer.download.nvidia.com/compute/cuda/12.8.0/local_installers/cuda-repo-ubuntu2204-12-8-local_12.8.0-570.86.10-1_amd64.deb --no-check-certificate && \
-        dpkg -i cuda-repo-ubuntu2204-12-8-local_12.8.0-570.86.10-1_amd64.deb && \
-        cp /var/cuda-repo-ubuntu2204-12-8-local/cuda-*-keyring.gpg /usr/share/keyrings/ && \
-        apt-get -y update && apt-get -y install cuda-toolkit-12-8
-
-    ENV HOME=/home/root 
-    RUN curl https://pyenv.run | bash
-    ENV PYENV_ROOT=${HOME}/.pyenv
-    ENV PATH=${PYENV_ROOT}/shims:${PYENV_ROOT}/bin:$PATH
-
-    RUN pyenv install ${PYTHON_VERSION}
-    RUN pyenv global ${PYTHON_VERSION}
-
-    # Install extra system packages
-    RUN if [ "${EXTRA_SYSTEM_PACKAGES}" != "" ]; then apt-get -y install --no-install-recommends ${EXTRA_SYSTEM_PACKAGES}; fi
-
-    # Install FastAPI as standard 
-    RUN pip install fastapi[standard]
-
-    # Install extra python packages 
-    RUN if [ "${EXTRA_PYTHON_PACKAGES}" != "" ]; then pip install --no-input ${EXTRA_PYTHON_PACKAGES}; fi
-
-    ENV PYTHONUNBUFFERED=TRUE
-    ENV PYTHONDONTWRITEBYTECODE=TRUE
-    ENV PATH="${PATH}:/opt/program"
-
-    COPY . /opt/program
-    COPY serve.py /opt/program
-    WORKDIR /opt/program
-
-    ENTRYPOINT [ "python", "serve.py" ]
-    "#;
-    content.to_string()
-}
\ No newline at end of file
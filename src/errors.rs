@@ -0,0 +1,299 @@
+use std::fmt::{self, Display};
+
+use aws_credential_types::provider::error::CredentialsError;
+use aws_sdk_sagemaker::error::{ConnectorError, DisplayErrorContext, ProvideErrorMetadata};
+
+use crate::{github, logging::LogFormat};
+
+// What a failure was to do with, so it can come with what to do about it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    // The config isn't valid
+    Config,
+    // The Docker daemon isn't there
+    Docker,
+    // The image didn't build
+    Build,
+    // The image didn't push
+    Push,
+    // Docker couldn't log in to ECR, or ECR turned the push away
+    EcrAuth,
+    // No AWS credentials were found, or they're expired or invalid
+    Credentials,
+    // The AWS identity isn't allowed to do something
+    PermissionDenied,
+    // A bucket or object isn't there, or is in another region
+    S3,
+    // An account quota, like how many of an instance type endpoints can use
+    Quota,
+    // AWS turned a request down as invalid
+    Validation,
+    // AWS is throttling requests
+    Throttled,
+    // AWS had a problem of its own, or couldn't be reached
+    Unavailable,
+    // Anything else AWS said no to
+    Aws,
+    // The endpoint didn't go into service, or an update to it rolled back
+    EndpointFailed,
+    // We gave up waiting on something
+    Timeout,
+}
+
+impl Category {
+    // What sageturner exits with, so CI can tell failures apart, e.g. to retry throttling but not a bad config.
+    // These are documented in the README, so only ever add to them
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Category::Config | Category::Validation => 2,
+            Category::Build => 3,
+            Category::Push | Category::EcrAuth => 4,
+            Category::Credentials | Category::PermissionDenied => 5,
+            Category::EndpointFailed => 6,
+            Category::Timeout => 7,
+            Category::Throttled => 8,
+            Category::Unavailable => 9,
+            Category::Docker | Category::S3 | Category::Quota | Category::Aws => 1,
+        }
+    }
+}
+
+// A failure with a message for people, and what to do next when there's something to say
+#[derive(Debug)]
+pub struct Error {
+    pub category: Category,
+    message: String,
+    hint: Option<String>,
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Category::Config => write!(f, "invalid config"),
+            Category::Docker => write!(f, "Docker"),
+            Category::Build => write!(f, "build"),
+            Category::Push => write!(f, "push"),
+            Category::EcrAuth => write!(f, "ECR login"),
+            Category::Credentials => write!(f, "AWS credentials"),
+            Category::PermissionDenied => write!(f, "permission denied"),
+            Category::S3 => write!(f, "S3"),
+            Category::Quota => write!(f, "quota"),
+            Category::Validation => write!(f, "invalid request"),
+            Category::Throttled => write!(f, "throttled"),
+            Category::Unavailable => write!(f, "AWS unavailable"),
+            Category::Aws => write!(f, "AWS"),
+            Category::EndpointFailed => write!(f, "endpoint failed"),
+            Category::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+pub fn categorized(category: Category, message: impl Display, hint: Option<&'static str>) -> anyhow::Error {
+    Error { category, message: message.to_string(), hint: hint.map(|h| h.to_string()) }.into()
+}
+
+const CREDENTIALS_HINT: &str =
+    "Run aws configure, or aws sso login if you use IAM Identity Center, or pick a profile with --profile or AWS_PROFILE";
+const EXPIRED_HINT: &str = "Your AWS credentials have expired or aren't valid. Refresh them (aws sso login for SSO) and try again";
+const PERMISSION_HINT: &str = "Your AWS identity isn't allowed to do this, and the message says which action it needs. Add that to its IAM \
+policy, or use a profile that has it with --profile";
+const QUOTA_HINT: &str = "You've hit an account quota. Ask for an increase in the Service Quotas console, or use fewer instances or \
+another instance type";
+const VALIDATION_HINT: &str = "AWS turned the request down, usually because of a value in your config, which the message names";
+const THROTTLED_HINT: &str = "AWS is throttling requests. Wait a minute and try again";
+const UNAVAILABLE_HINT: &str = "AWS had a problem of its own, or couldn't be reached. It's usually over in a few minutes, so try again";
+const NO_BUCKET_HINT: &str = "Run sageturner setup to create the bucket, or point overrides.bucket_name in your config at one you have";
+const BUCKET_REGION_HINT: &str = "The bucket is in another region. SageMaker reads model data from its own region, so use a bucket there \
+(sageturner setup --regional-bucket makes one per region)";
+const NO_ROLE_HINT: &str = "Run sageturner setup to create the role, or point overrides.role_arn in your config at one you have";
+const NO_OBJECT_HINT: &str = "Check the artefact's S3 URI, and that it's in the deploy's region";
+const DOCKER_HINT: &str = "Is Docker running? Start Docker Desktop or the Docker daemon, and check docker ps works. If \
+Docker's somewhere else, set DOCKER_HOST to it";
+const BUILD_HINT: &str = "The Docker build failed, and its output above says where. Run with -v for all of it";
+const CONFIG_HINT: &str = "Fix the setting the message names. sageturner config schema describes them all";
+const ENDPOINT_HINT: &str = "The end of the container's logs is above, and the rest are in CloudWatch, under /aws/sagemaker/Endpoints";
+const TIMEOUT_HINT: &str = "What was waited on may still finish, so check on it in the AWS console before trying again, with a longer timeout";
+const ECR_HINT: &str = "Check your AWS identity can call ecr:GetAuthorizationToken and push to the repository, and that Docker can \
+reach ECR in the deploy's region";
+
+// An error from an AWS call (an SdkError, or the service error in one), as what we were doing and what AWS said,
+// categorized by its error code
+pub fn aws<E>(err: E, doing: impl Display) -> anyhow::Error
+where
+    E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+{
+    let (category, hint) = match err.code() {
+        Some("AccessDenied" | "AccessDeniedException" | "UnauthorizedOperation" | "AuthorizationError") => {
+            (Category::PermissionDenied, Some(PERMISSION_HINT))
+        }
+        Some(
+            "ExpiredToken" | "ExpiredTokenException" | "InvalidClientTokenId" | "UnrecognizedClientException"
+            | "InvalidAccessKeyId" | "SignatureDoesNotMatch",
+        ) => (Category::Credentials, Some(EXPIRED_HINT)),
+        Some("ResourceLimitExceeded" | "ServiceQuotaExceededException" | "LimitExceededException" | "LimitExceeded") => {
+            (Category::Quota, Some(QUOTA_HINT))
+        }
+        Some("ValidationException" | "ValidationError") => (Category::Validation, Some(VALIDATION_HINT)),
+        Some("ThrottlingException" | "Throttling" | "TooManyRequestsException" | "SlowDown") => {
+            (Category::Throttled, Some(THROTTLED_HINT))
+        }
+        Some(
+            "InternalFailure" | "InternalError" | "InternalServerError" | "InternalServerException" | "ServiceUnavailable"
+            | "ServiceUnavailableException" | "RequestTimeout" | "RequestTimeoutException",
+        ) => (Category::Unavailable, Some(UNAVAILABLE_HINT)),
+        Some("NoSuchBucket") => (Category::S3, Some(NO_BUCKET_HINT)),
+        Some("PermanentRedirect" | "AuthorizationHeaderMalformed" | "IllegalLocationConstraintException") => {
+            (Category::S3, Some(BUCKET_REGION_HINT))
+        }
+        Some("NoSuchKey" | "NotFound") => (Category::S3, Some(NO_OBJECT_HINT)),
+        // IAM's not found, which sageturner only looks up roles with
+        Some("NoSuchEntity") => (Category::Aws, Some(NO_ROLE_HINT)),
+        _ if has_source::<CredentialsError>(&err) => (Category::Credentials, Some(CREDENTIALS_HINT)),
+        // The request didn't get to AWS, or its answer didn't get back
+        _ if has_source::<ConnectorError>(&err) => (Category::Unavailable, Some(UNAVAILABLE_HINT)),
+        _ => (Category::Aws, None),
+    };
+    let said = match (err.code(), err.message()) {
+        (Some(code), Some(message)) => format!("{}: {}", code, message),
+        _ => DisplayErrorContext(&err).to_string(),
+    };
+    categorized(category, format!("{}: {}", doing, said), hint)
+}
+
+// Whether an error, or one of its sources, is an E. Credentials that couldn't be loaded fail the call before it's
+// sent, somewhere down its sources
+fn has_source<E: std::error::Error + 'static>(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(e) = source {
+        if e.downcast_ref::<E>().is_some() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+// An error talking to the Docker daemon. Not reaching it at all is the usual one
+pub fn docker(err: bollard::errors::Error, doing: impl Display) -> anyhow::Error {
+    use bollard::errors::Error::*;
+    let hint = match &err {
+        DockerResponseServerError { .. } | JsonDataError { .. } | JsonSerdeError { .. } => None,
+        _ => Some(DOCKER_HINT),
+    };
+    categorized(Category::Docker, format!("{}: {}", doing, err), hint)
+}
+
+// Whether the daemon answered, so the error's from the operation itself rather than from not reaching Docker
+fn answered(err: &bollard::errors::Error) -> bool {
+    use bollard::errors::Error::*;
+    matches!(err, DockerStreamError { .. } | DockerResponseServerError { .. })
+}
+
+// A build that failed, which Docker reports in the build's output
+pub fn build(err: bollard::errors::Error, doing: impl Display) -> anyhow::Error {
+    if answered(&err) {
+        return categorized(Category::Build, format!("{}: {}", doing, err), Some(BUILD_HINT));
+    }
+    docker(err, doing)
+}
+
+// A push ECR turned away, which Docker reports in the push's output rather than as a failed request
+pub fn ecr_push(err: bollard::errors::Error, doing: impl Display) -> anyhow::Error {
+    let message = err.to_string().to_lowercase();
+    if ["denied", "unauthorized", "no basic auth credentials", "authorization"].iter().any(|m| message.contains(m)) {
+        return categorized(Category::EcrAuth, format!("{}: {}", doing, err), Some(ECR_HINT));
+    }
+    if answered(&err) {
+        return categorized(Category::Push, format!("{}: {}", doing, err), None);
+    }
+    docker(err, doing)
+}
+
+// Credentials that couldn't be loaded, with what to run about it, which names the profile
+pub fn credentials(message: impl Display, hint: String) -> anyhow::Error {
+    Error { category: Category::Credentials, message: message.to_string(), hint: Some(hint) }.into()
+}
+
+// An invalid config, keeping the error's message. Errors that were already categorized stay as they were
+pub fn config(err: anyhow::Error) -> anyhow::Error {
+    if categorize(&err).is_some() {
+        return err;
+    }
+    categorized(Category::Config, format!("{:#}", err), Some(CONFIG_HINT))
+}
+
+// An endpoint that didn't go into service
+pub fn endpoint_failed(message: impl Display) -> anyhow::Error {
+    categorized(Category::EndpointFailed, message, Some(ENDPOINT_HINT))
+}
+
+// Something that took longer than we'll wait
+pub fn timeout(message: impl Display) -> anyhow::Error {
+    categorized(Category::Timeout, message, Some(TIMEOUT_HINT))
+}
+
+// What an error was to do with and what to do about it, from the categorized error in its chain. Errors that weren't
+// categorized can still be from missing credentials, which fail every AWS call the same way
+fn categorize(err: &anyhow::Error) -> Option<(Category, Option<&str>)> {
+    if let Some(e) = err.chain().find_map(|e| e.downcast_ref::<Error>()) {
+        return Some((e.category, e.hint.as_deref()));
+    }
+    err.chain()
+        .any(|e| e.downcast_ref::<CredentialsError>().is_some())
+        .then_some((Category::Credentials, Some(CREDENTIALS_HINT)))
+}
+
+// Whether trying again might work: AWS throttling or having a problem of its own, or a push that didn't go through
+// for a reason other than ECR turning it away
+pub fn transient(err: &anyhow::Error) -> bool {
+    matches!(categorize(err), Some((Category::Throttled | Category::Unavailable | Category::Push, _)))
+}
+
+// What to exit with for an error, 1 when it wasn't categorized
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    categorize(err).map(|(category, _)| category.exit_code()).unwrap_or(1)
+}
+
+// Several deploys that failed, as one error. Failures that were all the same kind keep that kind, so exit the same
+// way one of them would have
+pub fn summarize<'a>(message: String, errs: impl Iterator<Item = &'a anyhow::Error>) -> anyhow::Error {
+    let mut categories = errs.map(|e| categorize(e).map(|(category, _)| category));
+    match categories.next().flatten() {
+        Some(category) if categories.all(|c| c == Some(category)) => categorized(category, message, None),
+        _ => anyhow::anyhow!(message),
+    }
+}
+
+// The error sageturner stops with, and its hint. In GitHub Actions, both go on the run as an annotation, titled with
+// what went wrong
+pub fn report(err: &anyhow::Error, format: LogFormat) {
+    let categorized = categorize(err);
+    let hint = categorized.and_then(|(_, hint)| hint);
+    match format {
+        LogFormat::Github => {
+            let title = match categorized {
+                Some((category, _)) => format!("sageturner failed: {}", category),
+                None => "sageturner failed".to_string(),
+            };
+            let message = match hint {
+                Some(hint) => format!("{:#}\n{}", err, hint),
+                None => format!("{:#}", err),
+            };
+            println!("{}", github::annotation("error", Some(&title), &message));
+        }
+        _ => {
+            eprintln!("Error: {:?}", err);
+            if let Some(hint) = hint {
+                eprintln!("\nHint: {}", hint);
+            }
+        }
+    }
+}
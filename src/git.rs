@@ -0,0 +1,40 @@
+use std::{path::Path, process::Command};
+
+use anyhow::{anyhow, Result};
+
+// Run git in dir (the config file's directory), returning trimmed stdout
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .map_err(|e| anyhow!("Couldn't run git: {}", e))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "git {} failed in {}: {}",
+            args.join(" "),
+            dir.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+pub fn short_sha(dir: &Path) -> Result<String> {
+    git(dir, &["rev-parse", "--short", "HEAD"])
+}
+
+pub fn full_sha(dir: &Path) -> Result<String> {
+    git(dir, &["rev-parse", "HEAD"])
+}
+
+// None on a detached HEAD, as CI checkouts often are
+pub fn branch(dir: &Path) -> Result<Option<String>> {
+    let branch = git(dir, &["rev-parse", "--abbrev-ref", "HEAD"])?;
+    Ok(Some(branch).filter(|b| b != "HEAD"))
+}
+
+// Whether there are uncommitted changes to tracked files, so the commit isn't the whole story
+pub fn is_dirty(dir: &Path) -> Result<bool> {
+    Ok(!git(dir, &["status", "--porcelain", "--untracked-files=no"])?.is_empty())
+}
@@ -0,0 +1,79 @@
+use std::{fs::OpenOptions, io::Write};
+
+use anyhow::{anyhow, Result};
+use tracing::info;
+
+use crate::{output::DeployResult, state::DeployStatus};
+
+// GitHub Actions sets this to true in every step it runs
+const ACTIONS_ENV: &str = "GITHUB_ACTIONS";
+// The file a step writes its outputs to, as name=value lines
+const OUTPUT_ENV: &str = "GITHUB_OUTPUT";
+
+pub fn in_actions() -> bool {
+    std::env::var(ACTIONS_ENV).is_ok_and(|v| v == "true")
+}
+
+// A workflow command like ::error::message, which Actions shows as an annotation on the run
+pub fn annotation(level: &str, title: Option<&str>, message: &str) -> String {
+    match title {
+        Some(title) => format!("::{} title={}::{}", level, escape_property(title), escape(message)),
+        None => format!("::{}::{}", level, escape(message)),
+    }
+}
+
+// Messages can span lines, which would end the command, so they're escaped the way Actions unescapes them
+fn escape(message: &str) -> String {
+    message.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+fn escape_property(property: &str) -> String {
+    escape(property).replace(':', "%3A").replace(',', "%2C")
+}
+
+// Annotate each deploy that went through, and write what later steps need, like the endpoint and image digest, to
+// the step's outputs. A deploy of one model to one region gets an output for each of its fields, any other gets
+// just deploys, a JSON list for fromJSON
+pub fn report(deploys: &[DeployResult]) -> Result<()> {
+    for deploy in deploys.iter().filter(|d| !matches!(d.status, DeployStatus::Failed | DeployStatus::Partial | DeployStatus::AwaitingApproval)) {
+        let title = format!("Deployed {}", deploy.model);
+        let message = format!(
+            "Endpoint {} in {} is {}",
+            deploy.endpoint.as_deref().unwrap_or_default(),
+            deploy.region,
+            deploy.status
+        );
+        println!("{}", annotation("notice", Some(&title), &message));
+    }
+
+    let Ok(path) = std::env::var(OUTPUT_ENV) else {
+        return Ok(());
+    };
+    let mut outputs = vec![("deploys".to_string(), serde_json::to_string(deploys)?)];
+    if let [deploy] = deploys {
+        let fields = [
+            // As in --output json, e.g. InService
+            ("status", serde_json::to_value(deploy.status)?.as_str().map(|s| s.to_string())),
+            ("endpoint", deploy.endpoint.clone()),
+            ("endpoint_arn", deploy.endpoint_arn.clone()),
+            ("sagemaker_model", deploy.sagemaker_model.clone()),
+            ("image", deploy.image.clone()),
+            ("image_digest", deploy.image_digest.clone()),
+            ("artefact", deploy.artefact.clone()),
+            ("model_package", deploy.model_package.clone()),
+            ("dashboard_url", deploy.dashboard_url.clone()),
+        ];
+        outputs.extend(fields.into_iter().map(|(name, value)| (name.to_string(), value.unwrap_or_default())));
+    }
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .map_err(|e| anyhow!("Couldn't open {} ({}): {}", OUTPUT_ENV, path, e))?;
+    for (name, value) in &outputs {
+        writeln!(file, "{}={}", name, value)?;
+    }
+    info!("Set step outputs: {}", outputs.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>().join(", "));
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use std::{collections::HashMap, path::Path, process::Stdio};
+
+use anyhow::{anyhow, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::Command,
+};
+use tracing::{info, info_span, Instrument};
+
+use crate::model_config::{Hooks, ModelConfig};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Hook {
+    PreBuild,
+    PostBuild,
+    PreDeploy,
+    PostDeploy,
+}
+
+impl Hook {
+    fn name(&self) -> &'static str {
+        match self {
+            Hook::PreBuild => "pre_build",
+            Hook::PostBuild => "post_build",
+            Hook::PreDeploy => "pre_deploy",
+            Hook::PostDeploy => "post_deploy",
+        }
+    }
+
+    fn commands<'a>(&self, hooks: &'a Hooks) -> &'a [String] {
+        match self {
+            Hook::PreBuild => &hooks.pre_build,
+            Hook::PostBuild => &hooks.post_build,
+            Hook::PreDeploy => &hooks.pre_deploy,
+            Hook::PostDeploy => &hooks.post_deploy,
+        }
+    }
+}
+
+// What every hook is told about the deploy. IMAGE_URI and S3_PATH are added once the deploy knows them
+pub fn environment(model_config: &ModelConfig, endpoint_name: &str, sagemaker_name: &str, region: &str) -> HashMap<&'static str, String> {
+    let mut environment = HashMap::from([
+        ("MODEL_NAME", model_config.name.clone()),
+        ("ENDPOINT_NAME", endpoint_name.to_string()),
+        ("SAGEMAKER_MODEL", sagemaker_name.to_string()),
+        // So the AWS CLI in a hook talks to the region the deploy's in
+        ("AWS_REGION", region.to_string()),
+    ]);
+    if let Some(version) = &model_config.version {
+        environment.insert("MODEL_VERSION", version.clone());
+    }
+    if let Some(deploy_environment) = &model_config.environment {
+        environment.insert("DEPLOY_ENVIRONMENT", deploy_environment.clone());
+    }
+    environment
+}
+
+// Run the hook's commands in order, in dir, stopping at the first that fails. Their output is logged, rather than
+// printed, so it doesn't end up in --output json or under a progress bar
+pub async fn run(hook: Hook, hooks: &Hooks, environment: &HashMap<&'static str, String>, dir: &Path) -> Result<()> {
+    for command in hook.commands(hooks) {
+        info!("Running {} hook: {}", hook.name(), command);
+        run_command(command, environment, dir)
+            .instrument(info_span!("hook", hook = hook.name()))
+            .await
+            .map_err(|e| anyhow!("{} hook `{}` failed: {}", hook.name(), command, e))?;
+    }
+    Ok(())
+}
+
+async fn run_command(command: &str, environment: &HashMap<&'static str, String>, dir: &Path) -> Result<()> {
+    let mut child = shell(command)
+        .current_dir(dir)
+        .envs(environment)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| anyhow!("couldn't start it: {}", e))?;
+    let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+    let (status, _, _) = tokio::join!(child.wait(), log_lines(stdout), log_lines(stderr));
+    let status = status?;
+    if !status.success() {
+        return Err(anyhow!("it exited with {}", status));
+    }
+    Ok(())
+}
+
+async fn log_lines(output: Option<impl AsyncRead + Unpin>) {
+    let Some(output) = output else { return };
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        info!("  {}", line);
+    }
+}
+
+fn shell(command: &str) -> Command {
+    let (program, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let mut shell = Command::new(program);
+    shell.args([flag, command]);
+    shell
+}
@@ -0,0 +1,253 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::{
+    instances::{self, InstanceKind},
+    model_config::{self, ModelConfig},
+    presets::Preset,
+    prompt, EndpointType,
+};
+
+// What init asks about. Without --interactive it writes the defaults
+struct Answers {
+    name: String,
+    // None writes a sageturner.py stub to fill in, instead of using a preset
+    preset: Option<Preset>,
+    gpu: bool,
+    endpoint_type: EndpointType,
+    instance_type: String,
+    python_version: String,
+}
+
+const PRESETS: &[Preset] = &[
+    Preset::Sklearn,
+    Preset::Pytorch,
+    Preset::Transformers,
+    Preset::Xgboost,
+    Preset::Onnx,
+    Preset::Embeddings,
+    Preset::Tensorflow,
+];
+
+// The files init wrote
+#[derive(Debug, Serialize)]
+pub struct Written {
+    pub config: PathBuf,
+    // None with a preset, or if there was already a sageturner.py
+    pub code: Option<PathBuf>,
+}
+
+// Write a sageturner.yaml (and a sageturner.py stub, if there's no preset) to dir, ready to edit and deploy. What to
+// do next is printed to out
+pub fn run(dir: &Path, name: Option<String>, interactive: bool, force: bool, out: &mut dyn Write) -> Result<Written> {
+    let config_path = dir.join("sageturner.yaml");
+    if config_path.exists() && !force {
+        return Err(anyhow!("{} already exists, pass --force to overwrite it", config_path.display()));
+    }
+    // The directory's name is a good guess at the model's, made safe for SageMaker names
+    let dir_name = std::path::absolute(dir)?
+        .file_name()
+        .map(|n| sagemaker_name(&n.to_string_lossy()))
+        .filter(|n| !n.is_empty())
+        .unwrap_or("my-model".to_string());
+    let name = name.unwrap_or(dir_name);
+
+    let answers = if interactive {
+        ask_answers(name)?
+    } else {
+        Answers {
+            name,
+            preset: None,
+            gpu: false,
+            endpoint_type: EndpointType::Serverless,
+            instance_type: "ml.m5.xlarge".to_string(),
+            python_version: "3.12".to_string(),
+        }
+    };
+
+    let config = render_config(&answers);
+    // Make sure what we write is a config deploy will read
+    let mut value: serde_yaml::Value = serde_yaml::from_str(&config)?;
+    if let Some(m) = value.as_mapping_mut() {
+        m.remove("schema_version");
+    }
+    serde_yaml::from_value::<ModelConfig>(value)
+        .map_err(|e| anyhow!("Sageturner wrote an invalid config, please raise an issue: {}", e))?;
+
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(&config_path, config)?;
+    writeln!(out, "Wrote {}", config_path.display())?;
+    let mut code = None;
+    if answers.preset.is_none() {
+        let code_dir = dir.join("code");
+        let code_path = code_dir.join("sageturner.py");
+        if code_path.exists() && !force {
+            writeln!(out, "Leaving your existing {} alone", code_path.display())?;
+        } else {
+            std::fs::create_dir_all(&code_dir)?;
+            std::fs::write(&code_path, STUB_CODE)?;
+            writeln!(out, "Wrote {}, fill in load() and predict()", code_path.display())?;
+            code = Some(code_path);
+        }
+    }
+
+    writeln!(out, "Put your model in {}, then deploy it with:", dir.join("model").display())?;
+    writeln!(
+        out,
+        "  sageturner deploy --endpoint-type {} --container-mode generate --config-path {}",
+        answers.endpoint_type,
+        config_path.display()
+    )?;
+    Ok(Written { config: config_path, code })
+}
+
+fn ask_answers(name: String) -> Result<Answers> {
+    let name = loop {
+        let answer = prompt::ask("Model name?", &name)?;
+        if !answer.is_empty() && answer == sagemaker_name(&answer) {
+            break answer;
+        }
+        eprintln!("Model names can only have letters, numbers and -");
+    };
+
+    let mut frameworks = vec!["custom (write your own load() and predict())".to_string()];
+    frameworks.extend(PRESETS.iter().map(|p| p.to_string()));
+    let frameworks: Vec<&str> = frameworks.iter().map(|f| f.as_str()).collect();
+    let preset = match prompt::choose("What framework is your model?", &frameworks, 0)? {
+        0 => None,
+        i => Some(PRESETS[i - 1]),
+    };
+
+    let gpu = prompt::choose("What should it run on?", &["cpu", "gpu (NVIDIA)"], 0)? == 1;
+
+    let endpoint_type = if gpu {
+        eprintln!("Serverless endpoints don't have GPUs, so it'll be a server endpoint");
+        EndpointType::Server
+    } else {
+        match prompt::choose(
+            "What kind of endpoint?",
+            &["serverless (pay per request, scales to zero)", "server (always-on instances)"],
+            0,
+        )? {
+            0 => EndpointType::Serverless,
+            _ => EndpointType::Server,
+        }
+    };
+
+    let default_instance = if gpu { "ml.g5.xlarge" } else { "ml.m5.xlarge" };
+    let instance_type = if endpoint_type == EndpointType::Server {
+        loop {
+            let answer = prompt::ask("Instance type?", default_instance)?;
+            match instances::lookup(&answer) {
+                Ok(InstanceKind::Gpu) => break answer,
+                Ok(_) if !gpu => break answer,
+                Ok(_) => eprintln!("{} doesn't have an NVIDIA GPU, try ml.g5.xlarge or ml.g6.xlarge", answer),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    } else {
+        default_instance.to_string()
+    };
+
+    let python_version = loop {
+        let answer = prompt::ask("Python version?", "3.12")?;
+        if answer.strip_prefix("3.").is_some_and(|minor| minor.parse::<u32>().is_ok()) {
+            break answer;
+        }
+        eprintln!("Use a python 3 version like 3.12");
+    };
+
+    Ok(Answers { name, preset, gpu, endpoint_type, instance_type, python_version })
+}
+
+// SageMaker names only allow letters, numbers and -
+fn sagemaker_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect::<String>()
+        .trim_matches('-')
+        .to_string()
+}
+
+fn render_config(answers: &Answers) -> String {
+    let mut lines = vec![
+        "# Written by sageturner init. Deploy it with:".to_string(),
+        format!(
+            "#   sageturner deploy --endpoint-type {} --container-mode generate --config-path sageturner.yaml",
+            answers.endpoint_type
+        ),
+        format!("schema_version: {}", model_config::SCHEMA_VERSION),
+        "# The name of the model, used to name the SageMaker model, endpoint and ECR repository".to_string(),
+        format!("name: {}", answers.name),
+        "# Your model: a .tar.gz, or a directory Sageturner packages for you. The container finds it at /opt/ml/model".to_string(),
+        "artefact: ./model".to_string(),
+        "container:".to_string(),
+        "  generate_container:".to_string(),
+    ];
+    match answers.preset {
+        None => {
+            lines.push("    # The directory with your sageturner.py (load() and predict()), copied into the image".to_string());
+            lines.push("    code_dir: ./code".to_string());
+        }
+        Some(p) => {
+            lines.push("    # Sageturner writes load() and predict() for you".to_string());
+            lines.push(format!("    preset: {}", p));
+            if p == Preset::Transformers {
+                lines.push("    preset_options:".to_string());
+                lines.push("      # The pipeline task, e.g. text-classification, text-generation".to_string());
+                lines.push("      task: text-classification".to_string());
+            }
+        }
+    }
+    lines.extend([
+        "    # Extra packages to pip install, with any pip args".to_string(),
+        "    python_packages: []".to_string(),
+        "    # Extra packages to apt-get install".to_string(),
+        "    system_packages: []".to_string(),
+        "    # Install the CUDA toolkit, for NVIDIA GPUs".to_string(),
+        format!("    install_cuda: {}", answers.gpu),
+        format!("    python_version: \"{}\"", answers.python_version),
+        "compute:".to_string(),
+    ]);
+    match answers.endpoint_type {
+        EndpointType::Serverless => lines.extend([
+            "  serverless:".to_string(),
+            "    # MB of memory: 1024 to 6144, in steps of 1024".to_string(),
+            "    memory: 2048".to_string(),
+            "    # Instances kept warm, 0 for none".to_string(),
+            "    provisioned_concurrency: 0".to_string(),
+            "    # Most instances running at once".to_string(),
+            "    max_concurrency: 5".to_string(),
+        ]),
+        EndpointType::Server => lines.extend([
+            "  server:".to_string(),
+            format!("    instance_type: {}", answers.instance_type),
+            "    initial_instance_count: 1".to_string(),
+        ]),
+    }
+    lines.join("\n") + "\n"
+}
+
+const STUB_CODE: &str = r#"import os
+
+# The generated container calls load() once at startup and predict() for every request.
+# Keep their signatures as they are
+def load():
+    # SageMaker puts your artefact in /opt/ml/model. Locally, it's the model directory next to sageturner.yaml
+    model_dir = "/opt/ml/model" if os.path.isdir("/opt/ml/model") else "../model"
+    raise NotImplementedError(f"Load your model from {model_dir} and return it")
+
+def predict(model, request):
+    # request is the JSON body sent to the endpoint. Return something JSON serialisable
+    raise NotImplementedError("Run your model on the request")
+
+if __name__ == "__main__":
+    # Try your code locally before deploying, the same way the container will call it
+    model = load()
+    print(predict(model, {}))
+"#;
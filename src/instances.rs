@@ -0,0 +1,76 @@
+use anyhow::{anyhow, Result};
+
+// What an instance family has to run models on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InstanceKind {
+    Cpu,
+    // NVIDIA GPUs
+    Gpu,
+    // AWS Inferentia/Trainium
+    Neuron,
+}
+
+const SIZES_5: &[&str] = &["large", "xlarge", "2xlarge", "4xlarge", "12xlarge", "24xlarge"];
+const SIZES_6: &[&str] = &["large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge", "24xlarge", "32xlarge"];
+const SIZES_7: &[&str] = &["large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge", "24xlarge", "48xlarge"];
+const SIZES_GRAVITON: &[&str] = &["large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge"];
+const SIZES_G: &[&str] = &["xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge", "24xlarge", "48xlarge"];
+
+// The instance families SageMaker real-time endpoints can run on, and their sizes
+const CATALOG: &[(&str, InstanceKind, &[&str])] = &[
+    ("t2", InstanceKind::Cpu, &["medium", "large", "xlarge", "2xlarge"]),
+    ("m4", InstanceKind::Cpu, &["xlarge", "2xlarge", "4xlarge", "10xlarge", "16xlarge"]),
+    ("m5", InstanceKind::Cpu, SIZES_5),
+    ("m5d", InstanceKind::Cpu, SIZES_5),
+    ("m6i", InstanceKind::Cpu, SIZES_6),
+    ("m7i", InstanceKind::Cpu, SIZES_7),
+    ("m6g", InstanceKind::Cpu, SIZES_GRAVITON),
+    ("c4", InstanceKind::Cpu, &["large", "xlarge", "2xlarge", "4xlarge", "8xlarge"]),
+    ("c5", InstanceKind::Cpu, &["large", "xlarge", "2xlarge", "4xlarge", "9xlarge", "18xlarge"]),
+    ("c5d", InstanceKind::Cpu, &["large", "xlarge", "2xlarge", "4xlarge", "9xlarge", "18xlarge"]),
+    ("c6i", InstanceKind::Cpu, SIZES_6),
+    ("c7i", InstanceKind::Cpu, SIZES_7),
+    ("c6g", InstanceKind::Cpu, SIZES_GRAVITON),
+    ("r5", InstanceKind::Cpu, SIZES_5),
+    ("r5d", InstanceKind::Cpu, SIZES_5),
+    ("r6i", InstanceKind::Cpu, SIZES_6),
+    ("r7i", InstanceKind::Cpu, SIZES_7),
+    ("r6g", InstanceKind::Cpu, SIZES_GRAVITON),
+    ("p2", InstanceKind::Gpu, &["xlarge", "8xlarge", "16xlarge"]),
+    ("p3", InstanceKind::Gpu, &["2xlarge", "8xlarge", "16xlarge"]),
+    ("p4d", InstanceKind::Gpu, &["24xlarge"]),
+    ("p5", InstanceKind::Gpu, &["48xlarge"]),
+    ("g4dn", InstanceKind::Gpu, &["xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge"]),
+    ("g5", InstanceKind::Gpu, SIZES_G),
+    ("g6", InstanceKind::Gpu, SIZES_G),
+    ("g6e", InstanceKind::Gpu, SIZES_G),
+    ("inf1", InstanceKind::Neuron, &["xlarge", "2xlarge", "6xlarge", "24xlarge"]),
+    ("inf2", InstanceKind::Neuron, &["xlarge", "8xlarge", "24xlarge", "48xlarge"]),
+    ("trn1", InstanceKind::Neuron, &["2xlarge", "32xlarge"]),
+    ("trn1n", InstanceKind::Neuron, &["32xlarge"]),
+];
+
+// Check an instance type is one SageMaker can host on, e.g. ml.g5.xlarge, and say what it has to run models on
+pub fn lookup(instance_type: &str) -> Result<InstanceKind> {
+    let (family, size) = instance_type
+        .strip_prefix("ml.")
+        .and_then(|t| t.split_once('.'))
+        .ok_or_else(|| anyhow!("{} isn't a SageMaker instance type, they look like ml.m5.large", instance_type))?;
+    let Some((_, kind, sizes)) = CATALOG.iter().find(|(f, _, _)| *f == family) else {
+        let families: Vec<&str> = CATALOG.iter().map(|(f, _, _)| *f).collect();
+        return Err(anyhow!(
+            "{} isn't a SageMaker instance family. Use one of {}",
+            family,
+            families.join(", ")
+        ));
+    };
+    if !sizes.contains(&size) {
+        return Err(anyhow!(
+            "{} isn't a size of ml.{}. Use one of {}",
+            size,
+            family,
+            sizes.join(", ")
+        ));
+    }
+    Ok(*kind)
+}
@@ -0,0 +1,209 @@
+// Sageturner as a library, for deploying to SageMaker from your own code rather than the CLI. Deployer builds,
+// pushes, uploads and deploys, and the CLI in main.rs is a thin layer over it
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+mod artefact;
+pub mod aws;
+mod canary;
+mod cost;
+mod dashboard;
+mod deploy;
+mod diff;
+pub mod docker;
+pub mod errors;
+mod git;
+mod hooks;
+pub mod github;
+pub mod init;
+mod instances;
+pub mod loadtest;
+pub mod logging;
+pub mod model_config;
+mod monitor;
+mod notify;
+pub mod orphans;
+#[cfg(feature = "otel")]
+mod otel;
+pub mod output;
+mod presets;
+pub mod progress;
+mod promote;
+pub mod prompt;
+mod provenance;
+pub mod recommend;
+mod registry;
+pub mod retention;
+mod retry;
+mod smoke;
+mod templates;
+pub mod state;
+
+pub use deploy::{bucket_and_role, BuiltImage, DeployOptions, DeployTarget, Deployer, ModelSource, PushedImage};
+
+pub const DEFAULT_ROLE_NAME: &str = "sageturner-role-sagemaker";
+pub const DEFAULT_BUCKET_NAME: &str = "sageturner-sagemaker-models";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EndpointType {
+    Serverless,
+    Server,
+}
+
+impl FromStr for EndpointType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "serverless" => Ok(EndpointType::Serverless),
+            "server" => Ok(EndpointType::Server),
+            _ => Err(anyhow!(
+                "Invalid endpoint type. serverless or server only, not: {}",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for EndpointType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EndpointType::Serverless => write!(f, "serverless"),
+            EndpointType::Server => write!(f, "server"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ContainerMode {
+    Generate,
+    Provide,
+    Vllm,
+    Tgi,
+    Triton,
+}
+
+impl FromStr for ContainerMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "generate" => Ok(ContainerMode::Generate),
+            "provide" => Ok(ContainerMode::Provide),
+            "vllm" => Ok(ContainerMode::Vllm),
+            "tgi" => Ok(ContainerMode::Tgi),
+            "triton" => Ok(ContainerMode::Triton),
+            _ => Err(anyhow!(
+                "Invalid container mode. use generate, provide, vllm, tgi or triton, not: {}",
+                s
+            )),
+        }
+    }
+}
+
+// A deploy's stages, in the order they run. deploy --from and --until run a slice of them
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Stage {
+    // Checking the config, budget and quotas
+    Validate,
+    Build,
+    // The image is in ECR, at the deploy record's image
+    Push,
+    // The artefact is in S3, at the deploy record's artefact
+    Upload,
+    Model,
+    // The endpoint config, and creating or updating the endpoint, which may not have gone into service yet
+    Endpoint,
+    Wait,
+    // Then setting up monitoring and cleaning up superseded models
+    SmokeTest,
+}
+
+impl Stage {
+    // Whether the stage is in the slice from..=until, either end of which is open if it's not given
+    pub fn between(self, from: Option<Stage>, until: Option<Stage>) -> bool {
+        from.is_none_or(|from| from <= self) && until.is_none_or(|until| self <= until)
+    }
+}
+
+impl FromStr for Stage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "validate" => Ok(Stage::Validate),
+            "build" => Ok(Stage::Build),
+            "push" => Ok(Stage::Push),
+            "upload" => Ok(Stage::Upload),
+            "model" => Ok(Stage::Model),
+            "endpoint" => Ok(Stage::Endpoint),
+            "wait" => Ok(Stage::Wait),
+            "smoke-test" => Ok(Stage::SmokeTest),
+            _ => Err(anyhow!(
+                "Invalid stage. use validate, build, push, upload, model, endpoint, wait or smoke-test, not: {}",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Validate => write!(f, "validate"),
+            Stage::Build => write!(f, "build"),
+            Stage::Push => write!(f, "push"),
+            Stage::Upload => write!(f, "upload"),
+            Stage::Model => write!(f, "model"),
+            Stage::Endpoint => write!(f, "endpoint"),
+            Stage::Wait => write!(f, "wait"),
+            Stage::SmokeTest => write!(f, "smoke-test"),
+        }
+    }
+}
+
+impl ContainerMode {
+    // Whether deploys in this mode build and push an image, so need Docker. TGI and Triton use AWS's images
+    pub fn builds_image(self) -> bool {
+        matches!(self, ContainerMode::Generate | ContainerMode::Provide | ContainerMode::Vllm)
+    }
+}
+
+impl std::fmt::Display for ContainerMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerMode::Generate => write!(f, "generate"),
+            ContainerMode::Provide => write!(f, "provide"),
+            ContainerMode::Vllm => write!(f, "vllm"),
+            ContainerMode::Tgi => write!(f, "tgi"),
+            ContainerMode::Triton => write!(f, "triton"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stage;
+
+    #[test]
+    fn between_with_open_ends() {
+        assert!(Stage::Validate.between(None, None));
+        assert!(Stage::SmokeTest.between(None, None));
+        assert!(Stage::Build.between(None, Some(Stage::Push)));
+        assert!(!Stage::Upload.between(None, Some(Stage::Push)));
+        assert!(Stage::SmokeTest.between(Some(Stage::Model), None));
+        assert!(!Stage::Upload.between(Some(Stage::Model), None));
+    }
+
+    #[test]
+    fn between_with_closed_ends() {
+        assert!(Stage::Model.between(Some(Stage::Model), Some(Stage::Endpoint)));
+        assert!(Stage::Endpoint.between(Some(Stage::Model), Some(Stage::Endpoint)));
+        assert!(!Stage::Upload.between(Some(Stage::Model), Some(Stage::Endpoint)));
+        assert!(!Stage::Wait.between(Some(Stage::Model), Some(Stage::Endpoint)));
+        assert!(Stage::Push.between(Some(Stage::Push), Some(Stage::Push)));
+    }
+}
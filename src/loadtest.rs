@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use tracing::info;
+
+use crate::aws;
+
+// What happened to one request
+struct Sample {
+    latency: Duration,
+    // None for a 2xx, otherwise the status the model answered with or why the request failed
+    error: Option<String>,
+}
+
+// Send the payload to the endpoint from concurrency workers at once, each sending its next request as soon as the
+// last is answered, until duration is up. Returns the throughput, latency and errors
+pub async fn run(
+    endpoint_name: &str,
+    body: Vec<u8>,
+    content_type: &str,
+    concurrency: usize,
+    duration: Duration,
+    runtime_client: &aws_sdk_sagemakerruntime::Client,
+) -> Result<Report> {
+    info!("Load testing endpoint {} with {} requests at a time for {}s", endpoint_name, concurrency, duration.as_secs());
+    let started = Instant::now();
+    let deadline = started + duration;
+    let workers = (0..concurrency).map(|_| {
+        let (endpoint_name, content_type) = (endpoint_name.to_string(), content_type.to_string());
+        let (body, runtime_client) = (body.clone(), runtime_client.clone());
+        tokio::spawn(async move {
+            let mut samples = Vec::new();
+            while Instant::now() < deadline {
+                let sent = Instant::now();
+                let result = aws::invoke_endpoint(&endpoint_name, None, &content_type, body.clone(), &runtime_client).await;
+                let error = match result {
+                    Ok((status, _)) if (200..300).contains(&status) => None,
+                    Ok((status, _)) => Some(format!("status {}", status)),
+                    Err(e) => Some(e.to_string()),
+                };
+                samples.push(Sample { latency: sent.elapsed(), error });
+            }
+            samples
+        })
+    });
+    let mut samples = Vec::new();
+    for worker in futures_util::future::join_all(workers).await {
+        samples.extend(worker.map_err(|e| anyhow!("A load test worker failed: {}", e))?);
+    }
+    Ok(Report::new(endpoint_name, &samples, started.elapsed()))
+}
+
+// How the endpoint coped
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub endpoint: String,
+    pub requests: usize,
+    pub seconds: f64,
+    pub requests_per_second: f64,
+    // Of successful requests, None if there weren't any
+    pub latency_ms: Option<Latency>,
+    pub failed: usize,
+    pub error_rate_percent: f64,
+    // Each error, and how many times it happened, most common first
+    pub errors: Vec<(String, usize)>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Latency {
+    pub p50: u128,
+    pub p90: u128,
+    pub p99: u128,
+    pub max: u128,
+}
+
+impl Report {
+    fn new(endpoint_name: &str, samples: &[Sample], elapsed: Duration) -> Report {
+        let seconds = elapsed.as_secs_f64();
+        let mut latencies: Vec<Duration> = samples.iter().filter(|s| s.error.is_none()).map(|s| s.latency).collect();
+        latencies.sort();
+        let latency_ms = latencies.last().map(|max| {
+            let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p).round() as usize].as_millis();
+            Latency { p50: percentile(0.5), p90: percentile(0.9), p99: percentile(0.99), max: max.as_millis() }
+        });
+
+        let mut errors: HashMap<&str, usize> = HashMap::new();
+        for error in samples.iter().filter_map(|s| s.error.as_deref()) {
+            *errors.entry(error).or_default() += 1;
+        }
+        let failed: usize = errors.values().sum();
+        let error_rate_percent = match samples.len() {
+            0 => 0.0,
+            n => failed as f64 * 100.0 / n as f64,
+        };
+        let mut errors: Vec<_> = errors.into_iter().map(|(error, count)| (error.to_string(), count)).collect();
+        errors.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        Report {
+            endpoint: endpoint_name.to_string(),
+            requests: samples.len(),
+            seconds,
+            requests_per_second: samples.len() as f64 / seconds,
+            latency_ms,
+            failed,
+            error_rate_percent,
+            errors,
+        }
+    }
+
+    pub fn print(&self) {
+        println!(
+            "Endpoint {}: {} requests in {:.1}s, {:.1} requests/s",
+            self.endpoint, self.requests, self.seconds, self.requests_per_second
+        );
+        if let Some(latency) = &self.latency_ms {
+            println!(
+                "Latency of successful requests: p50 {}ms, p90 {}ms, p99 {}ms, max {}ms",
+                latency.p50, latency.p90, latency.p99, latency.max
+            );
+        }
+        println!("Errors: {} ({:.2}%)", self.failed, self.error_rate_percent);
+        for (error, count) in &self.errors {
+            println!("  {:>6}  {}", count, error);
+        }
+    }
+}
+
+// A duration like 60s, 5m or 1h. A number on its own is seconds
+pub fn parse_duration(duration: &str) -> Result<Duration> {
+    let (number, unit) = duration.split_at(duration.find(|c: char| !c.is_ascii_digit()).unwrap_or(duration.len()));
+    let number: u64 = number
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| anyhow!("{} should be a number and a unit, like 60s", duration))?;
+    match unit {
+        "" | "s" => Ok(Duration::from_secs(number)),
+        "m" => Ok(Duration::from_secs(number * 60)),
+        "h" => Ok(Duration::from_secs(number * 60 * 60)),
+        _ => Err(anyhow!("{} has an unknown unit, use s, m or h", duration)),
+    }
+}
@@ -0,0 +1,178 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, Result};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::{
+    fmt::{format, writer::BoxMakeWriter, FmtContext, FormatEvent, FormatFields},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+use crate::{github, output::OutputFormat, progress::LogWriter};
+
+// Sets what's logged in place of -q and -v, e.g. SAGETURNER_LOG=sageturner=debug,aws_config=debug
+const LOG_ENV: &str = "SAGETURNER_LOG";
+
+// How much to log, from -q and -v
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Verbosity {
+    // Just errors. Results are still printed
+    Quiet,
+    // What each phase of a deploy is doing
+    Normal,
+    // Docker's full build output, image push progress and AWS request IDs
+    Verbose,
+    // Everything, including the AWS SDK's own logs
+    VeryVerbose,
+}
+
+impl Verbosity {
+    pub fn from_flags(quiet: bool, verbose: u8) -> Result<Verbosity> {
+        match (quiet, verbose) {
+            (true, 0) => Ok(Verbosity::Quiet),
+            (true, _) => Err(anyhow!("Pass one of --quiet or --verbose")),
+            (false, 0) => Ok(Verbosity::Normal),
+            (false, 1) => Ok(Verbosity::Verbose),
+            (false, _) => Ok(Verbosity::VeryVerbose),
+        }
+    }
+
+    fn filter(&self) -> &'static str {
+        match self {
+            Verbosity::Quiet => "sageturner=error",
+            Verbosity::Normal => "sageturner=info",
+            Verbosity::Verbose => "sageturner=debug",
+            Verbosity::VeryVerbose => "sageturner=trace,aws_config=debug,aws_smithy_runtime=debug",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LogFormat {
+    // Just the messages, as sageturner has always printed them
+    Plain,
+    // With timestamps, levels and the deploy phase each message is from
+    Full,
+    // One JSON object per line, for log aggregators
+    Json,
+    // Plain, with warnings and errors as GitHub Actions annotations
+    Github,
+}
+
+impl LogFormat {
+    // What to log in when --log-format isn't passed: plain, unless we're in GitHub Actions
+    pub fn detect() -> LogFormat {
+        match github::in_actions() {
+            true => LogFormat::Github,
+            false => LogFormat::Plain,
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "plain" => Ok(LogFormat::Plain),
+            "full" => Ok(LogFormat::Full),
+            "json" => Ok(LogFormat::Json),
+            "github" => Ok(LogFormat::Github),
+            _ => Err(anyhow!("Invalid log format. plain, full, json or github only, not: {}", s)),
+        }
+    }
+}
+
+// Send sageturner's logs to stdout in the format asked for, filtered by SAGETURNER_LOG, or the verbosity if it isn't
+// set. With --output json, stdout is kept for the result, so they go to stderr. Built with the otel feature, spans are
+// exported too, if there's a collector to send them to. Without ansi, the full format's levels aren't coloured, as
+// they otherwise are unless NO_COLOR is set
+pub fn init(format: LogFormat, output: OutputFormat, verbosity: Verbosity, ansi: bool) -> Result<Guard> {
+    let filter = match std::env::var(LOG_ENV) {
+        Ok(filter) => EnvFilter::try_new(&filter).map_err(|e| anyhow!("{} isn't a valid filter: {}", LOG_ENV, e))?,
+        Err(_) => EnvFilter::new(verbosity.filter()),
+    };
+    let writer = match output {
+        OutputFormat::Text => BoxMakeWriter::new(|| LogWriter(std::io::stdout())),
+        OutputFormat::Json => BoxMakeWriter::new(|| LogWriter(std::io::stderr())),
+    };
+    let mut layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    if !ansi {
+        layer = layer.with_ansi(false);
+    }
+    let layer = match format {
+        LogFormat::Plain => layer.event_format(Plain).boxed(),
+        LogFormat::Github => layer.event_format(Github).boxed(),
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Json => layer.json().with_span_list(true).with_current_span(false).boxed(),
+    };
+    let subscriber = tracing_subscriber::registry().with(layer.with_filter(filter));
+
+    #[cfg(feature = "otel")]
+    let (otel, provider) = crate::otel::layer()?.unzip();
+    #[cfg(feature = "otel")]
+    let subscriber = subscriber.with(otel);
+
+    subscriber.try_init().map_err(|e| anyhow!("Couldn't set up logging: {}", e))?;
+    Ok(Guard {
+        #[cfg(feature = "otel")]
+        provider,
+    })
+}
+
+// Exports any spans that haven't been yet when it's dropped, so it's kept until sageturner exits
+pub struct Guard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Couldn't export spans: {}", e);
+            }
+        }
+    }
+}
+
+// Messages on their own, with warnings and errors called out
+struct Plain;
+
+impl<S, N> FormatEvent<S, N> for Plain
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: format::Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        match *event.metadata().level() {
+            Level::WARN => write!(writer, "Warning: ")?,
+            Level::ERROR => write!(writer, "Error: ")?,
+            _ => {}
+        }
+        ctx.field_format().format_fields(writer.by_ref(), event)?;
+        writeln!(writer)
+    }
+}
+
+// Messages on their own, with warnings and errors as workflow commands, so they show on the run's summary
+struct Github;
+
+impl<S, N> FormatEvent<S, N> for Github
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(&self, ctx: &FmtContext<'_, S, N>, mut writer: format::Writer<'_>, event: &Event<'_>) -> fmt::Result {
+        let mut message = String::new();
+        ctx.field_format().format_fields(format::Writer::new(&mut message), event)?;
+        match *event.metadata().level() {
+            Level::WARN => writeln!(writer, "{}", github::annotation("warning", None, &message)),
+            Level::ERROR => writeln!(writer, "{}", github::annotation("error", None, &message)),
+            _ => writeln!(writer, "{}", message),
+        }
+    }
+}
@@ -1,35 +1,77 @@
-use std::{path::Path, str::FromStr, time::Duration};
+use std::{io::Write, path::Path, process::ExitCode};
 
 use anyhow::{anyhow, Result};
-use argh::FromArgs;
-use aws_config::timeout::TimeoutConfig;
-use bollard::Docker;
+use argh::{ArgsInfo, FromArgs};
+use aws_sdk_sagemaker::types::Tag;
 use chrono::Utc;
+use tracing::{error, info, info_span, Instrument};
 
+use sageturner::{
+    aws, bucket_and_role, docker, errors, github, init, loadtest, logging, model_config, orphans, output, progress, prompt,
+    recommend, retention, state, ContainerMode, DeployOptions, DeployTarget, Deployer, EndpointType,
+    ModelSource, Stage, DEFAULT_BUCKET_NAME, DEFAULT_ROLE_NAME,
+};
 
-mod aws;
-mod docker;
-mod model_config;
-mod pyserve;
+mod completions;
 
-const DEFAULT_ROLE_NAME: &str = "sageturner-role-sagemaker";
-const DEFAULT_BUCKET_NAME: &str = "sageturner-sagemaker-models";
-
-#[derive(Debug, FromArgs, PartialEq)]
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
 #[argh(description = "Sageturner deploys your models to Amazon SageMaker in one command.")]
 struct SageturnerCLI {
+    #[argh(
+        option,
+        description = "how to log progress: plain, full (with timestamps, levels and deploy phases), json, or github for \
+        GitHub Actions annotations and step outputs, the default in Actions"
+    )]
+    log_format: Option<logging::LogFormat>,
+
+    #[argh(
+        option,
+        default = "output::OutputFormat::Text",
+        description = "what to print on stdout: text, or json for just the command's result, with progress on stderr"
+    )]
+    output: output::OutputFormat,
+
+    #[argh(switch, short = 'q', description = "only print the result and errors")]
+    quiet: bool,
+
+    #[argh(
+        switch,
+        short = 'v',
+        description = "show Docker's full build output and AWS request IDs. Twice (-vv) for the AWS SDK's own logs too"
+    )]
+    verbose: u8,
+
+    #[argh(
+        switch,
+        description = "for CI and build systems: never prompt (failing instead of asking), print no colours, progress bars or \
+        other control sequences, and write output a line at a time"
+    )]
+    non_interactive: bool,
+
     #[argh(subcommand)]
     nested: SageturnerSubCommands,
 }
 
-#[derive(Debug, FromArgs, PartialEq)]
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
 #[argh(subcommand)]
 enum SageturnerSubCommands {
     Deploy(Deploy),
-    Setup(Setup)
+    Setup(Setup),
+    Artefact(Artefact),
+    List(List),
+    Config(Config),
+    Init(Init),
+    Gc(Gc),
+    Rollback(Rollback),
+    Unlock(Unlock),
+    Versions(Versions),
+    Promote(Promote),
+    Loadtest(Loadtest),
+    Recommend(Recommend),
+    Completions(Completions),
 }
 
-#[derive(Debug, FromArgs, PartialEq)]
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
 #[argh(
     subcommand,
     name = "deploy",
@@ -39,329 +81,1540 @@ struct Deploy {
     #[argh(
         option,
         short = 'e',
-        description = "the type of endpoint for deployment: serverless, server)"
+        description = "the type of endpoint for deployment: serverless, server. Workspace models can set their own"
     )]
-    endpoint_type: EndpointType,
+    endpoint_type: Option<EndpointType>,
 
     #[argh(
         option,
         short = 'm',
-        description = "sageturner container mode: generate, provide"
+        description = "sageturner container mode: generate, provide, vllm, tgi, triton. Workspace models can set their own"
     )]
-    container_mode: ContainerMode,
+    container_mode: Option<ContainerMode>,
 
-    #[argh(option, short = 'c', description = "path to config YAML")]
+    #[argh(option, short = 'c', description = "path to config file, or a workspace of several: YAML, or JSON/TOML by extension")]
     config_path: String,
+
+    #[argh(
+        option,
+        description = "environment from the config's environments section to deploy to, e.g. prod"
+    )]
+    env: Option<String>,
+
+    #[argh(
+        option,
+        description = "override a config value, e.g. --set compute.server.instance_type=ml.g5.xlarge. Repeatable"
+    )]
+    set: Vec<String>,
+
+    #[argh(
+        option,
+        description = "give a phase longer, e.g. --timeout upload=3h: build, push, upload, waiter or endpoint. Repeatable"
+    )]
+    timeout: Vec<String>,
+
+    #[argh(
+        option,
+        description = "deploy an approved Model Registry model package (its ARN) instead of building, e.g. for production"
+    )]
+    model_package: Option<String>,
+
+    #[argh(switch, short = 'y', description = "don't ask before creating endpoints, for CI")]
+    yes: bool,
+
+    #[argh(switch, description = "if the deploy fails, delete the model, endpoint config and endpoint it created without asking")]
+    clean_on_failure: bool,
+
+    #[argh(switch, description = "return once the endpoint is being created, without waiting for it to go into service")]
+    no_wait: bool,
+
+    #[argh(switch, description = "deploy even if the estimated cost is over the config's max_monthly_cost")]
+    force: bool,
+
+    #[argh(switch, description = "deploy without an approved model package, even if the config has require_approval, e.g. in dev")]
+    skip_approval: bool,
+
+    #[argh(switch, description = "push the image the last build left on this machine, instead of building it again")]
+    skip_build: bool,
+
+    #[argh(switch, description = "use the image already in ECR with the model's version (or latest), without building or pushing")]
+    skip_push: bool,
+
+    #[argh(switch, description = "use the artefact the model's last deploy uploaded, instead of uploading it again")]
+    skip_upload: bool,
+
+    #[argh(switch, description = "carry on from where the model's last deploy failed, reusing the image and artefact it got as far as pushing and uploading")]
+    resume: bool,
+
+    #[argh(option, description = "start at this stage, using what earlier deploys left for the ones before: validate, build, push, upload, model, endpoint, wait or smoke-test")]
+    from: Option<Stage>,
+
+    #[argh(option, description = "stop after this stage, e.g. --until push to build and push without deploying")]
+    until: Option<Stage>,
+
+    #[argh(option, description = "AWS region to deploy to, defaults to the region in your config, then your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
+
+    #[argh(option, description = "write a report of the deploy to this file, for CI artifacts: JSON, or YAML by extension")]
+    report: Option<String>,
 }
 
-#[derive(Debug, PartialEq)]
-enum EndpointType {
-    Serverless,
-    Server,
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "promote",
+    description = "Deploy the model one environment's endpoint is running to another, without rebuilding it"
+)]
+struct Promote {
+    #[argh(option, short = 'c', description = "path to the model's config file, with both environments in its environments section")]
+    config_path: String,
+
+    #[argh(option, description = "the environment to promote from, e.g. staging")]
+    from: String,
+
+    #[argh(option, description = "the environment to promote to, e.g. prod")]
+    to: String,
+
+    #[argh(
+        option,
+        short = 'e',
+        description = "the type of endpoint to promote to: serverless, server. Defaults to the one the config has compute for"
+    )]
+    endpoint_type: Option<EndpointType>,
+
+    #[argh(switch, short = 'y', description = "don't ask before creating endpoints, for CI")]
+    yes: bool,
+
+    #[argh(switch, description = "if the deploy fails, delete the model, endpoint config and endpoint it created without asking")]
+    clean_on_failure: bool,
+
+    #[argh(switch, description = "return once the endpoint is being updated, without waiting for it to go into service")]
+    no_wait: bool,
+
+    #[argh(switch, description = "promote even if the estimated cost is over the config's max_monthly_cost")]
+    force: bool,
+
+    #[argh(switch, description = "promote even if the environment promoted to has require_approval")]
+    skip_approval: bool,
+
+    #[argh(
+        option,
+        description = "give a phase longer, e.g. --timeout endpoint=3h: build, push, upload, waiter or endpoint. Repeatable"
+    )]
+    timeout: Vec<String>,
+
+    #[argh(option, description = "AWS region both environments are in, defaults to the region in your config, then your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
+
+    #[argh(option, description = "write a report of the promote to this file, for CI artifacts: JSON, or YAML by extension")]
+    report: Option<String>,
 }
 
-impl FromStr for EndpointType {
-    type Err = anyhow::Error;
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "setup",
+    description = "Create Sageturner bucket and role"
+)]
+struct Setup {
+    #[argh(
+        option,
+        default = "DEFAULT_ROLE_NAME.to_string()",
+        description = "name of the role to create (default sageturner-role-sagemaker)"
+    )]
+    role_name: String,
 
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "serverless" => Ok(EndpointType::Serverless),
-            "server" => Ok(EndpointType::Server),
-            _ => Err(anyhow!(
-                "Invalid endpoint type. serverless or server only, not: {}",
-                s
-            )),
-        }
-    }
+    #[argh(
+        option,
+        default = "DEFAULT_BUCKET_NAME.to_string()",
+        description = "name of the bucket to create (default sageturner-sagemaker-models)"
+    )]
+    bucket_name: String,
+
+    #[argh(
+        option,
+        description = "an ECR repository (model name) the role can pull images from, repeat for more. Defaults to all of the account's"
+    )]
+    repository: Vec<String>,
+
+    #[argh(
+        option,
+        description = "another bucket the role can read artefacts from, for configs with an s3:// artefact elsewhere. Repeat for more"
+    )]
+    artefact_bucket: Vec<String>,
+
+    #[argh(
+        switch,
+        description = "attach AmazonSageMakerFullAccess to the role, instead of a policy scoped to the bucket, repositories and logs"
+    )]
+    full_access: bool,
+
+    #[argh(option, description = "ARN of a managed policy to set as the role's permissions boundary")]
+    permissions_boundary: Option<String>,
+
+    #[argh(option, description = "IAM path to create the role under, like /sageturner/")]
+    role_path: Option<String>,
+
+    #[argh(
+        option,
+        description = "another principal that can assume the role, besides SageMaker: an account or role ARN, or a service. Repeat for more"
+    )]
+    trust_principal: Vec<String>,
+
+    #[argh(switch, description = "turn on versioning for the bucket")]
+    versioning: bool,
+
+    #[argh(
+        option,
+        description = "ARN of a KMS key to encrypt the bucket with by default, instead of S3 managed keys"
+    )]
+    kms_key_arn: Option<String>,
+
+    #[argh(
+        switch,
+        description = "put the region on the end of the bucket name (sageturner-sagemaker-models-<region>), for deploys to several regions"
+    )]
+    regional_bucket: bool,
+
+    #[argh(
+        option,
+        description = "delete artefacts (and old versions, with --versioning) this many days after upload"
+    )]
+    expire_after_days: Option<i32>,
+
+    #[argh(
+        option,
+        default = "7",
+        description = "clean up multipart uploads that never finished after this many days (default 7)"
+    )]
+    abort_multipart_after_days: i32,
+
+    #[argh(option, description = "how long to wait for a new role to show up in IAM, like 10m (default 5m)")]
+    role_wait: Option<String>,
+
+    #[argh(
+        option,
+        description = "AWS region to create the bucket and role in, defaults to your configured region"
+    )]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
 }
 
-impl std::fmt::Display for EndpointType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            EndpointType::Serverless => write!(f, "serverless"),
-            EndpointType::Server => write!(f, "server"),
-        }
-    }
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "artefact",
+    description = "Work with the artefacts of deployed models"
+)]
+struct Artefact {
+    #[argh(subcommand)]
+    nested: ArtefactSubCommands,
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(subcommand)]
+enum ArtefactSubCommands {
+    Pull(Pull),
 }
 
-#[derive(Debug, PartialEq)]
-enum ContainerMode {
-    Generate,
-    Provide,
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "pull",
+    description = "Download the model data a deployed model was created with"
+)]
+struct Pull {
+    #[argh(option, short = 'm', description = "the SageMaker model name")]
+    model: Option<String>,
+
+    #[argh(
+        option,
+        short = 'n',
+        description = "an endpoint name, to pull the model behind it"
+    )]
+    endpoint: Option<String>,
+
+    #[argh(
+        option,
+        short = 'o',
+        default = "String::from(\".\")",
+        description = "directory to download to, defaults to the current directory"
+    )]
+    output: String,
+
+    #[argh(switch, description = "extract a model.tar.gz after downloading it")]
+    extract: bool,
+
+    #[argh(option, description = "AWS region the model is in, defaults to your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
 }
 
-impl FromStr for ContainerMode {
-    type Err = anyhow::Error;
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "list",
+    description = "List the models Sageturner has deployed, with their versions"
+)]
+struct List {
+    #[argh(option, short = 'n', description = "only show the version history of this model")]
+    name: Option<String>,
 
-    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "generate" => Ok(ContainerMode::Generate),
-            "provide" => Ok(ContainerMode::Provide),
-            _ => Err(anyhow!(
-                "Invalid container mode. use generate or provide, not: {}",
-                s
-            )),
-        }
-    }
+    #[argh(option, description = "AWS region to list models in, defaults to your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
 }
 
-impl std::fmt::Display for ContainerMode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            ContainerMode::Generate => write!(f, "generate"),
-            ContainerMode::Provide => write!(f, "provide"),
-        }
-    }
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "gc",
+    description = "Delete endpoints Sageturner deployed that are no longer needed"
+)]
+struct Gc {
+    #[argh(switch, description = "delete endpoints whose ttl has run out")]
+    expired: bool,
+
+    #[argh(switch, description = "delete the models and endpoint configs of failed deploys in this project's state, that no endpoint uses")]
+    failed: bool,
+
+    #[argh(switch, description = "delete the models, endpoint configs, images and artefacts of deploys past the config's retention")]
+    retention: bool,
+
+    #[argh(switch, description = "delete models and endpoint configs Sageturner deployed whose endpoint is gone, and images no model uses")]
+    orphans: bool,
+
+    #[argh(option, short = 'c', description = "the config whose retention to apply, with --retention")]
+    config_path: Option<String>,
+
+    #[argh(option, description = "the environment from the config's environments section to apply retention to")]
+    env: Option<String>,
+
+    #[argh(switch, description = "show what would be deleted, without deleting it")]
+    dry_run: bool,
+
+    #[argh(switch, short = 'y', description = "don't ask before deleting, for scheduled jobs")]
+    yes: bool,
+
+    #[argh(option, description = "AWS region to clean up, defaults to your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
 }
 
-#[derive(Debug, FromArgs, PartialEq)]
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
 #[argh(
     subcommand,
-    name = "setup",
-    description = "Create Sageturner bucket and role"
+    name = "rollback",
+    description = "Point an endpoint back at an earlier deploy's model, from this project's state"
 )]
-struct Setup {}
+struct Rollback {
+    #[argh(option, short = 'n', description = "the endpoint to roll back")]
+    endpoint: String,
+
+    #[argh(
+        option,
+        description = "the SageMaker model to roll back to, defaults to the last one in service before the current one"
+    )]
+    to: Option<String>,
+
+    #[argh(switch, short = 'y', description = "don't ask before rolling back, for CI")]
+    yes: bool,
+
+    #[argh(switch, description = "return once the endpoint is updating, without waiting for it to go into service")]
+    no_wait: bool,
+
+    #[argh(option, description = "give the endpoint longer to go into service, e.g. --timeout endpoint=3h")]
+    timeout: Vec<String>,
+
+    #[argh(option, description = "AWS region the endpoint is in, defaults to your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "versions",
+    description = "Show every recorded deploy of a model, to pick one to roll back to"
+)]
+struct Versions {
+    #[argh(positional, description = "the model's name, from its config")]
+    model: String,
+
+    #[argh(option, description = "only show deploys to this environment")]
+    env: Option<String>,
+
+    #[argh(option, description = "AWS region for the state backend, defaults to your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "unlock",
+    description = "Remove an endpoint's deploy lock, left behind by a deploy that died"
+)]
+struct Unlock {
+    #[argh(option, short = 'n', description = "the endpoint to unlock")]
+    endpoint: String,
+
+    #[argh(option, description = "AWS region the endpoint is in, defaults to your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "loadtest",
+    description = "Send an endpoint requests from many workers at once, and report its throughput, latency and errors"
+)]
+struct Loadtest {
+    #[argh(option, description = "the endpoint to load test")]
+    endpoint: String,
+
+    #[argh(option, description = "a file with the request body to send")]
+    payload: String,
+
+    #[argh(option, default = "String::from(\"application/json\")", description = "the payload's content type, defaults to application/json")]
+    content_type: String,
+
+    #[argh(option, default = "8", description = "how many requests to have in flight at once, defaults to 8")]
+    concurrency: usize,
+
+    #[argh(option, default = "String::from(\"60s\")", description = "how long to send requests for, like 60s or 5m, defaults to 60s")]
+    duration: String,
+
+    #[argh(option, description = "AWS region the endpoint is in, defaults to your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "recommend",
+    description = "Load test a model with SageMaker Inference Recommender, to find the instance type that suits it"
+)]
+struct Recommend {
+    #[argh(option, short = 'c', description = "path to the model's config file, with a recommender section")]
+    config_path: String,
+
+    #[argh(option, description = "the environment from the config's environments section")]
+    env: Option<String>,
+
+    #[argh(option, description = "the SageMaker model to test, defaults to the config's latest recorded deploy")]
+    model: Option<String>,
+
+    #[argh(option, description = "show the results of a recommendation job that's already been started, instead of starting one")]
+    job: Option<String>,
+
+    #[argh(switch, description = "write the cheapest recommendation into the config's compute.server, or the environment's")]
+    apply: bool,
+
+    #[argh(option, description = "AWS region the model is in, defaults to the region in your config, then your configured region")]
+    region: Option<String>,
+
+    #[argh(option, description = "AWS profile (from ~/.aws/config) to use, instead of AWS_PROFILE or the default")]
+    profile: Option<String>,
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "init",
+    description = "Write a starter sageturner.yaml for a generated container, and a sageturner.py to fill in"
+)]
+struct Init {
+    #[argh(switch, short = 'i', description = "ask about your model (framework, CPU/GPU, endpoint type, instance type, python version) first")]
+    interactive: bool,
+
+    #[argh(
+        option,
+        short = 'd',
+        default = "String::from(\".\")",
+        description = "directory to write to, defaults to the current directory"
+    )]
+    dir: String,
+
+    #[argh(option, short = 'n', description = "the model's name, defaults to the directory's")]
+    name: Option<String>,
+
+    #[argh(switch, description = "overwrite an existing sageturner.yaml")]
+    force: bool,
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "config",
+    description = "Work with sageturner config files"
+)]
+struct Config {
+    #[argh(subcommand)]
+    nested: ConfigSubCommands,
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(subcommand)]
+enum ConfigSubCommands {
+    Schema(Schema),
+    Migrate(Migrate),
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "schema",
+    description = "Print the JSON Schema for sageturner config files"
+)]
+struct Schema {}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "migrate",
+    description = "Upgrade a config file to the current schema_version, printing what changed"
+)]
+struct Migrate {
+    #[argh(option, short = 'c', description = "path to the config file to migrate")]
+    config_path: String,
+
+    #[argh(switch, description = "print the changes without writing them")]
+    dry_run: bool,
+}
+
+#[derive(Debug, ArgsInfo, FromArgs, PartialEq)]
+#[argh(
+    subcommand,
+    name = "completions",
+    description = "Print a completion script for your shell",
+    example = "sageturner completions bash > ~/.local/share/bash-completion/completions/sageturner",
+    example = "sageturner completions zsh > \"${{fpath[1]}}/_sageturner\"",
+    example = "sageturner completions fish > ~/.config/fish/completions/sageturner.fish"
+)]
+struct Completions {
+    #[argh(positional, description = "the shell to complete in: bash, zsh or fish")]
+    shell: completions::Shell,
+}
+
+// Whether the command creates, changes or deletes anything in AWS
+fn mutates(command: &SageturnerSubCommands) -> bool {
+    match command {
+        SageturnerSubCommands::Setup(_)
+        | SageturnerSubCommands::Deploy(_)
+        | SageturnerSubCommands::Promote(_)
+        | SageturnerSubCommands::Rollback(_)
+        | SageturnerSubCommands::Unlock(_)
+        | SageturnerSubCommands::Recommend(_) => true,
+        SageturnerSubCommands::Gc(gc) => !gc.dry_run,
+        _ => false,
+    }
+}
+
+// argh::from_env, with -vv expanded to -v -v, as argh doesn't group short switches
+fn parse_args() -> SageturnerCLI {
+    let args: Vec<String> = std::env::args()
+        .skip(1)
+        .flat_map(|arg| match arg.len() > 2 && arg.starts_with("-v") && arg[1..].chars().all(|c| c == 'v') {
+            true => vec!["-v".to_string(); arg.len() - 1],
+            false => vec![arg],
+        })
+        .collect();
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    SageturnerCLI::from_args(&["sageturner"], &args).unwrap_or_else(|early_exit| {
+        std::process::exit(match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                0
+            }
+            Err(()) => {
+                eprintln!("{}\nRun sageturner --help for more information.", early_exit.output);
+                1
+            }
+        })
+    })
+}
 
 #[::tokio::main]
-async fn main() -> Result<()> {
-    let cmd: SageturnerCLI = argh::from_env();
+async fn main() -> ExitCode {
+    let cmd = parse_args();
+    let log_format = cmd.log_format.unwrap_or_else(logging::LogFormat::detect);
+    let verbosity = logging::Verbosity::from_flags(cmd.quiet, cmd.verbose);
+    if cmd.non_interactive {
+        prompt::disable();
+        progress::hide();
+        console::set_colors_enabled(false);
+        console::set_colors_enabled_stderr(false);
+    }
+    let result = match verbosity.and_then(|v| logging::init(log_format, cmd.output, v, !cmd.non_interactive)) {
+        Ok(logging) => {
+            let result = run(cmd, log_format).await;
+            drop(logging);
+            result
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            errors::report(&e, log_format);
+            ExitCode::from(errors::exit_code(&e))
+        }
+    }
+}
 
-    let config = aws_config::defaults(aws_config::BehaviorVersion::latest()).timeout_config(TimeoutConfig::builder()
-    .connect_timeout(Duration::from_secs(8))
-    .build()).load().await;
-    let sage_client = aws_sdk_sagemaker::Client::new(&config);
-    let ecr_client = aws_sdk_ecr::Client::new(&config);
-    let iam_client = aws_sdk_iam::Client::new(&config);
-    let s3_client = aws_sdk_s3::Client::new(&config);
+async fn run(cmd: SageturnerCLI, log_format: logging::LogFormat) -> Result<()> {
+    // Init, config and completions commands work offline, without AWS or Docker
+    if let SageturnerSubCommands::Completions(completions) = &cmd.nested {
+        print!("{}", completions::generate(completions.shell, &SageturnerCLI::get_args_info()));
+        return Ok(());
+    }
+    if let SageturnerSubCommands::Init(init) = &cmd.nested {
+        let written = init::run(Path::new(&init.dir), init.name.clone(), init.interactive, init.force, &mut output::human(cmd.output))?;
+        if cmd.output == output::OutputFormat::Json {
+            output::print_json(&written)?;
+        }
+        return Ok(());
+    }
+    if let SageturnerSubCommands::Config(config) = &cmd.nested {
+        match &config.nested {
+            ConfigSubCommands::Schema(_) => println!("{}", model_config::config_schema()?),
+            ConfigSubCommands::Migrate(migrate) => {
+                let migration = model_config::migrate_file(Path::new(&migrate.config_path), migrate.dry_run, &mut output::human(cmd.output))?;
+                if cmd.output == output::OutputFormat::Json {
+                    output::print_json(&migration)?;
+                }
+            }
+        }
+        return Ok(());
+    }
 
-    let docker = docker::get_client().await;
+    if let SageturnerSubCommands::Gc(gc) = &cmd.nested {
+        if !gc.expired && !gc.failed && !gc.retention && !gc.orphans {
+            return Err(anyhow!(
+                "Pass --expired to delete endpoints whose ttl has run out, --failed to clean up after failed deploys, --retention \
+                 to apply a config's retention, or --orphans to delete what's left of deleted endpoints"
+            ));
+        }
+        if gc.retention != gc.config_path.is_some() {
+            return Err(anyhow!("--retention applies the retention in the config passed with --config-path, so pass both"));
+        }
+    }
+    let load_test = match &cmd.nested {
+        SageturnerSubCommands::Loadtest(loadtest) => {
+            if loadtest.concurrency == 0 {
+                return Err(anyhow!("--concurrency has to be at least 1"));
+            }
+            let duration = loadtest::parse_duration(&loadtest.duration)?;
+            let body = std::fs::read(&loadtest.payload).map_err(|e| anyhow!("Couldn't read payload {}: {}", loadtest.payload, e))?;
+            Some((body, duration))
+        }
+        _ => None,
+    };
+
+    // Configs are parsed up front for deploys, as they can pick the region
+    let targets = match &cmd.nested {
+        SageturnerSubCommands::Deploy(deploy) => deploy_targets(deploy)?,
+        SageturnerSubCommands::Promote(promote) => promote_targets(promote)?,
+        _ => Vec::new(),
+    };
+    let retention_config = match &cmd.nested {
+        SageturnerSubCommands::Gc(Gc { config_path: Some(path), env, .. }) => {
+            let model_config = model_config::parse_config(path.into(), env.as_deref(), &[])?;
+            let retention = model_config
+                .retention
+                .as_ref()
+                .ok_or_else(|| anyhow!("{} has no retention to apply", path))?;
+            model_config::validate_retention(retention)?;
+            Some(model_config)
+        }
+        _ => None,
+    };
+    let recommend_config = match &cmd.nested {
+        SageturnerSubCommands::Recommend(recommend) => {
+            let model_config = model_config::parse_config(recommend.config_path.clone().into(), recommend.env.as_deref(), &[])?;
+            match &model_config.recommender {
+                Some(recommender) => {
+                    let config_dir = Path::new(&recommend.config_path).parent().unwrap_or(Path::new(""));
+                    model_config::validate_recommender(recommender, config_dir)?;
+                }
+                None if recommend.job.is_none() => {
+                    return Err(anyhow!("{} has no recommender section with payloads to send", recommend.config_path));
+                }
+                None => {}
+            }
+            Some(model_config)
+        }
+        _ => None,
+    };
+    // Commands without a config still pick up the region and profile from your defaults file
+    let defaults = match &cmd.nested {
+        SageturnerSubCommands::Deploy(_) | SageturnerSubCommands::Promote(_) => None,
+        _ => Some(model_config::user_defaults()?),
+    };
+    // Checked before anything's looked up in AWS
+    let rollback_limit = match &cmd.nested {
+        SageturnerSubCommands::Rollback(rollback) => rollback_endpoint_limit(&rollback.timeout, defaults.as_ref())?,
+        _ => aws::ENDPOINT_WAIT_LIMIT,
+    };
+    let default_setting = |key: &str| {
+        defaults
+            .as_ref()
+            .and_then(|d| d.get(key))
+            .and_then(|v| v.as_str())
+            .map(|v| v.to_string())
+    };
+    // --region and --profile beat the config's, which beat your AWS config and environment
+    let region = match &cmd.nested {
+        SageturnerSubCommands::Setup(setup) => setup.region.clone().or(default_setting("region")),
+        // A deploy to several regions starts off with clients for the first
+        SageturnerSubCommands::Deploy(deploy) => match (&deploy.region, shared_regions(&targets)?.first()) {
+            (Some(r), _) | (None, Some(r)) => Some(r.clone()),
+            (None, None) => shared_setting(&targets, "region", |mc| mc.region.clone())?,
+        },
+        SageturnerSubCommands::Promote(promote) => match (&promote.region, shared_regions(&targets)?.is_empty()) {
+            (Some(r), _) => Some(r.clone()),
+            (None, true) => shared_setting(&targets, "region", |mc| mc.region.clone())?,
+            (None, false) => return Err(anyhow!("The config deploys to several regions, pass --region to promote in one of them")),
+        },
+        SageturnerSubCommands::Artefact(Artefact { nested: ArtefactSubCommands::Pull(pull) }) => {
+            pull.region.clone().or(default_setting("region"))
+        }
+        SageturnerSubCommands::List(list) => list.region.clone().or(default_setting("region")),
+        SageturnerSubCommands::Gc(gc) => gc
+            .region
+            .clone()
+            .or(retention_config.as_ref().and_then(|mc| mc.region.clone()))
+            .or(default_setting("region")),
+        SageturnerSubCommands::Rollback(rollback) => rollback.region.clone().or(default_setting("region")),
+        SageturnerSubCommands::Unlock(unlock) => unlock.region.clone().or(default_setting("region")),
+        SageturnerSubCommands::Loadtest(loadtest) => loadtest.region.clone().or(default_setting("region")),
+        SageturnerSubCommands::Recommend(recommend) => recommend
+            .region
+            .clone()
+            .or(recommend_config.as_ref().and_then(|mc| mc.region.clone()))
+            .or(default_setting("region")),
+        SageturnerSubCommands::Versions(versions) => versions.region.clone().or(default_setting("region")),
+        SageturnerSubCommands::Config(_) | SageturnerSubCommands::Init(_) | SageturnerSubCommands::Completions(_) => None,
+    };
+    let profile = match &cmd.nested {
+        SageturnerSubCommands::Setup(setup) => setup.profile.clone().or(default_setting("profile")),
+        SageturnerSubCommands::Deploy(deploy) => match &deploy.profile {
+            Some(p) => Some(p.clone()),
+            None => shared_setting(&targets, "profile", |mc| mc.profile.clone())?,
+        },
+        SageturnerSubCommands::Promote(promote) => match &promote.profile {
+            Some(p) => Some(p.clone()),
+            None => shared_setting(&targets, "profile", |mc| mc.profile.clone())?,
+        },
+        SageturnerSubCommands::Artefact(Artefact { nested: ArtefactSubCommands::Pull(pull) }) => {
+            pull.profile.clone().or(default_setting("profile"))
+        }
+        SageturnerSubCommands::List(list) => list.profile.clone().or(default_setting("profile")),
+        SageturnerSubCommands::Gc(gc) => gc
+            .profile
+            .clone()
+            .or(retention_config.as_ref().and_then(|mc| mc.profile.clone()))
+            .or(default_setting("profile")),
+        SageturnerSubCommands::Rollback(rollback) => rollback.profile.clone().or(default_setting("profile")),
+        SageturnerSubCommands::Unlock(unlock) => unlock.profile.clone().or(default_setting("profile")),
+        SageturnerSubCommands::Loadtest(loadtest) => loadtest.profile.clone().or(default_setting("profile")),
+        SageturnerSubCommands::Recommend(recommend) => recommend
+            .profile
+            .clone()
+            .or(recommend_config.as_ref().and_then(|mc| mc.profile.clone()))
+            .or(default_setting("profile")),
+        SageturnerSubCommands::Versions(versions) => versions.profile.clone().or(default_setting("profile")),
+        SageturnerSubCommands::Config(_) | SageturnerSubCommands::Init(_) | SageturnerSubCommands::Completions(_) => None,
+    };
+    // Roles to assume come from configs, or your defaults file
+    let (assume_role_arn, external_id) = match &cmd.nested {
+        SageturnerSubCommands::Deploy(_) | SageturnerSubCommands::Promote(_) => (
+            shared_setting(&targets, "assume_role_arn", |mc| mc.assume_role_arn.clone())?,
+            shared_setting(&targets, "assume_role_external_id", |mc| mc.assume_role_external_id.clone())?,
+        ),
+        _ => (default_setting("assume_role_arn"), default_setting("assume_role_external_id")),
+    };
+    // Endpoint URLs, like the region, come from configs or your defaults file
+    let endpoint_url = match &cmd.nested {
+        SageturnerSubCommands::Deploy(_) | SageturnerSubCommands::Promote(_) => {
+            shared_setting(&targets, "endpoint_url", |mc| {
+                mc.endpoint_url.as_ref().and_then(|e| serde_json::to_string(e).ok())
+            })?
+            .map(|e| serde_json::from_str(&e))
+            .transpose()?
+        }
+        _ => defaults
+            .as_ref()
+            .and_then(|d| d.get("endpoint_url"))
+            .map(|e| serde_yaml::from_value(e.clone()))
+            .transpose()
+            .map_err(|e| anyhow!("Invalid endpoint_url in your defaults file: {}", e))?,
+    };
+    let settings = aws::AwsSettings { region, profile, assume_role_arn, external_id, endpoint_url };
+    let aws = aws::AwsClients::load(settings.clone()).await?;
+    // Commands that change things in AWS say where first, so nothing goes to the wrong account unnoticed
+    let identity = match mutates(&cmd.nested) {
+        true => {
+            let identity = aws::identity(&aws.sts).await?;
+            info!("Using AWS account {} in {}, as {}", identity.account, aws.region, identity.arn);
+            Some(identity)
+        }
+        false => None,
+    };
+    // Where a prompt is about to change things
+    let in_account = |identity: &Option<aws::Identity>| match identity {
+        Some(identity) => format!(" in account {}, {}", identity.account, aws.region),
+        None => String::new(),
+    };
+
+    // Only deploys that build or push an image need Docker, so nothing else fails when it isn't running
+    let reuses_pushed_image = matches!(&cmd.nested, SageturnerSubCommands::Deploy(deploy)
+        if deploy.skip_push || ![Stage::Build, Stage::Push].into_iter().any(|stage| stage.between(deploy.from, deploy.until)));
+    let builds = !reuses_pushed_image && targets.iter().any(|t| matches!(t.source, ModelSource::Build(mode) if mode.builds_image()));
+    let docker = match builds {
+        true => Some(docker::get_client().await?),
+        false => None,
+    };
+    // Deploys use their config's state backend, everything else the one in your defaults file
+    let state_backend = defaults
+        .as_ref()
+        .and_then(|d| d.get("state_backend"))
+        .map(|b| serde_yaml::from_value::<model_config::StateBackendConfig>(b.clone()))
+        .transpose()
+        .map_err(|e| anyhow!("Invalid state_backend in your defaults file: {}", e))?;
+    if let Some(backend) = &state_backend {
+        model_config::validate_state_backend(backend)?;
+    }
+    // gc with a config keeps its local state next to the config, like deploys. Other commands read the directory
+    // they're run from
+    let state_dir = retention_config.as_ref().map(|mc| mc.config_dir.as_path()).unwrap_or(Path::new(""));
+    let state_backend = state::Backend::new(state_backend.as_ref(), state_dir);
 
     match cmd.nested {
         SageturnerSubCommands::Deploy(deploy) => {
-            process_deploy(
-                &ecr_client,
-                &sage_client,
-                &docker,
-                &iam_client,
-                &s3_client,
-                &deploy,
-            )
-            .await?
+            let options = DeployOptions {
+                yes: deploy.yes,
+                wait: !deploy.no_wait,
+                force: deploy.force,
+                skip_approval: deploy.skip_approval,
+                clean_on_failure: deploy.clean_on_failure,
+                skip_build: deploy.skip_build,
+                skip_push: deploy.skip_push,
+                skip_upload: deploy.skip_upload,
+                resume: deploy.resume,
+                from: deploy.from,
+                until: deploy.until,
+                output: cmd.output,
+            };
+            let fan_out = match &deploy.region {
+                Some(_) => Vec::new(),
+                None => shared_regions(&targets)?,
+            };
+            let started = Utc::now();
+            let configs: Vec<_> = targets.iter().map(DeployTarget::resolved).collect();
+            let mut deploys = Vec::new();
+            let deployed = async {
+                if fan_out.len() <= 1 {
+                    info!("Deploying to {}", aws.region);
+                    return Deployer::new(aws.clone(), docker.clone()).deploy_all(targets, &options, &mut deploys).await;
+                }
+                // Deploy to every region, even if one fails, then report how they all went. Each region
+                // gets its own clients, and its own copy of the image and artefact
+                let total = fan_out.len();
+                let mut results = Vec::new();
+                for region in fan_out {
+                    info!("Deploying to {}", region);
+                    let result = async {
+                        let settings = aws::AwsSettings { region: Some(region.clone()), ..settings.clone() };
+                        let deployer = Deployer::new(aws::AwsClients::load(settings).await?, docker.clone());
+                        deployer.deploy_all(deploy_targets(&deploy)?, &options, &mut deploys).await
+                    }
+                    .instrument(info_span!("region", region = %region))
+                    .await;
+                    if let Err(e) = &result {
+                        error!("Deploying to {} failed: {}", region, e);
+                    }
+                    results.push((region, result));
+                }
+                let failed = results.iter().filter(|(_, r)| r.is_err()).count();
+                let mut out = output::human(cmd.output);
+                writeln!(out, "Deployed to {} of {} regions:", total - failed, total)?;
+                for (region, result) in &results {
+                    match result {
+                        Ok(_) => writeln!(out, "  {:<16} deployed", region)?,
+                        Err(e) => writeln!(out, "  {:<16} failed: {}", region, e)?,
+                    }
+                }
+                if failed > 0 {
+                    let message = format!("Deploying to {} of {} regions failed", failed, total);
+                    return Err(errors::summarize(message, results.iter().filter_map(|(_, r)| r.as_ref().err())));
+                }
+                Ok(())
+            }
+            .await;
+            if cmd.output == output::OutputFormat::Json {
+                output::print_json(&serde_json::json!({ "deploys": deploys }))?;
+            }
+            let reported = deploy.report.as_ref().map(|path| {
+                output::Report::new(started, configs, &deploys, &deployed).write(Path::new(path))
+            });
+            if log_format == logging::LogFormat::Github {
+                github::report(&deploys)?;
+            }
+            deployed?;
+            reported.transpose()?;
         },
-        SageturnerSubCommands::Setup(_) => {
-            println!("Performing initial setup: creating Sageturner role and bucket");
-            // Create role with name sageturner-role, attach SagemakerFullAccessPolicy
-            aws::create_sagemaker_role(DEFAULT_ROLE_NAME, &iam_client).await?;
-            // Create bucket with name sageturner-sagemaker-models, attach SagemakerFullAccessPolicy
-            aws::create_sagemaker_bucket(DEFAULT_BUCKET_NAME, &s3_client).await?;
+        SageturnerSubCommands::Setup(setup) => {
+            info!("Performing initial setup: creating Sageturner role and bucket in {}", aws.region);
+            // Role requirements from your defaults file, which setup's flags win over
+            let setup_defaults: model_config::SetupConfig = defaults
+                .as_ref()
+                .and_then(|d| d.get("setup"))
+                .map(|s| serde_yaml::from_value(s.clone()))
+                .transpose()
+                .map_err(|e| anyhow!("Invalid setup in your defaults file: {}", e))?
+                .unwrap_or_default();
+            // Create the bucket (sageturner-sagemaker-models by default, -<region> for multi-region deploys)
+            let bucket_name = match setup.regional_bucket {
+                true => format!("{}-{}", setup.bucket_name, aws.region),
+                false => setup.bucket_name.clone(),
+            };
+            // Create the role (sageturner-role-sagemaker by default), with a policy that can only read the bucket
+            // (every region's, for regional buckets). Everything setup does is safe to run again
+            let role_options = aws::RoleOptions {
+                bucket: match setup.regional_bucket {
+                    true => format!("{}-*", setup.bucket_name),
+                    false => setup.bucket_name.clone(),
+                },
+                artefact_buckets: setup.artefact_bucket.clone(),
+                repositories: setup.repository.clone(),
+                full_access: setup.full_access,
+                kms_key_arn: setup.kms_key_arn.clone(),
+                path: setup.role_path.clone().or(setup_defaults.role_path),
+                permissions_boundary: setup.permissions_boundary.clone().or(setup_defaults.permissions_boundary),
+                trusted_principals: match setup.trust_principal.is_empty() {
+                    true => setup_defaults.trusted_principals,
+                    false => setup.trust_principal.clone(),
+                },
+                wait: setup
+                    .role_wait
+                    .as_deref()
+                    .map(loadtest::parse_duration)
+                    .transpose()
+                    .map_err(|e| anyhow!("Invalid --role-wait: {}", e))?
+                    .unwrap_or(model_config::DEFAULT_WAITER_TIMEOUT),
+            };
+            let role_arn = aws::create_sagemaker_role(&setup.role_name, &aws.region, &role_options, &aws.iam).await?;
+            let bucket_arn = aws::create_sagemaker_bucket(&bucket_name, &aws.region, &aws.s3).await?;
+            aws::configure_sagemaker_bucket(
+                &bucket_name,
+                &aws.region,
+                &aws::BucketOptions {
+                    versioning: setup.versioning,
+                    expire_after_days: setup.expire_after_days,
+                    abort_multipart_after_days: setup.abort_multipart_after_days,
+                    kms_key_arn: setup.kms_key_arn,
+                },
+                &aws.s3,
+            )
+            .await?;
+            if cmd.output == output::OutputFormat::Json {
+                output::print_json(&serde_json::json!({ "role_arn": role_arn, "bucket_arn": bucket_arn }))?;
+                return Ok(());
+            }
             println!("Setup done");
+            println!("  role:   {}", role_arn);
+            println!("  bucket: {}", bucket_arn);
+            if setup.role_name != DEFAULT_ROLE_NAME || setup.bucket_name != DEFAULT_BUCKET_NAME {
+                println!("Deploy with them by adding to your sageturner.yaml:");
+                println!("overrides:");
+                println!("  bucket_name: {}", setup.bucket_name);
+                println!("  role_arn: {}", role_arn);
+            }
+        }
+        SageturnerSubCommands::Artefact(artefact) => match artefact.nested {
+            ArtefactSubCommands::Pull(pull) => {
+                let model_name = match (pull.model, pull.endpoint) {
+                    (Some(m), None) => m,
+                    (None, Some(e)) => aws::get_endpoint_model_name(&e, &aws.sage).await?,
+                    _ => return Err(anyhow!("Pass one of --model or --endpoint")),
+                };
+                let model_data = aws::get_model_data(&model_name, &aws.sage).await?;
+                aws::download_model_data(&model_data, Path::new(&pull.output), pull.extract, &aws.s3).await?;
+                if cmd.output == output::OutputFormat::Json {
+                    let (aws::ModelData::Compressed(uri) | aws::ModelData::Uncompressed(uri)) = &model_data;
+                    output::print_json(&serde_json::json!({ "model": model_name, "artefact": uri, "output": pull.output }))?;
+                }
+            }
+        },
+        SageturnerSubCommands::Config(_) | SageturnerSubCommands::Init(_) | SageturnerSubCommands::Completions(_) => {
+            unreachable!("init, config and completions commands return before AWS setup")
+        }
+        SageturnerSubCommands::List(list) => {
+            let mut models = aws::list_deployed_models(list.name.as_deref(), &aws.sage).await?;
+            if cmd.output == output::OutputFormat::Json {
+                let state = state_backend.load(&aws).await?;
+                let models: Vec<_> = models
+                    .iter()
+                    .map(|m| output::ListedModel { model: m, status: state.latest(&m.sagemaker_model, &aws.region).map(|d| d.status) })
+                    .collect();
+                output::print_json(&serde_json::json!({ "models": models }))?;
+                return Ok(());
+            }
+            if models.is_empty() {
+                println!("No models deployed by Sageturner found");
+                return Ok(());
+            }
+            // Group each model's versions together, keeping them newest first
+            models.sort_by(|a, b| a.name.cmp(&b.name));
+            // How each deploy went, if it was deployed from this project
+            let state = state_backend.load(&aws).await?;
+            println!(
+                "{:<32} {:<16} {:<24} {:<14} {:<40} {:<12} DEPLOYED BY",
+                "NAME", "VERSION", "CREATED", "COMMIT", "SAGEMAKER MODEL", "STATUS"
+            );
+            for m in models {
+                let status = state.latest(&m.sagemaker_model, &aws.region).map(|d| d.status.to_string());
+                println!(
+                    "{:<32} {:<16} {:<24} {:<14} {:<40} {:<12} {}",
+                    m.name,
+                    m.version.as_deref().unwrap_or("-"),
+                    m.created.as_deref().unwrap_or("-"),
+                    m.commit.as_deref().unwrap_or("-"),
+                    m.sagemaker_model,
+                    status.as_deref().unwrap_or("-"),
+                    m.deployed_by.as_deref().unwrap_or("-")
+                );
+            }
+        }
+        SageturnerSubCommands::Gc(gc) => {
+            let expired = match gc.expired {
+                true => aws::list_expired_endpoints(Utc::now(), &aws.sage).await?,
+                false => Vec::new(),
+            };
+            let state = state_backend.load(&aws).await?;
+            let failed = match gc.failed {
+                true => failed_deploys(&state, &aws).await?,
+                false => Vec::new(),
+            };
+            let retention = match &retention_config {
+                Some(mc) => {
+                    let (bucket_name, _) = bucket_and_role(mc, &aws.region);
+                    let retention = mc.retention.as_ref().expect("checked when the config was parsed");
+                    retention::plan(mc, retention, &bucket_name, &aws).await?
+                }
+                None => retention::RetentionPlan::default(),
+            };
+            let orphans = match gc.orphans {
+                true => orphans::find(&aws).await?,
+                false => orphans::Orphans::default(),
+            };
+            // What was found, and whether it was deleted
+            let gc_result = |deleted: bool| {
+                let expired: Vec<_> = expired
+                    .iter()
+                    .map(|(name, expired_at)| {
+                        let expired_at = expired_at.to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+                        serde_json::json!({ "endpoint": name, "expired_at": expired_at })
+                    })
+                    .collect();
+                serde_json::json!({
+                    "region": aws.region,
+                    "expired": expired,
+                    "failed": failed,
+                    "retention": retention,
+                    "orphans": orphans,
+                    "deleted": deleted,
+                })
+            };
+            let json = cmd.output == output::OutputFormat::Json;
+            let mut out = output::human(cmd.output);
+            if expired.is_empty() && failed.is_empty() && retention.is_empty() && orphans.is_empty() {
+                retention.print(&mut out)?;
+                writeln!(out, "Nothing to clean up in {}", aws.region)?;
+                if json {
+                    output::print_json(&gc_result(false))?;
+                }
+                return Ok(());
+            }
+            if !expired.is_empty() {
+                writeln!(out, "Expired endpoints in {}:", aws.region)?;
+                for (name, expired_at) in &expired {
+                    writeln!(out, "  {:<40} expired {}", name, expired_at.format("%Y-%m-%d %H:%M UTC"))?;
+                }
+            }
+            if !failed.is_empty() {
+                writeln!(out, "Models and endpoint configs of failed deploys in {}:", aws.region)?;
+                for name in &failed {
+                    writeln!(out, "  {}", name)?;
+                }
+            }
+            retention.print(&mut out)?;
+            orphans.print(&mut out)?;
+            if gc.dry_run {
+                writeln!(out, "Dry run, nothing deleted")?;
+                if json {
+                    output::print_json(&gc_result(false))?;
+                }
+                return Ok(());
+            }
+            let question = match gc.retention || gc.orphans {
+                true => format!("Delete these{}?", in_account(&identity)),
+                false => format!("Delete these {} endpoints and {} failed deploys{}?", expired.len(), failed.len(), in_account(&identity)),
+            };
+            if !gc.yes && !prompt::confirm(&question)? {
+                return Err(anyhow!("Cancelled, nothing was deleted"));
+            }
+            for (name, _) in &expired {
+                aws::delete_endpoint(name, &aws.sage).await?;
+                info!("Deleted endpoint {}", name);
+            }
+            for name in &failed {
+                aws::delete_model_and_config(name, &aws.sage).await?;
+                info!("Deleted model and endpoint config {}", name);
+            }
+            retention::apply(&retention, &aws).await?;
+            orphans::delete(&orphans, &aws).await?;
+            let deleted = failed.iter().chain(&retention.models).chain(&orphans.models);
+            state_backend.mark_cleaned_up(&state, deleted, &aws).await?;
+            if json {
+                output::print_json(&gc_result(true))?;
+            }
+        }
+        SageturnerSubCommands::Rollback(rollback) => {
+            let state = state_backend.load(&aws).await?;
+            let current = aws::current_endpoint_config(&rollback.endpoint, &aws.sage)
+                .await?
+                .ok_or_else(|| anyhow!("There's no endpoint {} in {}", rollback.endpoint, aws.region))?;
+            let mut history = state.endpoint_history(&rollback.endpoint, &aws.region);
+            let target = match &rollback.to {
+                Some(to) => history.find(|d| &d.sagemaker_model == to).ok_or_else(|| {
+                    anyhow!("{} isn't a deploy of {} in {}", to, rollback.endpoint, state_backend)
+                })?,
+                None => history
+                    .find(|d| d.status == state::DeployStatus::InService && d.endpoint_config() != current)
+                    .ok_or_else(|| {
+                        anyhow!("No earlier deploy of {} in {} to roll back to", rollback.endpoint, state_backend)
+                    })?,
+            };
+            if target.endpoint_config() == current {
+                return Err(anyhow!("Endpoint {} is already running {}", rollback.endpoint, current));
+            }
+            // keep_previous may have cleaned it up since
+            if !aws::endpoint_config_exists(target.endpoint_config(), &aws.sage).await? {
+                return Err(anyhow!(
+                    "Endpoint config {} has been deleted, so {} can't be rolled back to it",
+                    target.endpoint_config(),
+                    rollback.endpoint
+                ));
+            }
+            writeln!(
+                output::human(cmd.output),
+                "Rolling endpoint {} back from {} to {}, deployed {}{}",
+                rollback.endpoint,
+                current,
+                target.endpoint_config(),
+                target.started,
+                target.version.as_deref().map(|v| format!(" as version {}", v)).unwrap_or_default()
+            )?;
+            if !rollback.yes && !prompt::confirm(&format!("Roll back{}?", in_account(&identity)))? {
+                return Err(anyhow!("Rollback of {} cancelled", rollback.endpoint));
+            }
+            let deployed_by = aws::caller_identity(&aws.sts).await?;
+            let lock = state_backend.lock(&rollback.endpoint, &aws.region, &deployed_by, &target.sagemaker_model, &aws).await?;
+            let mut record = target.clone();
+            record.started = Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+            record.deployed_by = deployed_by;
+            record.rollback = true;
+            record.status = state::DeployStatus::InProgress;
+            record.reason = None;
+            state::record(&state_backend, &record, &aws).await;
+            let result = async {
+                aws::create_or_update_endpoint(&rollback.endpoint, target.endpoint_config(), &aws.sage, &[]).await?;
+                if !rollback.no_wait {
+                    aws::wait_for_endpoint(&rollback.endpoint, target.endpoint_config(), &aws.sage, &aws.logs, rollback_limit).await?;
+                }
+                Ok::<(), anyhow::Error>(())
+            }
+            .await;
+            record.status = match &result {
+                Ok(_) if rollback.no_wait => state::DeployStatus::Submitted,
+                Ok(_) => state::DeployStatus::InService,
+                Err(_) => state::DeployStatus::Failed,
+            };
+            record.reason = result.as_ref().err().map(|e| e.to_string());
+            state::record(&state_backend, &record, &aws).await;
+            if let Some(lock) = lock {
+                lock.release(&aws).await;
+            }
+            result?;
+            match cmd.output {
+                output::OutputFormat::Text => println!("Endpoint {} is running {}", rollback.endpoint, target.endpoint_config()),
+                output::OutputFormat::Json => output::print_json(&serde_json::json!({
+                    "endpoint": rollback.endpoint,
+                    "from": current,
+                    "to": target.endpoint_config(),
+                    "status": record.status,
+                }))?,
+            }
+        }
+        SageturnerSubCommands::Promote(promote) => {
+            let options = DeployOptions {
+                yes: promote.yes,
+                wait: !promote.no_wait,
+                force: promote.force,
+                skip_approval: promote.skip_approval,
+                clean_on_failure: promote.clean_on_failure,
+                skip_build: false,
+                skip_push: false,
+                skip_upload: false,
+                resume: false,
+                from: None,
+                until: None,
+                output: cmd.output,
+            };
+            info!("Promoting from {} to {} in {}", promote.from, promote.to, aws.region);
+            let started = Utc::now();
+            let configs: Vec<_> = targets.iter().map(DeployTarget::resolved).collect();
+            let mut deploys = Vec::new();
+            let promoted = Deployer::new(aws.clone(), docker.clone()).deploy_all(targets, &options, &mut deploys).await;
+            if cmd.output == output::OutputFormat::Json {
+                output::print_json(&serde_json::json!({ "deploys": deploys }))?;
+            }
+            let reported = promote.report.as_ref().map(|path| {
+                output::Report::new(started, configs, &deploys, &promoted).write(Path::new(path))
+            });
+            if log_format == logging::LogFormat::Github {
+                github::report(&deploys)?;
+            }
+            promoted?;
+            reported.transpose()?;
+        }
+        SageturnerSubCommands::Versions(versions) => {
+            let state = state_backend.load(&aws).await?;
+            let deploys: Vec<_> = state
+                .deploys
+                .iter()
+                .rev()
+                .filter(|d| d.model == versions.model)
+                .filter(|d| versions.env.is_none() || d.environment == versions.env)
+                .collect();
+            if cmd.output == output::OutputFormat::Json {
+                output::print_json(&serde_json::json!({ "deploys": deploys }))?;
+                return Ok(());
+            }
+            if deploys.is_empty() {
+                println!("No deploys of {} recorded in {}", versions.model, state_backend);
+                return Ok(());
+            }
+            println!(
+                "{:<21} {:<12} {:<32} {:<14} {:<40} {:<22} {:<19} {:<48} DEPLOYED BY",
+                "STARTED", "VERSION", "ENDPOINT", "REGION", "SAGEMAKER MODEL", "STATUS", "IMAGE DIGEST", "ARTEFACT"
+            );
+            for d in deploys {
+                let status = match d.rollback {
+                    true => format!("{} (rollback)", d.status),
+                    false => d.status.to_string(),
+                };
+                // sha256: and the first 12 characters are enough to tell images apart
+                let digest = d.image_digest.as_deref().map(|digest| digest.chars().take(19).collect::<String>());
+                println!(
+                    "{:<21} {:<12} {:<32} {:<14} {:<40} {:<22} {:<19} {:<48} {}",
+                    d.started,
+                    d.version.as_deref().unwrap_or("-"),
+                    d.endpoint,
+                    d.region,
+                    d.sagemaker_model,
+                    status,
+                    digest.as_deref().unwrap_or("-"),
+                    d.artefact.as_deref().or(d.model_package.as_deref()).unwrap_or("-"),
+                    d.deployed_by
+                );
+            }
+            println!("Roll an endpoint back to one of these with sageturner rollback -n <endpoint> --to <sagemaker model>");
+        }
+        SageturnerSubCommands::Unlock(unlock) => {
+            let unlocked = state_backend.unlock(&unlock.endpoint, &aws.region, &aws).await?;
+            match (cmd.output, unlocked) {
+                (output::OutputFormat::Json, _) => output::print_json(&serde_json::json!({
+                    "endpoint": unlock.endpoint,
+                    "region": aws.region,
+                    "unlocked": unlocked,
+                }))?,
+                (_, true) => println!("Unlocked endpoint {} in {}", unlock.endpoint, aws.region),
+                (_, false) => println!("Endpoint {} in {} wasn't locked", unlock.endpoint, aws.region),
+            }
+        }
+        SageturnerSubCommands::Loadtest(loadtest) => {
+            let (body, duration) = load_test.expect("read before the AWS clients were set up");
+            if aws::current_endpoint_config(&loadtest.endpoint, &aws.sage).await?.is_none() {
+                return Err(anyhow!("There's no endpoint {} in {}", loadtest.endpoint, aws.region));
+            }
+            let report =
+                loadtest::run(&loadtest.endpoint, body, &loadtest.content_type, loadtest.concurrency, duration, &aws.runtime).await?;
+            match cmd.output {
+                output::OutputFormat::Text => report.print(),
+                output::OutputFormat::Json => output::print_json(&report)?,
+            }
+        }
+        SageturnerSubCommands::Recommend(recommend) => {
+            let model_config = recommend_config.expect("parsed before the AWS clients were set up");
+            let job_name = match (&recommend.job, &model_config.recommender) {
+                (Some(job), _) => job.clone(),
+                (None, Some(recommender)) => {
+                    let sagemaker_model = match &recommend.model {
+                        Some(model) => model.clone(),
+                        None => latest_deployed_model(&model_config, &aws).await?,
+                    };
+                    let (bucket_name, execution_role) = bucket_and_role(&model_config, &aws.region);
+                    let role_arn = aws::resolve_role_arn(&execution_role, &aws.iam).await?;
+                    // Job names can be 64 characters
+                    let endpoint_name = aws::endpoint_name(&model_config.name, model_config.environment.as_deref());
+                    let job_name = format!("{}-{}", endpoint_name.chars().take(51).collect::<String>(), Utc::now().format("%d%m%Y%H%M"));
+                    let tags = [Tag::builder().key(aws::MODEL_TAG).value(&model_config.name).build()];
+                    let config_dir = Path::new(&recommend.config_path).parent().unwrap_or(Path::new(""));
+                    recommend::start(recommender, &sagemaker_model, &job_name, &bucket_name, &role_arn, config_dir, &aws, &tags).await?;
+                    job_name
+                }
+                (None, None) => return Err(anyhow!("Something went wrong with our validation. Raise an issue")),
+            };
+            let recommendations = recommend::wait(&job_name, &aws.sage).await?;
+            let Some(best) = recommendations.first() else {
+                return Err(anyhow!("Inference Recommender job {} finished without recommending anything", job_name));
+            };
+            if cmd.output == output::OutputFormat::Text {
+                recommend::print(&recommendations);
+                println!(
+                    "Cheapest per inference is {} instances of {}, which in your config's compute section is:",
+                    best.instance_count, best.instance_type
+                );
+                println!("  server:\n    instance_type: {}\n    initial_instance_count: {}", best.instance_type, best.instance_count);
+            }
+            if recommend.apply {
+                model_config::set_server_compute(
+                    Path::new(&recommend.config_path),
+                    model_config.environment.as_deref(),
+                    &best.instance_type,
+                    best.instance_count,
+                    &mut output::human(cmd.output),
+                )?;
+            } else {
+                info!("Pass --apply to write it into {}", recommend.config_path);
+            }
+            if cmd.output == output::OutputFormat::Json {
+                output::print_json(&serde_json::json!({
+                    "job": job_name,
+                    "recommendations": recommendations,
+                    "applied": recommend.apply,
+                }))?;
+            }
         }
     }
 
     Ok(())
 }
 
-async fn process_deploy(
-    ecr_client: &aws_sdk_ecr::Client,
-    sage_client: &aws_sdk_sagemaker::Client,
-    docker_client: &Docker,
-    iam_client: &aws_sdk_iam::Client,
-    s3_client: &aws_sdk_s3::Client,
-    deploy_params: &Deploy,
-) -> Result<()> {
-    println!(
-        "Deploying model with config at {} to {} endpoint, {} container mode",
-        &deploy_params.config_path, &deploy_params.endpoint_type, &deploy_params.container_mode
-    );
-
-    let config_dir = Path::new(&deploy_params.config_path).parent().expect("Your config path didn't point to a YAML file");
-    let deploy_timestamp = Utc::now().format("%d%m%Y%H%M").to_string();
-
-    // TODO - unclone this
-    let model_config = model_config::parse_config(deploy_params.config_path.clone().into())?;
-    model_config::validate_config(
-        &model_config,
-        &deploy_params.endpoint_type,
-        &deploy_params.container_mode,
-        config_dir
-    )?;
-
-    // Generate dockerfile & build, or build the supplied dockerfile
-    match deploy_params.container_mode {
-        ContainerMode::Provide => {
-            let docker_dir = model_config
-                .container
-                .provide_container
-                .ok_or_else(|| {
-                    anyhow!("Something went wrong with our validation. Raise an issue.")
-                })?
-                .docker_dir;
-            docker::build_image_byo(Path::new(&docker_dir), docker_client, &model_config.name, config_dir).await?;
+// The SageMaker model of the config's latest recorded deploy in this region that's still there
+async fn latest_deployed_model(model_config: &model_config::ModelConfig, aws: &aws::AwsClients) -> Result<String> {
+    let state_backend = state::Backend::new(model_config.state_backend.as_ref(), &model_config.config_dir);
+    let state = state_backend.load(aws).await?;
+    let deploy = state
+        .deploys
+        .iter()
+        .rev()
+        .find(|d| d.model == model_config.name && d.environment == model_config.environment && d.region == aws.region && !d.cleaned_up)
+        .ok_or_else(|| anyhow!("No deploys of {} recorded in {}, deploy it first or pass --model", model_config.name, state_backend))?;
+    if aws.sage.describe_model().model_name(&deploy.sagemaker_model).send().await.is_err() {
+        return Err(anyhow!("{}'s latest deploy's model {} has been deleted, pass --model", model_config.name, deploy.sagemaker_model));
+    }
+    Ok(deploy.sagemaker_model.clone())
+}
+
+// The SageMaker models (and endpoint configs) of deploys in the state that failed and haven't been cleaned up,
+// and that no endpoint is running. A deploy that failed then worked when it was run again reuses its model, so
+// only each model's latest deploy counts
+async fn failed_deploys(state: &state::State, aws: &aws::AwsClients) -> Result<Vec<String>> {
+    let mut failed: Vec<String> = Vec::new();
+    for deploy in state.deploys.iter().filter(|d| d.region == aws.region) {
+        let latest = state.latest(&deploy.sagemaker_model, &aws.region);
+        if deploy.status != state::DeployStatus::Failed || latest.is_some_and(|l| l.status != state::DeployStatus::Failed) {
+            continue;
         }
-        ContainerMode::Generate => {
-            let code_location = &model_config
-                .container
-                .generate_container
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .code_dir.as_str();
-            let python_packages = &model_config
-                .container
-                .generate_container
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .python_packages;
-            let system_packages = &model_config
-                .container
-                .generate_container
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .system_packages;
-            let serve_code = pyserve::get_serve_code();
-            let gpu = model_config
-                .container
-                .generate_container
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .install_cuda;
-            let python_version = model_config
-                .container
-                .generate_container
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .python_version.clone();
-
-            // TODO - unclone this
-            let python_packages_str = python_packages
-                .clone()
-                .unwrap_or(Vec::<String>::new())
-                .join(" ");
-            let system_packages_str = system_packages
-                .clone()
-                .unwrap_or(Vec::<String>::new())
-                .join(" ");
-            docker::build_image_ez_mode(
-                gpu,
-                &python_packages_str,
-                &system_packages_str,
-                &model_config.name,
-                &serve_code,
-                docker_client,
-                &python_version,
-                code_location, // TODO fix this unecessary auto deref,
-                config_dir
-            )
-            .await?;
+        if deploy.cleaned_up || failed.contains(&deploy.sagemaker_model) {
+            continue;
         }
+        if aws::current_endpoint_config(&deploy.endpoint, &aws.sage).await?.as_deref() == Some(deploy.endpoint_config()) {
+            continue;
+        }
+        failed.push(deploy.sagemaker_model.clone());
     }
+    Ok(failed)
+}
 
-    let repo_endpoint = docker::push_image(docker_client, ecr_client, &model_config.name).await?;
-    let uri = format!("{repo_endpoint}:latest");
+// The regions to fan a deploy out to, from the configs' regions lists, which every model in a workspace has to agree on
+fn shared_regions(targets: &[DeployTarget]) -> Result<Vec<String>> {
+    let regions = shared_setting(targets, "regions list", |mc| {
+        (!mc.regions.is_empty()).then(|| mc.regions.join(","))
+    })?;
+    Ok(regions.map(|r| r.split(',').map(|r| r.to_string()).collect()).unwrap_or_default())
+}
 
-    let mut bucket_name = DEFAULT_BUCKET_NAME.to_string();
-    let mut execution_role_name = DEFAULT_ROLE_NAME.to_string();
+// A setting that picks the AWS clients, like the region, which every model in a workspace has to agree on
+fn shared_setting(
+    targets: &[DeployTarget],
+    name: &str,
+    setting: impl Fn(&model_config::ModelConfig) -> Option<String>,
+) -> Result<Option<String>> {
+    let mut values: Vec<String> = targets.iter().filter_map(|t| setting(&t.model_config)).collect();
+    values.sort();
+    values.dedup();
+    if values.len() > 1 {
+        return Err(anyhow!("The models in your workspace set different {}s ({}). Deploy them separately", name, values.join(", ")));
+    }
+    Ok(values.pop())
+}
 
-    // TODO - unclone this
-    if let Some(o) = model_config.overrides {
-        if let Some(b) = o.bucket_name { 
-            println!("Overriding default bucket name with: {}", b);
-            bucket_name = b.clone() 
-        }
-        
-        if let Some(r) = o.role_arn { 
-            println!("Overriding default role name with: {}", r);
-            execution_role_name = r.clone();
-        }
+
+// Promoting deploys one config to the environment being promoted to, with the model from the one promoted from
+fn promote_targets(promote: &Promote) -> Result<Vec<DeployTarget>> {
+    if promote.from == promote.to {
+        return Err(anyhow!("--from and --to are both {}, promote between two environments", promote.from));
+    }
+    if model_config::parse_workspace(Path::new(&promote.config_path))?.is_some() {
+        return Err(anyhow!("promote takes one model's config, not a workspace"));
+    }
+    let from_config = model_config::parse_config(promote.config_path.clone().into(), Some(&promote.from), &[])?;
+    let model_config = model_config::parse_config(promote.config_path.clone().into(), Some(&promote.to), &timeout_sets(&promote.timeout))?;
+    if from_config.region != model_config.region || from_config.assume_role_arn != model_config.assume_role_arn {
+        return Err(anyhow!(
+            "{} and {} are in different regions or accounts. Promote copies a model within one, deploy its model package with --model-package instead",
+            promote.from,
+            promote.to
+        ));
     }
+    let endpoint_type = match (promote.endpoint_type, &model_config.compute.server, &model_config.compute.serverless) {
+        (Some(e), _, _) => e,
+        (None, Some(_), None) => EndpointType::Server,
+        (None, None, Some(_)) => EndpointType::Serverless,
+        _ => return Err(anyhow!("{} has compute for both endpoint types, pass --endpoint-type", promote.to)),
+    };
+    Ok(vec![DeployTarget {
+        config_path: promote.config_path.clone(),
+        endpoint_type,
+        source: ModelSource::Promote {
+            from: promote.from.clone(),
+            endpoint: aws::endpoint_name(&from_config.name, from_config.environment.as_deref()),
+            environment: from_config.container.environment.keys().cloned().collect(),
+        },
+        model_config,
+    }])
+}
 
-    let execution_role_arn = aws::get_role_arn(&execution_role_name, iam_client).await?;
-    let final_model_name: String;
-    // Upload a model artefact if we have it
-    match model_config.artefact {
-        Some(a) => {
-            let path = Path::new(&a);
-            let a_name = path.file_name().ok_or_else(|| anyhow!("Couldn't extract filename from artefact path"))?;
-            let s3_key = format!("{}/{}/{}", &model_config.name, deploy_timestamp, a_name.to_str().unwrap());
-            let s3_path = aws::upload_artefact(&a, &bucket_name, &s3_key, s3_client, config_dir).await?;
-            println!("S3 PATH PASSED AS model_data_url: {}", s3_path);
-            final_model_name = aws::create_sagemaker_model(
-                &model_config.name,
-                &execution_role_arn,
-                &uri,
-                sage_client,
-                Some(s3_path),
-                &deploy_timestamp
-            )
-            .await?;
-        }
-        None => {
-            // No artefact to put on S3
-            final_model_name = aws::create_sagemaker_model(
-                &model_config.name,
-                &execution_role_arn,
-                &uri,
-                sage_client,
-                None,
-                &deploy_timestamp
-            )
-            .await?;
+// --timeout upload=3h is shorthand for --set timeouts.upload=3h, so the config checks it like any other value
+fn timeout_sets(timeouts: &[String]) -> Vec<String> {
+    timeouts.iter().map(|t| format!("timeouts.{}", t)).collect()
+}
+
+// How long rollback waits for the endpoint: --timeout endpoint=3h, then timeouts.endpoint in your defaults file.
+// It's the only phase a rollback has
+fn rollback_endpoint_limit(timeouts: &[String], defaults: Option<&serde_yaml::Value>) -> Result<std::time::Duration> {
+    let mut limit = defaults
+        .and_then(|d| d.get("timeouts"))
+        .and_then(|t| t.get("endpoint"))
+        .and_then(|e| e.as_str())
+        .map(|e| e.to_string());
+    for timeout in timeouts {
+        match timeout.split_once('=') {
+            Some(("endpoint", value)) => limit = Some(value.to_string()),
+            _ => return Err(anyhow!("rollback only waits for the endpoint, so takes --timeout endpoint=<duration>, not {}", timeout)),
         }
     }
+    match limit {
+        Some(limit) => loadtest::parse_duration(&limit).map_err(|e| anyhow!("Invalid endpoint timeout: {}", e)),
+        None => Ok(aws::ENDPOINT_WAIT_LIMIT),
+    }
+}
 
-    match deploy_params.endpoint_type {
-        EndpointType::Serverless => {
-            let memory = model_config
-                .compute
-                .serverless
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .memory;
-            let max_concurrency = model_config
-                .compute
-                .serverless
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .max_concurrency;
-            let provisioned_concurrency = model_config
-                .compute
-                .serverless
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .provisioned_concurrency;
-            aws::create_serverless_endpoint(
-                &final_model_name,
-                memory,
-                max_concurrency,
-                provisioned_concurrency,
-                sage_client,
-                &deploy_timestamp
-            )
-            .await?;
+// A deploy's --set overrides, then its --timeouts, which win
+fn deploy_sets(deploy: &Deploy) -> Vec<String> {
+    deploy.set.iter().cloned().chain(timeout_sets(&deploy.timeout)).collect()
+}
+
+fn deploy_targets(deploy: &Deploy) -> Result<Vec<DeployTarget>> {
+    let config_path = Path::new(&deploy.config_path);
+    let workspace = model_config::parse_workspace(config_path)?;
+    // A model package is one model, that's already built
+    if let Some(arn) = &deploy.model_package {
+        if workspace.is_some() {
+            return Err(anyhow!("--model-package deploys one model, so needs a model's config, not a workspace"));
         }
-        EndpointType::Server => {
-            let instance_type = model_config
-                .compute
-                .server
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .instance_type
-                .clone();
-            let initial_instance_count = model_config
-                .compute
-                .server
-                .as_ref()
-                .ok_or_else(|| anyhow!("Something went wrong with our validation. Raise an issue"))?
-                .initial_instance_count;
-            aws::create_server_endpoint(
-                &final_model_name,
-                &instance_type,
-                initial_instance_count,
-                sage_client,
-                &deploy_timestamp
-            )
-            .await?;
+        if deploy.container_mode.is_some() {
+            return Err(anyhow!("--model-package deploys don't build a container, so don't take --container-mode"));
         }
+        let endpoint_type = deploy.endpoint_type.ok_or_else(|| anyhow!("No endpoint type: pass --endpoint-type"))?;
+        let model_config = model_config::parse_config(deploy.config_path.clone().into(), deploy.env.as_deref(), &deploy_sets(deploy))?;
+        return Ok(vec![DeployTarget {
+            config_path: deploy.config_path.clone(),
+            endpoint_type,
+            source: ModelSource::Package(arn.clone()),
+            model_config,
+        }]);
     }
-    println!("Sageturner done!");
-    Ok(())
+    let entries = match workspace {
+        Some(workspace) => {
+            let workspace_dir = config_path.parent().unwrap_or(Path::new(""));
+            workspace
+                .models
+                .into_iter()
+                .map(|m| (workspace_dir.join(&m.config).to_string_lossy().to_string(), m.endpoint_type, m.container_mode))
+                .collect()
+        }
+        None => vec![(deploy.config_path.clone(), None, None)],
+    };
+
+    entries
+        .into_iter()
+        .map(|(config_path, endpoint_type, container_mode)| {
+            let endpoint_type = match endpoint_type {
+                Some(e) => e.parse()?,
+                None => deploy.endpoint_type.ok_or_else(|| {
+                    anyhow!("No endpoint type for {}: pass --endpoint-type, or set endpoint_type for it in your workspace", config_path)
+                })?,
+            };
+            let container_mode = match container_mode {
+                Some(m) => m.parse()?,
+                None => deploy.container_mode.ok_or_else(|| {
+                    anyhow!("No container mode for {}: pass --container-mode, or set container_mode for it in your workspace", config_path)
+                })?,
+            };
+            let model_config = model_config::parse_config(config_path.clone().into(), deploy.env.as_deref(), &deploy_sets(deploy))?;
+            Ok(DeployTarget { config_path, endpoint_type, source: ModelSource::Build(container_mode), model_config })
+        })
+        .collect()
 }
+
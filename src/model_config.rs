@@ -1,19 +1,41 @@
-use std::{fs::File, io::Read, path::{Path, PathBuf, absolute}};
+use std::{collections::{BTreeMap, HashMap}, fs::File, io::{Read, Write}, path::{Path, PathBuf, absolute}, time::Duration};
 
 use anyhow::{anyhow, Result};
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
 
-use crate::{ContainerMode, EndpointType};
+use crate::{artefact, aws, errors, instances::{self, InstanceKind}, loadtest, presets::{Preset, PresetOptions}, ContainerMode, EndpointType};
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 #[serde(deny_unknown_fields)]
 pub struct ModelConfig {
     // The name of the model
     pub name: String,
+    // A semantic version for this release of the model, e.g. 1.4.0. Sageturner names the SageMaker model
+    // and endpoint after it, tags the image with it and uploads the artefact under it, in place of a timestamp
+    pub version: Option<String>,
     // The model artefact path. If provided, we upload this to S3
     // and pass the S3 path as ModelDataURI to the endpoint. SageMaker then makes this available
-    // to the container at /opt/ml/model, boosting load times
+    // to the container at /opt/ml/model, boosting load times.
+    // Either a .tar.gz, or a directory that we package into a model.tar.gz for you.
+    // An s3:// URI of a .tar.gz already in S3 is passed to SageMaker as is
     pub artefact: Option<String>,
+    // Where the artefact goes in the bucket. A template using {name}, {version}, {timestamp}, {file} (the artefact's file name,
+    // "model" for uncompressed artefacts) and {git_sha} (the commit the config file is at).
+    // Defaults to {name}/{version}/{file} for versioned models and {name}/{timestamp}/{file} otherwise. For uncompressed artefacts this is the prefix the files go under
+    pub artefact_key: Option<String>,
+    // Upload a directory artefact file by file under an S3 prefix instead of as a model.tar.gz.
+    // SageMaker copies the files to /opt/ml/model as they are, which loads huge LLM weights much faster.
+    // With an s3:// artefact, point it at the prefix (ending in /) holding your files
+    #[serde(default)]
+    pub uncompressed_artefact: bool,
+    // Globs for files to leave out of a directory artefact (and Triton's model repository), like checkpoints/ or *.ipynb.
+    // .git, __pycache__, .ipynb_checkpoints and .DS_Store are always left out
+    #[serde(default)]
+    pub exclude: Vec<String>,
     // Deployment configuration(s)
     pub container: Container,
     // Specify compute characterstics
@@ -21,22 +43,296 @@ pub struct ModelConfig {
     // Override the default role and bucket names created by Sageturner as part of the deploy process.
     // Expects the bucket and role to already exist
     pub overrides: Option<Overrides>,
+    // Tune how the artefact is uploaded to S3
+    #[serde(default)]
+    pub upload: UploadConfig,
+    // How long each phase of the deploy gets before sageturner gives up on it
+    #[serde(default)]
+    pub timeouts: TimeoutsConfig,
+    // The AWS region to deploy to, instead of your configured one
+    pub region: Option<String>,
+    // Deploy to each of these regions instead, e.g. for low latency around the world. Each region gets its own copy
+    // of the image in ECR, its own artefact bucket (see overrides.bucket_name) and its own endpoint
+    #[serde(default)]
+    pub regions: Vec<String>,
+    // The AWS profile (from ~/.aws/config) to deploy with, instead of the default one
+    pub profile: Option<String>,
+    // When a deploy updates an existing endpoint, delete all but this many of the models and endpoint configs
+    // it replaced, which are kept to roll back to. Unset keeps them all
+    pub keep_previous: Option<u32>,
+    // Before an update moves the endpoint's traffic to the new model, run it alongside the old one and check it answers
+    // these. If it doesn't, the endpoint goes back to its old model
+    pub canary: Option<CanaryConfig>,
+    // Requests to send the endpoint once it's in service, each with the answer it should get. If any gets the wrong
+    // one, the deploy fails
+    #[serde(default)]
+    pub smoke_tests: Vec<SmokeTest>,
+    // When a smoke test fails after an update, put the endpoint back on the model it was running before
+    #[serde(default)]
+    pub rollback_on_smoke_test_failure: bool,
+    // Requests for sageturner recommend to load test the model with, to find the instance type that suits it
+    pub recommender: Option<RecommenderConfig>,
+    // How long to keep old deploys' models, endpoint configs, images and artefacts. sageturner gc --retention
+    // does the deleting
+    pub retention: Option<RetentionConfig>,
+    // A role to assume for the deploy, e.g. a deploy role in another account, so a central CI account can deploy
+    // into workload accounts. Every AWS client uses the role's credentials
+    pub assume_role_arn: Option<String>,
+    // The external ID the role's trust policy asks for, if any
+    pub assume_role_external_id: Option<String>,
+    // Talk to these endpoints instead of AWS's standard ones, e.g. LocalStack for testing, or a VPC endpoint
+    pub endpoint_url: Option<EndpointUrl>,
+    // Delete the endpoint this long after it was last deployed, e.g. 6h, 2d or 1w, for experiments that shouldn't be
+    // left running. sageturner gc --expired does the deleting
+    pub ttl: Option<String>,
+    // Delete the endpoint's logs after this many days, instead of keeping them forever. One of the periods
+    // CloudWatch Logs allows: 1, 3, 5, 7, 14, 30, 60, 90, 120, 150, 180, 365 and so on
+    pub log_retention_days: Option<i32>,
+    // Create a CloudWatch dashboard for the endpoint, with its invocations, latency, errors and utilisation
+    #[serde(default)]
+    pub dashboard: bool,
+    // Where to send events as the deploy goes: started, endpoint in service, failed
+    pub notifications: Option<Notifications>,
+    // Shell commands to run at points in the deploy, for anything sageturner doesn't do itself
+    #[serde(default)]
+    pub hooks: Hooks,
+    // Refuse to deploy (without deploy --force) if the endpoint's estimated cost is more than this many USD a month
+    pub max_monthly_cost: Option<f64>,
+    // Capture the endpoint's requests and responses, and check them on a schedule with SageMaker Model Monitor
+    pub monitoring: Option<MonitoringConfig>,
+    // Register each deploy as a version in a SageMaker Model Registry model package group
+    pub registry: Option<RegistryConfig>,
+    // Only update the endpoint from an approved model package (deploy --model-package), usually set for a prod
+    // environment. Builds are registered for approval instead, unless deploy --skip-approval
+    #[serde(default)]
+    pub require_approval: bool,
+    // Keep the record of deploys in S3 or DynamoDB instead of .sageturner/state.json, so the whole team can list,
+    // roll back and clean up each other's deploys. Usually in your defaults file, so commands without a config use it too
+    pub state_backend: Option<StateBackendConfig>,
+    // Extra tags for the SageMaker model, endpoint config and endpoint
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    // How sageturner setup creates the role. Usually in your defaults file: setup reads it from there, and deploys
+    // only accept it so configs merged over your defaults still parse
+    #[allow(dead_code)]
+    pub setup: Option<SetupConfig>,
+    // The environment picked with deploy --env, after its overrides have been applied.
+    // Set by parse_config, not read from the file
+    #[serde(skip)]
+    pub environment: Option<String>,
+    // The directory the config file is in, which paths in it are relative to. Set by parse_config
+    #[serde(skip)]
+    pub config_dir: PathBuf,
+    // SHA-256 of the config as deployed, recorded with the deploy's provenance. Set by parse_config
+    #[serde(skip)]
+    pub config_hash: String,
+    // The config as deployed: defaults, extends, environment overrides and --set all applied. Set by parse_config
+    #[serde(skip)]
+    pub resolved: Value,
 }
 
-#[derive(Debug, Deserialize)]
+// One URL for every AWS service (LocalStack serves them all from one), or a URL per service
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(untagged)]
+pub enum EndpointUrl {
+    All(String),
+    PerService(HashMap<String, String>),
+}
+
+// The services endpoint_url can be set for, as they're named in the map
+pub const ENDPOINT_URL_SERVICES: &[&str] = &[
+    "sagemaker", "sagemaker-runtime", "ecr", "iam", "s3", "ssm", "secretsmanager", "sts", "logs", "servicequotas", "sns", "events",
+    "monitoring", "dynamodb",
+];
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct UploadConfig {
+    // Artefacts bigger than this are uploaded in parts of this size (MB). S3's minimum part size is 5MB
+    #[serde(default = "default_part_size_mb")]
+    pub part_size_mb: u64,
+    // How many parts to upload at once
+    #[serde(default = "default_upload_concurrency")]
+    pub concurrency: usize,
+    // How many times to try each part before giving up on the upload
+    #[serde(default = "default_part_attempts")]
+    pub part_attempts: u32,
+    // Upload through S3 Transfer Acceleration, which is faster over long distances.
+    // Acceleration has to be enabled on the bucket
+    #[serde(default)]
+    pub transfer_acceleration: bool,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        UploadConfig {
+            part_size_mb: default_part_size_mb(),
+            concurrency: default_upload_concurrency(),
+            part_attempts: default_part_attempts(),
+            transfer_acceleration: false,
+        }
+    }
+}
+
+fn default_part_size_mb() -> u64 {
+    64
+}
+
+fn default_upload_concurrency() -> usize {
+    8
+}
+
+fn default_part_attempts() -> u32 {
+    3
+}
+
+// Durations like 30m or 2h. Anything left out gets a default that's generous enough for
+// multi-GB images and artefacts over an ordinary connection
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    // Building the image. Defaults to 1h
+    pub build: Option<String>,
+    // Pushing the image to ECR, retries included. Defaults to 1h
+    pub push: Option<String>,
+    // Uploading the artefact to S3. Defaults to 10m, plus 5m for every GB of artefact
+    pub upload: Option<String>,
+    // Waiting for S3 to show an uploaded artefact. Defaults to 5m
+    pub waiter: Option<String>,
+    // Waiting for the endpoint to come InService. Defaults to 90m
+    pub endpoint: Option<String>,
+}
+
+const GB: u64 = 1024 * 1024 * 1024;
+
+impl TimeoutsConfig {
+    pub fn build(&self) -> Duration {
+        timeout_or(&self.build, Duration::from_secs(60 * 60))
+    }
+
+    pub fn push(&self) -> Duration {
+        timeout_or(&self.push, Duration::from_secs(60 * 60))
+    }
+
+    // Scales with the artefact, so a 20GB model gets 110 minutes without anyone asking
+    pub fn upload(&self, bytes: u64) -> Duration {
+        let gb = bytes.div_ceil(GB) as u32;
+        timeout_or(&self.upload, Duration::from_secs(10 * 60) + Duration::from_secs(5 * 60) * gb)
+    }
+
+    pub fn waiter(&self) -> Duration {
+        timeout_or(&self.waiter, DEFAULT_WAITER_TIMEOUT)
+    }
+
+    pub fn endpoint(&self) -> Duration {
+        timeout_or(&self.endpoint, aws::ENDPOINT_WAIT_LIMIT)
+    }
+
+    fn validate(&self) -> Result<()> {
+        let timeouts = [
+            ("build", &self.build),
+            ("push", &self.push),
+            ("upload", &self.upload),
+            ("waiter", &self.waiter),
+            ("endpoint", &self.endpoint),
+        ];
+        for (name, timeout) in timeouts {
+            if let Some(timeout) = timeout {
+                loadtest::parse_duration(timeout).map_err(|e| anyhow!("Invalid sageturner config: timeouts.{}: {}", name, e))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// How long the S3 and IAM waiters get when nothing says otherwise
+pub const DEFAULT_WAITER_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+// Validated in check_config, so a bad value can only get here from code that skipped it
+fn timeout_or(timeout: &Option<String>, default: Duration) -> Duration {
+    timeout.as_deref().and_then(|t| loadtest::parse_duration(t).ok()).unwrap_or(default)
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Container {
+    // Environment variables for the container, set on the SageMaker model. Values can be secrets,
+    // HF_TOKEN: !secret /ml/hf-token, which are looked up in SSM Parameter Store or Secrets Manager at deploy time
+    #[serde(default)]
+    pub environment: HashMap<String, EnvValue>,
     // Configuration for smart mode deploy
     pub generate_container: Option<GenerateContainerConfig>,
     // Configuration for a docker mode deploy
     pub provide_container: Option<ProvideContainerConfig>,
+    // Configuration for serving an LLM with vLLM
+    pub vllm: Option<VllmConfig>,
+    // Configuration for serving an LLM with the Hugging Face TGI image
+    pub tgi: Option<TgiConfig>,
+    // Configuration for serving a model repository with NVIDIA Triton
+    pub triton: Option<TritonConfig>,
 }
 
-#[derive(Debug, Deserialize)]
+// A container environment value: as is, or the name of a secret to look up at deploy time.
+// Secrets are written !secret name in YAML, or {"secret": "name"} in JSON and TOML, which don't have tags
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvValue {
+    Plain(String),
+    Secret(String),
+}
+
+impl<'de> Deserialize<'de> for EnvValue {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        use serde::de::Error;
+        let secret = |value: &Value| match value {
+            Value::String(name) if !name.is_empty() => Ok(EnvValue::Secret(name.clone())),
+            _ => Err(D::Error::custom("a secret needs the name of an SSM parameter or Secrets Manager secret")),
+        };
+        match Value::deserialize(deserializer)? {
+            Value::String(v) => Ok(EnvValue::Plain(v)),
+            Value::Number(n) => Ok(EnvValue::Plain(n.to_string())),
+            Value::Bool(b) => Ok(EnvValue::Plain(b.to_string())),
+            Value::Tagged(tagged) if tagged.tag == "secret" => secret(&tagged.value),
+            Value::Mapping(map) if map.len() == 1 && map.contains_key("secret") => secret(&map["secret"]),
+            _ => Err(D::Error::custom("expected a string, or !secret <SSM parameter or Secrets Manager secret>")),
+        }
+    }
+}
+
+impl JsonSchema for EnvValue {
+    fn schema_name() -> std::borrow::Cow<'static, str> {
+        "EnvValue".into()
+    }
+
+    fn json_schema(_: &mut schemars::SchemaGenerator) -> schemars::Schema {
+        schemars::json_schema!({
+            "anyOf": [
+                { "type": ["string", "number", "boolean"] },
+                {
+                    "type": "object",
+                    "properties": { "secret": { "type": "string" } },
+                    "required": ["secret"],
+                    "additionalProperties": false
+                }
+            ]
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct GenerateContainerConfig {
     // A path to a directory containing a sageturner.py file. 
     // the sageturner.py file, and the rest of the contents of the directory,
-    // will be copied into the container 
-    pub code_dir: String,
+    // will be copied into the container. Optional if you use a preset, in which case
+    // the directory is still copied in but must not contain a sageturner.py
+    pub code_dir: Option<String>,
+    // Globs for files in code_dir to leave out of the image, same rules as the top level exclude
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // Generate sageturner.py for a common framework instead of writing it yourself.
+    // The preset's python packages are installed alongside python_packages
+    pub preset: Option<Preset>,
+    // Settings for presets that need them, like the transformers task
+    #[serde(default)]
+    pub preset_options: PresetOptions,
     // Optional additional system packages to install to container 
     pub system_packages: Option<Vec<String>>,
     // Optional python packages to install to container 
@@ -50,88 +346,1259 @@ pub struct GenerateContainerConfig {
     // defaults to 3.12
     #[serde(default = "default_python")]
     pub python_version: String,
+    // Build for AWS Inferentia/Trainium instead (accelerator: neuron). Uses the Neuron SDK base image,
+    // whose python version wins over python_version. Needs an ml.inf2 or ml.trn1 server instance
+    pub accelerator: Option<Accelerator>,
+    // Your own Tera template for the Dockerfile, instead of sageturner's. See the README for what it's rendered with
+    pub dockerfile_template: Option<String>,
+    // Your own Tera template for serve.py, the server that calls load() and predict()
+    pub serve_template: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Accelerator {
+    Neuron,
+}
+
+// What a generated container is built to run on
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Hardware {
+    Cpu,
+    Cuda,
+    Neuron,
+}
+
+impl GenerateContainerConfig {
+    pub fn hardware(&self) -> Hardware {
+        match (self.accelerator, self.install_cuda) {
+            (Some(Accelerator::Neuron), _) => Hardware::Neuron,
+            (None, true) => Hardware::Cuda,
+            (None, false) => Hardware::Cpu,
+        }
+    }
 }
 
 fn default_python() -> String {
     "3.12".to_string()
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct ProvideContainerConfig {
     // If bringing your own Dockerfile, provide the directory where we can find the Dockerfile and artefacts to build.
     // We bundle everything in that directory to a TAR as part of the build process, so paths referenced in Docker COPY commands needs to work in that directory
     pub docker_dir: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct VllmConfig {
+    // Hugging Face Hub model id, downloaded by vLLM when the endpoint starts.
+    // If not set, vLLM serves the artefact SageMaker puts at /opt/ml/model
+    pub model_id: Option<String>,
+    // Number of GPUs to shard the model across. Must not exceed the GPUs on the instance type
+    #[serde(default = "default_tensor_parallel_size")]
+    pub tensor_parallel_size: u32,
+    // Max context length (prompt + generated tokens). Defaults to the model's own limit,
+    // lower it if the KV cache doesn't fit in GPU memory
+    pub max_model_len: Option<u32>,
+    // Your own Tera template for the Dockerfile, instead of sageturner's
+    pub dockerfile_template: Option<String>,
+}
+
+fn default_tensor_parallel_size() -> u32 {
+    1
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TgiConfig {
+    // Hugging Face Hub model id, downloaded by TGI when the endpoint starts.
+    // If not set, TGI serves the artefact SageMaker puts at /opt/ml/model
+    pub model_id: Option<String>,
+    // Number of GPUs to shard the model across
+    #[serde(default = "default_tensor_parallel_size")]
+    pub num_gpus: u32,
+    // Max prompt tokens, and max prompt + generated tokens. TGI picks these from the model if not set
+    pub max_input_tokens: Option<u32>,
+    pub max_total_tokens: Option<u32>,
+    // Use a different TGI/LMI image instead of the Hugging Face DLC sageturner resolves for your region
+    pub image: Option<String>,
+}
+
+impl TgiConfig {
+    // The env vars the Hugging Face TGI container reads its settings from
+    pub fn environment(&self) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+        env.insert(
+            "HF_MODEL_ID".to_string(),
+            self.model_id.clone().unwrap_or("/opt/ml/model".to_string()),
+        );
+        env.insert("SM_NUM_GPUS".to_string(), self.num_gpus.to_string());
+        if let Some(t) = self.max_input_tokens {
+            env.insert("MAX_INPUT_TOKENS".to_string(), t.to_string());
+        }
+        if let Some(t) = self.max_total_tokens {
+            env.insert("MAX_TOTAL_TOKENS".to_string(), t.to_string());
+        }
+        env
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct TritonConfig {
+    // Path to a Triton model repository: one directory per model, each with a config.pbtxt
+    // and numbered version directories. Sageturner packages it up as the model artefact
+    pub model_repository: String,
+    // The model Triton serves in single model mode. Can be left out if the repository only has one
+    pub model_name: Option<String>,
+    // Use a different Triton image instead of the SageMaker one sageturner resolves for your region
+    pub image: Option<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Compute {
     pub serverless: Option<ServerlessCompute>,
     pub server: Option<ServerCompute>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct ServerlessCompute {
     // Memory required by servless instance
     pub memory: i32,
-    // Provisioned servless instances at all times
+    // Provisioned servless instances at all times. 0 for none
     pub provisioned_concurrency: i32,
     // Max serverless instances to run at same time
     pub max_concurrency: i32, // Note: Sagemaker Servless endpoints don't support GPUs, so we're always using
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct ServerCompute {
     // AWS EC2 instance type
     pub instance_type: String,
     pub initial_instance_count: i32,
+    // Skip checking instance_type against the instance types sageturner knows about, for ones newer than it
+    #[serde(default)]
+    pub allow_unknown_instance_type: bool,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Overrides {
+    // {region} in the name is replaced with the region being deployed to
     pub bucket_name: Option<String>,
+    // The execution role: the name of a role in the account you're deploying to, or a role's full ARN
     pub role_arn: Option<String>,
 }
 
-pub fn parse_config(path: PathBuf) -> Result<ModelConfig> {
-    println!("Parsing model config file");
+// Each hook is a list of shell commands, run one after another in the config file's directory, with what the deploy
+// knows so far in environment variables: IMAGE_URI, ENDPOINT_NAME, S3_PATH and so on. A command that fails stops the deploy
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    // Before the image is built and the artefact uploaded. Only for deploys that build an image
+    #[serde(default)]
+    pub pre_build: Vec<String>,
+    // Once the image has been pushed, with IMAGE_URI
+    #[serde(default)]
+    pub post_build: Vec<String>,
+    // Once the model exists, before the endpoint is created or updated
+    #[serde(default)]
+    pub pre_deploy: Vec<String>,
+    // Once the deploy has worked. The endpoint's already been updated, so a command that fails here doesn't roll it back
+    #[serde(default)]
+    pub post_deploy: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Notifications {
+    // An SNS topic to publish each event to, as JSON
+    pub sns_topic_arn: Option<String>,
+    // An EventBridge bus to put each event on: a bus name (default for the account's default bus) or ARN
+    pub event_bus: Option<String>,
+    // A URL to post a summary to when a deploy finishes or fails, like a Slack incoming webhook
+    pub webhook_url: Option<String>,
+    // What to post to webhook_url: slack, a message Slack shows, or json, the event
+    #[serde(default)]
+    pub webhook_format: WebhookFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookFormat {
+    #[default]
+    Slack,
+    Json,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct MonitoringConfig {
+    // Where captured requests, baselines and monitoring reports go, as an s3:// prefix. Defaults to
+    // monitoring/<endpoint> in the artefact bucket, which the role setup creates can write to
+    pub s3_uri: Option<String>,
+    // The percentage of requests to capture
+    #[serde(default = "default_capture_percentage")]
+    pub capture_percentage: i32,
+    // The data the model was trained on, as an s3:// CSV with a header row. Data quality monitoring compares what the
+    // endpoint is sent against it
+    pub baseline_dataset: Option<String>,
+    pub model_quality: Option<ModelQualityConfig>,
+    // How often to run the checks: hourly, daily, or a cron(...) expression
+    #[serde(default = "default_monitoring_schedule")]
+    pub schedule: String,
+    // The instance the baseline and monitoring jobs run on
+    #[serde(default = "default_monitoring_instance_type")]
+    pub instance_type: String,
+    // The Model Monitor analyzer image, for regions sageturner doesn't know it for
+    pub image: Option<String>,
+}
+
+// Model quality monitoring compares the endpoint's predictions with labels you upload as they come in
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ModelQualityConfig {
+    // BinaryClassification, MulticlassClassification or Regression
+    pub problem_type: String,
+    // Predictions and labels to baseline against, as an s3:// CSV with a header row
+    pub baseline_dataset: String,
+    // Where you upload ground truth labels for captured requests
+    pub ground_truth_s3_uri: String,
+    // The column (or JSON path, for JSON responses) of the prediction in the endpoint's output, and of the label
+    // in the baseline dataset
+    pub inference_attribute: String,
+    pub ground_truth_attribute: String,
+    // For classification, the column of the prediction's probability
+    pub probability_attribute: Option<String>,
+}
+
+pub const MONITORING_PROBLEM_TYPES: &[&str] = &["BinaryClassification", "MulticlassClassification", "Regression"];
+
+fn default_capture_percentage() -> i32 {
+    100
+}
+
+fn default_monitoring_schedule() -> String {
+    "daily".to_string()
+}
+
+fn default_monitoring_instance_type() -> String {
+    "ml.m5.xlarge".to_string()
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RegistryConfig {
+    // The model package group deploys are registered in, created if it doesn't exist. Defaults to the model's name
+    pub model_package_group: Option<String>,
+    // Describes the group, when sageturner creates it
+    pub description: Option<String>,
+    // What each new version's approval status starts as
+    #[serde(default)]
+    pub approval_status: ApprovalStatus,
+    // The model's evaluation metrics, as a JSON file next to the config or an s3:// URI, shown with the version
+    pub metrics: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize, JsonSchema)]
+pub enum ApprovalStatus {
+    Approved,
+    #[default]
+    PendingManualApproval,
+    Rejected,
+}
+
+impl ApprovalStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApprovalStatus::Approved => "Approved",
+            ApprovalStatus::PendingManualApproval => "PendingManualApproval",
+            ApprovalStatus::Rejected => "Rejected",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryConfig {
+    // Requests to send the new model, each of which has to succeed
+    pub payloads: Vec<CanaryPayload>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryPayload {
+    // A file with the request body, relative to the config
+    pub file: String,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    // Keys the response has to have, for models that answer with a JSON object
+    #[serde(default)]
+    pub expect_keys: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SmokeTest {
+    // A file with the request body, relative to the config
+    pub file: String,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    // The status the model should answer with
+    #[serde(default = "default_expect_status")]
+    pub expect_status: i32,
+    // Checks on the JSON response
+    #[serde(default)]
+    pub assertions: Vec<SmokeTestAssertion>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SmokeTestAssertion {
+    // A JSONPath into the response, like $.predictions[0].label, which has to find something
+    pub path: String,
+    // A value one of the things it finds has to be
+    pub equals: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RecommenderConfig {
+    // Files with requests like the ones the endpoint will get, relative to the config
+    pub payloads: Vec<String>,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    // Only try these instance types, instead of the ones Inference Recommender picks
+    #[serde(default)]
+    pub instance_types: Vec<String>,
+}
+
+fn default_expect_status() -> i32 {
+    200
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+// Deploys are kept if they meet either, so with both set a deploy goes once it's neither one of the last
+// keep_last nor newer than keep_days
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionConfig {
+    pub keep_last: Option<u32>,
+    pub keep_days: Option<u32>,
+}
+
+// Set one of these
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct StateBackendConfig {
+    // An object to keep the state in, e.g. s3://my-team-sagemaker-state/sageturner.json
+    pub s3_uri: Option<String>,
+    // A table with a string partition key named id
+    pub dynamodb_table: Option<String>,
+}
+
+// A ttl like 30m, 6h, 2d or 1w
+pub fn parse_ttl(ttl: &str) -> Result<chrono::Duration> {
+    let (number, unit) = ttl.split_at(ttl.find(|c: char| !c.is_ascii_digit()).unwrap_or(ttl.len()));
+    let number: i64 = number
+        .parse()
+        .ok()
+        .filter(|n| *n > 0)
+        .ok_or_else(|| anyhow!("{} should be a number and a unit, like 6h", ttl))?;
+    let duration = match unit {
+        "m" => chrono::TimeDelta::try_minutes(number),
+        "h" => chrono::TimeDelta::try_hours(number),
+        "d" => chrono::TimeDelta::try_days(number),
+        "w" => chrono::TimeDelta::try_weeks(number),
+        _ => return Err(anyhow!("{} has an unknown unit, use m, h, d or w", ttl)),
+    };
+    // It has to fit in a date once it's added to now, for the endpoint's expiry
+    duration
+        .filter(|d| chrono::Utc::now().checked_add_signed(*d).is_some())
+        .ok_or_else(|| anyhow!("{} is too long", ttl))
+}
+
+// What many organisations require of every IAM role. setup's flags win over these
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SetupConfig {
+    // The ARN of a managed policy that caps what the role can do
+    pub permissions_boundary: Option<String>,
+    // The IAM path to create the role under, like /sageturner/
+    pub role_path: Option<String>,
+    // Who else can assume the role, besides SageMaker: account or role ARNs, or services like ec2.amazonaws.com
+    #[serde(default)]
+    pub trusted_principals: Vec<String>,
+}
+
+// A template's rendered when the image is built, but a missing one can be caught before the deploy starts
+fn check_template(template: Option<&str>, field: &str, config_dir: &Path) -> Result<()> {
+    match template {
+        Some(t) if !config_dir.join(t).is_file() => {
+            Err(anyhow!("Invalid sageturner config: container.{} {} isn't a file", field, config_dir.join(t).display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn validate_monitoring(monitoring: &MonitoringConfig, endpoint_type: &EndpointType) -> Result<()> {
+    // SageMaker only captures data from instances
+    if *endpoint_type != EndpointType::Server {
+        return Err(anyhow!("Invalid sageturner config: monitoring needs a server endpoint, serverless endpoints can't capture data"));
+    }
+    if monitoring.baseline_dataset.is_none() && monitoring.model_quality.is_none() {
+        return Err(anyhow!("Invalid sageturner config: monitoring needs a baseline_dataset for data quality, model_quality, or both"));
+    }
+    if !(1..=100).contains(&monitoring.capture_percentage) {
+        return Err(anyhow!("Invalid sageturner config: monitoring.capture_percentage has to be between 1 and 100"));
+    }
+    let model_quality = monitoring.model_quality.as_ref();
+    let uris = [
+        ("s3_uri", monitoring.s3_uri.as_deref()),
+        ("baseline_dataset", monitoring.baseline_dataset.as_deref()),
+        ("model_quality.baseline_dataset", model_quality.map(|m| m.baseline_dataset.as_str())),
+        ("model_quality.ground_truth_s3_uri", model_quality.map(|m| m.ground_truth_s3_uri.as_str())),
+    ];
+    for (field, uri) in uris {
+        if let Some(uri) = uri.filter(|u| artefact::s3_location(u).is_none_or(|(bucket, _)| bucket.is_empty())) {
+            return Err(anyhow!("Invalid sageturner config: monitoring.{} should be an s3:// URI, not {}", field, uri));
+        }
+    }
+    if let Some(problem_type) = model_quality.map(|m| &m.problem_type).filter(|p| !MONITORING_PROBLEM_TYPES.contains(&p.as_str())) {
+        return Err(anyhow!(
+            "Invalid sageturner config: monitoring.model_quality.problem_type {} isn't one of {}",
+            problem_type,
+            MONITORING_PROBLEM_TYPES.join(", ")
+        ));
+    }
+    let schedule = &monitoring.schedule;
+    let cron = schedule.starts_with("cron(") && schedule.ends_with(')');
+    if !cron && !matches!(schedule.as_str(), "hourly" | "daily") {
+        return Err(anyhow!("Invalid sageturner config: monitoring.schedule should be hourly, daily or a cron(...) expression, not {}", schedule));
+    }
+    Ok(())
+}
+
+pub fn validate_recommender(recommender: &RecommenderConfig, config_dir: &Path) -> Result<()> {
+    if recommender.payloads.is_empty() {
+        return Err(anyhow!("Invalid sageturner config: recommender needs at least one payload to send"));
+    }
+    for payload in &recommender.payloads {
+        if !config_dir.join(payload).is_file() {
+            return Err(anyhow!("Invalid sageturner config: recommender payload {} isn't a file", payload));
+        }
+    }
+    if let Some(instance_type) = recommender.instance_types.iter().find(|t| !t.starts_with("ml.")) {
+        return Err(anyhow!("Invalid sageturner config: recommender instance type {} should be a SageMaker one, like ml.c5.xlarge", instance_type));
+    }
+    Ok(())
+}
+
+pub fn validate_retention(retention: &RetentionConfig) -> Result<()> {
+    match (retention.keep_last, retention.keep_days) {
+        (None, None) => Err(anyhow!("Invalid sageturner config: retention needs keep_last, keep_days or both")),
+        (Some(0), _) => Err(anyhow!("Invalid sageturner config: retention.keep_last has to keep at least 1 deploy")),
+        (_, Some(0)) => Err(anyhow!("Invalid sageturner config: retention.keep_days has to be at least 1")),
+        _ => Ok(()),
+    }
+}
+
+pub fn validate_state_backend(backend: &StateBackendConfig) -> Result<()> {
+    match (&backend.s3_uri, &backend.dynamodb_table) {
+        (Some(uri), None) => match artefact::s3_location(uri) {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() && !key.ends_with('/') => Ok(()),
+            _ => Err(anyhow!("Invalid sageturner config: state_backend.s3_uri should be an s3:// URI of an object, not {}", uri)),
+        },
+        (None, Some(table)) if !table.is_empty() => Ok(()),
+        (None, Some(_)) => Err(anyhow!("Invalid sageturner config: state_backend.dynamodb_table can't be an empty string")),
+        _ => Err(anyhow!("Invalid sageturner config: state_backend needs one of s3_uri or dynamodb_table")),
+    }
+}
+
+// Parse the config, applying the overrides for environment (from its environments: section) if one is given,
+// then any key.path=value overrides from deploy --set
+pub fn parse_config(path: PathBuf, environment: Option<&str>, sets: &[String]) -> Result<ModelConfig> {
+    read_config(path, environment, sets).map_err(errors::config)
+}
+
+fn read_config(path: PathBuf, environment: Option<&str>, sets: &[String]) -> Result<ModelConfig> {
+    info!("Parsing model config file");
+    let mut value = user_defaults()?;
+    merge(&mut value, read_with_extends(&path, &mut Vec::new())?);
+
+    interpolate(&mut value)?;
+
+    // Each file's schema_version has done its job once it's been migrated
+    let environments = value.as_mapping_mut().and_then(|m| {
+        m.remove("schema_version");
+        m.remove("environments")
+    });
+    if let Some(env) = environment {
+        let overrides = environments
+            .as_ref()
+            .and_then(|e| e.get(env))
+            .ok_or_else(|| anyhow!("Invalid sageturner config: no environment called {} under environments", env))?;
+        info!("Applying overrides for environment {}", env);
+        merge(&mut value, overrides.clone());
+    }
+
+    for set in sets {
+        set_path(&mut value, set)?;
+    }
+
+    let config_hash = config_hash(&value)?;
+    let resolved = value.clone();
+
+    // Deserializing through serde_path_to_error says where in the config a bad field is
+    let mut mc: ModelConfig = serde_path_to_error::deserialize(value).map_err(|e| match e.path().to_string().as_str() {
+        "." => anyhow!("Invalid sageturner config: {}", e.inner()),
+        path => anyhow!("Invalid sageturner config at {}: {}", path, e.inner()),
+    })?;
+    mc.environment = environment.map(|e| e.to_string());
+    mc.config_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    mc.config_hash = config_hash;
+    mc.resolved = resolved;
+    Ok(mc)
+}
+
+// Hashed as JSON with its keys sorted, so reordering a config doesn't change its hash. serde_json keeps objects in
+// the order they're written (for --output json), so they're sorted here
+fn config_hash(value: &Value) -> Result<String> {
+    let json = serde_json::to_vec(&sort_keys(serde_json::to_value(value)?))?;
+    Ok(format!("{:x}", Sha256::digest(json)))
+}
+
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let sorted: BTreeMap<String, serde_json::Value> = map.into_iter().map(|(k, v)| (k, sort_keys(v))).collect();
+            serde_json::Value::Object(sorted.into_iter().collect())
+        }
+        serde_json::Value::Array(items) => serde_json::Value::Array(items.into_iter().map(sort_keys).collect()),
+        value => value,
+    }
+}
+
+// SageMaker's limits for serverless endpoints, which CreateEndpointConfig would otherwise reject
+const SERVERLESS_MEMORY_SIZES: &[i32] = &[1024, 2048, 3072, 4096, 5120, 6144];
+const SERVERLESS_MAX_CONCURRENCY: i32 = 200;
+
+fn validate_serverless(serverless: &ServerlessCompute) -> Result<()> {
+    if !SERVERLESS_MEMORY_SIZES.contains(&serverless.memory) {
+        return Err(anyhow!(
+            "Invalid sageturner config: serverless memory must be one of 1024, 2048, 3072, 4096, 5120 or 6144 (MB), not {}",
+            serverless.memory
+        ));
+    }
+    if !(1..=SERVERLESS_MAX_CONCURRENCY).contains(&serverless.max_concurrency) {
+        return Err(anyhow!(
+            "Invalid sageturner config: serverless max_concurrency must be between 1 and {}, not {}",
+            SERVERLESS_MAX_CONCURRENCY,
+            serverless.max_concurrency
+        ));
+    }
+    if serverless.provisioned_concurrency < 0 || serverless.provisioned_concurrency > serverless.max_concurrency {
+        return Err(anyhow!(
+            "Invalid sageturner config: serverless provisioned_concurrency must be between 0 and max_concurrency ({}), not {}",
+            serverless.max_concurrency,
+            serverless.provisioned_concurrency
+        ));
+    }
+    Ok(())
+}
+
+// Catch instance_type typos, and instances without the hardware the container needs, before anything is built
+fn validate_instance_type(mc: &ModelConfig, server: &ServerCompute, container_mode: Option<&ContainerMode>) -> Result<()> {
+    let kind = match instances::lookup(&server.instance_type) {
+        Ok(kind) => kind,
+        Err(_) if server.allow_unknown_instance_type => {
+            info!("Not checking instance type {}, allow_unknown_instance_type is set", server.instance_type);
+            return Ok(());
+        }
+        Err(e) => {
+            return Err(anyhow!(
+                "Invalid sageturner config: compute.server.instance_type {}: {}. If it's newer than sageturner, set allow_unknown_instance_type: true",
+                server.instance_type,
+                e
+            ))
+        }
+    };
+
+    let needs_gpu = match container_mode {
+        Some(ContainerMode::Vllm) => Some("vllm"),
+        Some(ContainerMode::Tgi) => Some("tgi"),
+        Some(ContainerMode::Generate) if mc.container.generate_container.as_ref().is_some_and(|g| g.install_cuda) => Some("install_cuda"),
+        _ => None,
+    };
+    if let Some(what) = needs_gpu {
+        if kind != InstanceKind::Gpu {
+            return Err(anyhow!(
+                "Invalid sageturner config: {} needs an NVIDIA GPU, but {} doesn't have one. Use a GPU instance like ml.g5.xlarge, ml.g6.xlarge or ml.p4d.24xlarge",
+                what,
+                server.instance_type
+            ));
+        }
+    }
+    if container_mode == Some(&ContainerMode::Generate) && needs_gpu.is_none() && kind == InstanceKind::Gpu {
+        let accelerated = mc.container.generate_container.as_ref().is_some_and(|g| g.accelerator.is_some());
+        if !accelerated {
+            warn!("{} has a GPU, but install_cuda is false so your model will run on the CPU", server.instance_type);
+        }
+    }
+    Ok(())
+}
+
+// JSON Schema for the config file, for editors and CI to check configs against
+pub fn config_schema() -> Result<String> {
+    let mut schema = schemars::schema_for!(ModelConfig);
+    // environments is taken out of the config before it's deserialized, so ModelConfig doesn't know about it.
+    // Each environment is a partial config merged over the rest
+    if let Some(properties) = schema.get_mut("properties").and_then(|p| p.as_object_mut()) {
+        properties.insert(
+            "environments".to_string(),
+            serde_json::json!({
+                "description": "Per-environment overrides, merged over the rest of the config by deploy --env",
+                "type": "object",
+                "additionalProperties": { "type": "object" }
+            }),
+        );
+        properties.insert(
+            "schema_version".to_string(),
+            serde_json::json!({
+                "description": "The version of the config format this file is written for",
+                "type": "integer",
+                "minimum": 1,
+                "maximum": SCHEMA_VERSION
+            }),
+        );
+        properties.insert(
+            "extends".to_string(),
+            serde_json::json!({
+                "description": "Base config(s) to merge this one over, relative to this file",
+                "type": ["string", "array"],
+                "items": { "type": "string" }
+            }),
+        );
+    }
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
+// Fill in ${VAR} and ${VAR:-default} in every string value from the environment sageturner runs in
+fn interpolate(value: &mut Value) -> Result<()> {
+    match value {
+        Value::String(s) if s.contains("${") => {
+            let whole = s.starts_with("${") && s.ends_with('}') && s.matches("${").count() == 1;
+            let interpolated = interpolate_str(s)?;
+            // initial_instance_count: ${COUNT} should give a number, not the string "2"
+            *value = match serde_yaml::from_str::<Value>(&interpolated) {
+                Ok(v @ (Value::Number(_) | Value::Bool(_))) if whole => v,
+                _ => Value::String(interpolated),
+            };
+        }
+        Value::Sequence(seq) => {
+            for v in seq {
+                interpolate(v)?;
+            }
+        }
+        Value::Mapping(map) => {
+            for (_, v) in map.iter_mut() {
+                interpolate(v)?;
+            }
+        }
+        Value::Tagged(tagged) => interpolate(&mut tagged.value)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+// $${ is a literal ${
+fn interpolate_str(s: &str) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            out.push_str(&rest[..start - 1]);
+            out.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| anyhow!("Invalid sageturner config: unclosed ${{ in {}", s))?;
+        let expr = &rest[start + 2..start + end];
+        let (var, default) = match expr.split_once(":-") {
+            Some((var, default)) => (var, Some(default)),
+            None => (expr, None),
+        };
+        let value = match (std::env::var(var), default) {
+            (Ok(v), _) if !v.is_empty() => v,
+            (_, Some(default)) => default.to_string(),
+            (Ok(v), None) => v,
+            (Err(_), None) => {
+                return Err(anyhow!(
+                    "Invalid sageturner config: environment variable {} isn't set. Set it, or give a default with ${{{}:-default}}",
+                    var,
+                    var
+                ))
+            }
+        };
+        out.push_str(&rest[..start]);
+        out.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// Apply one --set compute.server.instance_type=ml.g5.xlarge. The value is read as YAML, so numbers and
+// true/false keep their types. Maps along the path are created if they're missing, numbers index into lists
+fn set_path(value: &mut Value, set: &str) -> Result<()> {
+    let (path, raw) = set
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Invalid --set {}: expected key.path=value", set))?;
+    if path.is_empty() || path.split('.').any(|k| k.is_empty()) {
+        return Err(anyhow!("Invalid --set {}: empty key in {}", set, path));
+    }
+    let new_value = serde_yaml::from_str::<Value>(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+
+    let mut current = value;
+    for key in path.split('.') {
+        current = match current {
+            Value::Sequence(seq) => {
+                let index: usize = key
+                    .parse()
+                    .map_err(|_| anyhow!("Invalid --set {}: {} is a list, index it with a number", set, key))?;
+                seq.get_mut(index)
+                    .ok_or_else(|| anyhow!("Invalid --set {}: index {} is past the end of the list", set, index))?
+            }
+            other => {
+                if !other.is_mapping() {
+                    *other = Value::Mapping(Default::default());
+                }
+                let Value::Mapping(map) = other else { unreachable!() };
+                map.entry(Value::String(key.to_string())).or_insert(Value::Null)
+            }
+        };
+    }
+    *current = new_value;
+    Ok(())
+}
+
+// Migrations from each config format version to the next: MIGRATIONS[0] takes a schema_version 1 config to 2, and so on.
+// Add one whenever a field is renamed or moved, so configs written for older sageturners keep working
+type Migration = fn(&mut Value) -> Result<()>;
+const MIGRATIONS: &[Migration] = &[];
+pub const SCHEMA_VERSION: u64 = MIGRATIONS.len() as u64 + 1;
+
+// The schema_version a config was written for. Configs from before schema_version existed are version 1
+fn schema_version(value: &Value, path: &Path) -> Result<u64> {
+    match value.get("schema_version") {
+        None => Ok(1),
+        Some(v) => v
+            .as_u64()
+            .filter(|v| *v >= 1)
+            .ok_or_else(|| anyhow!("Invalid sageturner config: schema_version in {} should be a whole number from 1", path.display())),
+    }
+}
+
+// Bring a config up to the current schema_version. Returns the version it was at
+fn migrate(value: &mut Value, path: &Path) -> Result<u64> {
+    let from = schema_version(value, path)?;
+    if from > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "{} is schema_version {}, but this sageturner only understands up to {}. Upgrade sageturner",
+            path.display(),
+            from,
+            SCHEMA_VERSION
+        ));
+    }
+    for migration in &MIGRATIONS[from as usize - 1..] {
+        migration(value)?;
+    }
+    if from < SCHEMA_VERSION {
+        info!(
+            "{} is schema_version {}, migrated to {} for this run. Run sageturner config migrate to update the file",
+            path.display(),
+            from,
+            SCHEMA_VERSION
+        );
+    }
+    Ok(from)
+}
+
+// What config migrate did to a config file
+#[derive(Debug, Serialize)]
+pub struct Migrated {
+    pub path: PathBuf,
+    pub from: u64,
+    pub to: u64,
+    // A dry run, or a file that's already up to date, isn't written
+    pub written: bool,
+}
+
+// Upgrade a config file to the current schema_version in place, printing the diff to out. With dry_run, only print it
+pub fn migrate_file(path: &Path, dry_run: bool, out: &mut dyn Write) -> Result<Migrated> {
+    let original = std::fs::read_to_string(path)?;
+    let mut value = read_config_value(path)?;
+    let stamped = value.get("schema_version").is_some();
+    let from = migrate(&mut value, path)?;
+    if let Some(map) = value.as_mapping_mut() {
+        map.insert(Value::String("schema_version".to_string()), Value::Number(SCHEMA_VERSION.into()));
+    }
+    let mut migration = Migrated { path: path.to_path_buf(), from, to: SCHEMA_VERSION, written: false };
+    if from == SCHEMA_VERSION && stamped {
+        writeln!(out, "{} is already at schema_version {}", path.display(), SCHEMA_VERSION)?;
+        return Ok(migration);
+    }
+
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    let migrated = match extension.as_str() {
+        "json" => config_string(&value, path)?,
+        // Nothing to migrate but the version, so just add it and keep the file as written, comments and all
+        "toml" if from == SCHEMA_VERSION => format!("schema_version = {}\n{}", SCHEMA_VERSION, original),
+        _ if from == SCHEMA_VERSION => format!("schema_version: {}\n{}", SCHEMA_VERSION, original),
+        _ => config_string(&value, path)?,
+    };
+    if from < SCHEMA_VERSION && extension != "json" {
+        warn!("Migrating rewrites the file, so comments in it are lost");
+    }
+
+    let name = path.display().to_string();
+    let diff = similar::TextDiff::from_lines(&original, &migrated);
+    write!(out, "{}", diff.unified_diff().header(&name, &name))?;
+    if dry_run {
+        writeln!(out, "Dry run, {} not changed", name)?;
+    } else {
+        std::fs::write(path, migrated)?;
+        migration.written = true;
+        if from == SCHEMA_VERSION {
+            writeln!(out, "Set schema_version {} in {}", SCHEMA_VERSION, name)?;
+        } else {
+            writeln!(out, "Migrated {} from schema_version {} to {}", name, from, SCHEMA_VERSION)?;
+        }
+    }
+    Ok(migration)
+}
+
+// Set the config's compute.server instance type and count, or the environment's, e.g. to what sageturner recommend
+// suggests. Like migrating, this rewrites the file
+pub fn set_server_compute(
+    path: &Path,
+    environment: Option<&str>,
+    instance_type: &str,
+    instance_count: i32,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let original = std::fs::read_to_string(path)?;
+    let mut value = read_config_value(path)?;
+    let mut section = &mut value;
+    let keys = match environment {
+        Some(env) => vec!["environments", env, "compute", "server"],
+        None => vec!["compute", "server"],
+    };
+    for key in keys {
+        let map = section.as_mapping_mut().ok_or_else(|| anyhow!("Can't set {} in {}, it isn't a map", key, path.display()))?;
+        section = map.entry(Value::String(key.to_string())).or_insert_with(|| Value::Mapping(Default::default()));
+        if section.is_null() {
+            *section = Value::Mapping(Default::default());
+        }
+    }
+    let server = section.as_mapping_mut().ok_or_else(|| anyhow!("compute.server in {} isn't a map", path.display()))?;
+    server.insert(Value::String("instance_type".to_string()), Value::String(instance_type.to_string()));
+    server.insert(Value::String("initial_instance_count".to_string()), Value::Number(instance_count.into()));
+
+    let updated = config_string(&value, path)?;
+    let name = path.display().to_string();
+    let diff = similar::TextDiff::from_lines(&original, &updated);
+    write!(out, "{}", diff.unified_diff().header(&name, &name))?;
+    if path.extension().and_then(|e| e.to_str()).is_some_and(|e| !e.eq_ignore_ascii_case("json")) {
+        warn!("This rewrites the file, so comments in it are lost");
+    }
+    std::fs::write(path, updated)?;
+    Ok(())
+}
+
+// A config value written out in the format of the file at path
+fn config_string(value: &Value, path: &Path) -> Result<String> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    Ok(match extension.as_str() {
+        "json" => serde_json::to_string_pretty(value)? + "\n",
+        "toml" => toml::to_string(value)?,
+        _ => serde_yaml::to_string(value)?,
+    })
+}
+
+// Read a config file into a YAML value. JSON and TOML are parsed into the same YAML value,
+// so everything after this works the same for all three
+fn read_config_value(path: &Path) -> Result<Value> {
     let mut file = File::open(path)?;
     let mut contents = String::new();
     file.read_to_string(&mut contents)?;
 
-    match serde_yaml::from_str::<ModelConfig>(&contents) {
-        Ok(mc) => Ok(mc),
-        Err(err) => match err.location() {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+    match extension.as_str() {
+        "json" => serde_json::from_str::<Value>(&contents).map_err(|e| anyhow!("JSON parsing error: {}", e)),
+        "toml" => toml::from_str::<Value>(&contents).map_err(|e| anyhow!("TOML parsing error: {}", e)),
+        _ => serde_yaml::from_str::<Value>(&contents).map_err(|err| match err.location() {
             Some(l) => {
                 let location = format!("line {} column {}", l.line(), l.column());
-                Err(anyhow!("YAML parsing error at {}: {}", location, err))
+                anyhow!("YAML parsing error at {}: {}", location, err)
             }
-            None => Err(err.into()),
-        },
+            None => err.into(),
+        }),
+    }
+}
+
+// ~/.config/sageturner/config.toml (or under $XDG_CONFIG_HOME), for your own defaults
+pub fn user_defaults_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(config_home.join("sageturner").join("config.toml"))
+}
+
+// The user's defaults file, which holds any config fields (region, profile, overrides, tags...) that every
+// project config is merged over. An empty map if there isn't one
+pub fn user_defaults() -> Result<Value> {
+    match user_defaults_path().filter(|p| p.is_file()) {
+        Some(path) => {
+            info!("Using your defaults from {}", path.display());
+            let mut value = read_config_value(&path)
+                .map_err(|e| anyhow!("Couldn't read your defaults file {}: {}", path.display(), e))?;
+            migrate(&mut value, &path)?;
+            Ok(value)
+        }
+        None => Ok(Value::Mapping(Default::default())),
+    }
+}
+
+// Read a config and the base configs it extends: extends: base.yaml, or a list of them, relative to the file.
+// Bases are merged in order, then the file itself over them, the same deep merge as environments
+fn read_with_extends(path: &Path, chain: &mut Vec<PathBuf>) -> Result<Value> {
+    let canonical = std::fs::canonicalize(path).map_err(|e| anyhow!("Couldn't read config {}: {}", path.display(), e))?;
+    if chain.contains(&canonical) {
+        return Err(anyhow!("Invalid sageturner config: {} extends itself", path.display()));
+    }
+    chain.push(canonical);
+
+    let mut value = read_config_value(path)?;
+    migrate(&mut value, path)?;
+    let bases: Vec<String> = match value.as_mapping_mut().and_then(|m| m.remove("extends")) {
+        None => Vec::new(),
+        Some(Value::String(base)) => vec![base],
+        Some(v) => serde_yaml::from_value(v).map_err(|_| {
+            anyhow!("Invalid sageturner config: extends in {} should be a path or a list of paths", path.display())
+        })?,
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut merged = Value::Mapping(Default::default());
+    for base in bases {
+        merge(&mut merged, read_with_extends(&dir.join(base), chain)?);
+    }
+    merge(&mut merged, value);
+
+    chain.pop();
+    Ok(merged)
+}
+
+// A workspace deploys a suite of related models in one go: a models: list of their config files,
+// each with how to deploy it
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Workspace {
+    pub models: Vec<WorkspaceModel>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkspaceModel {
+    // Path to the model's config file, relative to the workspace file
+    pub config: String,
+    // serverless or server. Defaults to deploy --endpoint-type
+    pub endpoint_type: Option<String>,
+    // generate, provide, vllm, tgi or triton. Defaults to deploy --container-mode
+    pub container_mode: Option<String>,
+}
+
+// A config file with a top level models: list is a workspace rather than a model config
+pub fn parse_workspace(path: &Path) -> Result<Option<Workspace>> {
+    let value = read_config_value(path)?;
+    if value.get("models").is_none() {
+        return Ok(None);
+    }
+    info!("Parsing workspace file");
+    let workspace: Workspace = serde_path_to_error::deserialize(value)
+        .map_err(|e| anyhow!("Invalid sageturner workspace at {}: {}", e.path(), e.inner()))?;
+    if workspace.models.is_empty() {
+        return Err(anyhow!("Invalid sageturner workspace: models is empty"));
+    }
+    Ok(Some(workspace))
+}
+
+// Deep merge an environment's overrides into the base config: maps merge key by key, anything else is replaced
+fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Mapping(base), Value::Mapping(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+// In generate mode without a code_dir or preset, pick the preset by inspecting the artefact.
+// With a code_dir, just suggest one
+pub fn detect_preset(mc: &mut ModelConfig, config_dir: &Path) -> Result<()> {
+    let (Some(generate), Some(a)) = (mc.container.generate_container.as_mut(), mc.artefact.as_ref()) else {
+        return Ok(());
+    };
+    let artefact_path = absolute(config_dir.join(a))?;
+    if generate.preset.is_some() || artefact::hf_model_id(a).is_some() || !artefact_path.exists() {
+        return Ok(());
+    }
+
+    let Some(detected) = artefact::detect_preset(&artefact_path)? else {
+        warn!("Couldn't detect the framework from the artefact");
+        return Ok(());
+    };
+    if generate.code_dir.is_some() {
+        info!("Your artefact looks like a {} model: preset: {} could replace your sageturner.py", detected.preset, detected.preset);
+        return Ok(());
+    }
+
+    info!("Detected a {} model, using the {} preset", detected.preset, detected.preset);
+    generate.preset = Some(detected.preset);
+    if generate.preset_options.task.is_none() {
+        if let Some(task) = detected.task {
+            info!("Detected pipeline task {}", task);
+            generate.preset_options.task = Some(task);
+        }
+    }
+    Ok(())
+}
+
+// Placeholders always have a value, so only literal parts of the template can leave an empty path segment
+fn check_artefact_key(template: &str, versioned: bool) -> Result<()> {
+    if template.contains("{version}") && !versioned {
+        return Err(anyhow!("Invalid sageturner config: artefact_key uses {{version}}, but your config has no version"));
+    }
+    let placeholders = artefact::KEY_PLACEHOLDERS.iter().map(|p| (*p, "x".to_string())).collect();
+    let key = artefact::render_key(template, &placeholders).map_err(|e| anyhow!("Invalid sageturner config: {}", e))?;
+    if template.trim().is_empty() || key.contains("//") {
+        return Err(anyhow!("Invalid sageturner config: artefact_key {} would give an empty key or path segment", template));
     }
+    Ok(())
 }
 
 pub fn validate_config(
     mc: &ModelConfig,
     endpoint_type: &EndpointType,
-    container_mode: &ContainerMode,
+    container_mode: Option<&ContainerMode>,
     config_dir: &Path
 ) -> Result<()> {
-    println!("Validating config file");
+    check_config(mc, endpoint_type, container_mode, config_dir).map_err(errors::config)
+}
+
+fn check_config(
+    mc: &ModelConfig,
+    endpoint_type: &EndpointType,
+    container_mode: Option<&ContainerMode>,
+    config_dir: &Path
+) -> Result<()> {
+    info!("Validating config file");
     if mc.name.is_empty() {
         return Err(anyhow!(
             "Invalid sageturner config: model name can't be an empty string"
         ));
     }
 
+    if let Some(EndpointUrl::PerService(urls)) = &mc.endpoint_url {
+        for service in urls.keys() {
+            if !ENDPOINT_URL_SERVICES.contains(&service.as_str()) {
+                return Err(anyhow!(
+                    "Invalid sageturner config: endpoint_url has an unknown service {}, use one of {}",
+                    service,
+                    ENDPOINT_URL_SERVICES.join(", ")
+                ));
+            }
+        }
+    }
+    if mc.endpoint_url.is_some() && mc.upload.transfer_acceleration {
+        return Err(anyhow!("Invalid sageturner config: upload.transfer_acceleration only works with AWS's own S3 endpoints, not endpoint_url"));
+    }
+    mc.timeouts.validate()?;
+    if let Some(ttl) = &mc.ttl {
+        parse_ttl(ttl).map_err(|e| anyhow!("Invalid sageturner config: ttl: {}", e))?;
+    }
+    if let Some(days) = mc.log_retention_days.filter(|d| !aws::LOG_RETENTION_DAYS.contains(d)) {
+        let allowed: Vec<String> = aws::LOG_RETENTION_DAYS.iter().map(|d| d.to_string()).collect();
+        return Err(anyhow!(
+            "Invalid sageturner config: log_retention_days {} isn't a period CloudWatch Logs allows, use one of {}",
+            days,
+            allowed.join(", ")
+        ));
+    }
+    if mc.max_monthly_cost.is_some_and(|c| c <= 0.0) {
+        return Err(anyhow!("Invalid sageturner config: max_monthly_cost has to be more than 0"));
+    }
+    if let Some(monitoring) = &mc.monitoring {
+        validate_monitoring(monitoring, endpoint_type)?;
+    }
+    if let Some(group) = mc.registry.as_ref().and_then(|r| r.model_package_group.as_ref()) {
+        if group.is_empty() || group.len() > 63 || !group.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(anyhow!("Invalid sageturner config: registry.model_package_group has to be 1-63 letters, numbers and -, not {}", group));
+        }
+    }
+    if let Some(metrics) = mc.registry.as_ref().and_then(|r| r.metrics.as_deref()) {
+        let found = match metrics.starts_with("s3://") {
+            true => artefact::s3_location(metrics).is_some_and(|(bucket, key)| !bucket.is_empty() && !key.is_empty()),
+            false => config_dir.join(metrics).is_file(),
+        };
+        if !found {
+            return Err(anyhow!("Invalid sageturner config: registry.metrics should be a JSON file or an s3:// URI, not {}", metrics));
+        }
+    }
+    if let Some(backend) = &mc.state_backend {
+        validate_state_backend(backend)?;
+    }
+    if let Some(retention) = &mc.retention {
+        validate_retention(retention)?;
+    }
+    if let Some(recommender) = &mc.recommender {
+        validate_recommender(recommender, config_dir)?;
+    }
+    if let Some(canary) = &mc.canary {
+        if canary.payloads.is_empty() {
+            return Err(anyhow!("Invalid sageturner config: canary needs at least one payload to send"));
+        }
+        for payload in &canary.payloads {
+            if !config_dir.join(&payload.file).is_file() {
+                return Err(anyhow!("Invalid sageturner config: canary payload {} isn't a file", payload.file));
+            }
+        }
+    }
+    for test in &mc.smoke_tests {
+        if !config_dir.join(&test.file).is_file() {
+            return Err(anyhow!("Invalid sageturner config: smoke test {} isn't a file", test.file));
+        }
+        if !(100..600).contains(&test.expect_status) {
+            return Err(anyhow!("Invalid sageturner config: smoke test {} expects status {}, which isn't an HTTP status", test.file, test.expect_status));
+        }
+        for assertion in &test.assertions {
+            serde_json_path::JsonPath::parse(&assertion.path)
+                .map_err(|e| anyhow!("Invalid sageturner config: smoke test {} has an invalid JSONPath {}: {}", test.file, assertion.path, e))?;
+        }
+    }
+    if mc.rollback_on_smoke_test_failure && mc.smoke_tests.is_empty() {
+        return Err(anyhow!("Invalid sageturner config: rollback_on_smoke_test_failure is set, but there are no smoke_tests"));
+    }
+    if mc.region.is_some() && !mc.regions.is_empty() {
+        return Err(anyhow!("Invalid sageturner config: set region or regions, not both"));
+    }
+    for (i, region) in mc.regions.iter().enumerate() {
+        if region.is_empty() || mc.regions[..i].contains(region) {
+            return Err(anyhow!("Invalid sageturner config: regions has an empty or repeated region: {}", mc.regions.join(", ")));
+        }
+    }
+
+    if let Some(role) = mc.overrides.as_ref().and_then(|o| o.role_arn.as_deref()) {
+        if role.is_empty() {
+            return Err(anyhow!("Invalid sageturner config: overrides.role_arn can't be an empty string"));
+        }
+        if role.starts_with("arn:") {
+            aws::check_role_arn(role).map_err(|e| anyhow!("Invalid sageturner config: overrides.role_arn: {}", e))?;
+        }
+    }
+
     if mc.artefact.as_ref().is_some_and(|a| a.is_empty()) {
         return Err(anyhow!(
             "Invalid sageturner config: artefact can't be an empty string"
         ));
     }
 
-    // Validate minimal config present for each deploy mode
+    if let Some(a) = mc.artefact.as_deref().filter(|a| a.starts_with("s3://")) {
+        match artefact::s3_location(a) {
+            Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => {
+                if mc.uncompressed_artefact && !key.ends_with('/') {
+                    return Err(anyhow!("Invalid sageturner config: uncompressed S3 artefacts need to be a prefix ending in /: {}", a));
+                }
+                if !mc.uncompressed_artefact && !key.ends_with(".tar.gz") {
+                    return Err(anyhow!("Invalid sageturner config: S3 artefacts need to be a .tar.gz: {}", a));
+                }
+            }
+            _ => return Err(anyhow!("Invalid sageturner config: your S3 artefact should look like s3://bucket/path/model.tar.gz, not {}", a)),
+        }
+    }
+
+    if mc.uncompressed_artefact {
+        let local_file = mc.artefact.as_deref().filter(|a| !a.starts_with("s3://") && !config_dir.join(a).is_dir());
+        if let Some(a) = local_file {
+            return Err(anyhow!("Invalid sageturner config: uncompressed_artefact needs your artefact to be a directory or an s3:// prefix, not {}", a));
+        }
+    }
+
+    if let Some(model_id) = mc.artefact.as_deref().and_then(artefact::hf_model_id) {
+        if model_id.is_empty() {
+            return Err(anyhow!("Invalid sageturner config: your hf:// artefact is missing the model id, e.g. hf://org/model"));
+        }
+        let container_model_id = mc.container.vllm.as_ref().and_then(|v| v.model_id.as_ref())
+            .or(mc.container.tgi.as_ref().and_then(|t| t.model_id.as_ref()))
+            .or(mc.container.generate_container.as_ref().and_then(|g| g.preset_options.model_id.as_ref()));
+        if container_model_id.is_some() {
+            return Err(anyhow!("Invalid sageturner config: your artefact is a Hugging Face Hub model, and you've also set a model_id in your container config. Use one or the other"));
+        }
+    }
+
+    // The environment ends up in SageMaker resource names
+    if let Some(env) = &mc.environment {
+        if env.is_empty() || !env.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(anyhow!("Invalid sageturner config: environment names can only have letters, numbers and -, not {}", env));
+        }
+    }
+
+    if let Some(version) = &mc.version {
+        semver::Version::parse(version).map_err(|e| {
+            anyhow!("Invalid sageturner config: version {} isn't a semantic version like 1.4.0: {}", version, e)
+        })?;
+    }
+
+    if let Some(template) = &mc.artefact_key {
+        check_artefact_key(template, mc.version.is_some())?;
+    }
+
+    artefact::excludes(&mc.exclude).map_err(|e| anyhow!("Invalid sageturner config: {}", e))?;
+    if let Some(generate) = &mc.container.generate_container {
+        artefact::excludes(&generate.exclude)
+            .map_err(|e| anyhow!("Invalid sageturner config: container.generate_container.{}", e))?;
+    }
+
+    if mc.container.environment.keys().any(|k| k.is_empty() || k.contains('=')) {
+        return Err(anyhow!("Invalid sageturner config: container.environment names can't be empty or contain ="));
+    }
+
+    if mc.upload.part_size_mb < 5 {
+        return Err(anyhow!("Invalid sageturner config: upload.part_size_mb must be at least 5, S3's minimum part size"));
+    }
+    if mc.upload.concurrency == 0 || mc.upload.part_attempts == 0 {
+        return Err(anyhow!("Invalid sageturner config: upload.concurrency and upload.part_attempts must be at least 1"));
+    }
+
+    // Validate minimal config present for each deploy mode. Model packages are already built, so have no container
+    // config to check
     match container_mode {
-        ContainerMode::Provide => {
+        None => {}
+        Some(ContainerMode::Provide) => {
             if mc.container.provide_container.is_none() {
                 return Err(anyhow!("Invalid sageturner config: you're trying to deploy in provided container mode, but there's no container.provide_container in your YAML"));
             }
@@ -143,50 +1610,140 @@ pub fn validate_config(
                 return Err(anyhow!("Invalid sageturner config: you're trying to deploy in provided container mode, but your docker_dir is an empty string"));
             }
         }
-        ContainerMode::Generate => {
+        Some(ContainerMode::Generate) => {
             if mc.container.generate_container.is_none() {
                 return Err(anyhow!("Invalid sageturner config: you're trying to deploy in generate container mode, but there's no container.generate_container field in your YAML"));
             }
-            if mc
-                .container
-                .generate_container
-                .as_ref()
-                .is_some_and(|s| s.code_dir.is_empty())
-            {
-                return Err(anyhow!("Invalid sageturner config: you're trying to deploy in generate container mode, but your code field is an empty string. Needs to be path to code with load() and predict()"));
-            }
             if let Some(c) = mc.container.generate_container.as_ref() {
-                // check that codedir is a directory, and contains a sageturner.py file at minimum
-                let config_path = config_dir.join(&c.code_dir);
-                let abs_path = absolute(config_path)?;
-                let abs_path = abs_path.as_path();
-                if !abs_path.is_dir() {
-                    return Err(anyhow!("Invalid sageturner config: your code_dir was not a valid directory: {}", abs_path.display()));
+                match (&c.code_dir, &c.preset) {
+                    (None, None) => {
+                        return Err(anyhow!("Invalid sageturner config: you're trying to deploy in generate container mode, but there's no code_dir or preset. Provide a code_dir with load() and predict(), or pick a preset"));
+                    }
+                    (Some(d), _) if d.is_empty() => {
+                        return Err(anyhow!("Invalid sageturner config: you're trying to deploy in generate container mode, but your code_dir is an empty string. Needs to be path to code with load() and predict()"));
+                    }
+                    (Some(d), preset) => {
+                        // check that codedir is a directory, and contains a sageturner.py file unless a preset generates it
+                        let config_path = config_dir.join(d);
+                        let abs_path = absolute(config_path)?;
+                        let abs_path = abs_path.as_path();
+                        if !abs_path.is_dir() {
+                            return Err(anyhow!("Invalid sageturner config: your code_dir was not a valid directory: {}", abs_path.display()));
+                        }
+                        let has_sageturner_py = abs_path.join("sageturner.py").exists();
+                        match preset {
+                            None if !has_sageturner_py => {
+                                return Err(anyhow!("Invalid sageturner config: your code_dir did not contain a sageturner.py file. Please add one with a load() and predict() method. Code dir: {}", abs_path.display()));
+                            }
+                            Some(p) if has_sageturner_py => {
+                                return Err(anyhow!("Invalid sageturner config: you're using the {} preset, which generates sageturner.py for you, but your code_dir already has one. Remove it or drop the preset. Code dir: {}", p, abs_path.display()));
+                            }
+                            _ => {}
+                        }
+                    }
+                    (None, Some(_)) => {}
                 }
-                if !abs_path.join("sageturner.py").exists() {
-                    return Err(anyhow!("Invalid sageturner config: your code_dir did not contain a sageturner.py file. Please add one with a load() and predict() method. Code dir: {}", abs_path.display()));
+                check_template(c.dockerfile_template.as_deref(), "generate_container.dockerfile_template", config_dir)?;
+                check_template(c.serve_template.as_deref(), "generate_container.serve_template", config_dir)?;
+                if c.accelerator == Some(Accelerator::Neuron) {
+                    if c.install_cuda {
+                        return Err(anyhow!("Invalid sageturner config: accelerator neuron and install_cuda can't both be set, Inferentia/Trainium instances don't have NVIDIA GPUs"));
+                    }
+                    let is_neuron_instance = mc.compute.server.as_ref().is_some_and(|s| {
+                        ["ml.inf2.", "ml.trn1.", "ml.trn1n."].iter().any(|f| s.instance_type.starts_with(f))
+                    });
+                    if *endpoint_type == EndpointType::Serverless || !is_neuron_instance {
+                        return Err(anyhow!("Invalid sageturner config: accelerator neuron needs a Server endpoint with an ml.inf2 or ml.trn1 instance type"));
+                    }
+                }
+                if let Some(p) = c.preset {
+                    if !p.supports(c.hardware()) {
+                        return Err(anyhow!("Invalid sageturner config: the {} preset doesn't support accelerator neuron, use the pytorch preset with a torch_neuronx compiled model or write your own sageturner.py", p));
+                    }
+                    p.validate_options(&c.preset_options)?;
+                    if p.needs_artefact(&c.preset_options) && mc.artefact.is_none() {
+                        return Err(anyhow!("Invalid sageturner config: the {} preset loads your model from the artefact, but there's no artefact in your YAML", p));
+                    }
+                }
+            }
+        }
+        Some(ContainerMode::Vllm) => {
+            let Some(v) = mc.container.vllm.as_ref() else {
+                return Err(anyhow!("Invalid sageturner config: you're trying to deploy in vllm container mode, but there's no container.vllm field in your YAML"));
+            };
+            if v.model_id.as_ref().is_some_and(|m| m.is_empty()) {
+                return Err(anyhow!("Invalid sageturner config: your vllm model_id is an empty string"));
+            }
+            if v.model_id.is_none() && mc.artefact.is_none() {
+                return Err(anyhow!("Invalid sageturner config: vllm needs either container.vllm.model_id or an artefact to serve"));
+            }
+            if v.tensor_parallel_size == 0 {
+                return Err(anyhow!("Invalid sageturner config: vllm tensor_parallel_size must be at least 1"));
+            }
+            check_template(v.dockerfile_template.as_deref(), "vllm.dockerfile_template", config_dir)?;
+            if *endpoint_type == EndpointType::Serverless {
+                return Err(anyhow!("Invalid sageturner config: vllm needs a GPU, and Serverless endpoints don't support GPU. Deploy to a Server endpoint."));
+            }
+        }
+        Some(ContainerMode::Tgi) => {
+            let Some(t) = mc.container.tgi.as_ref() else {
+                return Err(anyhow!("Invalid sageturner config: you're trying to deploy in tgi container mode, but there's no container.tgi field in your YAML"));
+            };
+            if t.model_id.as_ref().is_some_and(|m| m.is_empty()) {
+                return Err(anyhow!("Invalid sageturner config: your tgi model_id is an empty string"));
+            }
+            if t.model_id.is_none() && mc.artefact.is_none() {
+                return Err(anyhow!("Invalid sageturner config: tgi needs either container.tgi.model_id or an artefact to serve"));
+            }
+            if t.num_gpus == 0 {
+                return Err(anyhow!("Invalid sageturner config: tgi num_gpus must be at least 1"));
+            }
+            if let (Some(input), Some(total)) = (t.max_input_tokens, t.max_total_tokens) {
+                if input >= total {
+                    return Err(anyhow!("Invalid sageturner config: tgi max_input_tokens ({}) must be less than max_total_tokens ({})", input, total));
                 }
             }
+            if *endpoint_type == EndpointType::Serverless {
+                return Err(anyhow!("Invalid sageturner config: tgi needs a GPU, and Serverless endpoints don't support GPU. Deploy to a Server endpoint."));
+            }
+        }
+        Some(ContainerMode::Triton) => {
+            let Some(t) = mc.container.triton.as_ref() else {
+                return Err(anyhow!("Invalid sageturner config: you're trying to deploy in triton container mode, but there's no container.triton field in your YAML"));
+            };
+            if mc.artefact.is_some() {
+                return Err(anyhow!("Invalid sageturner config: in triton container mode your model_repository becomes the artefact, remove the artefact field"));
+            }
+            let repository = absolute(config_dir.join(&t.model_repository))?;
+            if t.model_repository.is_empty() || !repository.is_dir() {
+                return Err(anyhow!("Invalid sageturner config: your triton model_repository was not a valid directory: {}", repository.display()));
+            }
+            artefact::triton_model_name(&repository, t.model_name.as_deref())?;
+            if *endpoint_type == EndpointType::Serverless {
+                return Err(anyhow!("Invalid sageturner config: the Triton image is over the 10gb Serverless image limit. Deploy to a Server endpoint."));
+            }
         }
     }
 
     // Validate minimal config present for each endpoint type
     match endpoint_type {
         EndpointType::Serverless => {
-            if mc.compute.serverless.is_none() {
+            let Some(serverless) = mc.compute.serverless.as_ref() else {
                 return Err(anyhow!("Invalid sageturner config: you're trying to deploy to a serverless endpoint, without compute->serverless field"));
-            }
+            };
+            validate_serverless(serverless)?;
         }
         EndpointType::Server => {
-            if mc.compute.server.is_none() {
+            let Some(server) = mc.compute.server.as_ref() else {
                 return Err(anyhow!("Invalid sageturner config: you're trying to deploy to a server endpoint, without compute->server field"));
-            }
+            };
+            validate_instance_type(mc, server, container_mode)?;
         }
     }
 
     // Special case: GPUs not supported on serverless
     if *endpoint_type == EndpointType::Serverless
-        && *container_mode == ContainerMode::Generate
+        && container_mode == Some(&ContainerMode::Generate)
         && mc
             .container
             .generate_container
@@ -199,3 +1756,36 @@ pub fn validate_config(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{check_artefact_key, config_hash, parse_ttl};
+
+    #[test]
+    fn artefact_key_with_a_literal_prefix_is_valid() {
+        check_artefact_key("models/{name}/{git_sha}/{file}", false).unwrap();
+        check_artefact_key("{name}/{version}/{file}", true).unwrap();
+    }
+
+    #[test]
+    fn artefact_key_with_an_empty_segment_is_invalid() {
+        assert!(check_artefact_key("models//{file}", false).is_err());
+        assert!(check_artefact_key("{name}/{version}/{file}", false).is_err());
+    }
+
+    #[test]
+    fn ttl_too_long_for_a_date_is_invalid() {
+        assert!(parse_ttl("99999999999999w").is_err());
+        assert!(parse_ttl("99999999w").is_err());
+        assert_eq!(parse_ttl("2d").unwrap(), chrono::TimeDelta::days(2));
+    }
+
+    #[test]
+    fn config_hash_ignores_key_order() {
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("name: resnet-50\ncompute:\n  instance_type: ml.m5.large\n  instance_count: 2\n").unwrap();
+        let reordered: serde_yaml::Value =
+            serde_yaml::from_str("compute:\n  instance_count: 2\n  instance_type: ml.m5.large\nname: resnet-50\n").unwrap();
+        assert_eq!(config_hash(&config).unwrap(), config_hash(&reordered).unwrap());
+    }
+}
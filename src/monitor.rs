@@ -0,0 +1,490 @@
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use aws_sdk_sagemaker::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_sagemaker::types::{
+    AppSpecification, CaptureMode, CaptureOption, DataCaptureConfig, DataQualityAppSpecification,
+    DataQualityBaselineConfig, DataQualityJobInput, EndpointInput, ModelQualityAppSpecification,
+    ModelQualityBaselineConfig, ModelQualityJobInput, MonitoringClusterConfig, MonitoringConstraintsResource,
+    MonitoringGroundTruthS3Input, MonitoringOutput, MonitoringOutputConfig, MonitoringProblemType,
+    MonitoringResources, MonitoringS3Output, MonitoringScheduleConfig, MonitoringStatisticsResource,
+    MonitoringStoppingCondition, MonitoringType, ProcessingClusterConfig, ProcessingInput, ProcessingJobStatus,
+    ProcessingOutput, ProcessingOutputConfig, ProcessingResources, ProcessingS3DataType, ProcessingS3Input,
+    ProcessingS3InputMode, ProcessingS3Output, ProcessingS3UploadMode, ProcessingStoppingCondition,
+    ScheduleConfig, Tag,
+};
+use tracing::info;
+
+use crate::errors;
+use crate::model_config::{ModelQualityConfig, MonitoringConfig};
+
+// Where the analyzer container reads its input and writes what it finds
+const INPUT_PATH: &str = "/opt/ml/processing/input/baseline_dataset_input";
+const ENDPOINT_INPUT_PATH: &str = "/opt/ml/processing/input/endpoint";
+const OUTPUT_PATH: &str = "/opt/ml/processing/output";
+
+// Baselining a dataset takes a few minutes, on a processing instance that has to start first
+const BASELINE_POLL_INTERVAL: Duration = Duration::from_secs(30);
+const BASELINE_WAIT_LIMIT: Duration = Duration::from_secs(60 * 60);
+const JOB_MAX_RUNTIME_SECONDS: i32 = 60 * 60;
+const JOB_VOLUME_SIZE_GB: i32 = 20;
+
+// The accounts AWS publishes the Model Monitor analyzer image from, by region
+const ANALYZER_ACCOUNTS: &[(&str, &str)] = &[
+    ("us-east-1", "156813124566"),
+    ("us-east-2", "777275614652"),
+    ("us-west-1", "890145073186"),
+    ("us-west-2", "159807026194"),
+    ("ca-central-1", "536280801234"),
+    ("sa-east-1", "539772159869"),
+    ("eu-west-1", "468650794304"),
+    ("eu-west-2", "749857270468"),
+    ("eu-central-1", "048819808253"),
+    ("eu-north-1", "895015795356"),
+    ("ap-northeast-1", "574779866223"),
+    ("ap-northeast-2", "709848358524"),
+    ("ap-southeast-1", "245545462676"),
+    ("ap-southeast-2", "563025443158"),
+    ("ap-south-1", "126357580389"),
+];
+
+// The ECR repository the analyzer image is in, which the execution role needs to be able to pull from
+pub const ANALYZER_REPOSITORY: &str = "sagemaker-model-monitor-analyzer";
+
+// Everything monitoring writes goes under this prefix in the artefact bucket, unless monitoring.s3_uri says otherwise
+pub const MONITORING_PREFIX: &str = "monitoring";
+
+pub fn analyzer_image_uri(monitoring: &MonitoringConfig, region: &str) -> Result<String> {
+    if let Some(image) = &monitoring.image {
+        return Ok(image.clone());
+    }
+    let (_, account) = ANALYZER_ACCOUNTS
+        .iter()
+        .find(|(r, _)| *r == region)
+        .ok_or_else(|| anyhow!("Sageturner doesn't know the Model Monitor image for {}, set monitoring.image", region))?;
+    Ok(format!("{}.dkr.ecr.{}.amazonaws.com/{}:latest", account, region, ANALYZER_REPOSITORY))
+}
+
+// The s3:// prefix captured data, baselines and reports go under, without a trailing /
+pub fn monitoring_s3_uri(monitoring: &MonitoringConfig, bucket_name: &str, endpoint_name: &str) -> String {
+    match &monitoring.s3_uri {
+        Some(uri) => uri.trim_end_matches('/').to_string(),
+        None => format!("s3://{}/{}/{}", bucket_name, MONITORING_PREFIX, endpoint_name),
+    }
+}
+
+// Capture requests and responses to <s3 uri>/capture
+pub fn data_capture_config(monitoring: &MonitoringConfig, s3_uri: &str) -> DataCaptureConfig {
+    DataCaptureConfig::builder()
+        .enable_capture(true)
+        .initial_sampling_percentage(monitoring.capture_percentage)
+        .destination_s3_uri(format!("{}/capture", s3_uri))
+        .capture_options(CaptureOption::builder().capture_mode(CaptureMode::Input).build())
+        .capture_options(CaptureOption::builder().capture_mode(CaptureMode::Output).build())
+        .build()
+}
+
+// Baseline the config's datasets, then schedule the endpoint's data quality and model quality checks against
+// them. Job definitions can't be changed, so each deploy creates its own, named after its SageMaker model, and
+// points the endpoint's schedules at them
+#[allow(clippy::too_many_arguments)]
+pub async fn set_up_monitoring(
+    monitoring: &MonitoringConfig,
+    endpoint_name: &str,
+    sagemaker_name: &str,
+    s3_uri: &str,
+    role_arn: &str,
+    region: &str,
+    sage_client: &aws_sdk_sagemaker::Client,
+    tags: &[Tag],
+) -> Result<()> {
+    let image = analyzer_image_uri(monitoring, region)?;
+    let jobs = Jobs { monitoring, s3_uri, endpoint_name, sagemaker_name, image: &image, role_arn, tags };
+    let schedule = schedule_expression(&monitoring.schedule);
+
+    if let Some(dataset) = &monitoring.baseline_dataset {
+        let baseline = jobs.baseline("dq", dataset, data_quality_environment(), sage_client).await?;
+        let definition = job_name(endpoint_name, sagemaker_name, "dq");
+        if !job_definition_exists(&definition, MonitoringType::DataQuality, sage_client).await? {
+            jobs.create_data_quality_definition(&definition, endpoint_name, &baseline, sage_client).await?;
+        }
+        put_schedule(&format!("{}-dq", endpoint_name), &definition, MonitoringType::DataQuality, &schedule, sage_client, tags)
+            .await?;
+    }
+    if let Some(model_quality) = &monitoring.model_quality {
+        let environment = model_quality_environment(model_quality);
+        let baseline = jobs.baseline("mq", &model_quality.baseline_dataset, environment, sage_client).await?;
+        let definition = job_name(endpoint_name, sagemaker_name, "mq");
+        if !job_definition_exists(&definition, MonitoringType::ModelQuality, sage_client).await? {
+            jobs.create_model_quality_definition(&definition, endpoint_name, model_quality, &baseline, sage_client).await?;
+        }
+        put_schedule(&format!("{}-mq", endpoint_name), &definition, MonitoringType::ModelQuality, &schedule, sage_client, tags)
+            .await?;
+    }
+    info!("Monitoring reports will be written to {}/reports", s3_uri);
+    Ok(())
+}
+
+// What the monitoring jobs for a deploy share
+struct Jobs<'a> {
+    monitoring: &'a MonitoringConfig,
+    s3_uri: &'a str,
+    endpoint_name: &'a str,
+    sagemaker_name: &'a str,
+    image: &'a str,
+    role_arn: &'a str,
+    tags: &'a [Tag],
+}
+
+impl Jobs<'_> {
+    // Run (or, if this deploy has been run before, reuse) a baseline job over the dataset and wait for it.
+    // Returns where it wrote its statistics.json and constraints.json
+    async fn baseline(
+        &self,
+        kind: &str,
+        dataset: &str,
+        environment: Vec<(&str, String)>,
+        sage_client: &aws_sdk_sagemaker::Client,
+    ) -> Result<String> {
+        let job = job_name(self.endpoint_name, self.sagemaker_name, &format!("{}-baseline", kind));
+        let output = format!("{}/baselines/{}/{}", self.s3_uri, self.sagemaker_name, kind);
+        match sage_client.describe_processing_job().processing_job_name(&job).send().await {
+            Ok(_) => info!("Baseline job {} already exists, reusing it", job),
+            Err(e) if is_missing(&e) => {
+                info!("Starting baseline job {} for {}", job, dataset);
+                let input = ProcessingInput::builder()
+                    .input_name("baseline_dataset_input")
+                    .s3_input(
+                        ProcessingS3Input::builder()
+                            .s3_uri(dataset)
+                            .local_path(INPUT_PATH)
+                            .s3_data_type(ProcessingS3DataType::S3Prefix)
+                            .s3_input_mode(ProcessingS3InputMode::File)
+                            .build(),
+                    )
+                    .build();
+                let output_config = ProcessingOutputConfig::builder()
+                    .outputs(
+                        ProcessingOutput::builder()
+                            .output_name("monitoring_output")
+                            .s3_output(
+                                ProcessingS3Output::builder()
+                                    .s3_uri(&output)
+                                    .local_path(OUTPUT_PATH)
+                                    .s3_upload_mode(ProcessingS3UploadMode::EndOfJob)
+                                    .build(),
+                            )
+                            .build(),
+                    )
+                    .build();
+                let cluster = ProcessingClusterConfig::builder()
+                    .instance_count(1)
+                    .instance_type(self.monitoring.instance_type.as_str().into())
+                    .volume_size_in_gb(JOB_VOLUME_SIZE_GB)
+                    .build();
+                let mut request = sage_client
+                    .create_processing_job()
+                    .processing_job_name(&job)
+                    .processing_inputs(input)
+                    .processing_output_config(output_config)
+                    .processing_resources(ProcessingResources::builder().cluster_config(cluster).build())
+                    .stopping_condition(
+                        ProcessingStoppingCondition::builder().max_runtime_in_seconds(JOB_MAX_RUNTIME_SECONDS).build(),
+                    )
+                    .app_specification(AppSpecification::builder().image_uri(self.image).build())
+                    .role_arn(self.role_arn)
+                    .set_tags(Some(self.tags.to_vec()));
+                for (key, value) in environment {
+                    request = request.environment(key, value);
+                }
+                request
+                    .send()
+                    .await
+                    .map_err(|e| errors::aws(e, format!("Couldn't start baseline job {}", job)))?;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        wait_for_baseline(&job, sage_client).await?;
+        Ok(output)
+    }
+
+    async fn create_data_quality_definition(
+        &self,
+        definition: &str,
+        endpoint_name: &str,
+        baseline: &str,
+        sage_client: &aws_sdk_sagemaker::Client,
+    ) -> Result<()> {
+        info!("Creating data quality job definition {}", definition);
+        sage_client
+            .create_data_quality_job_definition()
+            .job_definition_name(definition)
+            .data_quality_baseline_config(
+                DataQualityBaselineConfig::builder()
+                    .constraints_resource(
+                        MonitoringConstraintsResource::builder().s3_uri(format!("{}/constraints.json", baseline)).build(),
+                    )
+                    .statistics_resource(
+                        MonitoringStatisticsResource::builder().s3_uri(format!("{}/statistics.json", baseline)).build(),
+                    )
+                    .build(),
+            )
+            .data_quality_app_specification(DataQualityAppSpecification::builder().image_uri(self.image).build())
+            .data_quality_job_input(DataQualityJobInput::builder().endpoint_input(endpoint_input(endpoint_name, None)).build())
+            .data_quality_job_output_config(self.output_config("data-quality"))
+            .job_resources(self.resources())
+            .role_arn(self.role_arn)
+            .stopping_condition(self.stopping_condition())
+            .set_tags(Some(self.tags.to_vec()))
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't create job definition {}", definition)))?;
+        Ok(())
+    }
+
+    async fn create_model_quality_definition(
+        &self,
+        definition: &str,
+        endpoint_name: &str,
+        model_quality: &ModelQualityConfig,
+        baseline: &str,
+        sage_client: &aws_sdk_sagemaker::Client,
+    ) -> Result<()> {
+        info!("Creating model quality job definition {}", definition);
+        let job_input = ModelQualityJobInput::builder()
+            .endpoint_input(endpoint_input(endpoint_name, Some(model_quality)))
+            .ground_truth_s3_input(
+                MonitoringGroundTruthS3Input::builder().s3_uri(&model_quality.ground_truth_s3_uri).build(),
+            )
+            .build();
+        sage_client
+            .create_model_quality_job_definition()
+            .job_definition_name(definition)
+            .model_quality_baseline_config(
+                ModelQualityBaselineConfig::builder()
+                    .constraints_resource(
+                        MonitoringConstraintsResource::builder().s3_uri(format!("{}/constraints.json", baseline)).build(),
+                    )
+                    .build(),
+            )
+            .model_quality_app_specification(
+                ModelQualityAppSpecification::builder()
+                    .image_uri(self.image)
+                    .problem_type(MonitoringProblemType::from(model_quality.problem_type.as_str()))
+                    .build(),
+            )
+            .model_quality_job_input(job_input)
+            .model_quality_job_output_config(self.output_config("model-quality"))
+            .job_resources(self.resources())
+            .role_arn(self.role_arn)
+            .stopping_condition(self.stopping_condition())
+            .set_tags(Some(self.tags.to_vec()))
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't create job definition {}", definition)))?;
+        Ok(())
+    }
+
+    // Reports go to <s3 uri>/reports/<kind>, from every deploy, so they can be compared over time
+    fn output_config(&self, kind: &str) -> MonitoringOutputConfig {
+        let output = MonitoringS3Output::builder()
+            .s3_uri(format!("{}/reports/{}", self.s3_uri, kind))
+            .local_path(OUTPUT_PATH)
+            .s3_upload_mode(ProcessingS3UploadMode::EndOfJob)
+            .build();
+        MonitoringOutputConfig::builder()
+            .monitoring_outputs(MonitoringOutput::builder().s3_output(output).build())
+            .build()
+    }
+
+    fn resources(&self) -> MonitoringResources {
+        let cluster = MonitoringClusterConfig::builder()
+            .instance_count(1)
+            .instance_type(self.monitoring.instance_type.as_str().into())
+            .volume_size_in_gb(JOB_VOLUME_SIZE_GB)
+            .build();
+        MonitoringResources::builder().cluster_config(cluster).build()
+    }
+
+    fn stopping_condition(&self) -> MonitoringStoppingCondition {
+        MonitoringStoppingCondition::builder().max_runtime_in_seconds(JOB_MAX_RUNTIME_SECONDS).build()
+    }
+}
+
+// What the analyzer is told about a data quality baseline dataset
+fn data_quality_environment() -> Vec<(&'static str, String)> {
+    vec![
+        ("dataset_format", r#"{"csv": {"header": true, "output_columns_position": "START"}}"#.to_string()),
+        ("dataset_source", INPUT_PATH.to_string()),
+        ("output_path", OUTPUT_PATH.to_string()),
+        ("publish_cloudwatch_metrics", "Disabled".to_string()),
+    ]
+}
+
+// And about a model quality one, which has the model's predictions and the labels side by side
+fn model_quality_environment(model_quality: &ModelQualityConfig) -> Vec<(&'static str, String)> {
+    let mut environment = vec![
+        ("analysis_type", "MODEL_QUALITY".to_string()),
+        ("problem_type", model_quality.problem_type.clone()),
+        ("dataset_format", r#"{"csv": {"header": true}}"#.to_string()),
+        ("dataset_source", INPUT_PATH.to_string()),
+        ("output_path", OUTPUT_PATH.to_string()),
+        ("inference_attribute", model_quality.inference_attribute.clone()),
+        ("ground_truth_attribute", model_quality.ground_truth_attribute.clone()),
+        ("publish_cloudwatch_metrics", "Disabled".to_string()),
+    ];
+    if let Some(probability) = &model_quality.probability_attribute {
+        environment.push(("probability_attribute", probability.clone()));
+    }
+    environment
+}
+
+fn endpoint_input(endpoint_name: &str, model_quality: Option<&ModelQualityConfig>) -> EndpointInput {
+    let mut input = EndpointInput::builder().endpoint_name(endpoint_name).local_path(ENDPOINT_INPUT_PATH);
+    if let Some(model_quality) = model_quality {
+        input = input
+            .inference_attribute(&model_quality.inference_attribute)
+            .set_probability_attribute(model_quality.probability_attribute.clone());
+    }
+    input.build()
+}
+
+async fn wait_for_baseline(job: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<()> {
+    info!("Waiting for baseline job {} to finish", job);
+    let started = std::time::Instant::now();
+    loop {
+        let description = sage_client.describe_processing_job().processing_job_name(job).send().await?;
+        match description.processing_job_status() {
+            Some(ProcessingJobStatus::Completed) => return Ok(()),
+            Some(ProcessingJobStatus::Failed) | Some(ProcessingJobStatus::Stopped) => {
+                return Err(anyhow!(
+                    "Baseline job {} didn't finish: {}",
+                    job,
+                    description.failure_reason().or(description.exit_message()).unwrap_or("SageMaker didn't give a reason")
+                ));
+            }
+            _ => {}
+        }
+        if started.elapsed() > BASELINE_WAIT_LIMIT {
+            return Err(errors::timeout(format!("Gave up waiting for baseline job {}", job)));
+        }
+        tokio::time::sleep(BASELINE_POLL_INTERVAL).await;
+    }
+}
+
+async fn job_definition_exists(name: &str, kind: MonitoringType, sage_client: &aws_sdk_sagemaker::Client) -> Result<bool> {
+    let exists = match kind {
+        MonitoringType::ModelQuality => {
+            match sage_client.describe_model_quality_job_definition().job_definition_name(name).send().await {
+                Ok(_) => true,
+                Err(e) if is_missing(&e) => false,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        _ => match sage_client.describe_data_quality_job_definition().job_definition_name(name).send().await {
+            Ok(_) => true,
+            Err(e) if is_missing(&e) => false,
+            Err(e) => return Err(e.into()),
+        },
+    };
+    if exists {
+        info!("Job definition {} already exists, reusing it", name);
+    }
+    Ok(exists)
+}
+
+// Create the endpoint's schedule, or point the one an earlier deploy created at this deploy's job definition
+async fn put_schedule(
+    name: &str,
+    definition: &str,
+    kind: MonitoringType,
+    schedule: &str,
+    sage_client: &aws_sdk_sagemaker::Client,
+    tags: &[Tag],
+) -> Result<()> {
+    let config = MonitoringScheduleConfig::builder()
+        .schedule_config(ScheduleConfig::builder().schedule_expression(schedule).build())
+        .monitoring_job_definition_name(definition)
+        .monitoring_type(kind)
+        .build();
+    match sage_client.describe_monitoring_schedule().monitoring_schedule_name(name).send().await {
+        Ok(_) => {
+            info!("Updating monitoring schedule {}", name);
+            sage_client
+                .update_monitoring_schedule()
+                .monitoring_schedule_name(name)
+                .monitoring_schedule_config(config)
+                .send()
+                .await
+                .map_err(|e| errors::aws(e, format!("Couldn't update monitoring schedule {}", name)))?;
+        }
+        Err(e) if is_missing(&e) => {
+            info!("Creating monitoring schedule {}", name);
+            sage_client
+                .create_monitoring_schedule()
+                .monitoring_schedule_name(name)
+                .monitoring_schedule_config(config)
+                .set_tags(Some(tags.to_vec()))
+                .send()
+                .await
+                .map_err(|e| errors::aws(e, format!("Couldn't create monitoring schedule {}", name)))?;
+        }
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+// The endpoint's monitoring schedules, which have to be deleted before the endpoint can be
+pub async fn delete_monitoring_schedules(endpoint_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<()> {
+    let schedules = sage_client.list_monitoring_schedules().endpoint_name(endpoint_name).send().await?;
+    for name in schedules.monitoring_schedule_summaries().iter().filter_map(|s| s.monitoring_schedule_name()) {
+        info!("Deleting monitoring schedule {}", name);
+        sage_client.delete_monitoring_schedule().monitoring_schedule_name(name).send().await?;
+    }
+    Ok(())
+}
+
+fn schedule_expression(schedule: &str) -> String {
+    match schedule {
+        "hourly" => "cron(0 * ? * * *)".to_string(),
+        "daily" => "cron(0 0 ? * * *)".to_string(),
+        cron => cron.to_string(),
+    }
+}
+
+// SageMaker job and definition names are at most 63 characters. The SageMaker model's name is the endpoint's and
+// then the deploy's version or time, so it's the endpoint's part that's cut short, keeping each deploy's jobs apart
+fn job_name(endpoint_name: &str, sagemaker_name: &str, suffix: &str) -> String {
+    let deploy_id = sagemaker_name.strip_prefix(endpoint_name).unwrap_or(sagemaker_name).trim_matches('-');
+    let tail = match deploy_id {
+        "" => suffix.to_string(),
+        id => format!("{}-{}", id, suffix),
+    };
+    let tail = tail[tail.len().saturating_sub(62)..].trim_start_matches('-');
+    let base = &endpoint_name[..endpoint_name.len().min(63 - tail.len() - 1)];
+    match base.trim_end_matches('-') {
+        "" => tail.to_string(),
+        base => format!("{}-{}", base, tail),
+    }
+}
+
+// Processing jobs that don't exist are a ValidationException, job definitions and schedules a ResourceNotFound
+fn is_missing<E: ProvideErrorMetadata>(err: &SdkError<E>) -> bool {
+    err.as_service_error().is_some_and(|e| {
+        e.code() == Some("ResourceNotFound") || e.message().is_some_and(|m| m.contains("Could not find"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::job_name;
+
+    #[test]
+    fn job_name_keeps_the_deploy_of_a_long_endpoint() {
+        let endpoint = "a-model-with-a-really-quite-long-name-for-an-endpoint-production";
+        let name = job_name(endpoint, &format!("{}-20261016-193918", endpoint), "dq-baseline");
+        assert_eq!(name.len(), 63);
+        assert!(name.ends_with("-20261016-193918-dq-baseline"));
+        assert_eq!(job_name("resnet-50", "resnet-50-v1-2", "mq"), "resnet-50-v1-2-mq");
+    }
+}
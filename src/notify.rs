@@ -0,0 +1,201 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{aws::AwsClients, model_config::{ModelConfig, Notifications, WebhookFormat}};
+
+// The source and detail-types events are put on an EventBridge bus with, for rules to match on
+pub const EVENT_SOURCE: &str = "sageturner";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum DeployEventKind {
+    DeployStarted,
+    EndpointInService,
+    // The deploy finished without waiting for the endpoint (--no-wait), which is still being created or updated
+    DeploySubmitted,
+    DeployFailed,
+}
+
+impl DeployEventKind {
+    fn detail_type(&self) -> &'static str {
+        match self {
+            DeployEventKind::DeployStarted => "Sageturner Deploy Started",
+            DeployEventKind::EndpointInService => "Sageturner Endpoint InService",
+            DeployEventKind::DeploySubmitted => "Sageturner Deploy Submitted",
+            DeployEventKind::DeployFailed => "Sageturner Deploy Failed",
+        }
+    }
+}
+
+// What's sent for each step of a deploy, as JSON
+#[derive(Debug, Serialize)]
+pub struct DeployEvent {
+    pub event: DeployEventKind,
+    pub model: String,
+    pub version: Option<String>,
+    pub environment: Option<String>,
+    pub endpoint: String,
+    // The SageMaker model and endpoint config the deploy creates
+    pub sagemaker_model: String,
+    pub region: String,
+    // Why the deploy failed, for DeployFailed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    // How long the deploy took, and what the endpoint is estimated to cost (USD), once it's finished
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_seconds: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_monthly_cost: Option<f64>,
+    pub time: String,
+}
+
+impl DeployEvent {
+    pub fn new(
+        event: DeployEventKind,
+        model_config: &ModelConfig,
+        endpoint_name: &str,
+        sagemaker_name: &str,
+        region: &str,
+        reason: Option<String>,
+    ) -> DeployEvent {
+        DeployEvent {
+            event,
+            model: model_config.name.clone(),
+            version: model_config.version.clone(),
+            environment: model_config.environment.clone(),
+            endpoint: endpoint_name.to_string(),
+            sagemaker_model: sagemaker_name.to_string(),
+            region: region.to_string(),
+            reason,
+            duration_seconds: None,
+            estimated_monthly_cost: None,
+            time: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        }
+    }
+}
+
+// Publish the event to the config's SNS topic and EventBridge bus, if it has them. Notifications are for
+// downstream automation, so one that can't be sent is reported but doesn't stop the deploy
+pub async fn send(notifications: Option<&Notifications>, event: &DeployEvent, aws: &AwsClients) {
+    let Some(notifications) = notifications else { return };
+    let detail = match serde_json::to_string(event) {
+        Ok(d) => d,
+        Err(e) => {
+            warn!("Couldn't write the {:?} event: {}", event.event, e);
+            return;
+        }
+    };
+    if let Some(topic) = &notifications.sns_topic_arn {
+        if let Err(e) = publish_sns(topic, event, &detail, &aws.sns).await {
+            warn!("Couldn't send the {:?} event to SNS topic {}: {}", event.event, topic, e);
+        }
+    }
+    if let Some(bus) = &notifications.event_bus {
+        if let Err(e) = put_event(bus, event, &detail, &aws.events).await {
+            warn!("Couldn't send the {:?} event to EventBridge bus {}: {}", event.event, bus, e);
+        }
+    }
+    // Webhooks are for people, who want to hear how the deploy went, not that it started
+    if let Some(url) = notifications.webhook_url.as_deref().filter(|_| event.event != DeployEventKind::DeployStarted) {
+        if let Err(e) = post_webhook(url, notifications.webhook_format, event).await {
+            warn!("Couldn't post the {:?} event to your webhook: {}", event.event, e);
+        }
+    }
+}
+
+// One line on how the deploy went, e.g.
+// Deployed resnet-50 1.2.0 to endpoint resnet-50-prod in eu-west-2 in 12m 5s. Estimated cost: about $730 a month
+fn summary(event: &DeployEvent) -> String {
+    let model = match &event.version {
+        Some(v) => format!("{} {}", event.model, v),
+        None => event.model.clone(),
+    };
+    let took = event
+        .duration_seconds
+        .map(|s| format!(" in {}m {}s", s / 60, s % 60))
+        .unwrap_or_default();
+    let cost = event
+        .estimated_monthly_cost
+        .map(|c| format!(" Estimated cost: about ${:.0} a month", c))
+        .unwrap_or_default();
+    match event.event {
+        DeployEventKind::DeployFailed => format!(
+            ":x: Deploy of {} to endpoint {} in {} failed{}: {}",
+            model,
+            event.endpoint,
+            event.region,
+            took,
+            event.reason.as_deref().unwrap_or("unknown error")
+        ),
+        DeployEventKind::DeploySubmitted => format!(
+            ":hourglass: Deployed {} to endpoint {} in {}{}, it's still being created or updated.{}",
+            model, event.endpoint, event.region, took, cost
+        ),
+        _ => format!(
+            ":white_check_mark: Deployed {} to endpoint {} in {}{}.{}",
+            model, event.endpoint, event.region, took, cost
+        ),
+    }
+}
+
+async fn post_webhook(url: &str, format: WebhookFormat, event: &DeployEvent) -> Result<()> {
+    let body = match format {
+        WebhookFormat::Slack => serde_json::json!({ "text": summary(event) }),
+        WebhookFormat::Json => serde_json::to_value(event)?,
+    };
+    // The URL is the secret (a Slack webhook's token is in its path), so it's left out of errors
+    let response = reqwest::Client::new()
+        .post(url)
+        .timeout(std::time::Duration::from_secs(10))
+        .json(&body)
+        .send()
+        .await
+        .map_err(|e| anyhow!("{}", e.without_url()))?;
+    if !response.status().is_success() {
+        return Err(anyhow!("{} {}", response.status(), response.text().await.unwrap_or_default()));
+    }
+    Ok(())
+}
+
+async fn publish_sns(topic: &str, event: &DeployEvent, detail: &str, client: &aws_sdk_sns::Client) -> Result<()> {
+    // The event type is an attribute too, so subscriptions can filter on it
+    let event_type = aws_sdk_sns::types::MessageAttributeValue::builder()
+        .data_type("String")
+        .string_value(format!("{:?}", event.event))
+        .build()?;
+    client
+        .publish()
+        .topic_arn(topic)
+        .subject(format!("{}: {}", event.event.detail_type(), event.endpoint))
+        .message(detail)
+        .message_attributes("event", event_type)
+        .send()
+        .await
+        .map_err(|e| anyhow!("{}", aws_sdk_sns::error::DisplayErrorContext(&e)))?;
+    Ok(())
+}
+
+async fn put_event(bus: &str, event: &DeployEvent, detail: &str, client: &aws_sdk_eventbridge::Client) -> Result<()> {
+    let entry = aws_sdk_eventbridge::types::PutEventsRequestEntry::builder()
+        .event_bus_name(bus)
+        .source(EVENT_SOURCE)
+        .detail_type(event.event.detail_type())
+        .detail(detail)
+        .build();
+    let output = client
+        .put_events()
+        .entries(entry)
+        .send()
+        .await
+        .map_err(|e| anyhow!("{}", aws_sdk_eventbridge::error::DisplayErrorContext(&e)))?;
+    // PutEvents succeeds as a request even when its entries fail
+    if let Some(failed) = output.entries().iter().find(|e| e.error_code().is_some()) {
+        return Err(anyhow!(
+            "{}: {}",
+            failed.error_code().unwrap_or_default(),
+            failed.error_message().unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
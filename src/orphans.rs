@@ -0,0 +1,217 @@
+use std::{collections::HashSet, io::Write};
+
+use anyhow::{anyhow, Result};
+use aws_sdk_ecr::types::ImageIdentifier;
+use chrono::Utc;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    aws::{self, AwsClients},
+    errors, retention,
+};
+
+// Anything newer than this is left alone, as a deploy that's running may not have got as far as using it
+const GRACE_PERIOD: chrono::Duration = chrono::Duration::hours(1);
+
+// Sageturner resources nothing uses any more, found by find
+#[derive(Debug, Default, Serialize)]
+pub struct Orphans {
+    pub models: Vec<String>,
+    pub endpoint_configs: Vec<String>,
+    // ECR repository and digest
+    pub images: Vec<(String, String)>,
+}
+
+impl Orphans {
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty() && self.endpoint_configs.is_empty() && self.images.is_empty()
+    }
+
+    pub fn print(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        if !self.models.is_empty() {
+            writeln!(out, "Orphaned models, and their endpoint configs:")?;
+            for model in &self.models {
+                writeln!(out, "  {}", model)?;
+            }
+        }
+        if !self.endpoint_configs.is_empty() {
+            writeln!(out, "Orphaned endpoint configs:")?;
+            for config in &self.endpoint_configs {
+                writeln!(out, "  {}", config)?;
+            }
+        }
+        if !self.images.is_empty() {
+            writeln!(out, "Orphaned images:")?;
+            for (repository, digest) in &self.images {
+                writeln!(out, "  {}", retention::image_name(repository, digest))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// A model or endpoint config with sageturner's tags
+struct Tagged {
+    name: String,
+    // The endpoint it was deployed to, from its model and environment tags
+    endpoint: String,
+    created: i64,
+}
+
+// Find sageturner's models and endpoint configs that no endpoint is running, and whose endpoint has been deleted,
+// e.g. by hand in the console. Ones whose endpoint is still there are kept to roll back to, which keep_previous and
+// retention take care of. Then the images in sageturner's ECR repositories that no remaining model or registered
+// model package uses
+pub async fn find(aws: &AwsClients) -> Result<Orphans> {
+    let sage_client = &aws.sage;
+    let endpoints = aws::endpoint_names(sage_client).await?;
+    let in_use = aws::models_in_use(sage_client).await?;
+    let cutoff = (Utc::now() - GRACE_PERIOD).timestamp();
+    let orphaned = |t: &Tagged| !in_use.contains(&t.name) && !endpoints.contains(&t.endpoint) && t.created < cutoff;
+
+    let mut orphans = Orphans::default();
+    let models = tagged_models(sage_client).await?;
+    let (orphaned_models, kept_models): (Vec<_>, Vec<_>) = models.into_iter().partition(|m| orphaned(m));
+    orphans.models = orphaned_models.into_iter().map(|m| m.name).collect();
+    // Endpoint configs are deleted with the model they're named after, this is the ones left without one
+    orphans.endpoint_configs = tagged_endpoint_configs(sage_client)
+        .await?
+        .into_iter()
+        .filter(|c| orphaned(c) && !orphans.models.contains(&c.name))
+        .map(|c| c.name)
+        .collect();
+
+    // Each model's image is in a repository named after it
+    let mut repositories = HashSet::new();
+    // Model packages point at their image by tag, for deploy --model-package
+    let mut used = packaged_images(sage_client).await?;
+    for model in &kept_models {
+        if let (Some(image), _) = aws::model_container(&model.name, sage_client).await? {
+            if let Some(image) = aws::ecr_image(&image) {
+                repositories.insert(image.0.clone());
+                used.insert(image);
+            }
+        }
+    }
+    for model in &orphans.models {
+        if let (Some(image), _) = aws::model_container(model, sage_client).await? {
+            repositories.extend(aws::ecr_image(&image).map(|(repository, _)| repository));
+        }
+    }
+    for repository in repositories {
+        for image in repository_images(&repository, &aws.ecr).await? {
+            let Some(digest) = image.image_digest() else { continue };
+            let tagged_in_use = image.image_tags().iter().any(|t| used.contains(&(repository.clone(), t.clone())));
+            let pushed = image.image_pushed_at().map(|t| t.secs()).unwrap_or_default();
+            if !tagged_in_use && !used.contains(&(repository.clone(), digest.to_string())) && pushed < cutoff {
+                orphans.images.push((repository.clone(), digest.to_string()));
+            }
+        }
+    }
+    Ok(orphans)
+}
+
+// Delete the orphans
+pub async fn delete(orphans: &Orphans, aws: &AwsClients) -> Result<()> {
+    for name in orphans.models.iter().chain(&orphans.endpoint_configs) {
+        aws::delete_model_and_config(name, &aws.sage).await?;
+        info!("Deleted {}", name);
+    }
+    for (repository, digest) in &orphans.images {
+        aws.ecr
+            .batch_delete_image()
+            .repository_name(repository)
+            .image_ids(ImageIdentifier::builder().image_digest(digest).build())
+            .send()
+            .await
+            .map_err(|e| {
+                anyhow!("Couldn't delete image {}: {}", retention::image_name(repository, digest), aws_sdk_ecr::error::DisplayErrorContext(&e))
+            })?;
+        info!("Deleted image {}", retention::image_name(repository, digest));
+    }
+    Ok(())
+}
+
+async fn tagged_models(sage_client: &aws_sdk_sagemaker::Client) -> Result<Vec<Tagged>> {
+    let mut pages = sage_client.list_models().into_paginator().send();
+    let mut models = Vec::new();
+    while let Some(page) = pages.next().await {
+        for summary in page?.models() {
+            let (Some(name), Some(arn)) = (summary.model_name(), summary.model_arn()) else {
+                continue;
+            };
+            if let Some(endpoint) = deployed_to(arn, sage_client).await? {
+                let created = summary.creation_time().map(|t| t.secs()).unwrap_or_default();
+                models.push(Tagged { name: name.to_string(), endpoint, created });
+            }
+        }
+    }
+    Ok(models)
+}
+
+async fn tagged_endpoint_configs(sage_client: &aws_sdk_sagemaker::Client) -> Result<Vec<Tagged>> {
+    let mut pages = sage_client.list_endpoint_configs().into_paginator().send();
+    let mut configs = Vec::new();
+    while let Some(page) = pages.next().await {
+        for summary in page?.endpoint_configs() {
+            let (Some(name), Some(arn)) = (summary.endpoint_config_name(), summary.endpoint_config_arn()) else {
+                continue;
+            };
+            if let Some(endpoint) = deployed_to(arn, sage_client).await? {
+                let created = summary.creation_time().map(|t| t.secs()).unwrap_or_default();
+                configs.push(Tagged { name: name.to_string(), endpoint, created });
+            }
+        }
+    }
+    Ok(configs)
+}
+
+// The images of every version in the model package groups sageturner created, which have its model tag
+async fn packaged_images(sage_client: &aws_sdk_sagemaker::Client) -> Result<HashSet<(String, String)>> {
+    let mut images = HashSet::new();
+    let mut groups = sage_client.list_model_package_groups().into_paginator().send();
+    while let Some(page) = groups.next().await {
+        for group in page?.model_package_group_summary_list() {
+            let (Some(name), Some(arn)) = (group.model_package_group_name(), group.model_package_group_arn()) else {
+                continue;
+            };
+            let tags = sage_client.list_tags().resource_arn(arn).send().await?;
+            if !tags.tags().iter().any(|t| t.key() == Some(aws::MODEL_TAG)) {
+                continue;
+            }
+            let mut packages = sage_client.list_model_packages().model_package_group_name(name).into_paginator().send();
+            while let Some(page) = packages.next().await {
+                for arn in page?.model_package_summary_list().iter().filter_map(|p| p.model_package_arn()) {
+                    let package = sage_client.describe_model_package().model_package_name(arn).send().await?;
+                    let containers = package.inference_specification().map(|i| i.containers()).unwrap_or_default();
+                    images.extend(containers.iter().filter_map(|c| c.image()).filter_map(aws::ecr_image));
+                }
+            }
+        }
+    }
+    Ok(images)
+}
+
+// The endpoint a resource was deployed for, or None if sageturner didn't deploy it
+async fn deployed_to(arn: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<Option<String>> {
+    let tags = sage_client.list_tags().resource_arn(arn).send().await?;
+    let tag = |key: &str| tags.tags().iter().find(|t| t.key() == Some(key)).and_then(|t| t.value());
+    Ok(tag(aws::MODEL_TAG).map(|model| aws::endpoint_name(model, tag(aws::ENVIRONMENT_TAG))))
+}
+
+// Every image in a repository, or none if it's been deleted
+async fn repository_images(repository: &str, ecr_client: &aws_sdk_ecr::Client) -> Result<Vec<aws_sdk_ecr::types::ImageDetail>> {
+    let mut pages = ecr_client.describe_images().repository_name(repository).into_paginator().send();
+    let mut images = Vec::new();
+    while let Some(page) = pages.next().await {
+        match page {
+            Ok(page) => images.extend(page.image_details().iter().cloned()),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_repository_not_found_exception()) => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(errors::aws(e, format!("Couldn't list images in {}", repository)))
+            }
+        }
+    }
+    Ok(images)
+}
@@ -0,0 +1,41 @@
+use anyhow::{anyhow, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{trace::SdkTracerProvider, Resource};
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, EnvFilter, Layer};
+
+// Spans are only exported when there's somewhere to send them, set the usual OpenTelemetry way
+const ENDPOINT_ENVS: &[&str] = &["OTEL_EXPORTER_OTLP_ENDPOINT", "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"];
+// Otherwise the service is sageturner
+const SERVICE_NAME_ENV: &str = "OTEL_SERVICE_NAME";
+
+// Every phase's span, with the AWS request IDs logged in it as events, whatever -q and -v say
+const FILTER: &str = "sageturner=debug";
+
+// A layer sending sageturner's spans to an OTLP collector, and the provider to flush them with before exiting
+type Export<S> = (Box<dyn Layer<S> + Send + Sync>, SdkTracerProvider);
+
+// Spans go over HTTP, set up by the usual OTEL_ environment variables. None if no collector is set
+pub fn layer<S>() -> Result<Option<Export<S>>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    if !ENDPOINT_ENVS.iter().any(|env| std::env::var(env).is_ok_and(|v| !v.is_empty())) {
+        return Ok(None);
+    }
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .build()
+        .map_err(|e| anyhow!("Couldn't set up the OpenTelemetry exporter: {}", e))?;
+    let resource = match std::env::var(SERVICE_NAME_ENV) {
+        Ok(_) => Resource::builder().build(),
+        Err(_) => Resource::builder().with_service_name("sageturner").build(),
+    };
+    let provider = SdkTracerProvider::builder().with_batch_exporter(exporter).with_resource(resource).build();
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("sageturner"))
+        .with_filter(EnvFilter::new(FILTER))
+        .boxed();
+    Ok(Some((layer, provider)))
+}
@@ -0,0 +1,184 @@
+use std::{future::Future, io::{LineWriter, Write}, path::Path, str::FromStr, time::Instant};
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{aws::DeployedModel, retry, state::DeployStatus};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    // Tables and summaries for people, with progress logged alongside
+    Text,
+    // Just the command's result on stdout, as one JSON document, with progress logged to stderr
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(anyhow!("Invalid output. text or json only, not: {}", s)),
+        }
+    }
+}
+
+// Print a command's result on stdout
+pub fn print_json<T: Serialize>(result: &T) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer_pretty(&mut stdout, result)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+// Where to print what's for people to read: stdout, unless that's kept for the JSON result. Either way it's
+// written a line at a time, like stdout always is, so it doesn't interleave mid-line with other processes' output
+pub fn human(format: OutputFormat) -> Box<dyn Write> {
+    match format {
+        OutputFormat::Text => Box::new(std::io::stdout()),
+        OutputFormat::Json => Box::new(LineWriter::new(std::io::stderr())),
+    }
+}
+
+// What one deploy did, to one region
+#[derive(Debug, Serialize)]
+pub struct DeployResult {
+    pub model: String,
+    pub region: String,
+    pub status: DeployStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub endpoint: Option<String>,
+    pub endpoint_arn: Option<String>,
+    // The SageMaker model and endpoint config
+    pub sagemaker_model: Option<String>,
+    pub image: Option<String>,
+    pub image_digest: Option<String>,
+    // The model data's S3 URI
+    pub artefact: Option<String>,
+    pub model_package: Option<String>,
+    pub dashboard_url: Option<String>,
+    pub duration_seconds: f64,
+    // How long each phase took, in the order they ran
+    pub phases: Vec<Phase>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Phase {
+    pub name: &'static str,
+    pub seconds: f64,
+    // Calls in the phase that failed and were tried again, like a push ECR answered with a 503
+    #[serde(skip_serializing_if = "is_zero")]
+    pub retries: u32,
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+impl DeployResult {
+    // A deploy that hasn't got anywhere yet. It's failed until it says otherwise
+    pub fn new(model: &str, region: &str) -> DeployResult {
+        DeployResult {
+            model: model.to_string(),
+            region: region.to_string(),
+            status: DeployStatus::Failed,
+            error: None,
+            endpoint: None,
+            endpoint_arn: None,
+            sagemaker_model: None,
+            image: None,
+            image_digest: None,
+            artefact: None,
+            model_package: None,
+            dashboard_url: None,
+            duration_seconds: 0.0,
+            phases: Vec::new(),
+        }
+    }
+
+    // Run one phase of the deploy, timing it and counting its retries
+    pub async fn time<F: Future>(&mut self, name: &'static str, phase: F) -> F::Output {
+        let started = Instant::now();
+        let (output, retries) = retry::counted(phase).await;
+        self.phases.push(Phase { name, seconds: started.elapsed().as_secs_f64(), retries });
+        output
+    }
+}
+
+// A model list found, with how its latest deploy went, if it was deployed from this project
+#[derive(Debug, Serialize)]
+pub struct ListedModel<'a> {
+    #[serde(flatten)]
+    pub model: &'a DeployedModel,
+    pub status: Option<DeployStatus>,
+}
+
+// Everything about a deploy or promote, written by --report for CI artifacts and audits
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub sageturner_version: &'static str,
+    // sageturner's arguments, as it was run
+    pub arguments: Vec<String>,
+    pub started: String,
+    pub finished: String,
+    pub duration_seconds: f64,
+    pub succeeded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub configs: Vec<ResolvedConfig>,
+    pub deploys: &'a [DeployResult],
+}
+
+// One model's config, and how it was asked to deploy
+#[derive(Debug, Serialize)]
+pub struct ResolvedConfig {
+    pub model: String,
+    pub config_path: String,
+    pub environment: Option<String>,
+    pub endpoint_type: String,
+    // Where the SageMaker model came from: built in a container mode, a model package, or another environment
+    pub container_mode: Option<String>,
+    pub model_package: Option<String>,
+    pub promoted_from: Option<String>,
+    pub config_hash: String,
+    pub config: serde_yaml::Value,
+}
+
+impl Report<'_> {
+    pub fn new<'a>(
+        started: DateTime<Utc>,
+        configs: Vec<ResolvedConfig>,
+        deploys: &'a [DeployResult],
+        result: &Result<()>,
+    ) -> Report<'a> {
+        let finished = Utc::now();
+        Report {
+            sageturner_version: env!("CARGO_PKG_VERSION"),
+            arguments: std::env::args().skip(1).collect(),
+            started: started.to_rfc3339_opts(SecondsFormat::Secs, true),
+            finished: finished.to_rfc3339_opts(SecondsFormat::Secs, true),
+            duration_seconds: (finished - started).num_milliseconds() as f64 / 1000.0,
+            succeeded: result.is_ok(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+            configs,
+            deploys,
+        }
+    }
+
+    // As YAML for a .yaml or .yml path, JSON otherwise
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+        let report = match extension.as_str() {
+            "yaml" | "yml" => serde_yaml::to_string(self)?,
+            _ => serde_json::to_string_pretty(self)? + "\n",
+        };
+        std::fs::write(path, report).map_err(|e| anyhow!("Couldn't write the report to {}: {}", path.display(), e))?;
+        info!("Wrote the report to {}", path.display());
+        Ok(())
+    }
+}
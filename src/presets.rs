@@ -0,0 +1,499 @@
+use anyhow::{anyhow, Result};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::model_config::Hardware;
+
+// Presets generate the sageturner.py (load() and predict()) for common frameworks,
+// and pin the python packages that code needs, so the user doesn't have to write any Python.
+#[derive(Debug, Deserialize, JsonSchema, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    Sklearn,
+    Pytorch,
+    Transformers,
+    Xgboost,
+    Onnx,
+    Embeddings,
+    Tensorflow,
+}
+
+// Extra settings for presets that need them. Fields a preset doesn't use are ignored
+#[derive(Debug, Deserialize, JsonSchema, Default)]
+#[serde(deny_unknown_fields)]
+pub struct PresetOptions {
+    // transformers: the pipeline task, e.g. text-classification
+    pub task: Option<String>,
+    // transformers, embeddings: a Hugging Face Hub model id to download when the container starts,
+    // instead of loading from the artefact
+    pub model_id: Option<String>,
+    // embeddings: how many texts to encode per forward pass. Defaults to 32
+    pub batch_size: Option<u32>,
+    // tensorflow: the SavedModel signature to call. Defaults to serving_default
+    pub signature: Option<String>,
+}
+
+impl std::fmt::Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Preset::Sklearn => write!(f, "sklearn"),
+            Preset::Pytorch => write!(f, "pytorch"),
+            Preset::Transformers => write!(f, "transformers"),
+            Preset::Xgboost => write!(f, "xgboost"),
+            Preset::Onnx => write!(f, "onnx"),
+            Preset::Embeddings => write!(f, "embeddings"),
+            Preset::Tensorflow => write!(f, "tensorflow"),
+        }
+    }
+}
+
+impl Preset {
+    // Packages the generated code depends on. These get installed before the
+    // user's own python_packages, so users can still override a pin if they need to.
+    // On CUDA we pick wheels built against the CUDA version installed by the GPU Dockerfile
+    pub fn python_packages(&self, hardware: Hardware) -> Vec<String> {
+        let gpu = hardware == Hardware::Cuda;
+        let torch_index = if gpu { TORCH_CUDA_INDEX } else { TORCH_CPU_INDEX };
+        match self {
+            // The Neuron base image ships torch-neuronx, reinstalling torch would break it
+            Preset::Pytorch if hardware == Hardware::Neuron => vec![],
+            Preset::Sklearn => vec![
+                "scikit-learn==1.6.1".to_string(),
+                "joblib==1.4.2".to_string(),
+                "pandas==2.2.3".to_string(),
+            ],
+            Preset::Pytorch => vec![format!("--extra-index-url {torch_index} torch==2.7.0")],
+            Preset::Transformers => vec![
+                format!("--extra-index-url {torch_index} torch==2.7.0"),
+                "transformers==4.51.3".to_string(),
+            ],
+            Preset::Xgboost => vec!["xgboost==2.1.4".to_string()],
+            // onnxruntime-gpu 1.21 is built against CUDA 12
+            Preset::Onnx if gpu => vec!["onnxruntime-gpu==1.21.1".to_string(), "numpy<2.3".to_string()],
+            Preset::Onnx => vec!["onnxruntime==1.21.1".to_string(), "numpy<2.3".to_string()],
+            Preset::Embeddings => vec![
+                format!("--extra-index-url {torch_index} torch==2.7.0"),
+                "sentence-transformers==4.1.0".to_string(),
+            ],
+            // and-cuda pulls in the CUDA libraries TF is built against, alongside the toolkit
+            Preset::Tensorflow if gpu => vec!["tensorflow[and-cuda]==2.19.0".to_string()],
+            Preset::Tensorflow => vec!["tensorflow==2.19.0".to_string()],
+        }
+    }
+
+    // Whether the generated code loads the model from /opt/ml/model
+    pub fn needs_artefact(&self, options: &PresetOptions) -> bool {
+        match self {
+            Preset::Transformers | Preset::Embeddings => options.model_id.is_none(),
+            _ => true,
+        }
+    }
+
+    // Neuron runs compiled TorchScript, so only the pytorch preset makes sense there
+    pub fn supports(&self, hardware: Hardware) -> bool {
+        hardware != Hardware::Neuron || *self == Preset::Pytorch
+    }
+
+    pub fn validate_options(&self, options: &PresetOptions) -> Result<()> {
+        if *self == Preset::Transformers && options.task.as_ref().is_none_or(|t| t.is_empty()) {
+            return Err(anyhow!("Invalid sageturner config: the transformers preset needs preset_options.task, e.g. text-classification"));
+        }
+        if options.batch_size == Some(0) {
+            return Err(anyhow!("Invalid sageturner config: preset_options.batch_size must be at least 1"));
+        }
+        Ok(())
+    }
+
+    // The generated sageturner.py for this preset
+    pub fn model_code(&self, options: &PresetOptions) -> Result<String> {
+        self.validate_options(options)?;
+        let code = match self {
+            Preset::Sklearn => SKLEARN_CODE.to_string(),
+            Preset::Pytorch => PYTORCH_CODE.to_string(),
+            Preset::Transformers => TRANSFORMERS_CODE
+                .replace("{{TASK}}", &python_str(options.task.as_deref()))
+                .replace("{{MODEL_ID}}", &python_str(options.model_id.as_deref())),
+            Preset::Xgboost => XGBOOST_CODE.to_string(),
+            Preset::Onnx => ONNX_CODE.to_string(),
+            Preset::Embeddings => EMBEDDINGS_CODE
+                .replace("{{MODEL_ID}}", &python_str(options.model_id.as_deref()))
+                .replace("{{BATCH_SIZE}}", &options.batch_size.unwrap_or(32).to_string()),
+            Preset::Tensorflow => TENSORFLOW_CODE.replace(
+                "{{SIGNATURE}}",
+                &python_str(Some(options.signature.as_deref().unwrap_or("serving_default"))),
+            ),
+        };
+        info!("Generated sageturner.py for {} preset", self);
+        Ok(code)
+    }
+}
+
+// Render an optional config value as a python literal for the generated code
+fn python_str(value: Option<&str>) -> String {
+    match value {
+        Some(v) => format!("{:?}", v),
+        None => "None".to_string(),
+    }
+}
+
+// Must match the CUDA toolkit the GPU Dockerfile installs (12.8)
+const TORCH_CUDA_INDEX: &str = "https://download.pytorch.org/whl/cu128";
+const TORCH_CPU_INDEX: &str = "https://download.pytorch.org/whl/cpu";
+
+const SKLEARN_CODE: &str = r#"# Generated by sageturner (preset: sklearn)
+import glob
+import io
+import os
+import pickle
+
+import joblib
+import pandas as pd
+
+MODEL_DIR = "/opt/ml/model"
+
+
+def load():
+    # Load the first joblib or pickle file found in the model artefact
+    for pattern in ("*.joblib", "*.pkl", "*.pickle"):
+        matches = sorted(glob.glob(os.path.join(MODEL_DIR, "**", pattern), recursive=True))
+        if matches:
+            path = matches[0]
+            if path.endswith(".joblib"):
+                return joblib.load(path)
+            with open(path, "rb") as f:
+                return pickle.load(f)
+    raise RuntimeError(f"No .joblib, .pkl or .pickle file found in {MODEL_DIR}")
+
+
+def predict(model, request):
+    # CSV bodies arrive as a string, one row per line, no header.
+    # JSON bodies are {"instances": [...]} where each instance is a list of features
+    # or a dict of feature name -> value
+    if isinstance(request, str):
+        frame = pd.read_csv(io.StringIO(request), header=None)
+    else:
+        instances = request["instances"] if isinstance(request, dict) else request
+        frame = pd.DataFrame(instances)
+
+    response = {"predictions": model.predict(frame).tolist()}
+    if hasattr(model, "predict_proba"):
+        response["probabilities"] = model.predict_proba(frame).tolist()
+    return response
+"#;
+
+const PYTORCH_CODE: &str = r#"# Generated by sageturner (preset: pytorch)
+import glob
+import os
+
+# Thread defaults have to be set before torch is imported. One intra-op thread pool
+# sized to the instance; override by setting these in the container environment
+os.environ.setdefault("OMP_NUM_THREADS", str(os.cpu_count() or 1))
+os.environ.setdefault("MKL_NUM_THREADS", str(os.cpu_count() or 1))
+
+import torch
+
+try:
+    # On Inferentia/Trainium images this registers the Neuron ops, so TorchScript
+    # compiled with torch_neuronx.trace() loads with torch.jit.load
+    import torch_neuronx  # noqa: F401
+except ImportError:
+    pass
+
+MODEL_DIR = "/opt/ml/model"
+DEVICE = "cuda" if torch.cuda.is_available() else "cpu"
+
+torch.set_grad_enabled(False)
+
+
+def _find_checkpoint():
+    for pattern in ("*.pt", "*.pth", "*.bin"):
+        matches = sorted(glob.glob(os.path.join(MODEL_DIR, "**", pattern), recursive=True))
+        if matches:
+            return matches[0]
+    raise RuntimeError(f"No .pt, .pth or .bin file found in {MODEL_DIR}")
+
+
+def load():
+    path = _find_checkpoint()
+
+    # TorchScript archives load without any model code
+    try:
+        model = torch.jit.load(path, map_location=DEVICE)
+        model.eval()
+        return model
+    except RuntimeError:
+        pass
+
+    checkpoint = torch.load(path, map_location=DEVICE, weights_only=False)
+    if isinstance(checkpoint, torch.nn.Module):
+        model = checkpoint
+    else:
+        # A state dict needs the architecture: put a model.py with build_model() in your code_dir
+        from model import build_model
+
+        state_dict = checkpoint.get("state_dict", checkpoint)
+        model = build_model()
+        model.load_state_dict(state_dict)
+
+    model.to(DEVICE)
+    model.eval()
+    return model
+
+
+def _to_list(output):
+    if isinstance(output, torch.Tensor):
+        return output.detach().cpu().tolist()
+    if isinstance(output, (list, tuple)):
+        return [_to_list(o) for o in output]
+    if isinstance(output, dict):
+        return {k: _to_list(v) for k, v in output.items()}
+    return output
+
+
+def predict(model, request):
+    # Expects {"inputs": <nested list of numbers>}, passed to the model as a single float tensor
+    inputs = torch.tensor(request["inputs"], dtype=torch.float32, device=DEVICE)
+    return {"outputs": _to_list(model(inputs))}
+"#;
+
+const TRANSFORMERS_CODE: &str = r#"# Generated by sageturner (preset: transformers)
+import os
+
+import torch
+from transformers import pipeline
+
+MODEL_DIR = "/opt/ml/model"
+TASK = {{TASK}}
+MODEL_ID = {{MODEL_ID}}
+
+
+def load():
+    # The pipeline loads the matching tokenizer/processor alongside the model
+    source = MODEL_ID or MODEL_DIR
+    device = 0 if torch.cuda.is_available() else -1
+    return pipeline(TASK, model=source, device=device)
+
+
+def predict(model, request):
+    # Expects {"inputs": <whatever the pipeline task takes>, "parameters": {...optional pipeline kwargs}}
+    # e.g. {"inputs": ["I love this", "I hate this"], "parameters": {"truncation": true}}
+    parameters = request.get("parameters") or {}
+    return {"outputs": model(request["inputs"], **parameters)}
+"#;
+
+const XGBOOST_CODE: &str = r#"# Generated by sageturner (preset: xgboost)
+# Follows the SageMaker built-in XGBoost container's inference conventions, so
+# artefacts and clients written for it work unchanged
+import glob
+import io
+import os
+import pickle
+
+import numpy as np
+import xgboost as xgb
+from fastapi import Response
+
+MODEL_DIR = "/opt/ml/model"
+
+
+def load():
+    # The built-in container saves xgboost-model; also accept the standard booster formats
+    candidates = [os.path.join(MODEL_DIR, "xgboost-model")]
+    for pattern in ("*.json", "*.ubj", "*.model", "*.bst"):
+        candidates += sorted(glob.glob(os.path.join(MODEL_DIR, "**", pattern), recursive=True))
+    candidates = [c for c in candidates if os.path.isfile(c)]
+    if not candidates:
+        raise RuntimeError(f"No xgboost-model, .json, .ubj, .model or .bst file found in {MODEL_DIR}")
+
+    path = candidates[0]
+    booster = xgb.Booster()
+    try:
+        booster.load_model(path)
+    except xgb.core.XGBoostError:
+        # Older built-in versions pickle the booster
+        with open(path, "rb") as f:
+            booster = pickle.load(f)
+    return booster
+
+
+def predict(model, request):
+    # text/csv: one row per line, no header, no label column. Returns CSV, one score per row
+    if isinstance(request, str):
+        rows = np.loadtxt(io.StringIO(request), delimiter=",", ndmin=2)
+        scores = model.predict(xgb.DMatrix(rows))
+        return Response(content="\n".join(_format(s) for s in scores), media_type="text/csv")
+
+    # application/json: {"instances": [[...], ...]} or {"instances": [{"features": [...]}, ...]}
+    instances = request["instances"] if isinstance(request, dict) else request
+    rows = np.array([i["features"] if isinstance(i, dict) else i for i in instances], dtype=float)
+    scores = model.predict(xgb.DMatrix(rows))
+    return {"predictions": [{"score": s.tolist()} for s in scores]}
+
+
+def _format(score):
+    if np.ndim(score) == 0:
+        return str(float(score))
+    return ",".join(str(float(s)) for s in score)
+"#;
+
+const ONNX_CODE: &str = r#"# Generated by sageturner (preset: onnx)
+import glob
+import os
+
+import numpy as np
+import onnxruntime as ort
+
+MODEL_DIR = "/opt/ml/model"
+
+# onnx element types -> numpy dtypes, for marshalling JSON into input tensors
+DTYPES = {
+    "tensor(float)": np.float32,
+    "tensor(float16)": np.float16,
+    "tensor(double)": np.float64,
+    "tensor(int8)": np.int8,
+    "tensor(int16)": np.int16,
+    "tensor(int32)": np.int32,
+    "tensor(int64)": np.int64,
+    "tensor(uint8)": np.uint8,
+    "tensor(bool)": np.bool_,
+    "tensor(string)": np.object_,
+}
+
+
+def load():
+    matches = sorted(glob.glob(os.path.join(MODEL_DIR, "**", "*.onnx"), recursive=True))
+    if not matches:
+        raise RuntimeError(f"No .onnx file found in {MODEL_DIR}")
+    # Uses CUDA when the GPU runtime is installed and a GPU is present, CPU otherwise
+    providers = [p for p in ("CUDAExecutionProvider", "CPUExecutionProvider") if p in ort.get_available_providers()]
+    return ort.InferenceSession(matches[0], providers=providers)
+
+
+def predict(model, request):
+    # Expects {"inputs": {"<input name>": <nested list>, ...}}. Models with a single
+    # input can also take {"inputs": <nested list>}. Returns {"outputs": {"<output name>": <nested list>}}
+    inputs = request["inputs"]
+    model_inputs = model.get_inputs()
+    if not isinstance(inputs, dict):
+        if len(model_inputs) != 1:
+            names = [i.name for i in model_inputs]
+            raise ValueError(f"Model has several inputs, send a dict keyed by input name: {names}")
+        inputs = {model_inputs[0].name: inputs}
+
+    feed = {}
+    for model_input in model_inputs:
+        if model_input.name not in inputs:
+            raise ValueError(f"Missing input: {model_input.name}")
+        feed[model_input.name] = np.asarray(inputs[model_input.name], dtype=DTYPES.get(model_input.type, np.float32))
+
+    output_names = [o.name for o in model.get_outputs()]
+    results = model.run(output_names, feed)
+    return {"outputs": {name: result.tolist() for name, result in zip(output_names, results)}}
+"#;
+
+const EMBEDDINGS_CODE: &str = r#"# Generated by sageturner (preset: embeddings)
+import torch
+from sentence_transformers import SentenceTransformer
+
+MODEL_DIR = "/opt/ml/model"
+MODEL_ID = {{MODEL_ID}}
+BATCH_SIZE = {{BATCH_SIZE}}
+
+
+def load():
+    device = "cuda" if torch.cuda.is_available() else "cpu"
+    return SentenceTransformer(MODEL_ID or MODEL_DIR, device=device)
+
+
+def predict(model, request):
+    # Expects {"inputs": "a text" or ["text one", "text two", ...], "normalize": true}
+    # normalize is optional and defaults to true (unit length vectors, so dot product == cosine similarity).
+    # Returns {"embeddings": [[...], ...], "dimensions": <vector size>}, one embedding per input, in order
+    inputs = request["inputs"]
+    if isinstance(inputs, str):
+        inputs = [inputs]
+    embeddings = model.encode(
+        inputs,
+        batch_size=BATCH_SIZE,
+        normalize_embeddings=request.get("normalize", True),
+        convert_to_numpy=True,
+    )
+    return {"embeddings": embeddings.tolist(), "dimensions": int(embeddings.shape[1])}
+"#;
+
+const TENSORFLOW_CODE: &str = r#"# Generated by sageturner (preset: tensorflow)
+# Request and response bodies follow the TF Serving REST predict API, so existing clients keep working
+import glob
+import os
+
+import numpy as np
+import tensorflow as tf
+
+MODEL_DIR = "/opt/ml/model"
+SIGNATURE = {{SIGNATURE}}
+
+
+def load():
+    # A .keras/.h5 file is loaded as a Keras model, otherwise look for a SavedModel directory
+    for pattern in ("*.keras", "*.h5"):
+        matches = sorted(glob.glob(os.path.join(MODEL_DIR, "**", pattern), recursive=True))
+        if matches:
+            return {"keras": tf.keras.models.load_model(matches[0])}
+
+    saved_models = sorted(glob.glob(os.path.join(MODEL_DIR, "**", "saved_model.pb"), recursive=True))
+    if not saved_models:
+        raise RuntimeError(f"No .keras, .h5 or SavedModel (saved_model.pb) found in {MODEL_DIR}")
+    # Shallowest match wins, so versioned TF Serving layouts (model/1/saved_model.pb) work too
+    saved_models.sort(key=lambda p: p.count(os.sep))
+    loaded = tf.saved_model.load(os.path.dirname(saved_models[0]))
+    if SIGNATURE not in loaded.signatures:
+        raise RuntimeError(f"Signature {SIGNATURE} not found, the model has: {list(loaded.signatures.keys())}")
+    return {"saved_model": loaded, "signature": loaded.signatures[SIGNATURE]}
+
+
+def _to_list(value):
+    if isinstance(value, dict):
+        return {k: _to_list(v) for k, v in value.items()}
+    if isinstance(value, (list, tuple)):
+        return [_to_list(v) for v in value]
+    if hasattr(value, "numpy"):
+        value = value.numpy()
+    if isinstance(value, np.ndarray):
+        if value.dtype.kind in ("S", "O"):
+            return [v.decode("utf-8") if isinstance(v, bytes) else v for v in value.tolist()]
+        return value.tolist()
+    return value
+
+
+def _signature_inputs(signature, instances):
+    # Row format: {"instances": [...]}. Each instance is a value (single input signatures)
+    # or a dict of input name -> value. Column format: {"inputs": {"name": [...]}} or {"inputs": [...]}
+    specs = signature.structured_input_signature[1]
+    if isinstance(instances, dict):
+        columns = instances
+    elif len(specs) == 1:
+        columns = {next(iter(specs)): instances}
+    else:
+        columns = {name: [i[name] for i in instances] for name in specs}
+    missing = [name for name in specs if name not in columns]
+    if missing:
+        raise ValueError(f"Missing inputs for signature {SIGNATURE}: {missing}")
+    return {name: tf.constant(columns[name], dtype=spec.dtype) for name, spec in specs.items()}
+
+
+def predict(model, request):
+    rows = "instances" in request
+    data = request["instances"] if rows else request["inputs"]
+
+    if "keras" in model:
+        outputs = model["keras"](np.asarray(data) if not isinstance(data, dict) else data, training=False)
+    else:
+        outputs = model["signature"](**_signature_inputs(model["signature"], data))
+
+    outputs = _to_list(outputs)
+    # Single output signatures return the bare value, like TF Serving
+    if isinstance(outputs, dict) and len(outputs) == 1:
+        outputs = next(iter(outputs.values()))
+    return {"predictions": outputs} if rows else {"outputs": outputs}
+"#;
@@ -0,0 +1,71 @@
+use std::{
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock,
+    },
+    time::Duration,
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use tracing::Level;
+
+// Every bar goes in here, so deploys to several regions at once each get their own line, and logs print above them
+static BARS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+
+const TICK: Duration = Duration::from_millis(120);
+
+// Set by --non-interactive, as bars redraw themselves with control sequences
+static HIDDEN: AtomicBool = AtomicBool::new(false);
+
+pub fn hide() {
+    HIDDEN.store(true, Ordering::Relaxed);
+}
+
+// For something with a known size, like an upload
+pub fn bytes(total: u64, message: impl Into<String>) -> ProgressBar {
+    add(
+        ProgressBar::new(total),
+        "{msg} {bar:40} {bytes}/{total_bytes} {bytes_per_sec} [{elapsed}] eta {eta}",
+        message,
+    )
+}
+
+// For something we can count the bytes of as it goes, but don't know the size of up front, like packaging
+pub fn written(message: impl Into<String>) -> ProgressBar {
+    let bar = add(ProgressBar::no_length(), "{spinner} {msg} {bytes} [{elapsed}]", message);
+    bar.enable_steady_tick(TICK);
+    bar
+}
+
+// For waiting on something, with what it's doing as the message
+pub fn spinner(message: impl Into<String>) -> ProgressBar {
+    let bar = add(ProgressBar::no_length(), "{spinner} {msg} [{elapsed}]", message);
+    bar.enable_steady_tick(TICK);
+    bar
+}
+
+// Progress is hidden along with the logs by --quiet, by --non-interactive, and by indicatif itself when stderr isn't
+// a terminal
+fn add(bar: ProgressBar, template: &str, message: impl Into<String>) -> ProgressBar {
+    if !tracing::enabled!(Level::INFO) || HIDDEN.load(Ordering::Relaxed) {
+        return ProgressBar::hidden();
+    }
+    // The templates are fixed, so they always parse
+    bar.set_style(ProgressStyle::with_template(template).expect("invalid progress template"));
+    bar.set_message(message.into());
+    BARS.add(bar)
+}
+
+// Writes logs with the bars cleared out of the way, then redraws them underneath
+pub struct LogWriter<W>(pub W);
+
+impl<W: Write> Write for LogWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        BARS.suspend(|| self.0.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        BARS.suspend(|| self.0.flush())
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use aws_sdk_sagemaker::types::{ContainerDefinition, Tag};
+use tracing::{info, warn};
+
+use crate::{aws, errors};
+
+// Models promoted from another environment are tagged with the SageMaker model they were copied from
+pub const PROMOTED_FROM_TAG: &str = "sageturner:promoted-from";
+
+// The model an environment's endpoint is running, to promote to another environment
+pub struct Promotion {
+    pub model: String,
+    // Pinned to its digest, where ECR can say what that is
+    pub image: String,
+    container: ContainerDefinition,
+}
+
+// Find the model the endpoint is running, and the digest of its image, so the environment it's promoted to runs
+// exactly the same bits, even if the tag has been pushed over since
+pub async fn find(endpoint_name: &str, aws: &aws::AwsClients) -> Result<Promotion> {
+    let model = aws::get_endpoint_model_name(endpoint_name, &aws.sage).await?;
+    let described = aws
+        .sage
+        .describe_model()
+        .model_name(&model)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't find model {}", model)))?;
+    let container = described
+        .primary_container()
+        .ok_or_else(|| anyhow!("Model {} has no primary container to promote", model))?
+        .clone();
+    if container.model_package_name().is_some() {
+        return Err(anyhow!("Model {} was deployed from a model package, so deploy that package with --model-package instead", model));
+    }
+    let image = container.image().ok_or_else(|| anyhow!("Model {} has no image", model))?;
+    let image = match aws::image_digest(image, &aws.ecr).await {
+        Some(_) if image.contains('@') => image.to_string(),
+        Some(digest) => format!("{}@{}", untagged(image), digest),
+        None => {
+            warn!("Couldn't look up the digest of {}, promoting it by its tag", image);
+            image.to_string()
+        }
+    };
+    Ok(Promotion { model, image, container })
+}
+
+// Create the deploy's SageMaker model with the promoted model's image and model data. Its environment is carried
+// over, less the variables the config being promoted from set, which are replaced with the ones this config sets
+pub async fn create_model(
+    promotion: &Promotion,
+    sagemaker_name: &str,
+    execution_role_arn: &str,
+    from_environment: &[String],
+    environment: HashMap<String, String>,
+    sage_client: &aws_sdk_sagemaker::Client,
+    tags: &[Tag],
+) -> Result<()> {
+    info!("Creating model {} from {}, with image {}", sagemaker_name, promotion.model, promotion.image);
+    let mut container_environment = promotion.container.environment().cloned().unwrap_or_default();
+    container_environment.retain(|key, _| !from_environment.contains(key));
+    container_environment.extend(environment);
+    let container = ContainerDefinition::builder()
+        .image(&promotion.image)
+        .set_model_data_url(promotion.container.model_data_url().map(|u| u.to_string()))
+        .set_model_data_source(promotion.container.model_data_source().cloned())
+        .set_environment(Some(container_environment))
+        .build();
+    sage_client
+        .create_model()
+        .model_name(sagemaker_name)
+        .execution_role_arn(execution_role_arn)
+        .primary_container(container)
+        .set_tags(Some(tags.to_vec()))
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't create model {}", sagemaker_name)))?;
+    Ok(())
+}
+
+// An image URI without its tag. Registries can have a port, so only a : after the last / is a tag
+fn untagged(image: &str) -> &str {
+    match image.rsplit_once(':') {
+        Some((name, tag)) if !tag.contains('/') => name,
+        _ => image,
+    }
+}
@@ -0,0 +1,65 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use anyhow::{anyhow, Result};
+
+// Set by --non-interactive, so nothing is asked even with a terminal to ask in
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+pub fn disable() {
+    NON_INTERACTIVE.store(true, Ordering::Relaxed);
+}
+
+// Print the question and read one line of answer. With no terminal to ask (CI, piped input) it's an error
+// rather than a hang or a silent default. Questions go to stderr, so stdout is just the command's result
+fn read_answer(question: &str) -> Result<String> {
+    if NON_INTERACTIVE.load(Ordering::Relaxed) {
+        return Err(anyhow!("{} Not asking, as sageturner is running with --non-interactive", question));
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow!("{} There's no terminal to answer in", question));
+    }
+    eprint!("{} ", question);
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+// Ask a yes/no question, defaulting to no, so scripts have to opt in with --yes
+pub fn confirm(question: &str) -> Result<bool> {
+    let answer = read_answer(&format!("{} [y/N]", question))
+        .map_err(|e| anyhow!("{}, pass --yes to go ahead without asking", e))?;
+    Ok(matches!(answer.to_lowercase().as_str(), "y" | "yes"))
+}
+
+// Ask for a value, taking the default on an empty answer
+pub fn ask(question: &str, default: &str) -> Result<String> {
+    let answer = read_answer(&format!("{} [{}]", question, default))?;
+    Ok(if answer.is_empty() { default.to_string() } else { answer })
+}
+
+// Pick one of options, by number or by name. Returns its index, default on an empty answer
+pub fn choose(question: &str, options: &[&str], default: usize) -> Result<usize> {
+    eprintln!("{}", question);
+    for (i, option) in options.iter().enumerate() {
+        eprintln!("  {}) {}", i + 1, option);
+    }
+    loop {
+        let answer = read_answer(&format!("Choose 1-{} [{}]", options.len(), default + 1))?;
+        if answer.is_empty() {
+            return Ok(default);
+        }
+        let picked = match answer.parse::<usize>() {
+            Ok(n) if (1..=options.len()).contains(&n) => Some(n - 1),
+            Ok(_) => None,
+            Err(_) => options.iter().position(|o| o.split_whitespace().next() == Some(answer.as_str())),
+        };
+        match picked {
+            Some(i) => return Ok(i),
+            None => eprintln!("{} isn't one of the options", answer),
+        }
+    }
+}
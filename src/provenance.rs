@@ -0,0 +1,89 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::Result;
+use tracing::info;
+
+use crate::{aws, git};
+
+pub const GIT_COMMIT_TAG: &str = "sageturner:git-commit";
+pub const GIT_BRANCH_TAG: &str = "sageturner:git-branch";
+pub const GIT_DIRTY_TAG: &str = "sageturner:git-dirty";
+pub const CONFIG_HASH_TAG: &str = "sageturner:config-hash";
+pub const DEPLOYED_BY_TAG: &str = "sageturner:deployed-by";
+
+// Where a deploy came from: recorded as tags on the SageMaker resources and labels on the image,
+// so a running endpoint can be traced back to the source and config it was deployed from
+#[derive(Debug)]
+pub struct Provenance {
+    // The git details of the config file's directory, if it's in a repository
+    pub git_commit: Option<String>,
+    pub git_branch: Option<String>,
+    pub git_dirty: Option<bool>,
+    // SHA-256 of the config as deployed, after extends, environments and --set
+    pub config_hash: String,
+    // The ARN of the IAM identity that ran the deploy, and its account
+    pub deployed_by: String,
+    pub account: String,
+}
+
+impl Provenance {
+    pub async fn collect(config_dir: &Path, config_hash: &str, sts_client: &aws_sdk_sts::Client) -> Result<Provenance> {
+        // A config in the current directory has an empty parent
+        let config_dir = if config_dir.as_os_str().is_empty() { Path::new(".") } else { config_dir };
+        // Not being in a git repository (or not having git) isn't a reason to stop a deploy
+        let git_commit = git::full_sha(config_dir).ok();
+        if git_commit.is_none() {
+            info!("{} isn't in a git repository, so the deploy won't record a commit", config_dir.display());
+        }
+        let (git_branch, git_dirty) = match git_commit {
+            Some(_) => (git::branch(config_dir).ok().flatten(), git::is_dirty(config_dir).ok()),
+            None => (None, None),
+        };
+        if git_dirty == Some(true) {
+            info!("Your working tree has uncommitted changes, the deploy will be tagged {}=true", GIT_DIRTY_TAG);
+        }
+        let identity = aws::identity(sts_client).await?;
+        Ok(Provenance {
+            git_commit,
+            git_branch,
+            git_dirty,
+            config_hash: config_hash.to_string(),
+            deployed_by: identity.arn,
+            account: identity.account,
+        })
+    }
+
+    // Tag keys and values. Values only use the characters SageMaker allows in tags
+    pub fn tags(&self) -> Vec<(&'static str, String)> {
+        let mut tags = Vec::new();
+        if let Some(commit) = &self.git_commit {
+            tags.push((GIT_COMMIT_TAG, commit.clone()));
+        }
+        if let Some(branch) = &self.git_branch {
+            tags.push((GIT_BRANCH_TAG, tag_value(branch)));
+        }
+        if let Some(dirty) = self.git_dirty {
+            tags.push((GIT_DIRTY_TAG, dirty.to_string()));
+        }
+        tags.push((CONFIG_HASH_TAG, self.config_hash.clone()));
+        tags.push((DEPLOYED_BY_TAG, tag_value(&self.deployed_by)));
+        tags
+    }
+
+    // The same details as Docker image labels, which use . rather than : in their keys
+    pub fn labels(&self) -> HashMap<String, String> {
+        self.tags()
+            .into_iter()
+            .map(|(key, value)| (key.replace(':', "."), value))
+            .collect()
+    }
+}
+
+// SageMaker tag values are at most 256 of letters, numbers, spaces and _.:/=+-@
+fn tag_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c.is_alphanumeric() || " _.:/=+-@".contains(c) { c } else { '_' })
+        .take(256)
+        .collect()
+}
@@ -0,0 +1,171 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{anyhow, Result};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_sagemaker::types::{
+    RecommendationJobContainerConfig, RecommendationJobInputConfig, RecommendationJobPayloadConfig, RecommendationJobStatus,
+    RecommendationJobSupportedEndpointType, RecommendationJobType, Tag,
+};
+use flate2::{write::GzEncoder, Compression};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{aws::AwsClients, errors, model_config::RecommenderConfig};
+
+// How often to check on a recommendation job, and how long to give it. Default jobs usually take under an hour
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const JOB_WAIT_LIMIT: Duration = Duration::from_secs(3 * 60 * 60);
+
+// One instance type Inference Recommender tried, and how the model did on it
+#[derive(Debug, Serialize)]
+pub struct Recommendation {
+    pub instance_type: String,
+    pub instance_count: i32,
+    pub cost_per_hour: Option<f32>,
+    pub cost_per_inference: Option<f32>,
+    // Per minute
+    pub max_invocations: Option<i32>,
+    // Milliseconds
+    pub model_latency: Option<i32>,
+}
+
+// Start an Inference Recommender job for the SageMaker model. Its payloads are packaged into the tar.gz Inference
+// Recommender wants, and uploaded to the artefact bucket under recommender/<job name>
+#[allow(clippy::too_many_arguments)]
+pub async fn start(
+    recommender: &RecommenderConfig,
+    sagemaker_model: &str,
+    job_name: &str,
+    bucket_name: &str,
+    role_arn: &str,
+    config_dir: &Path,
+    aws: &AwsClients,
+    tags: &[Tag],
+) -> Result<()> {
+    let key = format!("recommender/{}/payload.tar.gz", job_name);
+    info!("Uploading {} payloads to s3://{}/{}", recommender.payloads.len(), bucket_name, key);
+    aws.s3
+        .put_object()
+        .bucket(bucket_name)
+        .key(&key)
+        .body(ByteStream::from(package_payloads(&recommender.payloads, config_dir)?))
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, "Couldn't upload the recommender's payloads"))?;
+
+    let payload_config = RecommendationJobPayloadConfig::builder()
+        .sample_payload_url(format!("s3://{}/{}", bucket_name, key))
+        .supported_content_types(&recommender.content_type)
+        .build();
+    let container_config = RecommendationJobContainerConfig::builder()
+        .payload_config(payload_config)
+        .supported_endpoint_type(RecommendationJobSupportedEndpointType::Realtime)
+        .set_supported_instance_types((!recommender.instance_types.is_empty()).then(|| recommender.instance_types.clone()))
+        .build();
+    let input_config = RecommendationJobInputConfig::builder().model_name(sagemaker_model).container_config(container_config).build();
+    info!("Starting Inference Recommender job {} for model {}", job_name, sagemaker_model);
+    aws.sage
+        .create_inference_recommendations_job()
+        .job_name(job_name)
+        .job_type(RecommendationJobType::Default)
+        .role_arn(role_arn)
+        .input_config(input_config)
+        .set_tags(Some(tags.to_vec()))
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't start Inference Recommender job {}", job_name)))?;
+    Ok(())
+}
+
+// Wait for the job to finish, and return what it recommends, cheapest per inference first
+pub async fn wait(job_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<Vec<Recommendation>> {
+    info!("Waiting for Inference Recommender job {}, which usually takes under an hour", job_name);
+    let started = std::time::Instant::now();
+    let mut last_status = None;
+    loop {
+        let job = sage_client
+            .describe_inference_recommendations_job()
+            .job_name(job_name)
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't check on job {}", job_name)))?;
+        let status = job.status().cloned();
+        match &status {
+            Some(RecommendationJobStatus::Completed) => {
+                let mut recommendations: Vec<Recommendation> = job
+                    .inference_recommendations()
+                    .iter()
+                    .filter_map(|r| {
+                        let endpoint = r.endpoint_configuration()?;
+                        let metrics = r.metrics();
+                        Some(Recommendation {
+                            instance_type: endpoint.instance_type()?.as_str().to_string(),
+                            instance_count: endpoint.initial_instance_count().unwrap_or(1),
+                            cost_per_hour: metrics.and_then(|m| m.cost_per_hour()),
+                            cost_per_inference: metrics.and_then(|m| m.cost_per_inference()),
+                            max_invocations: metrics.and_then(|m| m.max_invocations()),
+                            model_latency: metrics.and_then(|m| m.model_latency()),
+                        })
+                    })
+                    .collect();
+                recommendations.sort_by(|a, b| {
+                    let cost = |r: &Recommendation| r.cost_per_inference.unwrap_or(f32::MAX);
+                    cost(a).total_cmp(&cost(b))
+                });
+                return Ok(recommendations);
+            }
+            Some(RecommendationJobStatus::Failed) | Some(RecommendationJobStatus::Stopped) => {
+                let reason = job.failure_reason().unwrap_or("SageMaker didn't give a reason");
+                return Err(anyhow!("Inference Recommender job {} didn't finish: {}", job_name, reason));
+            }
+            Some(s) if last_status.as_ref() != Some(s) => info!("Job {} is {}", job_name, s.as_str()),
+            _ => {}
+        }
+        last_status = status;
+        if started.elapsed() > JOB_WAIT_LIMIT {
+            return Err(anyhow!(
+                "Gave up waiting for job {} after {} hours, pick it up later with sageturner recommend --job {}",
+                job_name,
+                JOB_WAIT_LIMIT.as_secs() / 3600,
+                job_name
+            ));
+        }
+        tokio::time::sleep(JOB_POLL_INTERVAL).await;
+    }
+}
+
+pub fn print(recommendations: &[Recommendation]) {
+    println!(
+        "{:<20} {:<9} {:<11} {:<20} {:<22} MODEL LATENCY",
+        "INSTANCE TYPE", "COUNT", "COST/HOUR", "COST/1M INFERENCES", "MAX INVOCATIONS/MIN"
+    );
+    let or_dash = |v: Option<String>| v.unwrap_or("-".to_string());
+    for r in recommendations {
+        println!(
+            "{:<20} {:<9} {:<11} {:<20} {:<22} {}",
+            r.instance_type,
+            r.instance_count,
+            or_dash(r.cost_per_hour.map(|c| format!("${:.3}", c))),
+            or_dash(r.cost_per_inference.map(|c| format!("${:.2}", c * 1_000_000.0))),
+            or_dash(r.max_invocations.map(|i| i.to_string())),
+            or_dash(r.model_latency.map(|l| format!("{}ms", l))),
+        );
+    }
+}
+
+// Inference Recommender reads its payloads from a tar.gz of request files
+fn package_payloads(payloads: &[String], config_dir: &Path) -> Result<Vec<u8>> {
+    let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+    for (i, payload) in payloads.iter().enumerate() {
+        let path = config_dir.join(payload);
+        // Named by position, so payloads with the same file name in different directories don't clash
+        let name = match path.file_name() {
+            Some(file_name) => format!("{}-{}", i, file_name.to_string_lossy()),
+            None => i.to_string(),
+        };
+        builder
+            .append_path_with_name(&path, name)
+            .map_err(|e| anyhow!("Couldn't package recommender payload {}: {}", payload, e))?;
+    }
+    Ok(builder.into_inner()?.finish()?)
+}
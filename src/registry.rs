@@ -0,0 +1,208 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_sagemaker::error::ProvideErrorMetadata;
+use aws_sdk_sagemaker::types::{
+    ContainerDefinition, InferenceSpecification, MetadataProperties, MetricsSource, ModelApprovalStatus, ModelMetrics,
+    ModelPackageContainerDefinition, ModelQuality, Tag,
+};
+use tracing::{info, warn};
+
+use crate::{aws::{self, AwsClients}, errors, model_config::{ModelConfig, RegistryConfig}, provenance};
+
+// Deploys from a model package are tagged with its ARN
+pub const MODEL_PACKAGE_TAG: &str = "sageturner:model-package";
+
+// The group a config's deploys are registered in
+pub fn group_name(model_config: &ModelConfig, registry: &RegistryConfig) -> String {
+    registry.model_package_group.clone().unwrap_or_else(|| model_config.name.clone())
+}
+
+// Register the deploy's SageMaker model as a new version in the config's model package group, and return the
+// version's ARN. The version gets the model's image, model data and environment, so it can be deployed again
+// without building anything, and the deploy's tags as metadata
+#[allow(clippy::too_many_arguments)]
+pub async fn register_model(
+    model_config: &ModelConfig,
+    registry: &RegistryConfig,
+    sagemaker_name: &str,
+    endpoint_name: &str,
+    bucket_name: &str,
+    config_dir: &Path,
+    aws: &AwsClients,
+    tags: &[Tag],
+) -> Result<String> {
+    let sage_client = &aws.sage;
+    let group = group_name(model_config, registry);
+    create_group(&group, registry, sage_client, tags).await?;
+
+    let model = sage_client
+        .describe_model()
+        .model_name(sagemaker_name)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't find model {} to register", sagemaker_name)))?;
+    let container = model
+        .primary_container()
+        .ok_or_else(|| anyhow!("Model {} has no primary container", sagemaker_name))?;
+    let package_container = ModelPackageContainerDefinition::builder()
+        .set_image(container.image().map(|i| i.to_string()))
+        .set_model_data_url(container.model_data_url().map(|u| u.to_string()))
+        .set_model_data_source(container.model_data_source().cloned())
+        .set_environment(container.environment().cloned())
+        .build();
+    let mut inference = InferenceSpecification::builder().containers(package_container);
+    if let Some(server) = &model_config.compute.server {
+        inference = inference.supported_realtime_inference_instance_types(server.instance_type.as_str().into());
+    }
+
+    let commit = tags
+        .iter()
+        .find(|t| t.key() == Some(provenance::GIT_COMMIT_TAG))
+        .and_then(|t| t.value().map(|v| v.to_string()));
+    let mut request = sage_client
+        .create_model_package()
+        .model_package_group_name(&group)
+        .model_package_description(description(model_config, endpoint_name))
+        .inference_specification(inference.build())
+        .model_approval_status(ModelApprovalStatus::from(registry.approval_status.as_str()))
+        .metadata_properties(
+            MetadataProperties::builder()
+                .generated_by("sageturner")
+                .set_commit_id(commit)
+                .build(),
+        )
+        .customer_metadata_properties("sageturner:sagemaker-model", sagemaker_name)
+        .customer_metadata_properties("sageturner:endpoint", endpoint_name)
+        .set_tags(Some(tags.to_vec()));
+    for tag in tags.iter().filter(|t| t.key().is_some_and(|k| k.starts_with("sageturner:"))) {
+        if let (Some(key), Some(value)) = (tag.key(), tag.value()) {
+            request = request.customer_metadata_properties(key, value);
+        }
+    }
+    if let Some(metrics) = &registry.metrics {
+        let uri = match metrics.starts_with("s3://") {
+            true => metrics.clone(),
+            false => upload_metrics(&config_dir.join(metrics), bucket_name, sagemaker_name, &aws.s3).await?,
+        };
+        let statistics = MetricsSource::builder().content_type("application/json").s3_uri(uri).build();
+        request = request.model_metrics(
+            ModelMetrics::builder().model_quality(ModelQuality::builder().statistics(statistics).build()).build(),
+        );
+    }
+    let package = request
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't register {} in model package group {}", sagemaker_name, group)))?;
+    let arn = package
+        .model_package_arn()
+        .ok_or_else(|| anyhow!("SageMaker didn't return an ARN for the model package"))?
+        .to_string();
+    info!("Registered {} in model package group {}: {}", sagemaker_name, group, arn);
+    Ok(arn)
+}
+
+// Create the model package group if it doesn't exist yet
+async fn create_group(
+    group: &str,
+    registry: &RegistryConfig,
+    sage_client: &aws_sdk_sagemaker::Client,
+    tags: &[Tag],
+) -> Result<()> {
+    match sage_client.describe_model_package_group().model_package_group_name(group).send().await {
+        Ok(_) => return Ok(()),
+        Err(e) if e.as_service_error().and_then(|e| e.message()).is_some_and(|m| m.contains("does not exist")) => {}
+        Err(e) => return Err(errors::aws(e, format!("Couldn't look up model package group {}", group))),
+    }
+    info!("Creating model package group {}", group);
+    // Only the sageturner:model tag, as the group outlives this deploy's version and commit
+    let model_tag = tags.iter().filter(|t| t.key() == Some(aws::MODEL_TAG)).cloned().collect();
+    sage_client
+        .create_model_package_group()
+        .model_package_group_name(group)
+        .set_model_package_group_description(registry.description.clone())
+        .set_tags(Some(model_tag))
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't create model package group {}", group)))?;
+    Ok(())
+}
+
+// e.g. resnet-50 1.2.0, deployed to endpoint resnet-50-prod
+fn description(model_config: &ModelConfig, endpoint_name: &str) -> String {
+    match &model_config.version {
+        Some(v) => format!("{} {}, deployed to endpoint {}", model_config.name, v, endpoint_name),
+        None => format!("{}, deployed to endpoint {}", model_config.name, endpoint_name),
+    }
+}
+
+// Local metrics files are uploaded to the artefact bucket, under registry/<SageMaker model>
+async fn upload_metrics(path: &Path, bucket_name: &str, sagemaker_name: &str, s3_client: &aws_sdk_s3::Client) -> Result<String> {
+    let key = format!("registry/{}/metrics.json", sagemaker_name);
+    let body = std::fs::read_to_string(path).map_err(|e| anyhow!("Couldn't read registry metrics {}: {}", path.display(), e))?;
+    serde_json::from_str::<serde_json::Value>(&body)
+        .map_err(|e| anyhow!("Registry metrics {} aren't valid JSON: {}", path.display(), e))?;
+    info!("Uploading registry metrics {} to s3://{}/{}", path.display(), bucket_name, key);
+    s3_client
+        .put_object()
+        .bucket(bucket_name)
+        .key(&key)
+        .content_type("application/json")
+        .body(ByteStream::from(body.into_bytes()))
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, "Couldn't upload registry metrics"))?;
+    Ok(format!("s3://{}/{}", bucket_name, key))
+}
+
+// A model package can be deployed if it's an approved version (unless --skip-approval) in this region. SageMaker
+// models can only use packages in their own region
+pub async fn check_package(arn: &str, region: &str, skip_approval: bool, sage_client: &aws_sdk_sagemaker::Client) -> Result<()> {
+    match arn.split(':').nth(3) {
+        Some(r) if r == region => {}
+        Some(r) if arn.starts_with("arn:") => {
+            return Err(anyhow!("Model package {} is in {}, so can't be deployed to {}", arn, r, region));
+        }
+        _ => return Err(anyhow!("{} isn't a model package ARN", arn)),
+    }
+    let package = sage_client
+        .describe_model_package()
+        .model_package_name(arn)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't find model package {}", arn)))?;
+    match package.model_approval_status() {
+        Some(ModelApprovalStatus::Approved) => Ok(()),
+        Some(status) if skip_approval => {
+            warn!("Model package {} is {}, deploying it anyway as --skip-approval is set", arn, status.as_str());
+            Ok(())
+        }
+        status => Err(anyhow!(
+            "Model package {} is {}, only Approved packages can be deployed without --skip-approval",
+            arn,
+            status.map(|s| s.as_str()).unwrap_or("not approved")
+        )),
+    }
+}
+
+// Create the deploy's SageMaker model from a model package, which brings its own image, model data and environment
+pub async fn create_model_from_package(
+    arn: &str,
+    sagemaker_name: &str,
+    execution_role_arn: &str,
+    sage_client: &aws_sdk_sagemaker::Client,
+    tags: &[Tag],
+) -> Result<()> {
+    info!("Creating model {} from model package {}", sagemaker_name, arn);
+    sage_client
+        .create_model()
+        .model_name(sagemaker_name)
+        .execution_role_arn(execution_role_arn)
+        .primary_container(ContainerDefinition::builder().model_package_name(arn).build())
+        .set_tags(Some(tags.to_vec()))
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't create model {} from {}", sagemaker_name, arn)))?;
+    Ok(())
+}
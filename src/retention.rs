@@ -0,0 +1,188 @@
+use std::{collections::HashSet, io::Write};
+
+use anyhow::Result;
+use aws_sdk_ecr::types::ImageIdentifier;
+use chrono::Utc;
+use serde::Serialize;
+use tracing::info;
+
+use crate::{
+    artefact,
+    aws::{self, AwsClients},
+    errors,
+    model_config::{ModelConfig, RetentionConfig},
+};
+
+// What a retention policy deletes for one model and environment, worked out by plan
+#[derive(Debug, Default, Serialize)]
+pub struct RetentionPlan {
+    // SageMaker models, and the endpoint configs named after them
+    pub models: Vec<String>,
+    // ECR repository and tag (or digest)
+    pub images: Vec<(String, String)>,
+    // S3 URIs, ending in / for uncompressed model data
+    pub artefacts: Vec<String>,
+    // Models the policy would delete, but an endpoint is running
+    pub in_use: Vec<String>,
+}
+
+impl RetentionPlan {
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty() && self.images.is_empty() && self.artefacts.is_empty()
+    }
+
+    pub fn print(&self, out: &mut dyn Write) -> std::io::Result<()> {
+        if !self.models.is_empty() {
+            writeln!(out, "Models and endpoint configs past retention:")?;
+            for model in &self.models {
+                writeln!(out, "  {}", model)?;
+            }
+        }
+        if !self.images.is_empty() {
+            writeln!(out, "Images past retention:")?;
+            for (repository, tag) in &self.images {
+                writeln!(out, "  {}", image_name(repository, tag))?;
+            }
+        }
+        if !self.artefacts.is_empty() {
+            writeln!(out, "Artefacts past retention:")?;
+            for uri in &self.artefacts {
+                writeln!(out, "  {}", uri)?;
+            }
+        }
+        for model in &self.in_use {
+            writeln!(out, "Keeping {}, which is past retention but an endpoint is running it", model)?;
+        }
+        Ok(())
+    }
+}
+
+// A SageMaker model sageturner deployed for the config's model, in any environment
+struct Deployed {
+    name: String,
+    environment: Option<String>,
+    created: i64,
+}
+
+// Work out what the config's retention policy deletes. Its model's deploys to the config's environment are kept
+// if they're one of the newest keep_last, or less than keep_days old. The rest are deleted, along with their
+// images and artefacts, unless an endpoint is running them, or a deploy that's kept (in any environment) uses the
+// same image or artefact. Only images in the model's own ECR repository and artefacts in sageturner's bucket go
+pub async fn plan(
+    model_config: &ModelConfig,
+    retention: &RetentionConfig,
+    bucket_name: &str,
+    aws: &AwsClients,
+) -> Result<RetentionPlan> {
+    let sage_client = &aws.sage;
+    let deployed = deployed_models(&model_config.name, sage_client).await?;
+    let in_use = aws::models_in_use(sage_client).await?;
+    let cutoff = retention.keep_days.map(|days| (Utc::now() - chrono::Duration::days(days.into())).timestamp());
+
+    let mut plan = RetentionPlan::default();
+    let mut kept = Vec::new();
+    // Newest first
+    let in_scope = deployed.iter().filter(|d| d.environment == model_config.environment);
+    for (i, model) in in_scope.enumerate() {
+        let recent = retention.keep_last.is_some_and(|n| i < n as usize) || cutoff.is_some_and(|c| model.created >= c);
+        if recent {
+            kept.push(&model.name);
+        } else if in_use.contains(&model.name) {
+            plan.in_use.push(model.name.clone());
+        } else {
+            plan.models.push(model.name.clone());
+        }
+    }
+    kept.extend(deployed.iter().filter(|d| d.environment != model_config.environment).map(|d| &d.name));
+    kept.extend(plan.in_use.iter());
+
+    // What the deploys being kept still use
+    let mut used_images = HashSet::new();
+    let mut used_artefacts = HashSet::new();
+    for model in kept {
+        let (image, artefact) = aws::model_container(model, sage_client).await?;
+        used_images.extend(image);
+        used_artefacts.extend(artefact);
+    }
+    for model in &plan.models {
+        let (image, artefact) = aws::model_container(model, sage_client).await?;
+        if let Some(image) = image.filter(|i| !used_images.contains(i)) {
+            if let Some((repository, tag)) = aws::ecr_image(&image).filter(|(r, _)| r == &model_config.name) {
+                if !plan.images.contains(&(repository.clone(), tag.clone())) {
+                    plan.images.push((repository, tag));
+                }
+            }
+        }
+        if let Some(artefact) = artefact.filter(|a| !used_artefacts.contains(a)) {
+            let in_bucket = artefact::s3_location(&artefact).is_some_and(|(bucket, _)| bucket == bucket_name);
+            if in_bucket && !plan.artefacts.contains(&artefact) {
+                plan.artefacts.push(artefact);
+            }
+        }
+    }
+    Ok(plan)
+}
+
+// Delete everything in the plan
+pub async fn apply(plan: &RetentionPlan, aws: &AwsClients) -> Result<()> {
+    for model in &plan.models {
+        aws::delete_model_and_config(model, &aws.sage).await?;
+        info!("Deleted model and endpoint config {}", model);
+    }
+    for (repository, tag) in &plan.images {
+        let id = match tag.starts_with("sha256:") {
+            true => ImageIdentifier::builder().image_digest(tag).build(),
+            false => ImageIdentifier::builder().image_tag(tag).build(),
+        };
+        aws.ecr
+            .batch_delete_image()
+            .repository_name(repository)
+            .image_ids(id)
+            .send()
+            .await
+            .map_err(|e| errors::aws(e, format!("Couldn't delete image {}", image_name(repository, tag))))?;
+        info!("Deleted image {}", image_name(repository, tag));
+    }
+    for uri in &plan.artefacts {
+        aws::delete_s3_uri(uri, &aws.s3).await?;
+        info!("Deleted artefact {}", uri);
+    }
+    Ok(())
+}
+
+// Every SageMaker model sageturner deployed for the model, newest first
+async fn deployed_models(model_name: &str, sage_client: &aws_sdk_sagemaker::Client) -> Result<Vec<Deployed>> {
+    let mut pages = sage_client
+        .list_models()
+        .name_contains(model_name)
+        .sort_by(aws_sdk_sagemaker::types::ModelSortKey::CreationTime)
+        .sort_order(aws_sdk_sagemaker::types::OrderKey::Descending)
+        .into_paginator()
+        .send();
+    let mut models = Vec::new();
+    while let Some(page) = pages.next().await {
+        for summary in page?.models() {
+            let (Some(name), Some(arn)) = (summary.model_name(), summary.model_arn()) else {
+                continue;
+            };
+            let tags = sage_client.list_tags().resource_arn(arn).send().await?;
+            let tag = |key: &str| tags.tags().iter().find(|t| t.key() == Some(key)).and_then(|t| t.value());
+            if tag(aws::MODEL_TAG) != Some(model_name) {
+                continue;
+            }
+            models.push(Deployed {
+                name: name.to_string(),
+                environment: tag(aws::ENVIRONMENT_TAG).map(|e| e.to_string()),
+                created: summary.creation_time().map(|t| t.secs()).unwrap_or_default(),
+            });
+        }
+    }
+    Ok(models)
+}
+
+pub fn image_name(repository: &str, tag: &str) -> String {
+    match tag.starts_with("sha256:") {
+        true => format!("{}@{}", repository, tag),
+        false => format!("{}:{}", repository, tag),
+    }
+}
@@ -0,0 +1,62 @@
+use std::{cell::Cell, fmt::Display, future::Future, time::Duration};
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::errors;
+
+// Tries for a call that fails now and then, like a push ECR answers with a 503 or a throttled SageMaker call
+pub const ATTEMPTS: u32 = 4;
+
+const BASE_DELAY: Duration = Duration::from_secs(2);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+tokio::task_local! {
+    // Retries in the deploy phase being timed, for its result
+    static RETRIES: Cell<u32>;
+}
+
+// Run op until it works, it fails in a way that trying again won't fix, or it's been tried attempts times. The wait
+// between tries doubles each time, and is a random part of that, so deploys that failed together don't retry together
+pub async fn retry<T, F, Fut>(doing: impl Display, attempts: u32, mut op: F) -> Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut attempt = 1;
+    loop {
+        match op().await {
+            Ok(t) => {
+                if attempt > 1 {
+                    info!("{} worked on attempt {}/{}", doing, attempt, attempts);
+                }
+                return Ok(t);
+            }
+            Err(e) if attempt < attempts && errors::transient(&e) => {
+                let delay = backoff(attempt);
+                warn!("{} failed (attempt {}/{}), retrying in {:.1}s: {}", doing, attempt, attempts, delay.as_secs_f64(), e);
+                let _ = RETRIES.try_with(|retries| retries.set(retries.get() + 1));
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) if attempt > 1 => return Err(e.context(format!("{} failed after {} attempts", doing, attempt))),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Up to BASE_DELAY * 2^(attempt - 1), capped at MAX_DELAY
+fn backoff(attempt: u32) -> Duration {
+    let ceiling = BASE_DELAY.saturating_mul(2u32.saturating_pow(attempt - 1)).min(MAX_DELAY);
+    ceiling.mul_f64(fastrand::f64()).max(Duration::from_millis(100))
+}
+
+// Run fut, counting the retries in it
+pub async fn counted<F: Future>(fut: F) -> (F::Output, u32) {
+    RETRIES
+        .scope(Cell::new(0), async {
+            let output = fut.await;
+            (output, RETRIES.with(Cell::get))
+        })
+        .await
+}
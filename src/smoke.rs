@@ -0,0 +1,65 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde_json_path::JsonPath;
+use tracing::{info, warn};
+
+use crate::{aws, model_config::SmokeTest};
+
+// Send each smoke test to the endpoint, and fail if any got the wrong answer. They all run, so one deploy shows
+// everything that's wrong
+pub async fn run(tests: &[SmokeTest], endpoint_name: &str, config_dir: &Path, runtime_client: &aws_sdk_sagemakerruntime::Client) -> Result<()> {
+    info!("Running {} smoke tests against endpoint {}", tests.len(), endpoint_name);
+    let mut failed = 0;
+    for test in tests {
+        match check(test, endpoint_name, config_dir, runtime_client).await {
+            Ok(()) => info!("  {} passed", test.file),
+            Err(e) => {
+                warn!("  {}", e);
+                failed += 1;
+            }
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(anyhow!("{} of {} smoke tests failed against endpoint {}", failed, tests.len(), endpoint_name)),
+    }
+}
+
+async fn check(test: &SmokeTest, endpoint_name: &str, config_dir: &Path, runtime_client: &aws_sdk_sagemakerruntime::Client) -> Result<()> {
+    let body = std::fs::read(config_dir.join(&test.file))?;
+    let (status, body) = aws::invoke_endpoint(endpoint_name, None, &test.content_type, body, runtime_client)
+        .await
+        .map_err(|e| anyhow!("{} failed: {}", test.file, e))?;
+    if status != test.expect_status {
+        return Err(anyhow!("{} failed: expected status {}, got {}: {}", test.file, test.expect_status, status, String::from_utf8_lossy(&body)));
+    }
+    if test.assertions.is_empty() {
+        return Ok(());
+    }
+    let json: serde_json::Value =
+        serde_json::from_slice(&body).map_err(|e| anyhow!("{} failed: the response isn't JSON: {}", test.file, e))?;
+    for assertion in &test.assertions {
+        // Checked when the config was validated
+        let path = JsonPath::parse(&assertion.path).map_err(|e| anyhow!("Invalid JSONPath {}: {}", assertion.path, e))?;
+        let found = path.query(&json).all();
+        match &assertion.equals {
+            None if found.is_empty() => return Err(anyhow!("{} failed: {} found nothing in the response", test.file, assertion.path)),
+            Some(expected) if !found.contains(&expected) => {
+                let found: Vec<String> = found.iter().map(|v| v.to_string()).collect();
+                return Err(anyhow!(
+                    "{} failed: expected {} to be {}, found {}",
+                    test.file,
+                    assertion.path,
+                    expected,
+                    match found.is_empty() {
+                        true => "nothing".to_string(),
+                        false => found.join(", "),
+                    }
+                ));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,622 @@
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, Result};
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_s3::primitives::ByteStream;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    artefact,
+    aws::AwsClients,
+    errors,
+    model_config::{ModelConfig, StateBackendConfig},
+    provenance::Provenance,
+    Stage,
+};
+
+// Each project's deploys are recorded in .sageturner/state.json, next to its config, unless state_backend keeps them
+// in S3 or DynamoDB for the whole team
+pub const STATE_DIR: &str = ".sageturner";
+const STATE_FILE: &str = "state.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DeployStatus {
+    // Started, and hasn't finished (or sageturner stopped before it could say how it went)
+    InProgress,
+    InService,
+    // Finished with --no-wait, so the endpoint was still being created or updated
+    Submitted,
+    Failed,
+    // Stopped after the stage --until asked for, on purpose
+    Partial,
+    // Stopped by require_approval before the endpoint, with its model registered for approval
+    AwaitingApproval,
+}
+
+impl std::fmt::Display for DeployStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeployStatus::InProgress => write!(f, "in progress"),
+            DeployStatus::InService => write!(f, "in service"),
+            DeployStatus::Submitted => write!(f, "submitted"),
+            DeployStatus::Failed => write!(f, "failed"),
+            DeployStatus::Partial => write!(f, "partial"),
+            DeployStatus::AwaitingApproval => write!(f, "awaiting approval"),
+        }
+    }
+}
+
+// One deploy (or rollback) of an endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeployRecord {
+    // When the deploy started, RFC 3339 UTC. With the SageMaker model and region, identifies the deploy
+    pub started: String,
+    pub model: String,
+    pub version: Option<String>,
+    pub environment: Option<String>,
+    pub endpoint: String,
+    // The SageMaker model, and the endpoint config unless it has its own name
+    pub sagemaker_model: String,
+    // Set when the deploy started after the model stage, so made a new endpoint config for an existing model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint_config: Option<String>,
+    pub region: String,
+    pub image: Option<String>,
+    pub image_digest: Option<String>,
+    // The model data's S3 URI
+    pub artefact: Option<String>,
+    pub model_package: Option<String>,
+    pub config_hash: String,
+    pub git_commit: Option<String>,
+    pub deployed_by: String,
+    pub status: DeployStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    // Set for sageturner rollback, which points the endpoint back at an earlier deploy's model
+    #[serde(default)]
+    pub rollback: bool,
+    // Set once sageturner gc --failed has deleted the deploy's model and endpoint config
+    #[serde(default)]
+    pub cleaned_up: bool,
+    // The stages the deploy finished, in order, so deploy --resume can carry on after the last
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stages: Vec<Stage>,
+}
+
+impl DeployRecord {
+    pub fn new(
+        model_config: &ModelConfig,
+        endpoint_name: &str,
+        sagemaker_name: &str,
+        region: &str,
+        provenance: &Provenance,
+    ) -> DeployRecord {
+        DeployRecord {
+            started: Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            model: model_config.name.clone(),
+            version: model_config.version.clone(),
+            environment: model_config.environment.clone(),
+            endpoint: endpoint_name.to_string(),
+            sagemaker_model: sagemaker_name.to_string(),
+            endpoint_config: None,
+            region: region.to_string(),
+            image: None,
+            image_digest: None,
+            artefact: None,
+            model_package: None,
+            config_hash: provenance.config_hash.clone(),
+            git_commit: provenance.git_commit.clone(),
+            deployed_by: provenance.deployed_by.clone(),
+            status: DeployStatus::InProgress,
+            reason: None,
+            rollback: false,
+            cleaned_up: false,
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn endpoint_config(&self) -> &str {
+        self.endpoint_config.as_deref().unwrap_or(&self.sagemaker_model)
+    }
+
+    pub fn finished(&mut self, stage: Stage) {
+        if !self.stages.contains(&stage) {
+            self.stages.push(stage);
+        }
+    }
+
+    pub fn has_finished(&self, stage: Stage) -> bool {
+        self.stages.contains(&stage)
+    }
+
+    fn same_deploy(&self, other: &DeployRecord) -> bool {
+        self.started == other.started && self.sagemaker_model == other.sagemaker_model && self.region == other.region
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    // Oldest first
+    pub deploys: Vec<DeployRecord>,
+}
+
+impl State {
+    // Add the deploy, or update it if it's already recorded
+    pub fn put(&mut self, record: &DeployRecord) {
+        match self.deploys.iter_mut().find(|d| d.same_deploy(record)) {
+            Some(existing) => *existing = record.clone(),
+            None => self.deploys.push(record.clone()),
+        }
+    }
+
+    // The latest deploy of a SageMaker model. Running a deploy again reuses its model, so there can be several
+    pub fn latest(&self, sagemaker_model: &str, region: &str) -> Option<&DeployRecord> {
+        self.deploys.iter().rev().find(|d| d.sagemaker_model == sagemaker_model && d.region == region)
+    }
+
+    // The last deploy of a model to an environment, not counting rollbacks
+    pub fn last_deploy(&self, model: &str, environment: Option<&str>, region: &str) -> Option<&DeployRecord> {
+        self.deploys
+            .iter()
+            .rev()
+            .find(|d| d.model == model && d.environment.as_deref() == environment && d.region == region && !d.rollback)
+    }
+
+    // An endpoint's deploys, newest first
+    pub fn endpoint_history<'a>(&'a self, endpoint: &'a str, region: &'a str) -> impl Iterator<Item = &'a DeployRecord> {
+        self.deploys.iter().rev().filter(move |d| d.endpoint == endpoint && d.region == region)
+    }
+}
+
+// Where the state is kept: the project's .sageturner/state.json, or somewhere the whole team can read and write
+#[derive(Debug, Clone)]
+pub enum Backend {
+    // The directory .sageturner is in
+    Local { dir: PathBuf },
+    // One JSON object, the same as the local file
+    S3 { bucket: String, key: String },
+    // An item per deploy, so deploys from different places don't have to take turns to write
+    DynamoDb { table: String },
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Backend::Local { dir } => write!(f, "{}", local_path(dir).display()),
+            Backend::S3 { bucket, key } => write!(f, "s3://{}/{}", bucket, key),
+            Backend::DynamoDb { table } => write!(f, "DynamoDB table {}", table),
+        }
+    }
+}
+
+// A writer that saw the S3 object changed under it starts again, this many times
+const S3_ATTEMPTS: u32 = 5;
+const DEPLOY_ID_PREFIX: &str = "deploy#";
+
+impl Backend {
+    // dir is the directory of the config, for the local state
+    pub fn new(config: Option<&StateBackendConfig>, dir: &Path) -> Backend {
+        match config {
+            Some(StateBackendConfig { s3_uri: Some(uri), .. }) => {
+                // Checked by validate_config
+                let (bucket, key) = artefact::s3_location(uri).unwrap_or_default();
+                Backend::S3 { bucket: bucket.to_string(), key: key.to_string() }
+            }
+            Some(StateBackendConfig { dynamodb_table: Some(table), .. }) => Backend::DynamoDb { table: table.clone() },
+            _ => Backend::Local { dir: dir.to_path_buf() },
+        }
+    }
+
+    // Every deploy recorded, oldest first. Empty if nothing's been recorded yet
+    pub async fn load(&self, aws: &AwsClients) -> Result<State> {
+        match self {
+            Backend::Local { dir } => load_local(dir),
+            Backend::S3 { bucket, key } => Ok(load_s3(bucket, key, &aws.s3).await?.0),
+            Backend::DynamoDb { table } => load_dynamodb(table, &aws.dynamodb).await,
+        }
+    }
+
+    // Add the deploy, or update it if it's already recorded
+    pub async fn put(&self, record: &DeployRecord, aws: &AwsClients) -> Result<()> {
+        match self {
+            Backend::Local { dir } => {
+                let mut state = load_local(dir)?;
+                state.put(record);
+                save_local(dir, &state)
+            }
+            Backend::S3 { bucket, key } => put_s3(bucket, key, record, &aws.s3).await,
+            Backend::DynamoDb { table } => put_dynamodb(table, record, &aws.dynamodb).await,
+        }
+    }
+
+    // Record that gc has deleted these SageMaker models, and their endpoint configs
+    pub async fn mark_cleaned_up<'a>(
+        &self,
+        state: &State,
+        sagemaker_models: impl Iterator<Item = &'a String>,
+        aws: &AwsClients,
+    ) -> Result<()> {
+        for name in sagemaker_models {
+            for deploy in state.deploys.iter().filter(|d| &d.sagemaker_model == name && d.region == aws.region) {
+                self.put(&DeployRecord { cleaned_up: true, ..deploy.clone() }, aws).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Record the deploy in the state. The endpoint matters more than the record of it, so a deploy doesn't fail
+// because its state couldn't be written
+pub async fn record(backend: &Backend, record: &DeployRecord, aws: &AwsClients) {
+    if let Err(e) = backend.put(record, aws).await {
+        warn!("Couldn't record the deploy in {}: {}", backend, e);
+    }
+}
+
+fn local_path(dir: &Path) -> PathBuf {
+    dir.join(STATE_DIR).join(STATE_FILE)
+}
+
+fn load_local(dir: &Path) -> Result<State> {
+    let path = local_path(dir);
+    if !path.exists() {
+        return Ok(State::default());
+    }
+    let contents = fs::read_to_string(&path)?;
+    serde_json::from_str(&contents).map_err(|e| anyhow!("Couldn't read {}: {}", path.display(), e))
+}
+
+fn save_local(dir: &Path, state: &State) -> Result<()> {
+    let path = local_path(dir);
+    fs::create_dir_all(dir.join(STATE_DIR))?;
+    // Written alongside and renamed over the old file, so an interrupted write can't leave half a file
+    let partial = path.with_extension("json.partial");
+    fs::write(&partial, serde_json::to_string_pretty(state)?)?;
+    fs::rename(&partial, &path)?;
+    Ok(())
+}
+
+// The state in S3, and its ETag to write it back with. No ETag if there's no state yet
+async fn load_s3(bucket: &str, key: &str, s3_client: &aws_sdk_s3::Client) -> Result<(State, Option<String>)> {
+    let object = match s3_client.get_object().bucket(bucket).key(key).send().await {
+        Ok(object) => object,
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok((State::default(), None)),
+        Err(e) => {
+            return Err(errors::aws(e, format!("Couldn't read s3://{}/{}", bucket, key)))
+        }
+    };
+    let etag = object.e_tag().map(|t| t.to_string());
+    let body = object.body.collect().await.map_err(|e| anyhow!("Couldn't read s3://{}/{}: {}", bucket, key, e))?;
+    let state = serde_json::from_slice(&body.into_bytes())
+        .map_err(|e| anyhow!("Couldn't read s3://{}/{}: {}", bucket, key, e))?;
+    Ok((state, etag))
+}
+
+// Read, update and write back the state, only if nobody else wrote it in between. If they did, start again
+async fn put_s3(bucket: &str, key: &str, record: &DeployRecord, s3_client: &aws_sdk_s3::Client) -> Result<()> {
+    for _ in 0..S3_ATTEMPTS {
+        let (mut state, etag) = load_s3(bucket, key, s3_client).await?;
+        state.put(record);
+        let mut request = s3_client
+            .put_object()
+            .bucket(bucket)
+            .key(key)
+            .content_type("application/json")
+            .body(ByteStream::from(serde_json::to_vec_pretty(&state)?));
+        request = match etag {
+            Some(etag) => request.if_match(etag),
+            None => request.if_none_match("*"),
+        };
+        match request.send().await {
+            Ok(_) => return Ok(()),
+            // 412 if the object changed, 409 if another write was in flight
+            Err(e) if e.raw_response().is_some_and(|r| matches!(r.status().as_u16(), 409 | 412)) => continue,
+            Err(e) => {
+                return Err(errors::aws(e, format!("Couldn't write s3://{}/{}", bucket, key)))
+            }
+        }
+    }
+    Err(anyhow!("s3://{}/{} kept changing while it was being written, try again", bucket, key))
+}
+
+async fn load_dynamodb(table: &str, dynamodb_client: &aws_sdk_dynamodb::Client) -> Result<State> {
+    let items: Vec<_> = dynamodb_client
+        .scan()
+        .table_name(table)
+        .filter_expression("begins_with(id, :prefix)")
+        .expression_attribute_values(":prefix", AttributeValue::S(DEPLOY_ID_PREFIX.to_string()))
+        .into_paginator()
+        .items()
+        .send()
+        .collect::<Result<_, _>>()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't read DynamoDB table {}", table)))?;
+    let mut deploys = items
+        .iter()
+        .map(|item| match item.get("record") {
+            Some(AttributeValue::S(record)) => serde_json::from_str(record)
+                .map_err(|e| anyhow!("Couldn't read a deploy in DynamoDB table {}: {}", table, e)),
+            _ => Err(anyhow!("A deploy in DynamoDB table {} has no record", table)),
+        })
+        .collect::<Result<Vec<DeployRecord>>>()?;
+    // Scans come back in no particular order. Start times all have the same format, so sort as strings
+    deploys.sort_by(|a, b| a.started.cmp(&b.started));
+    Ok(State { deploys })
+}
+
+async fn put_dynamodb(table: &str, record: &DeployRecord, dynamodb_client: &aws_sdk_dynamodb::Client) -> Result<()> {
+    let id = format!("{}{}#{}#{}", DEPLOY_ID_PREFIX, record.region, record.sagemaker_model, record.started);
+    dynamodb_client
+        .put_item()
+        .table_name(table)
+        .item("id", AttributeValue::S(id))
+        .item("record", AttributeValue::S(serde_json::to_string(record)?))
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't write to DynamoDB table {}", table)))?;
+    Ok(())
+}
+
+// A deploy holds its endpoint's lock from when it starts to when it's finished, so two deploys can't update the
+// endpoint at once. A deploy that died without letting go of it is given up on after this long
+const LOCK_HOURS: i64 = 6;
+const LOCK_ID_PREFIX: &str = "lock#";
+
+// Who holds a lock, kept in the same place as the state
+#[derive(Debug, Serialize, Deserialize)]
+struct LockInfo {
+    holder: String,
+    sagemaker_model: String,
+    acquired: String,
+    // Unix time
+    expires: i64,
+    // Only the deploy that took the lock lets go of it, not one that's taken it over since it expired
+    token: String,
+}
+
+impl LockInfo {
+    fn expired(&self) -> bool {
+        self.expires < Utc::now().timestamp()
+    }
+}
+
+// A held lock on an endpoint, let go of with release
+pub struct Lock {
+    backend: Backend,
+    endpoint: String,
+    region: String,
+    token: String,
+}
+
+impl Backend {
+    // Lock the endpoint for a deploy, or fail saying who's deploying it. Only shared state is locked: a project's
+    // own state.json is only deployed from one place
+    pub async fn lock(
+        &self,
+        endpoint: &str,
+        region: &str,
+        holder: &str,
+        sagemaker_model: &str,
+        aws: &AwsClients,
+    ) -> Result<Option<Lock>> {
+        let now = Utc::now();
+        let info = LockInfo {
+            holder: holder.to_string(),
+            sagemaker_model: sagemaker_model.to_string(),
+            acquired: now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            expires: (now + chrono::Duration::hours(LOCK_HOURS)).timestamp(),
+            token: format!("{}-{}", std::process::id(), now.timestamp_micros()),
+        };
+        let held_by = match self {
+            Backend::Local { .. } => return Ok(None),
+            Backend::S3 { bucket, key } => lock_s3(bucket, &lock_key(key, endpoint, region), &info, &aws.s3).await?,
+            Backend::DynamoDb { table } => lock_dynamodb(table, &lock_id(endpoint, region), &info, &aws.dynamodb).await?,
+        };
+        match held_by {
+            None => Ok(Some(Lock {
+                backend: self.clone(),
+                endpoint: endpoint.to_string(),
+                region: region.to_string(),
+                token: info.token,
+            })),
+            Some(other) => Err(anyhow!(
+                "Endpoint {} in {} is being deployed by {} (model {}, since {}). Wait for that deploy to finish. If it died, run \
+                 sageturner unlock -n {} --region {}, or the lock runs out at {}",
+                endpoint,
+                region,
+                other.holder,
+                other.sagemaker_model,
+                other.acquired,
+                endpoint,
+                region,
+                chrono::DateTime::from_timestamp(other.expires, 0).unwrap_or_default().format("%Y-%m-%d %H:%M UTC")
+            )),
+        }
+    }
+
+    // Remove an endpoint's lock whoever holds it, returning whether there was one
+    pub async fn unlock(&self, endpoint: &str, region: &str, aws: &AwsClients) -> Result<bool> {
+        match self {
+            Backend::Local { .. } => Err(anyhow!("Deploys are only locked with a state_backend, and there isn't one in your defaults file")),
+            Backend::S3 { bucket, key } => {
+                let key = lock_key(key, endpoint, region);
+                let held = read_lock_s3(bucket, &key, &aws.s3).await?.is_some();
+                if held {
+                    delete_lock_s3(bucket, &key, &aws.s3).await?;
+                }
+                Ok(held)
+            }
+            Backend::DynamoDb { table } => {
+                let deleted = aws
+                    .dynamodb
+                    .delete_item()
+                    .table_name(table)
+                    .key("id", AttributeValue::S(lock_id(endpoint, region)))
+                    .return_values(aws_sdk_dynamodb::types::ReturnValue::AllOld)
+                    .send()
+                    .await
+                    .map_err(|e| errors::aws(e, format!("Couldn't unlock {}", endpoint)))?;
+                Ok(deleted.attributes().is_some())
+            }
+        }
+    }
+}
+
+impl Lock {
+    // Let go of the lock. If that fails it runs out by itself, so this warns rather than failing the deploy
+    pub async fn release(self, aws: &AwsClients) {
+        let result = match &self.backend {
+            Backend::Local { .. } => Ok(()),
+            Backend::S3 { bucket, key } => {
+                let key = lock_key(key, &self.endpoint, &self.region);
+                match read_lock_s3(bucket, &key, &aws.s3).await {
+                    Ok(Some((info, _))) if info.token == self.token => delete_lock_s3(bucket, &key, &aws.s3).await,
+                    Ok(_) => Ok(()),
+                    Err(e) => Err(e),
+                }
+            }
+            Backend::DynamoDb { table } => aws
+                .dynamodb
+                .delete_item()
+                .table_name(table)
+                .key("id", AttributeValue::S(lock_id(&self.endpoint, &self.region)))
+                .condition_expression("#lock.#token = :token")
+                .expression_attribute_names("#lock", "lock")
+                .expression_attribute_names("#token", "token")
+                .expression_attribute_values(":token", AttributeValue::S(self.token.clone()))
+                .send()
+                .await
+                .map(|_| ())
+                .or_else(|e| match e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) {
+                    // Someone ran unlock, or took it over once it ran out
+                    true => Ok(()),
+                    false => Err(anyhow!("{}", aws_sdk_dynamodb::error::DisplayErrorContext(&e))),
+                }),
+        };
+        if let Err(e) = result {
+            warn!("Couldn't unlock endpoint {} in {}: {}", self.endpoint, self.backend, e);
+        }
+    }
+}
+
+// Locks sit next to the state object, one per endpoint
+fn lock_key(key: &str, endpoint: &str, region: &str) -> String {
+    format!("{}.locks/{}/{}.json", key, region, endpoint)
+}
+
+fn lock_id(endpoint: &str, region: &str) -> String {
+    format!("{}{}#{}", LOCK_ID_PREFIX, region, endpoint)
+}
+
+// Take the lock, or return who has it
+async fn lock_s3(bucket: &str, key: &str, info: &LockInfo, s3_client: &aws_sdk_s3::Client) -> Result<Option<LockInfo>> {
+    let mut request = s3_client
+        .put_object()
+        .bucket(bucket)
+        .key(key)
+        .content_type("application/json")
+        .body(ByteStream::from(serde_json::to_vec(info)?));
+    // Create it, or take over one that's run out, as long as nobody else got there first
+    request = match read_lock_s3(bucket, key, s3_client).await? {
+        Some((held, _)) if !held.expired() => return Ok(Some(held)),
+        Some((_, etag)) => request.set_if_match(etag),
+        None => request.if_none_match("*"),
+    };
+    match request.send().await {
+        Ok(_) => Ok(None),
+        Err(e) if e.raw_response().is_some_and(|r| matches!(r.status().as_u16(), 409 | 412)) => {
+            match read_lock_s3(bucket, key, s3_client).await? {
+                Some((held, _)) => Ok(Some(held)),
+                None => Err(anyhow!("Another deploy took and let go of the lock while this one was taking it, try again")),
+            }
+        }
+        Err(e) => Err(errors::aws(e, format!("Couldn't write lock s3://{}/{}", bucket, key))),
+    }
+}
+
+async fn read_lock_s3(bucket: &str, key: &str, s3_client: &aws_sdk_s3::Client) -> Result<Option<(LockInfo, Option<String>)>> {
+    let object = match s3_client.get_object().bucket(bucket).key(key).send().await {
+        Ok(object) => object,
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => return Ok(None),
+        Err(e) => {
+            return Err(errors::aws(e, format!("Couldn't read lock s3://{}/{}", bucket, key)))
+        }
+    };
+    let etag = object.e_tag().map(|t| t.to_string());
+    let body = object.body.collect().await.map_err(|e| anyhow!("Couldn't read lock s3://{}/{}: {}", bucket, key, e))?;
+    let info = serde_json::from_slice(&body.into_bytes())
+        .map_err(|e| anyhow!("Couldn't read lock s3://{}/{}: {}", bucket, key, e))?;
+    Ok(Some((info, etag)))
+}
+
+async fn delete_lock_s3(bucket: &str, key: &str, s3_client: &aws_sdk_s3::Client) -> Result<()> {
+    s3_client
+        .delete_object()
+        .bucket(bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| errors::aws(e, format!("Couldn't delete lock s3://{}/{}", bucket, key)))?;
+    Ok(())
+}
+
+// Take the lock with a conditional write that only succeeds if nobody holds it, or their hold has run out.
+// Otherwise return who has it
+async fn lock_dynamodb(
+    table: &str,
+    id: &str,
+    info: &LockInfo,
+    dynamodb_client: &aws_sdk_dynamodb::Client,
+) -> Result<Option<LockInfo>> {
+    let result = dynamodb_client
+        .put_item()
+        .table_name(table)
+        .item("id", AttributeValue::S(id.to_string()))
+        .item("lock", AttributeValue::M(lock_attributes(info)))
+        .condition_expression("attribute_not_exists(id) OR #lock.expires < :now")
+        .expression_attribute_names("#lock", "lock")
+        .expression_attribute_values(":now", AttributeValue::N(Utc::now().timestamp().to_string()))
+        .send()
+        .await;
+    match result {
+        Ok(_) => Ok(None),
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_conditional_check_failed_exception()) => {
+            let item = dynamodb_client
+                .get_item()
+                .table_name(table)
+                .key("id", AttributeValue::S(id.to_string()))
+                .consistent_read(true)
+                .send()
+                .await
+                .map_err(|e| errors::aws(e, format!("Couldn't read lock in DynamoDB table {}", table)))?;
+            match item.item().and_then(|i| i.get("lock")).and_then(|l| l.as_m().ok()) {
+                Some(lock) => Ok(Some(lock_info(lock))),
+                None => Err(anyhow!("Another deploy took and let go of the lock while this one was taking it, try again")),
+            }
+        }
+        Err(e) => Err(errors::aws(e, format!("Couldn't write lock to DynamoDB table {}", table))),
+    }
+}
+
+fn lock_attributes(info: &LockInfo) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("holder".to_string(), AttributeValue::S(info.holder.clone())),
+        ("sagemaker_model".to_string(), AttributeValue::S(info.sagemaker_model.clone())),
+        ("acquired".to_string(), AttributeValue::S(info.acquired.clone())),
+        ("expires".to_string(), AttributeValue::N(info.expires.to_string())),
+        ("token".to_string(), AttributeValue::S(info.token.clone())),
+    ])
+}
+
+fn lock_info(lock: &HashMap<String, AttributeValue>) -> LockInfo {
+    let string = |key: &str| lock.get(key).and_then(|v| v.as_s().ok()).cloned().unwrap_or_else(|| "-".to_string());
+    LockInfo {
+        holder: string("holder"),
+        sagemaker_model: string("sagemaker_model"),
+        acquired: string("acquired"),
+        expires: lock.get("expires").and_then(|v| v.as_n().ok()).and_then(|n| n.parse().ok()).unwrap_or_default(),
+        token: string("token"),
+    }
+}
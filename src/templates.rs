@@ -0,0 +1,86 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use tera::{Tera, Value};
+use tracing::debug;
+
+use crate::model_config::Hardware;
+
+// The files sageturner generates for generate_container and vllm images, each of which a config can swap for its own
+// template, rendered with the same context
+const DOCKERFILE: &str = include_str!("templates/Dockerfile.tera");
+const VLLM_DOCKERFILE: &str = include_str!("templates/vllm.Dockerfile.tera");
+// CONSTRAINT: the user's code must be called sageturner.py, so that serve.py's import works
+const SERVE: &str = include_str!("templates/serve.py.tera");
+
+// What generate_container's Dockerfile is rendered with
+#[derive(Debug, Serialize)]
+pub struct DockerfileContext<'a> {
+    pub hardware: Hardware,
+    pub python_version: &'a str,
+    pub system_packages: &'a [String],
+    pub python_packages: &'a [String],
+}
+
+// What vllm's Dockerfile is rendered with
+#[derive(Debug, Serialize)]
+pub struct VllmContext<'a> {
+    pub model_id: &'a str,
+    pub tensor_parallel_size: u32,
+    pub max_model_len: Option<u32>,
+}
+
+// What serve.py is rendered with
+#[derive(Debug, Serialize)]
+pub struct ServeContext {
+    pub port: u16,
+}
+
+pub fn dockerfile(context: &DockerfileContext, template: Option<&Path>) -> Result<String> {
+    render("Dockerfile", DOCKERFILE, template, context)
+}
+
+pub fn vllm_dockerfile(context: &VllmContext, template: Option<&Path>) -> Result<String> {
+    render("Dockerfile", VLLM_DOCKERFILE, template, context)
+}
+
+pub fn serve_code(context: &ServeContext, template: Option<&Path>) -> Result<String> {
+    render("serve.py", SERVE, template, context)
+}
+
+// Render the user's template if they have one, or ours. Tera's own errors say where in the template the problem is
+fn render(name: &str, builtin: &str, template: Option<&Path>, context: &impl Serialize) -> Result<String> {
+    let (source, from) = match template {
+        Some(path) => {
+            let source = std::fs::read_to_string(path).map_err(|e| anyhow!("Couldn't read template {}: {}", path.display(), e))?;
+            (source, path.display().to_string())
+        }
+        None => (builtin.to_string(), format!("sageturner's {} template", name)),
+    };
+    let mut tera = Tera::default();
+    tera.register_filter("shell_quote", shell_quote);
+    tera.add_raw_template(name, &source)
+        .map_err(|e| anyhow!("Couldn't parse {}: {:#}", from, anyhow::Error::new(e)))?;
+    let context = tera::Context::from_serialize(context)?;
+    let rendered = tera
+        .render(name, &context)
+        .map_err(|e| anyhow!("Couldn't render {}: {:#}", from, anyhow::Error::new(e)))?;
+    // Templates open with a comment on what they're rendered with, which leaves a blank line
+    let rendered = rendered.trim_start().to_string();
+    debug!("{}:\n{}", name, rendered);
+    Ok(rendered)
+}
+
+// Quote a string, or each of a list of them, for a shell, so packages like numpy>=2 install rather than redirect
+fn shell_quote(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let quote = |value: &Value| match value {
+        Value::String(s) => Ok(format!("'{}'", s.replace('\'', r"'\''"))),
+        other => Err(tera::Error::msg(format!("shell_quote expects strings, got {}", other))),
+    };
+    let quoted = match value {
+        Value::Array(values) => values.iter().map(quote).collect::<tera::Result<Vec<_>>>()?.join(" "),
+        value => quote(value)?,
+    };
+    Ok(Value::String(quoted))
+}